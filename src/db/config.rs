@@ -1,6 +1,13 @@
-use std::path::PathBuf;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::db::ErrDb;
+use crate::db::{ChecksumKind, ErrDb, FileDb, KeyComparator, Metrics, NoopMetrics, DATA_FILE_NAME_SUFFIX};
+use crate::index::{BPTREE_BUCKET_NAME, BPTREE_INDEX_FILE_NAME};
+use crate::io_db::DbIoFactory;
+#[cfg(feature = "encryption")]
+use crate::io_db::{EncryptedIo, EncryptionKey};
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum IndexType {
@@ -12,6 +19,9 @@ pub struct WriteBatchOptions {
     // 一个批次当中的最大数据量
     pub max_batch_num: usize,
 
+    // 一个批次当中暂存的 key 和 value 的最大字节数
+    pub max_batch_bytes: usize,
+
     // 提交时候是否进行 sync 持久化
     pub sync_writes: bool,
 }
@@ -20,15 +30,83 @@ impl Default for WriteBatchOptions {
     fn default() -> Self {
         Self {
             max_batch_num: 10000,
+            max_batch_bytes: 64 * 1024 * 1024,
             sync_writes: true,
         }
     }
 }
 
+/// 写入数据时的持久化策略
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SyncPolicy {
+    // 不主动持久化，由操作系统决定
+    Never,
+    // 每次写入之后都持久化
+    EveryWrite,
+    // 累计写入的字节数达到阈值之后持久化
+    EveryBytes(usize),
+    // 后台线程按照时间间隔持久化
+    Interval(Duration),
+}
+
+/// merge 时选择参与 merge 的数据文件的策略
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MergeStrategy {
+    // 重写所有的数据文件，包括切换之前的活跃文件
+    Full,
+    // 已经 merge 的文件作为一层，之后没有 merge 的旧数据文件累计到相近的大小时才一起 merge，活跃文件不参与
+    // 分摊重写的开销，和 merge_range 一样不回收 blob 文件
+    SizeTiered,
+}
+
+/// 一次写入返回时的持久化程度
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Durability {
+    // 只写到了操作系统的缓存中，还没有持久化
+    Buffered,
+    // 返回之前已经持久化
+    Synced,
+}
+
+/// merge 的进度，每处理完一个参与 merge 的数据文件报告一次
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct MergeProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    // 重写到 merge 数据文件中的字节数
+    pub bytes_written: u64,
+}
+
+/// 接收 merge 进度的回调，在执行 merge 的线程中调用
+#[derive(Clone)]
+pub struct MergeProgressFn(Arc<dyn Fn(MergeProgress) + Send + Sync>);
+
+impl MergeProgressFn {
+    pub fn new(progress: impl Fn(MergeProgress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(progress))
+    }
+
+    pub fn call(&self, progress: MergeProgress) {
+        (self.0)(progress)
+    }
+}
+
+impl Debug for MergeProgressFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MergeProgressFn")
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum IoType {
     StdIo,
     MemoryMap,
+    // 数据只保存在内存中
+    Memory,
+    // 只读打开已经存在的文件，写入时返回错误
+    ReadOnly,
+    // 用户自定义的 DbIo
+    Custom(DbIoFactory),
 }
 
 #[derive(Clone, Debug)]
@@ -40,23 +118,194 @@ pub struct Config {
     pub index_type: IndexType,
     pub mmap_at_startup: bool,
     pub merge_ratio: f32,
+    // merge 和 merge_if_needed 选择参与 merge 的文件的策略，默认是 Full
+    pub merge_strategy: MergeStrategy,
+    // 读取数据时是否校验 crc，可信的存储上可以关闭以提升读取性能
+    pub verify_crc_on_read: bool,
+    // 数据文件名中 id 的宽度，不足的补 0
+    pub file_name_width: usize,
+    // 每个子目录中存放的数据文件数量，为 0 时所有数据文件都在数据目录下
+    pub file_shard_size: u32,
+    // 内存模式，数据只保存在内存中，不创建任何文件，只支持 BTree 索引
+    pub in_memory: bool,
+    // 持久化策略，为 None 时由 sync_writes 和 bytes_per_sync 决定
+    pub sync_policy: Option<SyncPolicy>,
+    // 后台自动 merge 的检查间隔，为 None 时不自动 merge
+    pub auto_merge_interval: Option<Duration>,
+    // key 的排序方式，为 None 时按照字节序，只支持 BTree 索引
+    pub comparator: Option<KeyComparator>,
+    // 监控回调，为 None 时不做任何事情
+    pub metrics: Option<Arc<dyn Metrics>>,
+    // 没有数据文件时第一个活跃文件的 id，为 None 时从 0 开始
+    pub start_file_id: Option<u32>,
+    // 预计的 key 数量，不为 None 时使用布隆过滤器快速判断 key 不存在
+    pub bloom_filter_keys: Option<usize>,
+    // 创建数据文件时预先分配 file_size_db 大小的空间，内存模式下无效
+    pub preallocate: bool,
+    // 新写入记录的校验算法，读取时按照每条记录中保存的算法校验
+    pub checksum: ChecksumKind,
+    // 缓存最近读取的 value 的数量，为 0 时不缓存
+    pub value_cache_capacity: usize,
+    // value 的字节数超过阈值时写到单独的 blob 文件中，记录中只保存 blob 的位置，为 0 时不使用 blob 文件，内存模式下无效
+    pub large_value_threshold: usize,
+    // 新建数据目录时的权限，如 0o700，只在 Unix 上有效，为 None 时使用系统默认的权限
+    pub dir_mode: Option<u32>,
+    // BTree 索引打开时在多个线程中读取封存的数据文件，加快索引的重建
+    pub parallel_recovery: bool,
+    // 打开时发现多个数据文件的 id 相同，把多余的文件改为新的 id，为 false 时返回 CorruptLayout
+    pub repair_duplicate_file_ids: bool,
+    // 打开时把活跃文件截断到最后一条 crc 正确的记录，末尾不完整的记录也会被截断，为 false 时损坏的记录返回 CorruptLayout，不修改文件
+    // 只在从数据文件中加载索引时检查，截断的位置之后的记录都会丢失
+    pub repair_on_open: bool,
+    // 每个数据文件中最多的记录数量，达到 file_size_db 或者这个数量时都会切换活跃文件，为 None 时不限制
+    pub max_records_per_file: Option<usize>,
+    // merge 的进度回调，为 None 时不报告
+    pub merge_progress: Option<MergeProgressFn>,
+    // 只读模式，不创建、修改或者删除数据目录中的任何文件，可以打开只读文件系统上的数据库
+    // 写入、merge 等操作返回 ReadOnly，只支持 BTree 索引
+    pub read_only: bool,
+    // merge 时写入重写文件的目录，为 None 时和数据目录在同一级，完成之后移动到数据目录，不在同一个文件系统时复制
+    pub merge_dir: Option<PathBuf>,
+    // 删除标记在 merge 时保留的时间，让同步、复制等读取数据文件的使用者能看到删除，为 None 时 merge 直接丢弃删除标记
+    pub tombstone_ttl: Option<Duration>,
+    // group commit 收集写入的时间，为 None 时不使用 group commit
+    // 配置之后 add、remove 等写入先放入缓冲区，收集的时间到了或者达到 group_commit_max 条之后一起写入，只持久化一次，写入持久化之后才返回
+    pub group_commit_window: Option<Duration>,
+    // 一次 group commit 最多的记录数
+    pub group_commit_max: usize,
+    // 预计的 key 的数量，打开时用来预先分配从 hint 文件和数据文件恢复索引时暂存数据的空间，减少重新分配
+    // BTree 索引使用的 BTreeMap 不能预先分配，为 None 时不预先分配
+    pub estimated_keys: Option<usize>,
+    // get、add、事务提交和 merge 超过这个时间时输出警告日志，包括操作和耗时，为 None 时不检查
+    pub slow_op_threshold: Option<Duration>,
+    // 同时打开的旧的数据文件的最大数量，超过时关闭最久没有读取的文件，再次读取时重新打开，为 0 时不限制
+    // 活跃文件一直打开，不计算在内，每个列族单独计算，内存模式下无效
+    pub max_open_files: usize,
+    // B+ 树索引文件的名称，在数据目录下，默认是 bptree-index
+    pub bptree_index_file_name: String,
+    // B+ 树索引文件中保存索引的 bucket 名称，默认是 bitcask-index，打开已有的索引文件时需要和创建时一致
+    pub bptree_bucket_name: String,
+    // 自定义数据文件和 blob 文件的 IO，为 None 时使用标准的文件 IO，内存模式下无效
+    // 文件的查找、移动和删除仍然在数据目录中进行
+    pub io_factory: Option<DbIoFactory>,
+    // 加密数据文件、blob 文件和 hint 文件的密钥，为 None 时不加密，配置了 io_factory 时在其之上加密
+    #[cfg(feature = "encryption")]
+    pub encryption_key: Option<EncryptionKey>,
 }
 
 impl Config {
     pub(crate) fn check(&self) -> Option<ErrDb> {
         let mut err = ErrDb::None;
         let dir_path = self.path_db.to_str();
-        if dir_path.is_none() || dir_path.unwrap().len() == 0 {
+        if dir_path.is_none() || dir_path.unwrap().is_empty() {
             err = ErrDb::Err("the db config path is none".to_owned());
-        } else if self.file_size_db <= 0 {
+        } else if self.file_size_db == 0 {
             err = ErrDb::Err("the db config file size  <= 0".to_owned());
-        } else if self.merge_ratio < 0 as f32 || self.merge_ratio > 1 as f32 {
+        } else if self.merge_ratio < 0.0 || self.merge_ratio > 1.0 {
             err = ErrDb::Err("the db config merge ratio < 0 or > 1".to_owned());
+        } else if self.in_memory && self.index_type == IndexType::BPlusTree {
+            err = ErrDb::Err("the db config in memory mode do not support bplus tree index".to_owned());
+        } else if self.sync_policy == Some(SyncPolicy::EveryBytes(0)) || self.sync_policy == Some(SyncPolicy::Interval(Duration::ZERO)) {
+            err = ErrDb::Err("the db config sync policy bytes or interval is 0".to_owned());
+        } else if self.auto_merge_interval == Some(Duration::ZERO) {
+            err = ErrDb::Err("the db config auto merge interval is 0".to_owned());
+        } else if self.comparator.is_some() && self.index_type == IndexType::BPlusTree {
+            err = ErrDb::Err("the db config comparator do not support bplus tree index".to_owned());
+        } else if self.bloom_filter_keys == Some(0) {
+            err = ErrDb::Err("the db config bloom filter keys is 0".to_owned());
+        } else if self.bloom_filter_keys.is_some() && self.comparator.is_some() {
+            err = ErrDb::Err("the db config bloom filter do not support comparator".to_owned());
+        } else if self.read_only && (self.in_memory || self.index_type == IndexType::BPlusTree) {
+            err = ErrDb::Err("the db config read only mode do not support memory mode or bplus tree index".to_owned());
+        } else if self.max_records_per_file == Some(0) {
+            err = ErrDb::Err("the db config max records per file is 0".to_owned());
+        } else if self.group_commit_window.is_some() && self.group_commit_max == 0 {
+            err = ErrDb::Err("the db config group commit max is 0".to_owned());
+        } else if self.start_file_id == Some(u32::MAX) {
+            err = ErrDb::Err("the db config start file id is u32::MAX".to_owned());
+        } else if self.index_type == IndexType::BPlusTree && (self.bptree_index_file_name.is_empty() || self.bptree_bucket_name.is_empty()) {
+            err = ErrDb::Err("the db config bptree index file name or bucket name is empty".to_owned());
         }
         if err.is_not_none() {
             Some(err)
         } else {
-            return None;
+            None
+        }
+    }
+
+    /// 实际使用的持久化策略
+    /// 没有配置 sync_policy 时，sync_writes 对应 EveryWrite，bytes_per_sync 大于 0 对应 EveryBytes
+    pub fn get_sync_policy(&self) -> SyncPolicy {
+        if let Some(policy) = self.sync_policy {
+            return policy;
+        }
+        if self.sync_writes {
+            SyncPolicy::EveryWrite
+        } else if self.bytes_per_sync > 0 {
+            SyncPolicy::EveryBytes(self.bytes_per_sync)
+        } else {
+            SyncPolicy::Never
+        }
+    }
+
+    /// 实际使用的监控回调，没有配置时使用 NoopMetrics
+    pub fn get_metrics(&self) -> Arc<dyn Metrics> {
+        match &self.metrics {
+            Some(metrics) => metrics.clone(),
+            None => Arc::new(NoopMetrics),
+        }
+    }
+
+    /// 新建数据文件时使用的 IO 类型
+    pub fn data_io_type(&self) -> IoType {
+        let io_type = if self.in_memory {
+            IoType::Memory
+        } else if let Some(factory) = &self.io_factory {
+            IoType::Custom(factory.clone())
+        } else if self.read_only {
+            IoType::ReadOnly
+        } else {
+            IoType::StdIo
+        };
+        #[cfg(feature = "encryption")]
+        if let (Some(key), false) = (&self.encryption_key, self.in_memory) {
+            return IoType::Custom(EncryptedIo::factory(key.clone(), io_type));
+        }
+        io_type
+    }
+
+    /// 再写入 bytes 字节、records 条记录之后是否超过了数据文件的大小或者记录数量的限制
+    pub(crate) fn exceeds_file_limit(&self, file: &FileDb, bytes: u64, records: u64) -> bool {
+        file.get_write_off() + bytes > self.file_size_db || self.max_records_per_file.is_some_and(|max| file.record_count() + records > max as u64)
+    }
+
+    /// 删除标记的 value，配置了 tombstone_ttl 时是删除时的毫秒时间戳，否则为空
+    pub(crate) fn tombstone_value(&self) -> Vec<u8> {
+        match self.tombstone_ttl {
+            Some(_) => (SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64)
+                .to_be_bytes()
+                .to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    /// merge 时删除标记是否已经过了保留的时间，没有时间戳的删除标记直接丢弃
+    pub(crate) fn tombstone_expired(&self, value: &[u8]) -> bool {
+        let (Some(ttl), Ok(millis)) = (self.tombstone_ttl, <[u8; 8]>::try_from(value)) else {
+            return true;
+        };
+        let deleted_at = UNIX_EPOCH + Duration::from_millis(u64::from_be_bytes(millis));
+        SystemTime::now().duration_since(deleted_at).unwrap_or_default() >= ttl
+    }
+
+    /// 按照配置的命名方式获取数据文件的完整名称
+    /// 配置了 file_shard_size 时，数据文件按照 file_id / file_shard_size 放到子目录中
+    pub fn data_file_name(&self, dir_path: &Path, file_id: u32) -> PathBuf {
+        let width = self.file_name_width;
+        let name = std::format!("{:0width$}", file_id) + DATA_FILE_NAME_SUFFIX;
+        match file_id.checked_div(self.file_shard_size) {
+            Some(shard) => dir_path.join(std::format!("{:0width$}", shard)).join(name),
+            None => dir_path.join(name),
         }
     }
 }
@@ -71,6 +320,40 @@ impl Default for Config {
             index_type: IndexType::BTree,
             mmap_at_startup: true,
             merge_ratio: 0.5,
+            merge_strategy: MergeStrategy::Full,
+            verify_crc_on_read: true,
+            file_name_width: 9,
+            file_shard_size: 0,
+            in_memory: false,
+            sync_policy: None,
+            auto_merge_interval: None,
+            comparator: None,
+            metrics: None,
+            start_file_id: None,
+            bloom_filter_keys: None,
+            preallocate: false,
+            checksum: ChecksumKind::Crc32,
+            value_cache_capacity: 0,
+            large_value_threshold: 0,
+            dir_mode: None,
+            parallel_recovery: false,
+            repair_duplicate_file_ids: false,
+            repair_on_open: false,
+            max_records_per_file: None,
+            merge_progress: None,
+            read_only: false,
+            merge_dir: None,
+            tombstone_ttl: None,
+            group_commit_window: None,
+            group_commit_max: 128,
+            estimated_keys: None,
+            slow_op_threshold: None,
+            max_open_files: 0,
+            bptree_index_file_name: BPTREE_INDEX_FILE_NAME.to_owned(),
+            bptree_bucket_name: BPTREE_BUCKET_NAME.to_owned(),
+            io_factory: None,
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
         }
     }
 }
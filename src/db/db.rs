@@ -1,6 +1,7 @@
-use crate::db::ResultDb;
+use crate::db::{ErrDb, ResultDb};
 
 // pub struct Key {}
+/// key 可以是任意的字节，包括 0x00、0xff 和不合法的 UTF-8，默认按照字节序排序，不能为空
 pub type Key = bytes::Bytes;
 
 // pub struct Value {}
@@ -8,6 +9,14 @@ pub type Value = bytes::Bytes;
 
 pub trait Getter {
     fn get(&self, key: &Key) -> ResultDb<Value>;
+    /// if can not find Key，then return None, Err is only for the io or corrupt record
+    fn get_opt(&self, key: &Key) -> ResultDb<Option<Value>> {
+        match self.get(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(ErrDb::NotFindKey) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
 }
 
 pub trait Adder {
@@ -17,7 +26,7 @@ pub trait Adder {
 pub trait Remover {
     /// if can not find Key，then return None
     fn remove(&self, key: &Key) -> ResultDb<Option<Value>>;
-    ///
+    /// remove the key without reading the old value
     fn remove_fast(&self, key: &Key) -> ResultDb<()>;
 }
 
@@ -10,27 +10,87 @@ pub enum ErrDb {
     None,
     NotFindKey,
     InvalidParameter,
-    InvalidBatch,
+    InvalidBatch(InvalidBatchReason),
     Err(String),
     InvalidLogDbCrc,
+    // 记录的校验值不正确，expected 是记录中保存的校验值，actual 是按照读取的数据计算出的校验值
+    CrcMismatch { file_id: u32, offset: u64, expected: u64, actual: u64 },
+    InvalidRecordType(u8),
     IoErr(io::Error),
     ParseIntError(std::num::ParseIntError),
+    Serialize(String),
+    // 磁盘空间不足，释放空间之后可以重试，已经写入的数据不受影响
+    DiskFull,
+    // 数据目录已经被另一个进程或者实例打开
+    DatabaseLocked,
+    // 只读模式下不能写入
+    ReadOnly,
+    // 数据目录中文件的布局不正确，如多个数据文件的 id 相同
+    CorruptLayout(String),
+    // 事务读取过的 key 在提交之前被其它的写入修改，可以重试整个事务
+    Conflict,
+    // 数据库已经关闭，或者 reopen 失败之后没有重新打开
+    Closed,
+    // 附加了上下文信息的错误，如出错的文件和偏移
+    Context { source: Box<ErrDb>, msg: String },
+}
+
+/// 批量操作失败的原因
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InvalidBatchReason {
+    // 数据的条数超过了 max_batch_num，需要拆分成多个批次
+    TooManyRecords,
+    // 暂存的字节数超过了 max_batch_bytes，需要先提交或者拆分
+    TooManyBytes,
+    // BTree 索引下没有事务序列号文件，无法保证序列号不重复，不能使用批量操作
+    SeqNoUnavailable,
+}
+
+impl Display for InvalidBatchReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidBatchReason::TooManyRecords => write!(f, "too many records"),
+            InvalidBatchReason::TooManyBytes => write!(f, "too many bytes"),
+            InvalidBatchReason::SeqNoUnavailable => write!(f, "seq no unavailable for a reopened BTree index, use the BPlusTree index for write batches"),
+        }
+    }
+}
+
+/// 为 ResultDb 的错误附加上下文信息
+pub trait ResultDbExt<T> {
+    /// 出错时才会调用 f 生成上下文信息
+    fn with_context<S: Into<String>>(self, f: impl FnOnce() -> S) -> ResultDb<T>;
+}
+
+impl<T> ResultDbExt<T> for ResultDb<T> {
+    fn with_context<S: Into<String>>(self, f: impl FnOnce() -> S) -> ResultDb<T> {
+        self.map_err(|e| e.context(f()))
+    }
 }
 
 impl ErrDb {
     #[inline]
     pub fn is_none(&self) -> bool {
-        match self {
-            ErrDb::None => true,
-            _ => false,
-        }
+        matches!(self, ErrDb::None)
     }
 
     #[inline]
     pub fn is_not_none(&self) -> bool {
+        !matches!(self, ErrDb::None)
+    }
+
+    pub fn context(self, msg: impl Into<String>) -> ErrDb {
+        ErrDb::Context {
+            source: Box::new(self),
+            msg: msg.into(),
+        }
+    }
+
+    /// 去掉所有的上下文，返回最初的错误
+    pub fn root(&self) -> &ErrDb {
         match self {
-            ErrDb::None => false,
-            _ => true,
+            ErrDb::Context { source, .. } => source.root(),
+            _ => self,
         }
     }
 
@@ -48,16 +108,44 @@ impl Display for ErrDb {
             ErrDb::None => write!(f, "None"),
             ErrDb::NotFindKey => write!(f, "not find key"),
             ErrDb::InvalidParameter => write!(f, "invalid parameter"),
-            ErrDb::InvalidBatch => write!(f, "invalid batch"),
+            ErrDb::InvalidBatch(reason) => write!(f, "invalid batch: {}", reason),
             ErrDb::Err(e) => write!(f, "{}", e),
             ErrDb::InvalidLogDbCrc => write!(f, "invalid log db crc"),
+            ErrDb::CrcMismatch {
+                file_id,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "crc mismatch in file {} at offset {}: expected {:#x}, actual {:#x}",
+                file_id, offset, expected, actual
+            ),
+            ErrDb::InvalidRecordType(t) => write!(f, "invalid log db record type: {}", t),
             ErrDb::IoErr(e) => write!(f, "{}", e),
             ErrDb::ParseIntError(e) => write!(f, "{}", e),
+            ErrDb::Serialize(e) => write!(f, "serialize: {}", e),
+            ErrDb::DiskFull => write!(f, "disk full"),
+            ErrDb::DatabaseLocked => write!(f, "the database is locked by another process or instance"),
+            ErrDb::ReadOnly => write!(f, "the database is opened in read-only mode"),
+            ErrDb::CorruptLayout(e) => write!(f, "corrupt layout: {}", e),
+            ErrDb::Conflict => write!(f, "transaction conflict, a key read by the transaction was modified"),
+            ErrDb::Closed => write!(f, "the database is closed"),
+            ErrDb::Context { source, msg } => write!(f, "{}: {}", msg, source),
         }
     }
 }
 
-impl std::error::Error for ErrDb {}
+impl std::error::Error for ErrDb {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ErrDb::Context { source, .. } => Some(source.as_ref()),
+            ErrDb::IoErr(e) => Some(e),
+            ErrDb::ParseIntError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 impl PartialEq for ErrDb {
     fn eq(&self, other: &Self) -> bool {
@@ -65,11 +153,34 @@ impl PartialEq for ErrDb {
             (ErrDb::None, ErrDb::None) => true,
             (ErrDb::NotFindKey, ErrDb::NotFindKey) => true,
             (ErrDb::InvalidParameter, ErrDb::InvalidParameter) => true,
-            (ErrDb::InvalidBatch, ErrDb::InvalidBatch) => true,
+            (ErrDb::InvalidBatch(r1), ErrDb::InvalidBatch(r2)) => r1 == r2,
             (ErrDb::Err(s), ErrDb::Err(s2)) => s.eq(s2),
             (ErrDb::InvalidLogDbCrc, ErrDb::InvalidLogDbCrc) => true,
+            (
+                ErrDb::CrcMismatch {
+                    file_id: f1,
+                    offset: o1,
+                    expected: e1,
+                    actual: a1,
+                },
+                ErrDb::CrcMismatch {
+                    file_id: f2,
+                    offset: o2,
+                    expected: e2,
+                    actual: a2,
+                },
+            ) => f1 == f2 && o1 == o2 && e1 == e2 && a1 == a2,
+            (ErrDb::InvalidRecordType(t1), ErrDb::InvalidRecordType(t2)) => t1 == t2,
             (ErrDb::IoErr(e1), ErrDb::IoErr(e2)) => (e1.kind() == e2.kind()) && (e1.to_string() == e2.to_string()),
             (ErrDb::ParseIntError(s1), ErrDb::ParseIntError(e2)) => s1.eq(e2),
+            (ErrDb::Serialize(s1), ErrDb::Serialize(s2)) => s1.eq(s2),
+            (ErrDb::DiskFull, ErrDb::DiskFull) => true,
+            (ErrDb::DatabaseLocked, ErrDb::DatabaseLocked) => true,
+            (ErrDb::ReadOnly, ErrDb::ReadOnly) => true,
+            (ErrDb::CorruptLayout(s1), ErrDb::CorruptLayout(s2)) => s1.eq(s2),
+            (ErrDb::Conflict, ErrDb::Conflict) => true,
+            (ErrDb::Closed, ErrDb::Closed) => true,
+            (ErrDb::Context { source: e1, msg: m1 }, ErrDb::Context { source: e2, msg: m2 }) => m1 == m2 && e1 == e2,
             _ => false,
         }
     }
@@ -77,7 +188,10 @@ impl PartialEq for ErrDb {
 
 impl From<io::Error> for ErrDb {
     fn from(e: io::Error) -> Self {
-        ErrDb::IoErr(e)
+        match e.kind() {
+            io::ErrorKind::StorageFull => ErrDb::DiskFull,
+            _ => ErrDb::IoErr(e),
+        }
     }
 }
 
@@ -86,3 +200,38 @@ impl From<std::num::ParseIntError> for ErrDb {
         ErrDb::ParseIntError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+
+    #[test]
+    fn test_err_db_context() {
+        let re: ResultDb<()> = Err(ErrDb::InvalidLogDbCrc);
+        let err = re
+            .with_context(|| "corrupt record in file 3 at offset 128")
+            .with_context(|| "failed to load index")
+            .unwrap_err();
+        assert_eq!(
+            "failed to load index: corrupt record in file 3 at offset 128: invalid log db crc",
+            err.to_string()
+        );
+        assert_eq!(&ErrDb::InvalidLogDbCrc, err.root());
+        assert_eq!("corrupt record in file 3 at offset 128: invalid log db crc", err.source().unwrap().to_string());
+
+        // the context is not evaluated when ok
+        let re: ResultDb<i32> = Ok(1);
+        assert_eq!(1, re.with_context(|| -> String { panic!("should not be called") }).unwrap());
+
+        assert_eq!(&ErrDb::NotFindKey, ErrDb::NotFindKey.context("a").root());
+    }
+
+    #[test]
+    fn test_err_db_from_io() {
+        assert_eq!(ErrDb::DiskFull, ErrDb::from(io::Error::from(io::ErrorKind::StorageFull)));
+        assert_eq!(ErrDb::DiskFull, ErrDb::from(io::Error::from_raw_os_error(28)));
+        assert!(matches!(ErrDb::from(io::Error::from(io::ErrorKind::NotFound)), ErrDb::IoErr(_)));
+    }
+}
@@ -1,11 +1,12 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use bytes::{Buf, BytesMut};
-use parking_lot::RwLock;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use parking_lot::{Mutex, RwLock};
 use prost::{decode_length_delimiter, length_delimiter_len};
 
-use crate::db::{max_log_db_header_size, LogDb, LogDbPos, LogDbType, ReadLogDb};
+use crate::db::{max_log_db_header_size, ChecksumKind, LogDb, LogDbPos, LogDbType, ReadLogDb};
 use crate::db::{ErrDb, IoType, ResultDb};
 use crate::io_db;
 use crate::io_db::new_dbio;
@@ -16,58 +17,106 @@ pub struct FileDb {
     write_off: Arc<RwLock<u64>>,
     // 当前写偏移，记录该数据文件写到哪个位置了
     db_io: Box<dyn io_db::DbIo>,
+    // 读取时是否校验 crc
+    verify_crc: bool,
+    // 完整的文件名称
+    file_name: PathBuf,
+    // 写入时累计的统计，封存时写到 footer 中，已有数据的文件重新打开时不知道统计为 None
+    footer: Mutex<Option<FileFooter>>,
+    // 写入的记录数量，不包括 footer，按照记录数量切换活跃文件时使用，回滚之后可能偏大
+    record_count: AtomicU64,
+}
+
+/// 封存的数据文件末尾的统计信息，保存为一条系统记录
+/// 崩溃时没有正常封存的文件没有 footer，需要扫描整个文件
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct FileFooter {
+    /// 文件中记录的数量，不包括 footer 本身
+    pub record_count: u64,
+    /// 文件中最大的事务序列号
+    pub max_seq_no: u64,
 }
 
 pub const DATA_FILE_NAME_SUFFIX: &str = ".data";
+pub const BLOB_FILE_NAME_SUFFIX: &str = ".blob";
 pub const HINT_FILE_NAME: &str = "hint-index";
 pub const MERGE_FINISHED_FILE_NAME: &str = "merge-finished";
 pub const SEQ_NO_FILE_NAME: &str = "seq-no";
+// 最小的 header，type 加上两个一字节的长度
+const MIN_LOG_DB_HEADER_SIZE: usize = 3;
+// footer 记录的 key，value 是记录数量和最大的事务序列号
+const FOOTER_KEY: &[u8] = b"lite-db-footer";
 
 impl FileDb {
     pub fn new(dir_path: PathBuf, file_id: u32, io_type: IoType) -> ResultDb<FileDb> {
         // 根据 path 和 id 构造出完整的文件名称
         let file_name = FileDb::get_data_file_name(dir_path, file_id);
+        FileDb::new_with_file_name(file_name, file_id, io_type)
+    }
+
+    /// 使用完整的文件名称打开数据文件，文件所在的目录不存在时会创建
+    pub fn new_with_file_name(file_name: PathBuf, file_id: u32, io_type: IoType) -> ResultDb<FileDb> {
+        if io_type != IoType::Memory {
+            if let Some(parent) = file_name.parent() {
+                if !parent.is_dir() {
+                    std::fs::create_dir_all(parent)?;
+                }
+            }
+        }
         // 初始化 io manager
-        let io_manager = new_dbio(file_name, io_type);
+        let io_manager = new_dbio(file_name.clone(), io_type)?;
+        // 封存的文件没有预分配的空间，文件大小就是写入的末尾，活跃文件需要调用 recover_write_off
+        let file_size = io_manager.size();
 
-        Ok(FileDb {
+        let file_db = FileDb {
             file_id: Arc::new(RwLock::new(file_id)),
-            write_off: Arc::new(RwLock::new(0)),
+            write_off: Arc::new(RwLock::new(file_size)),
             db_io: io_manager,
-        })
+            verify_crc: true,
+            file_name,
+            footer: Mutex::new(None),
+            record_count: AtomicU64::new(0),
+        };
+        if file_size == 0 {
+            file_db.set_footer(Some(FileFooter::default()));
+        }
+        Ok(file_db)
     }
 
-    pub fn new_hint_file(dir_path: PathBuf) -> ResultDb<FileDb> {
-        let file_name = dir_path.join(HINT_FILE_NAME);
-        let io_manager = new_dbio(file_name, IoType::StdIo);
+    /// 重新打开的活跃文件从实际写入的末尾开始追加写，和 IO 类型无关
+    /// 预分配的文件末尾是 0，读取只到实际写入的位置，中间有损坏的记录时返回 CorruptLayout
+    /// repair 时截断到最后一条正确的记录，返回截断的位置
+    pub(crate) fn recover_write_off(&self, repair: bool) -> ResultDb<Option<u64>> {
+        let (write_off, repaired) = match self.find_write_off() {
+            Ok(write_off) => (write_off, None),
+            Err(ErrDb::CrcMismatch { .. } | ErrDb::InvalidLogDbCrc | ErrDb::InvalidRecordType(_)) if repair => {
+                let valid = self.last_valid_offset();
+                self.truncate(valid)?;
+                (valid, Some(valid))
+            }
+            Err(e @ (ErrDb::CrcMismatch { .. } | ErrDb::InvalidLogDbCrc | ErrDb::InvalidRecordType(_))) => {
+                return Err(ErrDb::CorruptLayout(format!("{}, open with repair_on_open to truncate the corrupt tail", e)));
+            }
+            Err(e) => return Err(e),
+        };
+        self.set_write_off(write_off);
+        if write_off == 0 {
+            self.set_footer(Some(FileFooter::default()));
+        }
+        Ok(repaired)
+    }
 
-        Ok(FileDb {
-            file_id: Arc::new(RwLock::new(0)),
-            write_off: Arc::new(RwLock::new(0)),
-            db_io: io_manager,
-        })
+    /// hint 文件中有所有的 key，和数据文件使用相同的 IO 类型
+    pub fn new_hint_file(dir_path: PathBuf, io_type: IoType) -> ResultDb<FileDb> {
+        FileDb::new_with_file_name(dir_path.join(HINT_FILE_NAME), 0, io_type)
     }
 
     pub fn new_merge_fin_file(dir_path: PathBuf) -> ResultDb<FileDb> {
-        let file_name = dir_path.join(MERGE_FINISHED_FILE_NAME);
-        let io_manager = new_dbio(file_name, IoType::StdIo);
-
-        Ok(FileDb {
-            file_id: Arc::new(RwLock::new(0)),
-            write_off: Arc::new(RwLock::new(0)),
-            db_io: io_manager,
-        })
+        FileDb::new_with_file_name(dir_path.join(MERGE_FINISHED_FILE_NAME), 0, IoType::StdIo)
     }
 
     pub fn new_seq_no_file(dir_path: PathBuf) -> ResultDb<FileDb> {
-        let file_name = dir_path.join(SEQ_NO_FILE_NAME);
-        let io_manager = new_dbio(file_name, IoType::StdIo);
-
-        Ok(FileDb {
-            file_id: Arc::new(RwLock::new(0)),
-            write_off: Arc::new(RwLock::new(0)),
-            db_io: io_manager,
-        })
+        FileDb::new_with_file_name(dir_path.join(SEQ_NO_FILE_NAME), 0, IoType::StdIo)
     }
 
     pub fn file_size(&self) -> u64 {
@@ -84,60 +133,301 @@ impl FileDb {
         *write_guard = offset;
     }
 
+    pub fn set_verify_crc(&mut self, verify_crc: bool) {
+        self.verify_crc = verify_crc;
+    }
+
+    /// 预先分配文件的大小，减少文件追加写时的碎片，多出的部分填充 0
+    pub fn preallocate(&mut self, size: u64) -> ResultDb<()> {
+        self.db_io.preallocate(size)?;
+        Ok(())
+    }
+
+    /// 把文件截断到 size 并从这里继续写入，封存时不再写 footer，统计已经不准确
+    pub fn truncate(&self, size: u64) -> ResultDb<()> {
+        let mut write_off = self.write_off.write();
+        self.db_io.truncate(size)?;
+        *write_off = size;
+        self.set_footer(None);
+        Ok(())
+    }
+
+    /// 封存文件时截断预分配的空间，文件大小和写入的数据一样，不影响 footer
+    pub(crate) fn trim_preallocated(&self) -> ResultDb<()> {
+        let write_off = self.write_off.write();
+        if self.db_io.size() > *write_off {
+            self.db_io.truncate(*write_off)?;
+        }
+        Ok(())
+    }
+
+    /// 从头读取所有的记录，返回最后一条记录结束的位置
+    /// 文件最后一个字节不为 0 时没有预分配的空间，直接返回文件大小
+    pub(crate) fn find_write_off(&self) -> ResultDb<u64> {
+        let file_size = self.db_io.size();
+        let mut last = [0u8; 1];
+        if file_size == 0 || (self.db_io.read(&mut last, file_size - 1)? == 1 && last[0] != 0) {
+            return Ok(file_size);
+        }
+        let mut offset = 0;
+        loop {
+            match self.read_log_db_with_crc(offset) {
+                Ok((read_log_db, _, _)) => offset += read_log_db.size as u64,
+                // type 字节为 0 不是合法的记录，是预分配时填充的 0
+                Err(ErrDb::InvalidRecordType(0)) => return Ok(offset),
+                Err(e) if e == ErrDb::new_io_eof("") => return Ok(offset),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 从头读取并校验每一条记录，返回最后一条 crc 正确的记录结束的位置
+    /// 记录是变长的，不能从末尾向前扫描，读取失败或者 crc 错误的记录及之后的数据都不算在内
+    pub fn last_valid_offset(&self) -> u64 {
+        let mut offset = 0;
+        while let Ok((true, size)) = self.check_log_db(offset) {
+            offset += size as u64;
+        }
+        offset
+    }
+
+    /// 写入一条记录之后更新统计，seq_no 为记录的事务序列号
+    pub(crate) fn add_footer_record(&self, seq_no: usize) {
+        self.record_count.fetch_add(1, Ordering::SeqCst);
+        if let Some(footer) = self.footer.lock().as_mut() {
+            footer.record_count += 1;
+            footer.max_seq_no = footer.max_seq_no.max(seq_no as u64);
+        }
+    }
+
+    /// 扫描文件得到统计之后设置，之后封存时可以写入 footer
+    pub(crate) fn set_footer(&self, footer: Option<FileFooter>) {
+        if let Some(footer) = &footer {
+            self.record_count.store(footer.record_count, Ordering::SeqCst);
+        }
+        *self.footer.lock() = footer;
+    }
+
+    /// 文件中记录的数量，已有数据的文件在扫描得到统计之前只计算打开之后写入的记录
+    pub(crate) fn record_count(&self) -> u64 {
+        self.record_count.load(Ordering::SeqCst)
+    }
+
+    /// 封存文件时在末尾写入 footer，没有统计时不写入，返回写入的字节数
+    pub(crate) fn write_footer(&self) -> ResultDb<usize> {
+        let footer = match self.footer.lock().take() {
+            Some(footer) => footer,
+            None => return Ok(0),
+        };
+        self.write(&footer_log_db(&footer).encode())
+    }
+
+    /// 读取文件末尾的 footer，文件没有正常封存时返回 None
+    /// 预分配的文件末尾是 0，重新打开之后也返回 None
+    pub fn read_footer(&self) -> Option<FileFooter> {
+        let size = footer_log_db(&FileFooter::default()).encode().len() as u64;
+        let write_off = self.get_write_off();
+        if write_off < size {
+            return None;
+        }
+        let (read_log_db, crc, kind) = self.read_log_db_with_crc(write_off - size).ok()?;
+        let log_db = read_log_db.log_db;
+        if log_db.value.len() != 16 || !is_footer_log_db(&log_db) || crc != log_db.get_checksum(kind) {
+            return None;
+        }
+        let mut value = log_db.value.as_slice();
+        Some(FileFooter {
+            record_count: value.get_u64(),
+            max_seq_no: value.get_u64(),
+        })
+    }
+
+    /// 扫描整个文件得到统计，用于没有 footer 的文件
+    pub fn scan_footer(&self) -> ResultDb<FileFooter> {
+        let mut footer = FileFooter::default();
+        let mut offset = 0;
+        loop {
+            let read_log_db = match self.read_log_db(offset) {
+                Ok(read_log_db) => read_log_db,
+                Err(e) if e == ErrDb::new_io_eof("") => return Ok(footer),
+                Err(e) => return Err(e),
+            };
+            offset += read_log_db.size as u64;
+            let log_db = read_log_db.log_db;
+            if is_footer_log_db(&log_db) {
+                continue;
+            }
+            footer.record_count += 1;
+            footer.max_seq_no = footer.max_seq_no.max(log_db.seq_no() as u64);
+        }
+    }
+
     pub fn get_file_id(&self) -> u32 {
         let read_guard = self.file_id.read();
         *read_guard
     }
 
     pub fn read_log_db(&self, offset: u64) -> ResultDb<ReadLogDb> {
-        // 先读取出 header 部分的数据
-        let mut header_buf = BytesMut::zeroed(max_log_db_header_size());
+        let (read_log_db, crc, kind) = self.read_log_db_with_crc(offset)?;
+        if self.verify_crc {
+            let actual = read_log_db.log_db.get_checksum(kind);
+            if crc != actual {
+                return Err(ErrDb::CrcMismatch {
+                    file_id: self.get_file_id(),
+                    offset,
+                    expected: crc,
+                    actual,
+                });
+            }
+        }
+        Ok(read_log_db)
+    }
 
-        self.db_io.read(&mut header_buf, offset)?;
+    /// 读取 offset 开始的 size 个字节，不解码也不校验 crc，超过已经写入的部分时返回 EOF
+    pub fn read_raw(&self, offset: u64, size: usize) -> ResultDb<Vec<u8>> {
+        let file_size = self.get_write_off().min(self.db_io.size());
+        if offset + size as u64 > file_size {
+            return Err(ErrDb::new_io_eof("the record exceeds the file size"));
+        }
+        let mut buf = vec![0; size];
+        self.db_io.read_exact(&mut buf, offset)?;
+        Ok(buf)
+    }
 
-        // 取出 type，在第一个字节
-        let rec_type = header_buf.get_u8();
+    /// 读取索引中 pos 位置的整条记录，返回记录的类型和直接引用 IO 中数据的 value，不复制
+    /// IO 不支持 read_shared 时返回 None，由调用者使用 read_log_db 读取
+    pub(crate) fn read_value_shared(&self, pos: &LogDbPos) -> Option<ResultDb<(LogDbType, Bytes)>> {
+        let record = self.db_io.read_shared(pos.offset, pos.size as usize)?;
+        Some(self.decode_shared(pos.offset, record))
+    }
 
-        // 取出 key 和 value 的长度
-        let key_size = decode_length_delimiter(&mut header_buf).unwrap();
-        let value_size = decode_length_delimiter(&mut header_buf).unwrap();
+    // 和 read_log_db_with_crc 的格式一样，value 是 record 的一部分
+    fn decode_shared(&self, offset: u64, record: Bytes) -> ResultDb<(LogDbType, Bytes)> {
+        let type_byte = *record.first().ok_or_else(|| ErrDb::new_io_eof("the record is empty"))?;
+        let (rec_type, kind) = match (LogDbType::from_u8(type_byte & 0x0f), ChecksumKind::from_u8(type_byte >> 4)) {
+            (Some(t), Some(k)) => (t, k),
+            _ => return Err(ErrDb::InvalidRecordType(type_byte)),
+        };
+        let mut header = &record[1..];
+        let key_size = decode_length_delimiter(&mut header).map_err(|_| ErrDb::new_io_eof("failed to read the whole header"))?;
+        let value_size = decode_length_delimiter(&mut header).map_err(|_| ErrDb::new_io_eof("failed to read the whole header"))?;
+        let header_size = length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
+        let value_end = header_size as u64 + key_size as u64 + value_size as u64;
+        if value_end + kind.size() as u64 != record.len() as u64 {
+            return Err(ErrDb::new_io_eof("the record size does not match the index"));
+        }
 
-        // 如果 key 和 value 均为空，则说明读取到了文件的末尾，直接返回
-        if key_size == 0 && value_size == 0 {
+        let value_end = value_end as usize;
+        if self.verify_crc {
+            let mut checksum = &record[value_end..];
+            let expected = match kind {
+                ChecksumKind::Crc32 => checksum.get_u32() as u64,
+                ChecksumKind::XxHash64 => checksum.get_u64(),
+            };
+            let actual = kind.checksum(&record[..value_end]);
+            if expected != actual {
+                return Err(ErrDb::CrcMismatch {
+                    file_id: self.get_file_id(),
+                    offset,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok((rec_type, record.slice(header_size + key_size..value_end)))
+    }
+
+    /// 读取一条记录并校验 crc，返回 crc 是否正确以及记录的大小
+    /// crc 错误时仍然可以根据记录的大小继续读取下一条记录
+    pub(crate) fn check_log_db(&self, offset: u64) -> ResultDb<(bool, usize)> {
+        let (read_log_db, crc, kind) = self.read_log_db_with_crc(offset)?;
+        Ok((crc == read_log_db.log_db.get_checksum(kind), read_log_db.size))
+    }
+
+    // 读取一条记录，同时返回文件中保存的校验值及校验算法
+    fn read_log_db_with_crc(&self, offset: u64) -> ResultDb<(ReadLogDb, u64, ChecksumKind)> {
+        // 预分配的文件末尾是 0，只读取已经写入的部分
+        // 只读取到 write_off 的位置，预分配或者写入失败留下的数据都不会被读取
+        let file_size = self.get_write_off().min(self.db_io.size());
+        if offset >= file_size {
             return Err(ErrDb::new_io_eof(""));
         }
 
+        // 先读取出 header 部分的数据，header 是变长的，文件末尾剩余的数据可能不足最大的 header
+        let header_len = (max_log_db_header_size() as u64).min(file_size - offset) as usize;
+        let mut header_buf = BytesMut::zeroed(header_len);
+        let header_read = self.db_io.read(&mut header_buf, offset)?;
+        if header_read == 0 {
+            return Err(ErrDb::new_io_eof("failed to read the whole header"));
+        }
+
+        // 取出 type，在第一个字节，高 4 位是校验算法
+        // 先检查 type，预分配的文件末尾剩余的 0 不足最小的 header 时同样是不合法的记录
+        let type_byte = header_buf.get_u8();
+        let (rec_type, kind) = match (LogDbType::from_u8(type_byte & 0x0f), ChecksumKind::from_u8(type_byte >> 4)) {
+            (Some(t), Some(k)) => (t, k),
+            _ => return Err(ErrDb::InvalidRecordType(type_byte)),
+        };
+        if header_read < MIN_LOG_DB_HEADER_SIZE {
+            return Err(ErrDb::new_io_eof("failed to read the whole header"));
+        }
+
+        // 取出 key 和 value 的长度
+        let key_size = decode_length_delimiter(&mut header_buf).map_err(|_| ErrDb::new_io_eof("failed to read the whole header"))?;
+        let value_size = decode_length_delimiter(&mut header_buf).map_err(|_| ErrDb::new_io_eof("failed to read the whole header"))?;
+
         // 获取实际的 header 大小
         let actual_header_size = length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
+        if header_read < actual_header_size {
+            return Err(ErrDb::new_io_eof("failed to read the whole header"));
+        }
 
-        // 读取实际的 key 和 value，最后的 4 个字节是 crc 校验值
-        let mut kv_buf = BytesMut::zeroed(key_size + value_size + 4);
-        self.db_io.read(&mut kv_buf, offset + actual_header_size as u64)?;
+        // 读取实际的 key 和 value，最后是校验值，value 为空时只有 key 和校验值，可以刚好在文件的末尾
+        // 长度被损坏时可能是很大的数，超过文件剩余的大小时直接返回，不分配内存
+        let checksum_size = kind.size();
+        let body_size = key_size as u64 + value_size as u64 + checksum_size as u64;
+        if body_size > file_size - offset - actual_header_size as u64 {
+            return Err(ErrDb::new_io_eof("the record size exceeds the file size"));
+        }
+        let mut kv_buf = BytesMut::zeroed(key_size + value_size + checksum_size);
+        self.db_io.read_exact(&mut kv_buf, offset + actual_header_size as u64)?;
 
         let log_db = LogDb {
             key: kv_buf.get(..key_size).unwrap().to_vec(),
-            value: kv_buf.get(key_size..kv_buf.len() - 4).unwrap().to_vec(),
-            rec_type: LogDbType::from_u8(rec_type),
+            value: kv_buf.get(key_size..kv_buf.len() - checksum_size).unwrap().to_vec(),
+            rec_type,
         };
 
-        // 向前移动到最后的 4 个字节，就是 crc 的值
+        // 向前移动到最后的校验值
         kv_buf.advance(key_size + value_size);
-
-        if kv_buf.get_u32() != log_db.get_crc() {
-            return Err(ErrDb::InvalidLogDbCrc);
-        }
+        let crc = match kind {
+            ChecksumKind::Crc32 => kv_buf.get_u32() as u64,
+            ChecksumKind::XxHash64 => kv_buf.get_u64(),
+        };
 
         // 构造结果并返回
-        Ok(ReadLogDb {
-            log_db: log_db,
-            size: actual_header_size + key_size + value_size + 4,
-        })
+        Ok((
+            ReadLogDb {
+                log_db,
+                size: actual_header_size + key_size + value_size + checksum_size,
+            },
+            crc,
+            kind,
+        ))
     }
 
+    /// 写入失败或者只写入了一部分时返回错误，write_off 不变，之后的写入会覆盖这部分数据
     pub fn write(&self, buf: &[u8]) -> ResultDb<usize> {
-        let n_bytes = self.db_io.write(buf)?;
-        // 更新 write_off 字段
+        // 在 write_off 的位置写入，预分配的文件大小和 write_off 不一致
         let mut write_off = self.write_off.write();
+        let n_bytes = self.db_io.write_at(buf, *write_off)?;
+        if n_bytes != buf.len() {
+            return Err(ErrDb::IoErr(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                "failed to write the whole record",
+            )));
+        }
+        // 完整写入之后才更新 write_off 字段
         *write_off += n_bytes as u64;
 
         Ok(n_bytes)
@@ -158,12 +448,866 @@ impl FileDb {
         self.db_io.sync()
     }
 
-    pub fn set_io_manager(&mut self, dir_path: PathBuf, io_type: IoType) {
-        self.db_io = new_dbio(FileDb::get_data_file_name(dir_path, self.get_file_id()), io_type);
+    pub fn set_io_manager(&mut self, io_type: IoType) -> ResultDb<()> {
+        self.db_io = new_dbio(self.file_name.clone(), io_type)?;
+        Ok(())
+    }
+
+    pub(crate) fn set_db_io(&mut self, db_io: Box<dyn io_db::DbIo>) {
+        self.db_io = db_io;
+    }
+
+    pub fn get_file_name(&self) -> &Path {
+        &self.file_name
     }
 
+    /// 默认的数据文件名称，id 补 0 到 9 位，不分子目录
     pub fn get_data_file_name(dir_path: PathBuf, file_id: u32) -> PathBuf {
         let name = std::format!("{:09}", file_id) + DATA_FILE_NAME_SUFFIX;
         dir_path.join(name)
     }
+
+    /// blob 文件的名称，id 补 0 到 9 位，和数据文件在同一个目录
+    pub fn get_blob_file_name(dir_path: &Path, file_id: u32) -> PathBuf {
+        let name = std::format!("{:09}", file_id) + BLOB_FILE_NAME_SUFFIX;
+        dir_path.join(name)
+    }
+
+    /// 查找目录中所有的 blob 文件，按照文件 id 从小到大排序
+    pub fn find_blob_files(dir_path: &Path) -> ResultDb<Vec<(u32, PathBuf)>> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir_path)?.flatten() {
+            let file_os_str = entry.file_name();
+            let file_name = file_os_str.to_str().unwrap();
+            if entry.path().is_file() && file_name.ends_with(BLOB_FILE_NAME_SUFFIX) {
+                let split_names: Vec<&str> = file_name.split(".").collect();
+                let file_id = split_names[0].parse::<u32>()?;
+                files.push((file_id, entry.path()));
+            }
+        }
+        files.sort_by_key(|(file_id, _)| *file_id);
+        Ok(files)
+    }
+
+    /// 查找目录（包括子目录）中所有的数据文件，按照文件 id 从小到大排序
+    /// 只查找数字命名的分片子目录，列族等其它子目录中的数据文件不属于这个目录
+    pub fn find_data_files(dir_path: &Path) -> ResultDb<Vec<(u32, PathBuf)>> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir_path)?.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if !entry.file_name().to_str().is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit())) {
+                    continue;
+                }
+                files.extend(FileDb::find_data_files(&path)?);
+                continue;
+            }
+            let file_os_str = entry.file_name();
+            let file_name = file_os_str.to_str().unwrap();
+            if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
+                let split_names: Vec<&str> = file_name.split(".").collect();
+                let file_id = split_names[0].parse::<u32>()?;
+                files.push((file_id, path));
+            }
+        }
+        files.sort_by_key(|(file_id, _)| *file_id);
+        Ok(files)
+    }
+}
+
+/// 是否是文件末尾的 footer 记录
+pub(crate) fn is_footer_log_db(log_db: &LogDb) -> bool {
+    log_db.rec_type == LogDbType::SYSTEM && log_db.key == FOOTER_KEY
+}
+
+fn footer_log_db(footer: &FileFooter) -> LogDb {
+    let mut value = Vec::with_capacity(16);
+    value.put_u64(footer.record_count);
+    value.put_u64(footer.max_seq_no);
+    LogDb {
+        key: FOOTER_KEY.to_vec(),
+        value,
+        rec_type: LogDbType::SYSTEM,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::time::Instant;
+
+    use function_name::named;
+
+    use crate::db::{FileDb, IoType, LogDb, LogDbType};
+    use crate::io_db::{DbIo, HookIo, MemIo};
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+
+    use super::*;
+
+    fn ready_dir(file: &str, name: &str) -> PathBuf {
+        let path = PathBuf::from("temp").join(kits::com_names::path_name(file, name));
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).expect("failed to create dir");
+        path
+    }
+
+    // 每次最多只读 3 个字节的 DbIo
+    fn short_read_io(data: &[u8]) -> HookIo {
+        let mem_io = MemIo::new();
+        mem_io.write(data).unwrap();
+        HookIo::new(mem_io)
+            .on_read(|io, buf, offset| {
+                let n = buf.len().min(3);
+                io.read(&mut buf[..n], offset)
+            })
+            .on_write(|io, buf, offset| {
+                assert_eq!(offset, io.size());
+                io.write_at(buf, offset)
+            })
+    }
+
+    // 写入失败或者只写入一部分的 DbIo，written 为 None 时返回磁盘空间不足，否则只写入这么多字节
+    fn failed_write_io(data: &[u8], written: Option<usize>) -> HookIo {
+        let mem_io = MemIo::new();
+        mem_io.write(data).unwrap();
+        HookIo::new(mem_io).on_write(move |io, buf, offset| {
+            let n = match written {
+                Some(n) => n.min(buf.len()),
+                None => return Err(std::io::Error::from(std::io::ErrorKind::StorageFull).into()),
+            };
+            io.truncate(offset)?;
+            io.write_at(&buf[..n], offset)
+        })
+    }
+
+    #[test]
+    fn test_file_db_write_failed() {
+        let log_db = LogDb {
+            key: get_test_key(1).to_vec(),
+            value: get_test_value(1).to_vec(),
+            rec_type: LogDbType::NORMAL,
+        };
+        let enc = log_db.encode();
+        for (written, err) in [
+            (None, ErrDb::DiskFull),
+            (
+                Some(5),
+                ErrDb::IoErr(std::io::Error::new(std::io::ErrorKind::WriteZero, "failed to write the whole record")),
+            ),
+        ] {
+            let mut file_db = FileDb::new_with_file_name(PathBuf::new(), 0, IoType::Memory).unwrap();
+            file_db.write(&enc).unwrap();
+            let write_off = file_db.get_write_off();
+            file_db.set_db_io(Box::new(failed_write_io(&enc, written)));
+
+            // 写入失败时 write_off 不变，之前的数据仍然可以读取
+            assert_eq!(err, file_db.write(&enc).err().unwrap());
+            assert_eq!(write_off, file_db.get_write_off());
+            assert_eq!(log_db.value, file_db.read_log_db(0).unwrap().log_db.value);
+        }
+    }
+
+    #[test]
+    fn test_file_db_read_short_read() {
+        let file_db = FileDb {
+            file_id: Arc::new(RwLock::new(0)),
+            write_off: Arc::new(RwLock::new(0)),
+            db_io: Box::new(short_read_io(&[])),
+            verify_crc: true,
+            file_name: PathBuf::new(),
+            footer: Mutex::new(None),
+            record_count: AtomicU64::new(0),
+        };
+        let log_db1 = LogDb {
+            key: get_test_key(1).to_vec(),
+            value: get_test_value(1).to_vec(),
+            rec_type: LogDbType::NORMAL,
+        };
+        let log_db2 = LogDb {
+            key: get_test_key(2).to_vec(),
+            value: get_test_value(2).to_vec(),
+            rec_type: LogDbType::DELETED,
+        };
+        file_db.write(&log_db1.encode()).unwrap();
+        let offset2 = file_db.get_write_off();
+        file_db.write(&log_db2.encode()).unwrap();
+
+        // the key and value are read by several short reads
+        let re1 = file_db.read_log_db(0).expect("failed to read");
+        assert_eq!(log_db1.value, re1.log_db.value);
+        assert_eq!(offset2, re1.size as u64);
+        let re2 = file_db.read_log_db(offset2).expect("failed to read");
+        assert_eq!(log_db2.key, re2.log_db.key);
+        assert_eq!(LogDbType::DELETED, re2.log_db.rec_type);
+
+        // the last record is truncated
+        let size = file_db.file_size() as usize;
+        {
+            let short = FileDb {
+                file_id: Arc::new(RwLock::new(0)),
+                write_off: Arc::new(RwLock::new(size as u64 - 6)),
+                db_io: Box::new(short_read_io(
+                    &log_db1.encode().into_iter().chain(log_db2.encode()).take(size - 6).collect::<Vec<u8>>(),
+                )),
+                verify_crc: true,
+                file_name: PathBuf::new(),
+                footer: Mutex::new(None),
+                record_count: AtomicU64::new(0),
+            };
+            let re3 = short.read_log_db(0);
+            assert!(re3.is_ok());
+            let re4 = short.read_log_db(offset2);
+            assert!(matches!(re4, Err(ErrDb::IoErr(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+        }
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_invalid_record_type() {
+        let dir = ready_dir(file!(), function_name!());
+        {
+            let file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            for i in 0..2 {
+                let log_db = LogDb {
+                    key: get_test_key(i).to_vec(),
+                    value: get_test_value(i).to_vec(),
+                    rec_type: LogDbType::NORMAL,
+                };
+                file_db.write(&log_db.encode()).expect("failed to write");
+            }
+            file_db.sync().expect("failed to sync");
+        }
+
+        // corrupt the type byte of the first record
+        let file_name = FileDb::get_data_file_name(dir.clone(), 0);
+        {
+            let mut f = fs::OpenOptions::new().write(true).open(file_name).expect("failed to open file");
+            f.write_all(&[0x7f]).unwrap();
+            f.sync_all().unwrap();
+        }
+
+        let file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+        let re = file_db.read_log_db(0);
+        assert_eq!(ErrDb::InvalidRecordType(0x7f), re.err().unwrap());
+
+        // the other record is still readable
+        let size = file_db.file_size() / 2;
+        let re2 = file_db.read_log_db(size);
+        assert_eq!(get_test_value(1).to_vec(), re2.unwrap().log_db.value);
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_preallocate() {
+        let dir = ready_dir(file!(), function_name!());
+        let log_dbs: Vec<LogDb> = (0..10)
+            .map(|i| LogDb {
+                key: get_test_key(i).to_vec(),
+                value: get_test_value(i).to_vec(),
+                rec_type: LogDbType::NORMAL,
+            })
+            .collect();
+        let end;
+        {
+            let mut file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            file_db.preallocate(64 * 1024).expect("failed to preallocate");
+            assert_eq!(64 * 1024, file_db.file_size());
+            assert_eq!(0, file_db.get_write_off());
+            for log_db in log_dbs.iter() {
+                file_db.write(&log_db.encode()).expect("failed to write");
+            }
+            end = file_db.get_write_off();
+            assert_eq!(64 * 1024, file_db.file_size());
+
+            // only read the written records
+            let mut offset = 0;
+            for log_db in log_dbs.iter() {
+                let re = file_db.read_log_db(offset).expect("failed to read");
+                assert_eq!(log_db.value, re.log_db.value);
+                offset += re.size as u64;
+            }
+            assert_eq!(ErrDb::new_io_eof(""), file_db.read_log_db(end).err().unwrap());
+            file_db.sync().expect("failed to sync");
+        }
+
+        // reopen the file, reads stop at the real data boundary instead of the zero tail
+        for io_type in [IoType::StdIo, IoType::MemoryMap] {
+            let file_db = FileDb::new(dir.clone(), 0, io_type).expect("failed to new file db");
+            assert_eq!(64 * 1024, file_db.file_size());
+            // only the active file looks for the real data boundary
+            assert_eq!(64 * 1024, file_db.get_write_off());
+            file_db.recover_write_off(false).expect("failed to recover write off");
+            assert_eq!(end, file_db.get_write_off());
+            assert_eq!(end, file_db.find_write_off().unwrap());
+            assert_eq!(ErrDb::new_io_eof(""), file_db.read_log_db(end).err().unwrap());
+            let mut offset = 0;
+            let mut count = 0;
+            while let Ok(re) = file_db.read_log_db(offset) {
+                offset += re.size as u64;
+                count += 1;
+            }
+            assert_eq!(log_dbs.len(), count);
+            assert_eq!(end, offset);
+
+            // the zero tail is not a valid record, it is never read as an empty record
+            file_db.set_write_off(64 * 1024);
+            assert_eq!(ErrDb::InvalidRecordType(0), file_db.read_log_db(end).err().unwrap());
+        }
+
+        // a zero padded region in a file that is not preallocated
+        {
+            let file_name = FileDb::get_data_file_name(dir.clone(), 2);
+            let mut buf = log_dbs[0].encode();
+            let first = buf.len() as u64;
+            buf.extend_from_slice(&[0u8; 100]);
+            fs::write(&file_name, &buf).expect("failed to write");
+            let file_db = FileDb::new(dir.clone(), 2, IoType::StdIo).expect("failed to new file db");
+            file_db.recover_write_off(false).expect("failed to recover write off");
+            assert_eq!(first, file_db.get_write_off());
+            assert_eq!(ErrDb::new_io_eof(""), file_db.read_log_db(first).err().unwrap());
+
+            // new records are appended at the real data boundary
+            file_db.write(&log_dbs[1].encode()).expect("failed to write");
+            let re = file_db.read_log_db(first).expect("failed to read");
+            assert_eq!(log_dbs[1].key, re.log_db.key);
+        }
+
+        // the zero tail is shorter than the smallest header
+        {
+            let file_name = FileDb::get_data_file_name(dir.clone(), 3);
+            let mut buf = log_dbs[0].encode();
+            let first = buf.len() as u64;
+            buf.push(0);
+            fs::write(&file_name, &buf).expect("failed to write");
+            let file_db = FileDb::new(dir.clone(), 3, IoType::StdIo).expect("failed to new file db");
+            file_db.recover_write_off(false).expect("failed to recover write off");
+            assert_eq!(first, file_db.get_write_off());
+            assert_eq!(ErrDb::new_io_eof(""), file_db.read_log_db(first).err().unwrap());
+        }
+
+        // not preallocated file
+        {
+            let file_db = FileDb::new(dir.clone(), 1, IoType::StdIo).expect("failed to new file db");
+            assert_eq!(0, file_db.find_write_off().unwrap());
+            file_db.write(&log_dbs[0].encode()).expect("failed to write");
+            assert_eq!(file_db.file_size(), file_db.find_write_off().unwrap());
+        }
+
+        fs::remove_dir_all(dir).expect("failed to remove dir");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_footer() {
+        let dir = ready_dir(file!(), function_name!());
+        let log_dbs: Vec<LogDb> = (0..10)
+            .map(|i| LogDb {
+                // 前 5 条是事务数据，key 的前面是事务序列号
+                key: [vec![(i % 5) as u8], get_test_key(i).to_vec()].concat(),
+                value: get_test_value(i).to_vec(),
+                rec_type: LogDbType::NORMAL,
+            })
+            .collect();
+        let footer;
+        {
+            let file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            for log_db in log_dbs.iter() {
+                file_db.write(&log_db.encode()).expect("failed to write");
+                file_db.add_footer_record(log_db.seq_no());
+            }
+            // 没有封存时没有 footer
+            assert_eq!(None, file_db.read_footer());
+            file_db.write_footer().expect("failed to write footer");
+            footer = file_db.read_footer().expect("failed to read footer");
+            assert_eq!(
+                FileFooter {
+                    record_count: 10,
+                    max_seq_no: 4
+                },
+                footer
+            );
+            assert_eq!(footer, file_db.scan_footer().unwrap());
+        }
+
+        // 重新打开之后读取的 footer 和扫描的结果一致
+        for io_type in [IoType::StdIo, IoType::MemoryMap] {
+            let file_db = FileDb::new(dir.clone(), 0, io_type).expect("failed to new file db");
+            assert_eq!(Some(footer), file_db.read_footer());
+            assert_eq!(footer, file_db.scan_footer().unwrap());
+        }
+
+        // 重新打开的文件不知道统计，封存时不写入 footer
+        {
+            let file_db = FileDb::new(dir.clone(), 1, IoType::StdIo).expect("failed to new file db");
+            file_db.write(&log_dbs[0].encode()).expect("failed to write");
+        }
+        {
+            let file_db = FileDb::new(dir.clone(), 1, IoType::StdIo).expect("failed to new file db");
+            let size = file_db.file_size();
+            file_db.write_footer().expect("failed to write footer");
+            assert_eq!(size, file_db.file_size());
+            assert_eq!(None, file_db.read_footer());
+            assert_eq!(
+                FileFooter {
+                    record_count: 1,
+                    max_seq_no: 0
+                },
+                file_db.scan_footer().unwrap()
+            );
+        }
+
+        fs::remove_dir_all(dir).expect("failed to remove dir");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_reopen_append() {
+        let dir = ready_dir(file!(), function_name!());
+        let log_db1 = LogDb {
+            key: get_test_key(1).to_vec(),
+            value: get_test_value(1).to_vec(),
+            rec_type: LogDbType::NORMAL,
+        };
+        let log_db2 = LogDb {
+            key: get_test_key(2).to_vec(),
+            value: get_test_value(2).to_vec(),
+            rec_type: LogDbType::NORMAL,
+        };
+        let size1 = log_db1.encode().len() as u64;
+        let size2 = log_db2.encode().len() as u64;
+        {
+            let file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            assert_eq!(0, file_db.get_write_off());
+            file_db.write(&log_db1.encode()).expect("failed to write");
+            file_db.sync().expect("failed to sync");
+        }
+
+        // reopen the existing file with mmap and append
+        {
+            let file_db = FileDb::new(dir.clone(), 0, IoType::MemoryMap).expect("failed to new file db");
+            assert_eq!(size1, file_db.get_write_off());
+            file_db.write(&log_db2.encode()).expect("failed to write");
+            assert_eq!(size1 + size2, file_db.get_write_off());
+            file_db.sync().expect("failed to sync");
+        }
+
+        for io_type in [IoType::StdIo, IoType::MemoryMap] {
+            let file_db = FileDb::new(dir.clone(), 0, io_type).expect("failed to new file db");
+            assert_eq!(size1 + size2, file_db.get_write_off());
+            let re1 = file_db.read_log_db(0).expect("failed to read");
+            assert_eq!(log_db1.key, re1.log_db.key);
+            let re2 = file_db.read_log_db(size1).expect("failed to read");
+            assert_eq!(log_db2.key, re2.log_db.key);
+            assert_eq!(log_db2.value, re2.log_db.value);
+        }
+
+        fs::remove_dir_all(dir).expect("failed to remove dir");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_mmap_empty_value() {
+        let dir = ready_dir(file!(), function_name!());
+        let log_dbs = [
+            LogDb {
+                key: get_test_key(33).to_vec(),
+                value: Vec::new(),
+                rec_type: LogDbType::NORMAL,
+            },
+            LogDb {
+                key: get_test_key(34).to_vec(),
+                value: get_test_value(34).to_vec(),
+                rec_type: LogDbType::NORMAL,
+            },
+            // the smallest record, at the end of the file
+            LogDb {
+                key: b"k".to_vec(),
+                value: Vec::new(),
+                rec_type: LogDbType::NORMAL,
+            },
+        ];
+        assert_eq!(8, log_dbs[2].encode().len());
+        let mut offsets = Vec::new();
+        {
+            let file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            for log_db in log_dbs.iter() {
+                offsets.push(file_db.get_write_off());
+                file_db.write(&log_db.encode()).expect("failed to write");
+            }
+            file_db.sync().expect("failed to sync");
+        }
+
+        // the key, the empty value and the crc end exactly at the end of the mmap
+        let file_db = FileDb::new(dir.clone(), 0, IoType::MemoryMap).expect("failed to new file db");
+        for (log_db, offset) in log_dbs.iter().zip(offsets.iter()) {
+            let re = file_db.read_log_db(*offset).expect("failed to read");
+            assert_eq!(log_db.key, re.log_db.key);
+            assert_eq!(log_db.value, re.log_db.value);
+            assert_eq!(log_db.encode().len(), re.size);
+        }
+        assert_eq!(file_db.file_size(), offsets[2] + 8);
+        assert_eq!(ErrDb::new_io_eof(""), file_db.read_log_db(file_db.file_size()).err().unwrap());
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_read_header_at_end() {
+        let dir = ready_dir(file!(), function_name!());
+        let last = LogDb {
+            key: b"k".to_vec(),
+            value: Vec::new(),
+            rec_type: LogDbType::NORMAL,
+        };
+        // the last record is shorter than the max header size
+        assert!(last.encode().len() < max_log_db_header_size());
+        let last_offset;
+        {
+            let file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            let log_db = LogDb {
+                key: get_test_key(1).to_vec(),
+                value: get_test_value(1).to_vec(),
+                rec_type: LogDbType::NORMAL,
+            };
+            file_db.write(&log_db.encode()).expect("failed to write");
+            last_offset = file_db.get_write_off();
+            file_db.write(&last.encode()).expect("failed to write");
+            file_db.sync().expect("failed to sync");
+        }
+
+        for io_type in [IoType::StdIo, IoType::MemoryMap] {
+            let file_db = FileDb::new(dir.clone(), 0, io_type).expect("failed to new file db");
+            let re = file_db.read_log_db(last_offset).expect("failed to read");
+            assert_eq!(last.key, re.log_db.key);
+            assert_eq!(file_db.file_size(), last_offset + re.size as u64);
+
+            let re2 = file_db.read_log_db(file_db.file_size());
+            assert_eq!(ErrDb::new_io_eof(""), re2.err().unwrap());
+        }
+
+        // only part of the header is in the file
+        {
+            let file_name = FileDb::get_data_file_name(dir.clone(), 0);
+            let f = fs::OpenOptions::new().write(true).open(file_name).expect("failed to open file");
+            f.set_len(last_offset + 2).unwrap();
+            let file_db = FileDb::new(dir.clone(), 0, IoType::MemoryMap).expect("failed to new file db");
+            let re = file_db.read_log_db(last_offset);
+            assert_eq!(ErrDb::new_io_eof("failed to read the whole header"), re.err().unwrap());
+        }
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_corrupt_record_size() {
+        let dir = ready_dir(file!(), function_name!());
+        let log_db = LogDb {
+            key: get_test_key(1).to_vec(),
+            value: get_test_value(1).to_vec(),
+            rec_type: LogDbType::NORMAL,
+        };
+        let corrupt_offset;
+        {
+            let file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            file_db.write(&log_db.encode()).expect("failed to write");
+            corrupt_offset = file_db.get_write_off();
+            // the value size is corrupted to about 4GB, the key size to about 2GB
+            for sizes in [[5u8, 0xff, 0xff, 0xff, 0xff, 0x0f], [0xff, 0xff, 0xff, 0xff, 0x07, 5]] {
+                let mut buf = vec![LogDbType::NORMAL as u8];
+                buf.extend_from_slice(&sizes);
+                buf.extend_from_slice(b"hello value");
+                buf.extend_from_slice(&[0u8; 4]);
+                file_db.write(&buf).expect("failed to write");
+            }
+            file_db.sync().expect("failed to sync");
+        }
+
+        for io_type in [IoType::StdIo, IoType::MemoryMap] {
+            let file_db = FileDb::new(dir.clone(), 0, io_type).expect("failed to new file db");
+            assert_eq!(log_db.value, file_db.read_log_db(0).expect("failed to read").log_db.value);
+            // both corrupted records are rejected before the key and value are read
+            let re = file_db.read_log_db(corrupt_offset);
+            assert_eq!(ErrDb::new_io_eof("the record size exceeds the file size"), re.err().unwrap());
+            let re = file_db.read_log_db(corrupt_offset + 22);
+            assert_eq!(ErrDb::new_io_eof("the record size exceeds the file size"), re.err().unwrap());
+        }
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_last_valid_offset() {
+        let dir = ready_dir(file!(), function_name!());
+        let file_id = 4;
+        let mut offsets = Vec::new();
+        {
+            let file_db = FileDb::new(dir.clone(), file_id, IoType::StdIo).expect("failed to new file db");
+            assert_eq!(0, file_db.last_valid_offset());
+            for i in 0..10 {
+                let log_db = LogDb {
+                    key: get_test_key(i).to_vec(),
+                    value: get_test_value(i).to_vec(),
+                    rec_type: LogDbType::NORMAL,
+                };
+                file_db.write(&log_db.encode()).expect("failed to write");
+                offsets.push(file_db.get_write_off());
+            }
+            assert_eq!(offsets[9], file_db.last_valid_offset());
+            file_db.sync().expect("failed to sync");
+        }
+
+        // 末尾是不完整的记录和无法解析的数据
+        let file_name = FileDb::get_data_file_name(dir.clone(), file_id);
+        let torn = LogDb {
+            key: get_test_key(10).to_vec(),
+            value: get_test_value(10).to_vec(),
+            rec_type: LogDbType::NORMAL,
+        }
+        .encode();
+        for garbage in [&torn[..torn.len() - 3], b"\x01garbage".as_slice(), &[0xffu8; 32]] {
+            let mut f = fs::OpenOptions::new().write(true).open(&file_name).expect("failed to open file");
+            f.set_len(offsets[9]).unwrap();
+            f.seek(SeekFrom::End(0)).unwrap();
+            f.write_all(garbage).unwrap();
+            f.sync_all().unwrap();
+            drop(f);
+
+            let file_db = FileDb::new(dir.clone(), file_id, IoType::StdIo).expect("failed to new file db");
+            assert_eq!(offsets[9], file_db.last_valid_offset());
+        }
+
+        // crc 错误的记录及之后的记录都不算在内
+        {
+            let mut f = fs::OpenOptions::new().write(true).open(&file_name).expect("failed to open file");
+            f.set_len(offsets[9]).unwrap();
+            f.seek(SeekFrom::Start(offsets[6] - 5)).unwrap();
+            f.write_all(b"#").unwrap();
+            f.sync_all().unwrap();
+        }
+        let file_db = FileDb::new(dir.clone(), file_id, IoType::StdIo).expect("failed to new file db");
+        assert_eq!(offsets[5], file_db.last_valid_offset());
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_crc_mismatch() {
+        let dir = ready_dir(file!(), function_name!());
+        let file_id = 3;
+        let log_dbs: Vec<LogDb> = (0..10)
+            .map(|i| LogDb {
+                key: get_test_key(i).to_vec(),
+                value: get_test_value(i).to_vec(),
+                rec_type: LogDbType::NORMAL,
+            })
+            .collect();
+        let mut offsets = Vec::new();
+        {
+            let file_db = FileDb::new(dir.clone(), file_id, IoType::StdIo).expect("failed to new file db");
+            for log_db in log_dbs.iter() {
+                offsets.push(file_db.get_write_off());
+                file_db.write(&log_db.encode()).expect("failed to write");
+            }
+            file_db.sync().expect("failed to sync");
+        }
+
+        // corrupt the first byte of the 6th record's value
+        let corrupt = &log_dbs[5];
+        let value_offset = offsets[6] - 4 - corrupt.value.len() as u64;
+        {
+            let file_name = FileDb::get_data_file_name(dir.clone(), file_id);
+            let mut f = fs::OpenOptions::new().write(true).open(file_name).expect("failed to open file");
+            f.seek(SeekFrom::Start(value_offset)).unwrap();
+            f.write_all(b"#").unwrap();
+            f.sync_all().unwrap();
+        }
+        let mut value = corrupt.value.clone();
+        value[0] = b'#';
+        let corrupted = LogDb {
+            key: corrupt.key.clone(),
+            value,
+            rec_type: LogDbType::NORMAL,
+        };
+
+        let file_db = FileDb::new(dir.clone(), file_id, IoType::StdIo).expect("failed to new file db");
+        let err = file_db.read_log_db(offsets[5]).err().unwrap();
+        let expected = ErrDb::CrcMismatch {
+            file_id,
+            offset: offsets[5],
+            expected: corrupt.get_crc() as u64,
+            actual: corrupted.get_crc() as u64,
+        };
+        assert_eq!(expected, err);
+        assert_ne!(corrupt.get_crc(), corrupted.get_crc());
+        assert_eq!(
+            format!(
+                "crc mismatch in file 3 at offset {}: expected {:#x}, actual {:#x}",
+                offsets[5],
+                corrupt.get_crc(),
+                corrupted.get_crc()
+            ),
+            err.to_string()
+        );
+        // the other records are not affected
+        assert_eq!(log_dbs[4].value, file_db.read_log_db(offsets[4]).unwrap().log_db.value);
+        assert_eq!(log_dbs[6].value, file_db.read_log_db(offsets[6]).unwrap().log_db.value);
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_verify_crc_on_read() {
+        let dir = ready_dir(file!(), function_name!());
+        let count = 10000;
+
+        let mut offsets = Vec::with_capacity(count);
+        {
+            let file_db = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            for i in 0..count {
+                let log_db = LogDb {
+                    key: get_test_key(i).to_vec(),
+                    value: get_test_value(i).to_vec(),
+                    rec_type: LogDbType::NORMAL,
+                };
+                offsets.push(file_db.get_write_off());
+                file_db.write(&log_db.encode()).expect("failed to write");
+            }
+            file_db.sync().expect("failed to sync");
+        }
+
+        // 1. the values are same with or without crc verification
+        {
+            let verify = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            let mut no_verify = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            no_verify.set_verify_crc(false);
+
+            let start = Instant::now();
+            for (i, offset) in offsets.iter().enumerate() {
+                let re = verify.read_log_db(*offset).expect("failed to read");
+                assert_eq!(get_test_value(i).to_vec(), re.log_db.value);
+            }
+            let verify_elapsed = start.elapsed();
+
+            let start = Instant::now();
+            for (i, offset) in offsets.iter().enumerate() {
+                let re = no_verify.read_log_db(*offset).expect("failed to read");
+                assert_eq!(get_test_value(i).to_vec(), re.log_db.value);
+            }
+            let no_verify_elapsed = start.elapsed();
+            log::info!(
+                "read {} records, verify crc: {:?}, no verify crc: {:?}",
+                count,
+                verify_elapsed,
+                no_verify_elapsed
+            );
+        }
+
+        // 2. a corrupt record is only caught with crc verification
+        {
+            let file_name = FileDb::get_data_file_name(dir.clone(), 0);
+            let mut f = fs::OpenOptions::new().write(true).open(file_name).expect("failed to open file");
+            // the last byte of the first record's value
+            let first_size = offsets[1];
+            f.seek(SeekFrom::Start(first_size - 5)).unwrap();
+            f.write_all(b"#").unwrap();
+            f.sync_all().unwrap();
+
+            let verify = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            let re = verify.read_log_db(0);
+            assert!(matches!(re, Err(ErrDb::CrcMismatch { file_id: 0, offset: 0, .. })));
+
+            let mut no_verify = FileDb::new(dir.clone(), 0, IoType::StdIo).expect("failed to new file db");
+            no_verify.set_verify_crc(false);
+            let re2 = no_verify.read_log_db(0);
+            assert!(re2.is_ok());
+            assert_ne!(get_test_value(0).to_vec(), re2.unwrap().log_db.value);
+        }
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_open_failed() {
+        let dir = ready_dir(file!(), function_name!());
+        // 文件名称是目录时打开失败，返回错误而不是 panic
+        for io_type in [IoType::StdIo, IoType::MemoryMap] {
+            assert!(FileDb::new_with_file_name(dir.clone(), 0, io_type.clone()).is_err());
+            let mut file_db = FileDb::new_with_file_name(dir.join("000000001.data"), 1, io_type.clone()).unwrap();
+            assert!(file_db.set_io_manager(IoType::StdIo).is_ok());
+            file_db.file_name = dir.clone();
+            assert!(file_db.set_io_manager(io_type).is_err());
+        }
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_file_db_open_read_only_failed() {
+        let dir = ready_dir(file!(), function_name!());
+        // 只读模式不会创建文件，文件不存在时返回错误
+        let file_name = dir.join("000000001.data");
+        assert!(matches!(
+            FileDb::new_with_file_name(file_name.clone(), 1, IoType::ReadOnly),
+            Err(ErrDb::IoErr(_))
+        ));
+        assert!(!file_name.exists());
+
+        FileDb::new_with_file_name(file_name.clone(), 1, IoType::StdIo)
+            .unwrap()
+            .write(&get_test_value(1))
+            .unwrap();
+        assert!(FileDb::new_with_file_name(file_name, 1, IoType::ReadOnly).is_ok());
+
+        fs::remove_dir_all(dir).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_file_db_recover_write_off_failed() {
+        let mut file_db = FileDb::new_with_file_name(PathBuf::new(), 0, IoType::Memory).unwrap();
+        // 读取总是失败的 DbIo
+        let mem_io = MemIo::new();
+        mem_io.preallocate(1024).unwrap();
+        file_db.set_db_io(Box::new(
+            HookIo::new(mem_io).on_read(|_, _, _| Err(std::io::Error::other("failed to read").into())),
+        ));
+        // 读取失败时返回错误，不把文件大小当作写入的末尾
+        assert!(matches!(file_db.recover_write_off(false), Err(ErrDb::IoErr(_))));
+
+        // 预分配的文件中间有损坏的记录
+        let file_db = FileDb::new_with_file_name(PathBuf::new(), 0, IoType::Memory).unwrap();
+        let mut offsets = Vec::new();
+        for i in 0..3 {
+            let log_db = LogDb {
+                key: get_test_key(i).to_vec(),
+                value: get_test_value(i).to_vec(),
+                rec_type: LogDbType::NORMAL,
+            };
+            offsets.push(file_db.get_write_off());
+            file_db.write(&log_db.encode()).unwrap();
+        }
+        // 第二条记录的类型不合法
+        file_db.db_io.write_at(&[0x0f], offsets[1]).unwrap();
+        file_db.db_io.preallocate(64 * 1024).unwrap();
+
+        // 重新打开时 write_off 是文件大小，不修复时返回错误，不从文件大小开始写入
+        file_db.set_write_off(file_db.file_size());
+        assert!(matches!(file_db.recover_write_off(false), Err(ErrDb::CorruptLayout(_))));
+        assert_eq!(64 * 1024, file_db.file_size());
+        // 修复时截断到最后一条正确的记录
+        assert_eq!(Some(offsets[1]), file_db.recover_write_off(true).unwrap());
+        assert_eq!(offsets[1], file_db.get_write_off());
+        assert_eq!(offsets[1], file_db.file_size());
+        assert_eq!(None, file_db.recover_write_off(true).unwrap());
+    }
 }
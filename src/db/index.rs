@@ -1,3 +1,7 @@
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
 use bytes::Bytes;
 
 use crate::db::LogDbPos;
@@ -13,26 +17,113 @@ pub trait Indexer: Sync + Send {
     fn list_keys(&self) -> ResultDb<Vec<Bytes>>;
 
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
+
+    /// 删除所有的 key
+    fn clear(&self) -> ResultDb<()>;
+
+    /// 返回 false 时 key 一定不存在，没有布隆过滤器时总是返回 true
+    fn probably_contains(&self, _key: &[u8]) -> bool {
+        true
+    }
+
+    /// 迭代顺序中最小的 key，没有 key 时返回 None
+    fn first_key(&self) -> Option<Vec<u8>> {
+        let mut iter = self.iterator(IteratorOptions::default());
+        iter.next().map(|(key, _)| key.clone())
+    }
+
+    /// 迭代顺序中最大的 key，没有 key 时返回 None
+    fn last_key(&self) -> Option<Vec<u8>> {
+        let mut iter = self.iterator(IteratorOptions {
+            prefix: Vec::new(),
+            reverse: true,
+        });
+        iter.next().map(|(key, _)| key.clone())
+    }
+
+    /// 和 put 一样，索引出错时返回错误而不是 panic
+    fn try_put(&self, key: Vec<u8>, pos: LogDbPos) -> ResultDb<Option<LogDbPos>> {
+        Ok(self.put(key, pos))
+    }
+
+    /// 和 get 一样，索引出错时返回错误而不是 panic
+    fn try_get(&self, key: Vec<u8>) -> ResultDb<Option<LogDbPos>> {
+        Ok(self.get(key))
+    }
+
+    /// 和 delete 一样，索引出错时返回错误而不是 panic
+    fn try_delete(&self, key: Vec<u8>) -> ResultDb<Option<LogDbPos>> {
+        Ok(self.delete(key))
+    }
 }
 
 pub trait IndexIterator: Sync + Send {
     fn rewind(&mut self);
 
+    /// 正向时定位到第一个大于等于 key 的位置，反向时定位到第一个小于等于 key 的位置
+    /// 没有满足条件的 key 时，之后的 next 返回 None
     fn seek(&mut self, key: Vec<u8>);
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogDbPos)>;
 }
 
+#[derive(Default)]
 pub struct IteratorOptions {
+    /// 只遍历以 prefix 开头的 key，为空时遍历所有的 key
+    /// 没有匹配的 key 时迭代器直接结束，可以用 LiteDb::contains_prefix 和 LiteDb::count_prefix 判断是否有匹配的 key 和数量
     pub prefix: Vec<u8>,
     pub reverse: bool,
 }
 
-impl Default for IteratorOptions {
-    fn default() -> Self {
-        Self {
-            prefix: Default::default(),
-            reverse: false,
-        }
+type CompareFn = dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync;
+
+/// 在按照迭代顺序排列的 items 中查找 seek 的位置，compare 为索引的排序方式
+pub(crate) fn seek_position(items: &[(Vec<u8>, LogDbPos)], key: &[u8], reverse: bool, compare: impl Fn(&[u8], &[u8]) -> Ordering) -> usize {
+    if reverse {
+        // 从大到小排列，跳过所有大于 key 的
+        items.partition_point(|(x, _)| compare(x, key) == Ordering::Greater)
+    } else {
+        // 从小到大排列，跳过所有小于 key 的
+        items.partition_point(|(x, _)| compare(x, key) == Ordering::Less)
+    }
+}
+
+/// 按照字节序排列时以 prefix 开头的 key 是连续的，返回迭代顺序中第一个可能匹配的位置
+pub(crate) fn prefix_start(items: &[(Vec<u8>, LogDbPos)], prefix: &[u8], reverse: bool) -> usize {
+    if reverse {
+        // 从大到小排列，跳过所有大于 prefix 并且不以 prefix 开头的
+        items.partition_point(|(x, _)| x.as_slice() > prefix && !x.starts_with(prefix))
+    } else {
+        // 从小到大排列，跳过所有小于 prefix 的
+        items.partition_point(|(x, _)| x.as_slice() < prefix)
+    }
+}
+
+/// 按照字节序排列时，key 之后是否不会再有以 prefix 开头的 key
+pub(crate) fn past_prefix(key: &[u8], prefix: &[u8], reverse: bool) -> bool {
+    if reverse {
+        key < prefix
+    } else {
+        key > prefix && !key.starts_with(prefix)
+    }
+}
+
+/// 自定义 key 的排序方式，迭代器按照该顺序遍历
+#[derive(Clone)]
+pub struct KeyComparator(Arc<CompareFn>);
+
+impl KeyComparator {
+    pub fn new(compare: impl Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static) -> Self {
+        Self(Arc::new(compare))
+    }
+
+    pub fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        (self.0)(a, b)
+    }
+}
+
+impl Debug for KeyComparator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KeyComparator")
     }
 }
@@ -1,10 +1,14 @@
+use std::fmt::{Display, Formatter};
+
 use bytes::{BufMut, BytesMut};
 use prost::{
-    encode_length_delimiter,
+    decode_length_delimiter, encode_length_delimiter,
     encoding::{decode_varint, encode_varint},
     length_delimiter_len,
 };
 
+use crate::db::{ErrDb, ResultDb};
+
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum LogDbType {
     // 正常 put 的数据
@@ -15,6 +19,23 @@ pub enum LogDbType {
 
     // 事务完成的标识
     TXNFINISHED = 3,
+
+    // 系统内部使用的数据，如事务序列号、merge 完成标识，不会出现在用户的索引中
+    SYSTEM = 4,
+
+    // value 保存在 blob 文件中，记录的 value 是 blob 在 blob 文件中的位置
+    BLOB = 5,
+}
+
+/// 记录的校验算法，保存在 type 字节的高 4 位，读取时按照记录中的算法校验
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum ChecksumKind {
+    // 4 字节的 crc32，旧的数据都是这种
+    #[default]
+    Crc32 = 0,
+
+    // 8 字节的 xxhash64，value 较大时更快
+    XxHash64 = 1,
 }
 
 /// LogDb log of db
@@ -25,7 +46,7 @@ pub struct LogDb {
     pub(crate) rec_type: LogDbType,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct LogDbPos {
     pub(crate) file_id: u32,
     pub(crate) offset: u64,
@@ -50,24 +71,35 @@ impl LogDb {
     //	+-------------+--------------+-------------+--------------+-------------+-------------+
     //	|  type 类型   |    key size |   value size |      key    |      value   |  crc 校验值  |
     //	+-------------+-------------+--------------+--------------+-------------+-------------+
-    //	    1字节        变长（最大5）   变长（最大5）        变长           变长           4字节
+    //	    1字节        变长（最大5）   变长（最大5）        变长           变长        4字节或8字节
+    //
+    // type 字节的低 4 位是记录的类型，高 4 位是校验算法
     pub fn encode(&self) -> Vec<u8> {
-        let (enc_buf, _) = self.encode_and_get_crc();
+        self.encode_with(ChecksumKind::Crc32)
+    }
+
+    /// 使用指定的校验算法编码
+    pub fn encode_with(&self, kind: ChecksumKind) -> Vec<u8> {
+        let (enc_buf, _) = self.encode_and_get_checksum(kind);
         enc_buf
     }
 
     pub fn get_crc(&self) -> u32 {
-        let (_, crc_value) = self.encode_and_get_crc();
-        crc_value
+        self.get_checksum(ChecksumKind::Crc32) as u32
     }
 
-    fn encode_and_get_crc(&self) -> (Vec<u8>, u32) {
+    pub(crate) fn get_checksum(&self, kind: ChecksumKind) -> u64 {
+        let (_, checksum) = self.encode_and_get_checksum(kind);
+        checksum
+    }
+
+    fn encode_and_get_checksum(&self, kind: ChecksumKind) -> (Vec<u8>, u64) {
         // 初始化字节数组，存放编码数据
         let mut buf = BytesMut::new();
-        buf.reserve(self.encoded_length());
+        buf.reserve(self.encoded_length(kind));
 
-        // 第一个字节存放 Type 类型
-        buf.put_u8(self.rec_type as u8);
+        // 第一个字节存放 Type 类型和校验算法
+        buf.put_u8(self.rec_type as u8 | ((kind as u8) << 4));
 
         // 再存储 key 和 value 的长度
         encode_length_delimiter(self.key.len(), &mut buf).unwrap();
@@ -77,33 +109,91 @@ impl LogDb {
         buf.extend_from_slice(&self.key);
         buf.extend_from_slice(&self.value);
 
-        // 计算并存储 CRC 校验值
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.update(&buf);
-        let crc = hasher.finalize();
-        buf.put_u32(crc);
+        // 计算并存储校验值
+        let checksum = kind.checksum(&buf);
+        match kind {
+            ChecksumKind::Crc32 => buf.put_u32(checksum as u32),
+            ChecksumKind::XxHash64 => buf.put_u64(checksum),
+        }
+
+        (buf.to_vec(), checksum)
+    }
 
-        (buf.to_vec(), crc)
+    /// key 前面的事务序列号，系统数据的 key 没有序列号，返回 0
+    pub(crate) fn seq_no(&self) -> usize {
+        match self.rec_type {
+            LogDbType::SYSTEM => 0,
+            _ => decode_length_delimiter(self.key.as_slice()).unwrap_or(0),
+        }
     }
 
     // LogDb 编码后的长度
-    fn encoded_length(&self) -> usize {
-        std::mem::size_of::<u8>() + length_delimiter_len(self.key.len()) + length_delimiter_len(self.value.len()) + self.key.len() + self.value.len() + 4
+    fn encoded_length(&self, kind: ChecksumKind) -> usize {
+        std::mem::size_of::<u8>()
+            + length_delimiter_len(self.key.len())
+            + length_delimiter_len(self.value.len())
+            + self.key.len()
+            + self.value.len()
+            + kind.size()
+    }
+}
+
+impl ChecksumKind {
+    /// 未知的算法返回 None
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(ChecksumKind::Crc32),
+            1 => Some(ChecksumKind::XxHash64),
+            _ => None,
+        }
+    }
+
+    /// 校验值的字节数
+    pub fn size(&self) -> usize {
+        match self {
+            ChecksumKind::Crc32 => 4,
+            ChecksumKind::XxHash64 => 8,
+        }
+    }
+
+    pub(crate) fn checksum(&self, buf: &[u8]) -> u64 {
+        match self {
+            ChecksumKind::Crc32 => crc32fast::hash(buf) as u64,
+            ChecksumKind::XxHash64 => xxhash_rust::xxh64::xxh64(buf, 0),
+        }
     }
 }
 
 impl LogDbType {
-    pub fn from_u8(v: u8) -> Self {
+    /// 未知的类型返回 None
+    pub fn from_u8(v: u8) -> Option<Self> {
         match v {
-            1 => LogDbType::NORMAL,
-            2 => LogDbType::DELETED,
-            3 => LogDbType::TXNFINISHED,
-            _ => panic!("unknown log db type"),
+            1 => Some(LogDbType::NORMAL),
+            2 => Some(LogDbType::DELETED),
+            3 => Some(LogDbType::TXNFINISHED),
+            4 => Some(LogDbType::SYSTEM),
+            5 => Some(LogDbType::BLOB),
+            _ => None,
         }
     }
 }
 
 impl LogDbPos {
+    /// 记录所在的数据文件 id
+    pub fn file_id(&self) -> u32 {
+        self.file_id
+    }
+
+    /// 记录在数据文件中的偏移
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// 记录编码之后的大小
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let mut buf = BytesMut::new();
         encode_varint(self.file_id as u64, &mut buf);
@@ -113,31 +203,35 @@ impl LogDbPos {
     }
 }
 
+impl Display for LogDbPos {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file={} off={} size={}", self.file_id, self.offset, self.size)
+    }
+}
+
 pub fn max_log_db_header_size() -> usize {
-    std::mem::size_of::<u8>() + length_delimiter_len(std::u32::MAX as usize) * 2
+    std::mem::size_of::<u8>() + length_delimiter_len(u32::MAX as usize) * 2
 }
 
 pub fn decode_log_db_pos(pos: Vec<u8>) -> LogDbPos {
-    let mut buf = BytesMut::new();
-    buf.put_slice(&pos);
-
-    let fid = match decode_varint(&mut buf) {
-        Ok(fid) => fid,
-        Err(e) => panic!("decode log db pos err: {}", e),
-    };
-    let offset = match decode_varint(&mut buf) {
-        Ok(offset) => offset,
-        Err(e) => panic!("decode log db pos err: {}", e),
-    };
-    let size = match decode_varint(&mut buf) {
-        Ok(size) => size,
+    match try_decode_log_db_pos(&pos) {
+        Ok(pos) => pos,
         Err(e) => panic!("decode log db pos err: {}", e),
-    };
-    LogDbPos {
+    }
+}
+
+/// 和 decode_log_db_pos 一样，数据损坏时返回错误而不是 panic
+pub fn try_decode_log_db_pos(pos: &[u8]) -> ResultDb<LogDbPos> {
+    let mut buf = pos;
+    let mut decode = || decode_varint(&mut buf).map_err(|e| ErrDb::Err(format!("failed to decode the log db pos: {}", e)));
+    let fid = decode()?;
+    let offset = decode()?;
+    let size = decode()?;
+    Ok(LogDbPos {
         file_id: fid as u32,
         offset,
         size: size as u32,
-    }
+    })
 }
 
 #[cfg(test)]
@@ -176,4 +270,35 @@ mod tests {
         assert!(en_log_db3.len() > 5);
         assert_eq!(1867197446, log_db3.get_crc());
     }
+
+    #[test]
+    fn test_log_db_encode_checksum_kind() {
+        let log_db = LogDb {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs".as_bytes().to_vec(),
+            rec_type: LogDbType::DELETED,
+        };
+        // crc32 的编码和以前的格式一致
+        assert_eq!(log_db.encode(), log_db.encode_with(ChecksumKind::Crc32));
+        assert_eq!(LogDbType::DELETED as u8, log_db.encode()[0]);
+
+        let enc = log_db.encode_with(ChecksumKind::XxHash64);
+        assert_eq!(log_db.encode().len() + 4, enc.len());
+        assert_eq!(Some(ChecksumKind::XxHash64), ChecksumKind::from_u8(enc[0] >> 4));
+        assert_eq!(Some(LogDbType::DELETED), LogDbType::from_u8(enc[0] & 0x0f));
+        assert_eq!(log_db.get_checksum(ChecksumKind::XxHash64).to_be_bytes(), enc[enc.len() - 8..]);
+    }
+
+    #[test]
+    fn test_log_db_pos_display() {
+        let pos = LogDbPos {
+            file_id: 3,
+            offset: 1024,
+            size: 57,
+        };
+        assert_eq!("file=3 off=1024 size=57", pos.to_string());
+        assert_eq!(3, pos.file_id());
+        assert_eq!(1024, pos.offset());
+        assert_eq!(57, pos.size());
+    }
 }
@@ -0,0 +1,32 @@
+use std::fmt::{Debug, Formatter};
+
+/// 数据库的监控回调，默认的实现不做任何事情
+/// 回调在读写的路径上同步调用，实现中不要做耗时的操作
+pub trait Metrics: Send + Sync {
+    /// 读取 key，hit 表示 key 是否存在
+    fn on_get(&self, _hit: bool) {}
+
+    /// 写入一条数据，bytes 为写入数据文件的字节数
+    fn on_put(&self, _bytes: usize) {}
+
+    /// 活跃文件持久化
+    fn on_sync(&self) {}
+
+    /// 创建新的数据文件之后持久化了数据目录，只在 Unix 上调用
+    fn on_dir_sync(&self) {}
+
+    /// merge 完成，reclaimed 为回收的字节数
+    fn on_merge(&self, _reclaimed: usize) {}
+}
+
+impl Debug for dyn Metrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Metrics")
+    }
+}
+
+/// 不做任何事情的 Metrics
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
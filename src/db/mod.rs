@@ -4,6 +4,7 @@ pub use err::*;
 pub use file_db::*;
 pub use index::*;
 pub use log_db::*;
+pub use metrics::*;
 
 mod config;
 mod db;
@@ -11,3 +12,4 @@ mod err;
 mod file_db;
 mod index;
 mod log_db;
+mod metrics;
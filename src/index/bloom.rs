@@ -0,0 +1,224 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use bytes::Bytes;
+
+use crate::db::{IndexIterator, Indexer, IteratorOptions, LogDbPos, ResultDb};
+
+// 默认的误判率
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// 计数的布隆过滤器，支持删除
+/// 计数达到上限之后不再减少，只会增加误判，不会出现存在的 key 判断为不存在
+pub struct BloomFilter {
+    counters: Vec<AtomicU8>,
+    hashes: usize,
+}
+
+impl BloomFilter {
+    /// expected_keys 为预计的 key 数量，超过之后误判率会升高
+    pub fn new(expected_keys: usize, false_positive_rate: f64) -> Self {
+        let n = expected_keys.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let size = (-n * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((size as f64 / n) * ln2).round().clamp(1.0, 16.0) as usize;
+        BloomFilter {
+            counters: (0..size).map(|_| AtomicU8::new(0)).collect(),
+            hashes,
+        }
+    }
+
+    pub fn add(&self, key: &[u8]) {
+        for i in self.positions(key) {
+            let _ = self.counters[i].fetch_update(Ordering::SeqCst, Ordering::SeqCst, |c| c.checked_add(1));
+        }
+    }
+
+    pub fn remove(&self, key: &[u8]) {
+        for i in self.positions(key) {
+            // 已经达到上限的计数不知道真实的值，不再减少
+            let _ = self.counters[i].fetch_update(Ordering::SeqCst, Ordering::SeqCst, |c| match c {
+                0 | u8::MAX => None,
+                c => Some(c - 1),
+            });
+        }
+    }
+
+    /// 返回 false 时 key 一定不存在，返回 true 时 key 可能存在
+    pub fn probably_contains(&self, key: &[u8]) -> bool {
+        self.positions(key).all(|i| self.counters[i].load(Ordering::SeqCst) > 0)
+    }
+
+    pub fn clear(&self) {
+        for c in self.counters.iter() {
+            c.store(0, Ordering::SeqCst);
+        }
+    }
+
+    // 双重哈希得到 key 对应的所有计数位置
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = usize> + '_ {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h = hasher.finish();
+        let h1 = h & 0xffff_ffff;
+        let h2 = (h >> 32) | 1;
+        let size = self.counters.len() as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+}
+
+// 在索引之外维护存活 key 的布隆过滤器
+pub(crate) struct BloomIndexer {
+    inner: Box<dyn Indexer>,
+    bloom: BloomFilter,
+}
+
+impl BloomIndexer {
+    // 持久化的索引中已经有 key，需要先加入过滤器
+    pub(crate) fn new(inner: Box<dyn Indexer>, expected_keys: usize) -> ResultDb<Self> {
+        let bloom = BloomFilter::new(expected_keys, FALSE_POSITIVE_RATE);
+        for key in inner.list_keys()?.iter() {
+            bloom.add(key);
+        }
+        Ok(BloomIndexer { inner, bloom })
+    }
+}
+
+impl Indexer for BloomIndexer {
+    fn put(&self, key: Vec<u8>, pos: LogDbPos) -> Option<LogDbPos> {
+        // 先加入过滤器，并发读取时不会出现索引中有而过滤器中没有的情况
+        self.bloom.add(&key);
+        let old = self.inner.put(key.clone(), pos);
+        if old.is_some() {
+            self.bloom.remove(&key);
+        }
+        old
+    }
+
+    fn get(&self, key: Vec<u8>) -> Option<LogDbPos> {
+        self.inner.get(key)
+    }
+
+    fn delete(&self, key: Vec<u8>) -> Option<LogDbPos> {
+        let old = self.inner.delete(key.clone());
+        if old.is_some() {
+            self.bloom.remove(&key);
+        }
+        old
+    }
+
+    // 转发到 inner 的 try_*，inner 出错时返回错误而不是 panic，多加入过滤器的 key 只会增加误判
+    fn try_put(&self, key: Vec<u8>, pos: LogDbPos) -> ResultDb<Option<LogDbPos>> {
+        self.bloom.add(&key);
+        let old = self.inner.try_put(key.clone(), pos)?;
+        if old.is_some() {
+            self.bloom.remove(&key);
+        }
+        Ok(old)
+    }
+
+    fn try_get(&self, key: Vec<u8>) -> ResultDb<Option<LogDbPos>> {
+        self.inner.try_get(key)
+    }
+
+    fn try_delete(&self, key: Vec<u8>) -> ResultDb<Option<LogDbPos>> {
+        let old = self.inner.try_delete(key.clone())?;
+        if old.is_some() {
+            self.bloom.remove(&key);
+        }
+        Ok(old)
+    }
+
+    fn list_keys(&self) -> ResultDb<Vec<Bytes>> {
+        self.inner.list_keys()
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        self.inner.iterator(options)
+    }
+
+    fn clear(&self) -> ResultDb<()> {
+        self.inner.clear()?;
+        self.bloom.clear();
+        Ok(())
+    }
+
+    fn probably_contains(&self, key: &[u8]) -> bool {
+        self.bloom.probably_contains(key)
+    }
+
+    fn first_key(&self) -> Option<Vec<u8>> {
+        self.inner.first_key()
+    }
+
+    fn last_key(&self) -> Option<Vec<u8>> {
+        self.inner.last_key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::btree::BTree;
+    use crate::kits::rand_kv::get_test_key;
+
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let bloom = BloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            bloom.add(&get_test_key(i));
+        }
+        // 不会有误判为不存在的 key
+        for i in 0..1000 {
+            assert!(bloom.probably_contains(&get_test_key(i)));
+        }
+        let false_positives = (1000..11000).filter(|i| bloom.probably_contains(&get_test_key(*i))).count();
+        assert!(false_positives < 500, "false positives: {}", false_positives);
+
+        for i in 0..500 {
+            bloom.remove(&get_test_key(i));
+        }
+        for i in 500..1000 {
+            assert!(bloom.probably_contains(&get_test_key(i)));
+        }
+
+        bloom.clear();
+        assert!(!bloom.probably_contains(&get_test_key(600)));
+    }
+
+    #[test]
+    fn test_bloom_indexer() {
+        let indexer = BloomIndexer::new(Box::new(BTree::new()), 100).unwrap();
+        let pos = LogDbPos {
+            file_id: 1,
+            offset: 10,
+            size: 11,
+        };
+        for i in 0..100 {
+            indexer.put(get_test_key(i).to_vec(), pos);
+        }
+        // 覆盖写入和删除之后，存活的 key 仍然存在
+        for i in 0..100 {
+            indexer.put(get_test_key(i).to_vec(), pos);
+        }
+        for i in 0..50 {
+            assert!(indexer.delete(get_test_key(i).to_vec()).is_some());
+            assert!(indexer.delete(get_test_key(i).to_vec()).is_none());
+        }
+        for i in 50..100 {
+            assert!(indexer.probably_contains(&get_test_key(i)));
+            assert!(indexer.get(get_test_key(i).to_vec()).is_some());
+        }
+
+        // 已经有数据的索引
+        let indexer2 = BloomIndexer::new(indexer.inner, 100).unwrap();
+        for i in 50..100 {
+            assert!(indexer2.probably_contains(&get_test_key(i)));
+        }
+        indexer2.clear().unwrap();
+        assert!(!indexer2.probably_contains(&get_test_key(60)));
+        assert!(indexer2.get(get_test_key(60).to_vec()).is_none());
+    }
+}
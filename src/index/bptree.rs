@@ -1,24 +1,66 @@
+use std::collections::VecDeque;
+use std::ops::Bound;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use bytes::Bytes;
-use jammdb::DB;
+use jammdb::{Tx, DB};
+use parking_lot::Mutex;
 
-use crate::db::{decode_log_db_pos, ErrDb, IndexIterator, Indexer, IteratorOptions, LogDbPos, ResultDb};
+use crate::db::{decode_log_db_pos, past_prefix, try_decode_log_db_pos, ErrDb, IndexIterator, Indexer, IteratorOptions, LogDbPos, ResultDb};
 
-const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
-const BPTREE_BUCKET_NAME: &str = "bitcask-index";
+/// B+ 树索引文件的默认名称
+pub const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
+/// B+ 树索引文件中默认的 bucket 名称
+pub const BPTREE_BUCKET_NAME: &str = "bitcask-index";
 
 pub struct BPlusTree {
     tree: DB,
+    // jammdb 创建只读事务时先读取 meta 再登记事务，期间创建的写事务可能释放只读事务还要读取的页
+    // 串行化事务的创建，事务创建之后仍然可以并发执行
+    // 迭代器读取时也需要
+    tx_lock: Arc<Mutex<()>>,
+    bucket_name: Arc<str>,
 }
 
 impl BPlusTree {
-    pub fn new(dir_path: PathBuf) -> ResultDb<Self> {
-        let bptree = DB::open(dir_path.join(BPTREE_INDEX_FILE_NAME)).expect("failed to open bptree");
-        let tx = bptree.tx(true).map_err(|e| ErrDb::Err(e.to_string()))?;
-        tx.get_or_create_bucket(BPTREE_BUCKET_NAME).map_err(|e| ErrDb::Err(e.to_string()))?;
-        tx.commit().map_err(|e| ErrDb::Err(e.to_string()))?;
-        Ok(Self { tree: bptree })
+    /// 打开 dir_path 下名为 file_name 的索引文件，索引保存在 bucket_name 中，bucket 不存在时创建
+    pub fn new(dir_path: PathBuf, file_name: &str, bucket_name: &str) -> ResultDb<Self> {
+        // jammdb 打开损坏的文件时会 panic，转成错误返回
+        let file_name = dir_path.join(file_name);
+        let bptree = std::panic::catch_unwind(|| DB::open(file_name))
+            .map_err(|_| ErrDb::Err("bptree index: the index file is corrupt".to_owned()))?
+            .map_err(bptree_err)?;
+        let tx = bptree.tx(true).map_err(bptree_err)?;
+        tx.get_or_create_bucket(bucket_name).map_err(bptree_err)?;
+        tx.commit().map_err(bptree_err)?;
+        Ok(Self {
+            tree: bptree,
+            tx_lock: Arc::new(Mutex::new(())),
+            bucket_name: Arc::from(bucket_name),
+        })
+    }
+
+    fn tx(&self, writable: bool) -> Result<Tx<'_>, jammdb::Error> {
+        let _lock = self.tx_lock.lock();
+        self.tree.tx(writable)
+    }
+
+    fn new_iterator(&self, options: IteratorOptions, batch_size: usize) -> BPTreeIterator {
+        BPTreeIterator {
+            tree: self.tree.clone(),
+            tx_lock: self.tx_lock.clone(),
+            bucket_name: self.bucket_name.clone(),
+            options,
+            batch_size,
+            bound: Bound::Unbounded,
+            buffer: VecDeque::new(),
+            done: false,
+            checkpoints: None,
+            current: None,
+            #[cfg(test)]
+            scanned: 0,
+        }
     }
 
     // pub fn close(&self) {}
@@ -26,45 +68,54 @@ impl BPlusTree {
 
 impl Indexer for BPlusTree {
     fn put(&self, key: Vec<u8>, pos: LogDbPos) -> Option<LogDbPos> {
-        let mut result = None;
-        let tx = self.tree.tx(true).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+        self.try_put(key, pos).unwrap_or_else(|e| panic!("failed to put value in bptree: {}", e))
+    }
 
-        if let Some(kv) = bucket.get_kv(&key) {
-            let pos = decode_log_db_pos(kv.value().to_vec());
-            result = Some(pos);
-        }
+    fn get(&self, key: Vec<u8>) -> Option<LogDbPos> {
+        self.try_get(key).unwrap_or_else(|e| panic!("failed to get value in bptree: {}", e))
+    }
 
-        bucket.put(key, pos.encode()).expect("failed to put value in bptree");
-        tx.commit().unwrap();
+    fn delete(&self, key: Vec<u8>) -> Option<LogDbPos> {
+        self.try_delete(key).unwrap_or_else(|e| panic!("failed to delete value in bptree: {}", e))
+    }
 
-        result
+    fn try_put(&self, key: Vec<u8>, pos: LogDbPos) -> ResultDb<Option<LogDbPos>> {
+        let tx = self.tx(true).map_err(bptree_err)?;
+        let bucket = tx.get_bucket(&*self.bucket_name).map_err(bptree_err)?;
+        let result = match bucket.get_kv(&key) {
+            Some(kv) => Some(try_decode_log_db_pos(kv.value())?),
+            None => None,
+        };
+        bucket.put(key, pos.encode()).map_err(bptree_err)?;
+        tx.commit().map_err(bptree_err)?;
+        Ok(result)
     }
 
-    fn get(&self, key: Vec<u8>) -> Option<LogDbPos> {
-        let tx = self.tree.tx(false).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-        if let Some(kv) = bucket.get_kv(key) {
-            return Some(decode_log_db_pos(kv.value().to_vec()));
+    fn try_get(&self, key: Vec<u8>) -> ResultDb<Option<LogDbPos>> {
+        let tx = self.tx(false).map_err(bptree_err)?;
+        let bucket = tx.get_bucket(&*self.bucket_name).map_err(bptree_err)?;
+        match bucket.get_kv(key) {
+            Some(kv) => Ok(Some(try_decode_log_db_pos(kv.value())?)),
+            None => Ok(None),
         }
-        None
     }
 
-    fn delete(&self, key: Vec<u8>) -> Option<LogDbPos> {
-        let mut result = None;
-        let tx = self.tree.tx(true).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-        if let Ok(kv) = bucket.delete(key) {
-            let pos = decode_log_db_pos(kv.value().to_vec());
-            result = Some(pos);
-        }
-        tx.commit().unwrap();
-        result
+    fn try_delete(&self, key: Vec<u8>) -> ResultDb<Option<LogDbPos>> {
+        let tx = self.tx(true).map_err(bptree_err)?;
+        let bucket = tx.get_bucket(&*self.bucket_name).map_err(bptree_err)?;
+        let result = match bucket.delete(key) {
+            Ok(kv) => Some(try_decode_log_db_pos(kv.value())?),
+            // key 不存在
+            Err(jammdb::Error::KeyValueMissing) => None,
+            Err(e) => return Err(bptree_err(e)),
+        };
+        tx.commit().map_err(bptree_err)?;
+        Ok(result)
     }
 
     fn list_keys(&self) -> ResultDb<Vec<bytes::Bytes>> {
-        let tx = self.tree.tx(false).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+        let tx = self.tx(false).map_err(bptree_err)?;
+        let bucket = tx.get_bucket(&*self.bucket_name).map_err(bptree_err)?;
         let mut keys = Vec::new();
 
         for data in bucket.cursor() {
@@ -73,60 +124,196 @@ impl Indexer for BPlusTree {
         Ok(keys)
     }
 
+    fn clear(&self) -> ResultDb<()> {
+        // 删除整个 bucket 之后重新创建
+        let tx = self.tx(true).map_err(bptree_err)?;
+        tx.delete_bucket(&*self.bucket_name).map_err(bptree_err)?;
+        tx.create_bucket(&*self.bucket_name).map_err(bptree_err)?;
+        tx.commit().map_err(bptree_err)?;
+        Ok(())
+    }
+
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-        let mut items = Vec::new();
-        let tx = self.tree.tx(false).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+        Box::new(self.new_iterator(options, BPTREE_ITER_BATCH))
+    }
+}
 
-        for data in bucket.cursor() {
-            let key = data.key().to_vec();
-            let pos = decode_log_db_pos(data.kv().value().to_vec());
-            items.push((key, pos));
+fn bptree_err(e: jammdb::Error) -> ErrDb {
+    ErrDb::Err(format!("bptree index: {}", e))
+}
+
+/// 每次从 bptree 读取的最多的 key 数量
+const BPTREE_ITER_BATCH: usize = 1024;
+
+/// jammdb 的事务和游标不能跨线程，迭代器不持有它们，每次用一个短的只读事务读取下一批 key
+/// 不同批次之间可能看到并发的修改；jammdb 的游标只能正向移动，反向时第一批从头扫描并每隔 batch_size 个 key 记录一个检查点，
+/// 之后每一批从上一个检查点扫描到上一批的位置
+pub struct BPTreeIterator {
+    tree: DB,
+    tx_lock: Arc<Mutex<()>>,
+    bucket_name: Arc<str>,
+    options: IteratorOptions,
+    batch_size: usize,
+    // 下一批从这里继续，正向时是下界，反向时是上界
+    bound: Bound<Vec<u8>>,
+    // 没有解码的 key 和 value，长度不超过 batch_size
+    buffer: VecDeque<(Vec<u8>, Vec<u8>)>,
+    // 没有更多的 key 需要读取
+    done: bool,
+    // 反向时下一批开始扫描的 key，从小到大排列，第一批扫描之前为 None
+    checkpoints: Option<Vec<Vec<u8>>>,
+    current: Option<(Vec<u8>, LogDbPos)>,
+    // 扫描过的 key 的数量
+    #[cfg(test)]
+    scanned: usize,
+}
+
+impl BPTreeIterator {
+    fn reset(&mut self, bound: Bound<Vec<u8>>) {
+        self.bound = bound;
+        self.buffer.clear();
+        self.done = false;
+        self.checkpoints = None;
+        self.current = None;
+    }
+
+    // 读取出错时结束迭代，IndexIterator 不能返回错误
+    fn fill(&mut self) {
+        let tx = {
+            let _lock = self.tx_lock.lock();
+            self.tree.tx(false)
+        };
+        let bucket = match tx
+            .as_ref()
+            .map_err(|e| e.to_string())
+            .and_then(|tx| tx.get_bucket(&*self.bucket_name).map_err(|e| e.to_string()))
+        {
+            Ok(bucket) => bucket,
+            Err(e) => {
+                log::error!("failed to read the bptree index, stop the iterator: {}", e);
+                self.done = true;
+                return;
+            }
+        };
+        let prefix = &self.options.prefix;
+        let mut cursor = bucket.cursor();
+        // 反向时第一批从 prefix 开始扫描，之后从最后一个检查点开始
+        let first_scan = self.checkpoints.is_none();
+        let lower = match (self.options.reverse, &mut self.checkpoints) {
+            (true, Some(checkpoints)) => checkpoints.pop(),
+            _ => None,
+        };
+        let start = match (&self.bound, self.options.reverse, &lower) {
+            (Bound::Included(key) | Bound::Excluded(key), false, _) => key.as_slice().max(prefix.as_slice()),
+            (_, true, Some(lower)) => lower.as_slice().max(prefix.as_slice()),
+            _ => prefix.as_slice(),
+        };
+        if !start.is_empty() {
+            // key 不存在时游标停在它的前一个 key，下面跳过小于 start 的 key
+            cursor.seek(start);
         }
-        if options.reverse {
-            items.reverse();
+
+        let mut overflow = false;
+        let mut checkpoints = Vec::new();
+        let mut count = 0;
+        for data in cursor {
+            let key = data.key();
+            #[cfg(test)]
+            {
+                self.scanned += 1;
+            }
+            if key < start || (!self.options.reverse && matches!(&self.bound, Bound::Excluded(k) if k.as_slice() == key)) {
+                continue;
+            }
+            if !prefix.is_empty() && !key.starts_with(prefix) {
+                if past_prefix(key, prefix, false) {
+                    break;
+                }
+                continue;
+            }
+            if self.options.reverse {
+                let below = match &self.bound {
+                    Bound::Included(k) => key <= k.as_slice(),
+                    Bound::Excluded(k) => key < k.as_slice(),
+                    Bound::Unbounded => true,
+                };
+                if !below {
+                    break;
+                }
+                // 第一批只保留最后的 batch_size 个 key，之后的批次从检查点开始，不超过 batch_size 个 key
+                if first_scan {
+                    if count % self.batch_size == 0 {
+                        checkpoints.push(key.to_vec());
+                    }
+                    count += 1;
+                    if self.buffer.len() == self.batch_size {
+                        self.buffer.pop_front();
+                        overflow = true;
+                    }
+                }
+                self.buffer.push_back((key.to_vec(), data.kv().value().to_vec()));
+            } else {
+                if self.buffer.len() == self.batch_size {
+                    overflow = true;
+                    break;
+                }
+                self.buffer.push_back((key.to_vec(), data.kv().value().to_vec()));
+            }
         }
 
-        Box::new(BPTreeIterator { items, curr_index: 0, options })
+        if self.options.reverse {
+            if first_scan {
+                self.checkpoints = Some(checkpoints);
+            }
+            if let Some((key, _)) = self.buffer.front() {
+                self.bound = Bound::Excluded(key.clone());
+                // 不小于这一批的第一个 key 的检查点已经读取过了
+                if let Some(checkpoints) = self.checkpoints.as_mut() {
+                    while checkpoints.last().is_some_and(|c| c >= key) {
+                        checkpoints.pop();
+                    }
+                }
+            }
+            // 第一个检查点之前没有 key，从它开始的一批读取之后结束
+            if (first_scan && !overflow) || self.checkpoints.as_ref().is_some_and(|c| c.is_empty()) {
+                self.done = true;
+            }
+            self.buffer.make_contiguous().reverse();
+        } else {
+            if !overflow {
+                self.done = true;
+            }
+            if let Some((key, _)) = self.buffer.back() {
+                self.bound = Bound::Excluded(key.clone());
+            }
+        }
     }
 }
 
-pub struct BPTreeIterator {
-    items: Vec<(Vec<u8>, LogDbPos)>,
-    // 存储 key+索引
-    curr_index: usize,
-    // 当前遍历的位置下标
-    options: IteratorOptions, // 配置项
-}
-
 impl IndexIterator for BPTreeIterator {
     fn rewind(&mut self) {
-        self.curr_index = 0;
+        // 按照字节序排列，正向从 prefix 开始，反向从以 prefix 开头的最大的 key 开始
+        self.reset(Bound::Unbounded);
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        self.curr_index = match self
-            .items
-            .binary_search_by(|(x, _)| if self.options.reverse { x.cmp(&key).reverse() } else { x.cmp(&key) })
-        {
-            Ok(equal_val) => equal_val,
-            Err(insert_val) => insert_val,
-        };
+        // 反向时 seek 到 prefix，从以 prefix 开头的最大的 key 开始，而不是跳过它们
+        if self.options.reverse && !self.options.prefix.is_empty() && self.options.prefix.starts_with(&key) {
+            self.reset(Bound::Unbounded);
+            return;
+        }
+        self.reset(Bound::Included(key));
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogDbPos)> {
-        if self.curr_index >= self.items.len() {
-            return None;
-        }
-
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
-            let prefix = &self.options.prefix;
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
-                return Some((&item.0, &item.1));
-            }
+        // 反向时检查点之间的 key 可能都被删除了，继续读取下一批
+        while self.buffer.is_empty() && !self.done {
+            self.fill();
         }
-        None
+        // 用到的时候才解码位置
+        let (key, value) = self.buffer.pop_front()?;
+        let item = self.current.insert((key, decode_log_db_pos(value)));
+        Some((&item.0, &item.1))
     }
 }
 
@@ -142,7 +329,7 @@ mod tests {
     fn test_bptree_put() {
         let path = PathBuf::from("/tmp/bptree-put");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone()).expect("");
+        let bpt = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("");
 
         let res1 = bpt.put(
             b"ccbde".to_vec(),
@@ -201,7 +388,7 @@ mod tests {
     fn test_bptree_get() {
         let path = PathBuf::from("/tmp/bptree-get");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone()).expect("");
+        let bpt = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("");
 
         let v1 = bpt.get(b"not exist".to_vec());
         assert!(v1.is_none());
@@ -235,7 +422,7 @@ mod tests {
     fn test_bptree_delete() {
         let path = PathBuf::from("/tmp/bptree-delete");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone()).expect("");
+        let bpt = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("");
 
         let r1 = bpt.delete(b"not exist".to_vec());
         assert!(r1.is_none());
@@ -264,7 +451,7 @@ mod tests {
     fn test_bptree_list_keys() {
         let path = PathBuf::from("/tmp/bptree-list-keys");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone()).expect("");
+        let bpt = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("");
 
         let keys1 = bpt.list_keys();
         assert_eq!(keys1.ok().unwrap().len(), 0);
@@ -312,7 +499,7 @@ mod tests {
     fn test_bptree_itreator() {
         let path = PathBuf::from("/tmp/bptree-iterator");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone()).expect("");
+        let bpt = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("");
 
         bpt.put(
             b"ccbde".to_vec(),
@@ -356,4 +543,293 @@ mod tests {
 
         fs::remove_dir_all(path.clone()).unwrap();
     }
+
+    #[test]
+    fn test_bptree_iterator_seek_bounds() {
+        let path = PathBuf::from("/tmp/bptree-iterator-seek-bounds");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+        let idx = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("failed to create bptree");
+        for key in ["b", "d", "f"] {
+            idx.put(
+                key.as_bytes().to_vec(),
+                LogDbPos {
+                    file_id: 1,
+                    offset: 10,
+                    size: 11,
+                },
+            );
+        }
+        let seek = |reverse: bool, key: &str| -> Vec<String> {
+            let mut iter = idx.iterator(IteratorOptions { prefix: Vec::new(), reverse });
+            iter.seek(key.as_bytes().to_vec());
+            let mut keys = Vec::new();
+            while let Some((k, _)) = iter.next() {
+                keys.push(String::from_utf8(k.clone()).unwrap());
+            }
+            keys
+        };
+
+        // forward: the first key >= the seek key
+        assert_eq!(vec!["b", "d", "f"], seek(false, "a"));
+        assert_eq!(vec!["d", "f"], seek(false, "d"));
+        assert_eq!(vec!["f"], seek(false, "e"));
+        assert!(seek(false, "g").is_empty());
+
+        // reverse: the first key <= the seek key
+        assert_eq!(vec!["f", "d", "b"], seek(true, "g"));
+        assert_eq!(vec!["d", "b"], seek(true, "d"));
+        assert_eq!(vec!["b"], seek(true, "c"));
+        assert!(seek(true, "a").is_empty());
+
+        // reverse with prefix: seek to the prefix yields all the matching keys in descending order
+        for key in ["da", "db", "e"] {
+            idx.put(
+                key.as_bytes().to_vec(),
+                LogDbPos {
+                    file_id: 1,
+                    offset: 10,
+                    size: 11,
+                },
+            );
+        }
+        let seek_prefix = |reverse: bool, key: &str| -> Vec<String> {
+            let mut iter = idx.iterator(IteratorOptions {
+                prefix: b"d".to_vec(),
+                reverse,
+            });
+            iter.seek(key.as_bytes().to_vec());
+            let mut keys = Vec::new();
+            while let Some((k, _)) = iter.next() {
+                keys.push(String::from_utf8(k.clone()).unwrap());
+            }
+            keys
+        };
+        assert_eq!(vec!["db", "da", "d"], seek_prefix(true, "d"));
+        assert_eq!(vec!["da", "d"], seek_prefix(true, "da"));
+        assert_eq!(vec!["db", "da", "d"], seek_prefix(true, "e"));
+        assert_eq!(vec!["d", "da", "db"], seek_prefix(false, "d"));
+        assert_eq!(vec!["db"], seek_prefix(false, "daa"));
+
+        fs::remove_dir_all(path.clone()).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_iterator_streaming() {
+        let path = PathBuf::from("/tmp/bptree-iterator-streaming");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+        let idx = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("failed to create bptree");
+
+        // 空的 bptree
+        let mut iter = idx.new_iterator(IteratorOptions::default(), 16);
+        iter.seek(b"a".to_vec());
+        assert!(iter.next().is_none());
+
+        let keys: Vec<Vec<u8>> = (0..3000u32).map(|i| format!("key-{:02}-{:05}", i % 30, i).into_bytes()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            idx.put(
+                key.clone(),
+                LogDbPos {
+                    file_id: 1,
+                    offset: i as u64,
+                    size: 11,
+                },
+            );
+        }
+        let mut sorted = keys.clone();
+        sorted.sort();
+
+        let collect = |prefix: &[u8], reverse: bool, seek: Option<&[u8]>| -> Vec<Vec<u8>> {
+            let mut iter = idx.new_iterator(
+                IteratorOptions {
+                    prefix: prefix.to_vec(),
+                    reverse,
+                },
+                16,
+            );
+            if let Some(key) = seek {
+                iter.seek(key.to_vec());
+            }
+            let mut result = Vec::new();
+            while let Some((key, pos)) = iter.next() {
+                assert_eq!(keys[pos.offset as usize], *key);
+                result.push(key.clone());
+                // 缓存的 key 不超过一批
+                assert!(iter.buffer.len() <= 16);
+            }
+            result
+        };
+
+        assert_eq!(sorted, collect(b"", false, None));
+        let mut reversed = sorted.clone();
+        reversed.reverse();
+        assert_eq!(reversed, collect(b"", true, None));
+
+        let with_prefix: Vec<Vec<u8>> = sorted.iter().filter(|k| k.starts_with(b"key-07-")).cloned().collect();
+        assert_eq!(100, with_prefix.len());
+        assert_eq!(with_prefix, collect(b"key-07-", false, None));
+        let mut prefix_reversed = with_prefix.clone();
+        prefix_reversed.reverse();
+        assert_eq!(prefix_reversed, collect(b"key-07-", true, None));
+        assert_eq!(prefix_reversed, collect(b"key-07-", true, Some(b"key-07")));
+
+        // seek 到不存在的 key
+        let seek_key = b"key-07-01500x";
+        let expected: Vec<Vec<u8>> = with_prefix.iter().filter(|k| k.as_slice() >= seek_key.as_slice()).cloned().collect();
+        assert_eq!(expected, collect(b"key-07-", false, Some(seek_key)));
+        let expected: Vec<Vec<u8>> = prefix_reversed.iter().filter(|k| k.as_slice() <= seek_key.as_slice()).cloned().collect();
+        assert_eq!(expected, collect(b"key-07-", true, Some(seek_key)));
+        assert!(collect(b"", false, Some(b"zzz")).is_empty());
+        assert_eq!(reversed, collect(b"", true, Some(b"zzz")));
+
+        fs::remove_dir_all(path.clone()).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_iterator_reverse_large() {
+        let path = PathBuf::from("/tmp/bptree-iterator-reverse-large");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+        let idx = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("failed to create bptree");
+
+        // 一个事务写入所有的 key
+        let count = 100_000u64;
+        {
+            let tx = idx.tx(true).unwrap();
+            let bucket = tx.get_bucket(&*idx.bucket_name).unwrap();
+            for i in 0..count {
+                let pos = LogDbPos {
+                    file_id: 1,
+                    offset: i,
+                    size: 11,
+                };
+                bucket.put(format!("key-{:08}", i).into_bytes(), pos.encode()).unwrap();
+            }
+            tx.commit().unwrap();
+        }
+
+        let mut iter = idx.new_iterator(
+            IteratorOptions {
+                prefix: Vec::new(),
+                reverse: true,
+            },
+            64,
+        );
+        let mut expected = count;
+        while let Some((key, pos)) = iter.next() {
+            expected -= 1;
+            assert_eq!(format!("key-{:08}", expected).into_bytes(), *key);
+            assert_eq!(expected, pos.offset);
+            assert!(iter.buffer.len() <= 64);
+        }
+        assert_eq!(0, expected);
+        // 第一批扫描所有的 key，之后每一批只扫描检查点之后的 key，总的扫描次数和 key 的数量成正比
+        assert!(iter.scanned < 3 * count as usize, "scanned {} keys", iter.scanned);
+
+        // 检查点之间的 key 被删除之后继续读取前面的 key
+        let mut iter = idx.new_iterator(
+            IteratorOptions {
+                prefix: Vec::new(),
+                reverse: true,
+            },
+            64,
+        );
+        assert_eq!(format!("key-{:08}", count - 1).into_bytes(), *iter.next().unwrap().0);
+        {
+            let tx = idx.tx(true).unwrap();
+            let bucket = tx.get_bucket(&*idx.bucket_name).unwrap();
+            for i in 1000..count - 64 {
+                bucket.delete(format!("key-{:08}", i).into_bytes()).unwrap();
+            }
+            tx.commit().unwrap();
+        }
+        let mut rest = Vec::new();
+        while let Some((key, _)) = iter.next() {
+            rest.push(key.clone());
+        }
+        let mut remaining: Vec<Vec<u8>> = (0..1000).chain(count - 64..count - 1).map(|i| format!("key-{:08}", i).into_bytes()).collect();
+        remaining.reverse();
+        assert_eq!(remaining, rest);
+
+        fs::remove_dir_all(path.clone()).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_failed_operation() {
+        let path = PathBuf::from("/tmp/bptree-failed-operation");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+        let pos = LogDbPos {
+            file_id: 1,
+            offset: 2,
+            size: 3,
+        };
+        {
+            let bpt = BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).expect("failed to open bptree");
+            assert_eq!(None, bpt.try_put(b"key".to_vec(), pos).unwrap());
+            assert_eq!(Some(pos), bpt.try_get(b"key".to_vec()).unwrap());
+
+            // 删除 bucket 模拟 jammdb 的操作失败，返回错误而不是 panic
+            let tx = bpt.tree.tx(true).unwrap();
+            tx.delete_bucket(BPTREE_BUCKET_NAME).unwrap();
+            tx.commit().unwrap();
+            assert!(bpt.try_put(b"key".to_vec(), pos).is_err());
+            assert!(bpt.try_get(b"key".to_vec()).is_err());
+            assert!(bpt.try_delete(b"key".to_vec()).is_err());
+            assert!(bpt.list_keys().is_err());
+            // 迭代器直接结束
+            assert!(bpt.iterator(IteratorOptions::default()).next().is_none());
+        }
+
+        // 损坏的索引文件打开失败
+        fs::write(path.join(BPTREE_INDEX_FILE_NAME), vec![0xab; 8192]).unwrap();
+        assert!(BPlusTree::new(path.clone(), BPTREE_INDEX_FILE_NAME, BPTREE_BUCKET_NAME).is_err());
+
+        fs::remove_dir_all(path.clone()).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_custom_bucket_name() {
+        let path = PathBuf::from("/tmp/bptree-custom-bucket-name");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+        let pos = LogDbPos {
+            file_id: 1,
+            offset: 2,
+            size: 3,
+        };
+        // 用 jammdb 直接创建一个已有的索引文件，bucket 不是默认的名称
+        {
+            let db = DB::open(path.join("custom-index")).unwrap();
+            let tx = db.tx(true).unwrap();
+            let bucket = tx.create_bucket("custom-bucket").unwrap();
+            bucket.put(b"key".to_vec(), pos.encode()).unwrap();
+            tx.commit().unwrap();
+        }
+
+        {
+            let bpt = BPlusTree::new(path.clone(), "custom-index", "custom-bucket").expect("failed to open bptree");
+            assert_eq!(Some(pos), bpt.get(b"key".to_vec()));
+            assert_eq!(vec![Bytes::from("key")], bpt.list_keys().unwrap());
+            let mut iter = bpt.iterator(IteratorOptions::default());
+            assert_eq!(Some((&b"key".to_vec(), &pos)), iter.next());
+            assert!(iter.next().is_none());
+            drop(iter);
+            assert!(bpt.put(b"key2".to_vec(), pos).is_none());
+        }
+        assert!(!path.join(BPTREE_INDEX_FILE_NAME).exists());
+
+        // 默认的 bucket 名称看不到自定义 bucket 中的索引
+        {
+            let bpt = BPlusTree::new(path.clone(), "custom-index", BPTREE_BUCKET_NAME).expect("failed to open bptree");
+            assert_eq!(None, bpt.get(b"key".to_vec()));
+        }
+        {
+            let bpt = BPlusTree::new(path.clone(), "custom-index", "custom-bucket").expect("failed to open bptree");
+            assert_eq!(2, bpt.list_keys().unwrap().len());
+        }
+
+        fs::remove_dir_all(path.clone()).unwrap();
+    }
 }
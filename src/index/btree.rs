@@ -1,35 +1,89 @@
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 
 use bytes::Bytes;
 use parking_lot::RwLock;
 
-use crate::db::{IndexIterator, Indexer, IteratorOptions, LogDbPos, ResultDb};
+use crate::db::{past_prefix, prefix_start, seek_position, IndexIterator, Indexer, IteratorOptions, KeyComparator, LogDbPos, ResultDb};
 
 // BTree 索引，主要封装了标准库中的 BTreeMap 结构
 pub struct BTree {
-    tree: RwLock<BTreeMap<Vec<u8>, LogDbPos>>,
+    tree: RwLock<BTreeMap<IndexKey, LogDbPos>>,
+    comparator: Option<KeyComparator>,
+}
+
+// BTreeMap 中的 key，按照 comparator 排序，没有 comparator 时按照字节序
+struct IndexKey {
+    key: Vec<u8>,
+    comparator: Option<KeyComparator>,
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_key(&self.comparator, &self.key, &other.key)
+    }
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+fn compare_key(comparator: &Option<KeyComparator>, a: &[u8], b: &[u8]) -> Ordering {
+    match comparator {
+        Some(c) => c.compare(a, b),
+        None => a.cmp(b),
+    }
 }
 
 impl BTree {
     pub fn new() -> Self {
         Self {
             tree: RwLock::new(BTreeMap::new()),
+            comparator: None,
+        }
+    }
+
+    /// 使用自定义的 key 排序方式
+    pub fn with_comparator(comparator: KeyComparator) -> Self {
+        Self {
+            tree: RwLock::new(BTreeMap::new()),
+            comparator: Some(comparator),
+        }
+    }
+
+    fn index_key(&self, key: Vec<u8>) -> IndexKey {
+        IndexKey {
+            key,
+            comparator: self.comparator.clone(),
         }
     }
 }
 
 impl Indexer for BTree {
     fn put(&self, key: Vec<u8>, pos: LogDbPos) -> Option<LogDbPos> {
+        let key = self.index_key(key);
         let mut write_guard = self.tree.write();
         write_guard.insert(key, pos)
     }
 
     fn get(&self, key: Vec<u8>) -> Option<LogDbPos> {
+        let key = self.index_key(key);
         let read_guard = self.tree.read();
         read_guard.get(&key).copied()
     }
 
     fn delete(&self, key: Vec<u8>) -> Option<LogDbPos> {
+        let key = self.index_key(key);
         let mut write_guard = self.tree.write();
         write_guard.remove(&key)
     }
@@ -38,22 +92,43 @@ impl Indexer for BTree {
         let read_guard = self.tree.read();
         let mut keys = Vec::with_capacity(read_guard.len());
         for (k, _) in read_guard.iter() {
-            keys.push(Bytes::copy_from_slice(&k));
+            keys.push(Bytes::copy_from_slice(&k.key));
         }
         Ok(keys)
     }
 
+    fn clear(&self) -> ResultDb<()> {
+        self.tree.write().clear();
+        Ok(())
+    }
+
+    // 直接取 BTreeMap 的两端，不需要复制所有的 key
+    fn first_key(&self) -> Option<Vec<u8>> {
+        self.tree.read().first_key_value().map(|(key, _)| key.key.clone())
+    }
+
+    fn last_key(&self) -> Option<Vec<u8>> {
+        self.tree.read().last_key_value().map(|(key, _)| key.key.clone())
+    }
+
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
         let read_guard = self.tree.read();
         let mut items = Vec::with_capacity(read_guard.len());
         // 将 BTree 中的数据存储到数组中
         for (key, value) in read_guard.iter() {
-            items.push((key.clone(), value.clone()));
+            items.push((key.key.clone(), *value));
         }
         if options.reverse {
             items.reverse();
         }
-        Box::new(BTreeIterator { items, curr_index: 0, options })
+        let mut iter = BTreeIterator {
+            items,
+            curr_index: 0,
+            options,
+            comparator: self.comparator.clone(),
+        };
+        iter.rewind();
+        Box::new(iter)
     }
 }
 
@@ -64,21 +139,34 @@ pub struct BTreeIterator {
     curr_index: usize,
     // 当前遍历的位置下标
     options: IteratorOptions, // 配置项
+    // 和索引相同的排序方式
+    comparator: Option<KeyComparator>,
+}
+
+impl BTreeIterator {
+    // 是否按照字节序排列
+    fn by_bytes(&self) -> bool {
+        self.comparator.is_none()
+    }
 }
 
 impl IndexIterator for BTreeIterator {
     fn rewind(&mut self) {
-        self.curr_index = 0;
+        // 有 prefix 时直接定位到第一个可能匹配的位置
+        self.curr_index = match self.by_bytes() && !self.options.prefix.is_empty() {
+            true => prefix_start(&self.items, &self.options.prefix, self.options.reverse),
+            false => 0,
+        };
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        self.curr_index = match self
-            .items
-            .binary_search_by(|(x, _)| if self.options.reverse { x.cmp(&key).reverse() } else { x.cmp(&key) })
-        {
-            Ok(equal_val) => equal_val,
-            Err(insert_val) => insert_val,
-        };
+        // 反向时 seek 到 prefix，从以 prefix 开头的最大的 key 开始，而不是跳过它们
+        if self.by_bytes() && self.options.reverse && !self.options.prefix.is_empty() && self.options.prefix.starts_with(&key) {
+            self.curr_index = prefix_start(&self.items, &self.options.prefix, true);
+            return;
+        }
+        let comparator = &self.comparator;
+        self.curr_index = seek_position(&self.items, &key, self.options.reverse, |a, b| compare_key(comparator, a, b));
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogDbPos)> {
@@ -89,9 +177,14 @@ impl IndexIterator for BTreeIterator {
         while let Some(item) = self.items.get(self.curr_index) {
             self.curr_index += 1;
             let prefix = &self.options.prefix;
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
+            if prefix.is_empty() || item.0.starts_with(prefix) {
                 return Some((&item.0, &item.1));
             }
+            // 已经越过了以 prefix 开头的范围，之后不会再有匹配的 key
+            if self.by_bytes() && past_prefix(&item.0, prefix, self.options.reverse) {
+                self.curr_index = self.items.len();
+                return None;
+            }
         }
         None
     }
@@ -266,13 +359,13 @@ mod tests {
         let mut iter4 = bt.iterator(IteratorOptions::default());
         iter4.seek("b".as_bytes().to_vec());
         while let Some(item) = iter4.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
         }
 
         let mut iter5 = bt.iterator(IteratorOptions::default());
         iter5.seek("cadd".as_bytes().to_vec());
         while let Some(item) = iter5.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
             // println!("{:?}", String::from_utf8(item.0.to_vec()));
         }
 
@@ -286,7 +379,7 @@ mod tests {
         let mut iter7 = bt.iterator(iter_opts);
         iter7.seek("bb".as_bytes().to_vec());
         while let Some(item) = iter7.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
         }
     }
 
@@ -338,14 +431,172 @@ mod tests {
         iter_opt2.reverse = true;
         let mut iter3 = bt.iterator(iter_opt2);
         while let Some(item) = iter3.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
         }
 
         let mut iter_opt3 = IteratorOptions::default();
         iter_opt3.prefix = "bbed".as_bytes().to_vec();
         let mut iter4 = bt.iterator(iter_opt3);
         while let Some(item) = iter4.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_btree_iterator_prefix() {
+        let bt = BTree::new();
+        for (i, key) in ["a", "ab", "abc", "abd", "b", "ba"].iter().enumerate() {
+            bt.put(
+                key.as_bytes().to_vec(),
+                LogDbPos {
+                    file_id: 1,
+                    offset: i as u64,
+                    size: 11,
+                },
+            );
         }
+        let keys = |prefix: &str, reverse: bool| {
+            let mut iter = bt.iterator(IteratorOptions {
+                prefix: prefix.as_bytes().to_vec(),
+                reverse,
+            });
+            let mut keys = Vec::new();
+            while let Some((key, _)) = iter.next() {
+                keys.push(String::from_utf8(key.clone()).unwrap());
+            }
+            // rewind 之后从第一个匹配的 key 重新开始
+            iter.rewind();
+            assert_eq!(keys.first(), iter.next().map(|(k, _)| String::from_utf8(k.clone()).unwrap()).as_ref());
+            keys
+        };
+        assert_eq!(vec!["ab", "abc", "abd"], keys("ab", false));
+        assert_eq!(vec!["abd", "abc", "ab"], keys("ab", true));
+        assert_eq!(vec!["b", "ba"], keys("b", false));
+        assert_eq!(vec!["ba", "b"], keys("b", true));
+        assert_eq!(vec!["a", "ab", "abc", "abd"], keys("a", false));
+        assert_eq!(vec!["abd", "abc", "ab", "a"], keys("a", true));
+        assert_eq!(6, keys("", false).len());
+        // prefix 比所有的 key 都长、都大或者都小
+        assert!(keys("abcd", false).is_empty());
+        assert!(keys("abcd", true).is_empty());
+        assert!(keys("c", false).is_empty());
+        assert!(keys("c", true).is_empty());
+        assert!(keys("0", false).is_empty());
+        assert!(keys("0", true).is_empty());
+
+        let seek = |prefix: &str, reverse: bool, key: &str| {
+            let mut iter = bt.iterator(IteratorOptions {
+                prefix: prefix.as_bytes().to_vec(),
+                reverse,
+            });
+            iter.seek(key.as_bytes().to_vec());
+            let mut keys = Vec::new();
+            while let Some((key, _)) = iter.next() {
+                keys.push(String::from_utf8(key.clone()).unwrap());
+            }
+            keys
+        };
+        // 反向 seek 到 prefix 时返回所有匹配的 key
+        assert_eq!(vec!["abd", "abc", "ab"], seek("ab", true, "ab"));
+        assert_eq!(vec!["abd", "abc", "ab"], seek("ab", true, "a"));
+        assert_eq!(vec!["ba", "b"], seek("b", true, "b"));
+        // 反向 seek 到 prefix 范围中间或者之后的 key
+        assert_eq!(vec!["abc", "ab"], seek("ab", true, "abc"));
+        assert_eq!(vec!["abd", "abc", "ab"], seek("ab", true, "b"));
+        assert!(seek("ab", true, "aa").is_empty());
+        // 正向
+        assert_eq!(vec!["ab", "abc", "abd"], seek("ab", false, "ab"));
+        assert_eq!(vec!["ab", "abc", "abd"], seek("ab", false, "a"));
+        assert_eq!(vec!["abd"], seek("ab", false, "abd"));
+        assert!(seek("ab", false, "b").is_empty());
+    }
+
+    #[test]
+    fn test_btree_comparator() {
+        // 大端编码的 u64，按照数值从大到小排序
+        let comparator = KeyComparator::new(|a, b| {
+            let a = u64::from_be_bytes(a.try_into().unwrap());
+            let b = u64::from_be_bytes(b.try_into().unwrap());
+            b.cmp(&a)
+        });
+        let bt = BTree::with_comparator(comparator);
+        for (i, n) in [5u64, 300, 1, 70000].iter().enumerate() {
+            bt.put(
+                n.to_be_bytes().to_vec(),
+                LogDbPos {
+                    file_id: 1,
+                    offset: i as u64,
+                    size: 11,
+                },
+            );
+        }
+        assert_eq!(2, bt.get(1u64.to_be_bytes().to_vec()).unwrap().offset);
+
+        let numbers = |iter: &mut Box<dyn IndexIterator>| {
+            let mut numbers = Vec::new();
+            while let Some((key, _)) = iter.next() {
+                numbers.push(u64::from_be_bytes(key.as_slice().try_into().unwrap()));
+            }
+            numbers
+        };
+        let mut iter1 = bt.iterator(IteratorOptions::default());
+        assert_eq!(vec![70000, 300, 5, 1], numbers(&mut iter1));
+        iter1.seek(300u64.to_be_bytes().to_vec());
+        assert_eq!(vec![300, 5, 1], numbers(&mut iter1));
+
+        let mut iter2 = bt.iterator(IteratorOptions {
+            prefix: Vec::new(),
+            reverse: true,
+        });
+        iter2.seek(6u64.to_be_bytes().to_vec());
+        assert_eq!(vec![300, 70000], numbers(&mut iter2));
+
+        let keys: Vec<u64> = bt
+            .list_keys()
+            .unwrap()
+            .iter()
+            .map(|k| u64::from_be_bytes(k.as_ref().try_into().unwrap()))
+            .collect();
+        assert_eq!(vec![70000, 300, 5, 1], keys);
+
+        assert!(bt.delete(300u64.to_be_bytes().to_vec()).is_some());
+        let mut iter3 = bt.iterator(IteratorOptions::default());
+        assert_eq!(vec![70000, 5, 1], numbers(&mut iter3));
+    }
+
+    #[test]
+    fn test_btree_iterator_seek_bounds() {
+        let idx = BTree::new();
+        for key in ["b", "d", "f"] {
+            idx.put(
+                key.as_bytes().to_vec(),
+                LogDbPos {
+                    file_id: 1,
+                    offset: 10,
+                    size: 11,
+                },
+            );
+        }
+        let seek = |reverse: bool, key: &str| -> Vec<String> {
+            let mut iter = idx.iterator(IteratorOptions { prefix: Vec::new(), reverse });
+            iter.seek(key.as_bytes().to_vec());
+            let mut keys = Vec::new();
+            while let Some((k, _)) = iter.next() {
+                keys.push(String::from_utf8(k.clone()).unwrap());
+            }
+            keys
+        };
+
+        // forward: the first key >= the seek key
+        assert_eq!(vec!["b", "d", "f"], seek(false, "a"));
+        assert_eq!(vec!["d", "f"], seek(false, "d"));
+        assert_eq!(vec!["f"], seek(false, "e"));
+        assert!(seek(false, "g").is_empty());
+
+        // reverse: the first key <= the seek key
+        assert_eq!(vec!["f", "d", "b"], seek(true, "g"));
+        assert_eq!(vec!["d", "b"], seek(true, "d"));
+        assert_eq!(vec!["b"], seek(true, "c"));
+        assert!(seek(true, "a").is_empty());
     }
 }
@@ -1,13 +1,32 @@
 use std::path::PathBuf;
 
-use crate::db::{IndexType, Indexer, ResultDb};
+use crate::db::{IndexType, Indexer, KeyComparator, ResultDb};
 
+pub use bloom::BloomFilter;
+pub use bptree::{BPTREE_BUCKET_NAME, BPTREE_INDEX_FILE_NAME};
+
+mod bloom;
 mod bptree;
 mod btree;
 
-pub fn new_indexer(index_type: IndexType, _dir_path: PathBuf) -> ResultDb<Box<dyn Indexer>> {
-    match index_type {
-        IndexType::BTree => Ok(Box::new(btree::BTree::new())),
-        IndexType::BPlusTree => Ok(Box::new(bptree::BPlusTree::new(_dir_path)?)),
+/// comparator 只对 BTree 索引有效，bloom_filter_keys 不为 None 时在索引之外维护布隆过滤器
+/// bptree_names 是 B+ 树索引文件和 bucket 的名称，只对 BPlusTree 索引有效
+pub fn new_indexer(
+    index_type: IndexType,
+    dir_path: PathBuf,
+    bptree_names: (&str, &str),
+    comparator: Option<KeyComparator>,
+    bloom_filter_keys: Option<usize>,
+) -> ResultDb<Box<dyn Indexer>> {
+    let indexer: Box<dyn Indexer> = match index_type {
+        IndexType::BTree => match comparator {
+            Some(comparator) => Box::new(btree::BTree::with_comparator(comparator)),
+            None => Box::new(btree::BTree::new()),
+        },
+        IndexType::BPlusTree => Box::new(bptree::BPlusTree::new(dir_path, bptree_names.0, bptree_names.1)?),
+    };
+    match bloom_filter_keys {
+        Some(expected_keys) => Ok(Box::new(bloom::BloomIndexer::new(indexer, expected_keys)?)),
+        None => Ok(indexer),
     }
 }
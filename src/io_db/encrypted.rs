@@ -0,0 +1,155 @@
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+
+use aes::Aes256;
+use ctr::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use parking_lot::Mutex;
+
+use crate::db::{IoType, ResultDb};
+
+use super::{new_dbio, DbIo, DbIoFactory};
+
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+/// 加密数据文件的 256 位密钥，Debug 时不输出密钥的内容
+#[derive(Clone, PartialEq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// 使用 AES-256-CTR 加密的 DbIo，写入时加密，读取时解密，文件中每个字节的位置决定使用的密钥流
+/// iv 由文件名计算，merge 移动文件之后不变；记录的 crc 按照明文计算，密钥错误时读取会校验失败
+/// 同一个位置重复写入时使用相同的密钥流，不能防御能够多次获取文件内容的攻击者
+pub struct EncryptedIo {
+    inner: Box<dyn DbIo>,
+    key: EncryptionKey,
+    iv: [u8; 16],
+    // write 先取得文件大小再写入，两步需要互斥
+    append_lock: Mutex<()>,
+}
+
+impl EncryptedIo {
+    pub fn new(inner: Box<dyn DbIo>, key: EncryptionKey, file_name: &Path) -> Self {
+        let name = file_name.file_name().map(|n| n.as_encoded_bytes()).unwrap_or_default();
+        let mut iv = [0u8; 16];
+        // 低 8 字节留给计数器
+        iv[..8].copy_from_slice(&xxhash_rust::xxh64::xxh64(name, 0).to_be_bytes());
+        Self {
+            inner,
+            key,
+            iv,
+            append_lock: Mutex::new(()),
+        }
+    }
+
+    /// 在 io_type 创建的 DbIo 之上加密
+    pub fn factory(key: EncryptionKey, io_type: IoType) -> DbIoFactory {
+        DbIoFactory::new(move |file_name| {
+            let inner = new_dbio(file_name.clone(), io_type.clone())?;
+            Ok(Box::new(EncryptedIo::new(inner, key.clone(), &file_name)))
+        })
+    }
+
+    // 加密和解密是同一个操作
+    fn apply_keystream(&self, buf: &mut [u8], offset: u64) {
+        let mut cipher = Aes256Ctr::new(&self.key.0.into(), &self.iv.into());
+        cipher.seek(offset);
+        cipher.apply_keystream(buf);
+    }
+}
+
+impl DbIo for EncryptedIo {
+    fn read(&self, buf: &mut [u8], offset: u64) -> ResultDb<usize> {
+        let n = self.inner.read(buf, offset)?;
+        self.apply_keystream(&mut buf[..n], offset);
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> ResultDb<usize> {
+        let _lock = self.append_lock.lock();
+        self.write_at(buf, self.inner.size())
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> ResultDb<usize> {
+        let mut enc = buf.to_vec();
+        self.apply_keystream(&mut enc, offset);
+        self.inner.write_at(&enc, offset)
+    }
+
+    /// 预分配的部分写入加密的 0，解密之后和没有加密的文件一样是 0
+    fn preallocate(&self, size: u64) -> ResultDb<()> {
+        let _lock = self.append_lock.lock();
+        let file_size = self.inner.size();
+        if file_size >= size {
+            return Ok(());
+        }
+        self.write_at(&vec![0u8; (size - file_size) as usize], file_size)?;
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> ResultDb<()> {
+        self.inner.truncate(size)
+    }
+
+    fn sync(&self) -> ResultDb<()> {
+        self.inner.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::io_db::{DbIo, EncryptedIo, EncryptionKey, MemIo};
+
+    #[test]
+    fn test_encrypted_io() {
+        let key = EncryptionKey::new([7u8; 32]);
+        let io = EncryptedIo::new(Box::new(MemIo::new()), key.clone(), Path::new("000000001.data"));
+        io.write(b"hello lite db").unwrap();
+        io.write_at(b"LITE", 6).unwrap();
+        io.preallocate(32).unwrap();
+        assert_eq!(32, io.size());
+
+        // 任意位置读取都可以正确解密，预分配的部分是 0
+        let mut buf = [0u8; 32];
+        io.read_exact(&mut buf, 0).unwrap();
+        assert_eq!(b"hello LITE db", &buf[..13]);
+        assert!(buf[13..].iter().all(|b| *b == 0));
+        let mut buf = [0u8; 4];
+        io.read_exact(&mut buf, 6).unwrap();
+        assert_eq!(b"LITE", &buf);
+
+        // 底层保存的是密文
+        let inner = MemIo::new();
+        inner.write(b"hello lite db").unwrap();
+        let plain = EncryptedIo::new(Box::new(inner), key.clone(), Path::new("000000001.data"));
+        let mut buf = [0u8; 13];
+        plain.read_exact(&mut buf, 0).unwrap();
+        assert_ne!(b"hello lite db", &buf);
+
+        // 文件名不同时密钥流不同
+        let other = EncryptedIo::new(Box::new(MemIo::new()), key, Path::new("000000002.data"));
+        other.write(&[0u8; 16]).unwrap();
+        io.truncate(0).unwrap();
+        io.write(&[0u8; 16]).unwrap();
+        let (mut a, mut b) = ([0u8; 16], [0u8; 16]);
+        other.inner.read_exact(&mut a, 0).unwrap();
+        io.inner.read_exact(&mut b, 0).unwrap();
+        assert_ne!(a, b);
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     fs::{File, OpenOptions},
-    io::Write,
+    io::{Seek, SeekFrom, Write},
     path::PathBuf,
     sync::Arc,
 };
@@ -18,15 +18,27 @@ pub struct FileIo {
 
 impl FileIo {
     pub fn new(file_name: PathBuf) -> ResultDb<Self> {
-        match OpenOptions::new().create(true).read(true).write(true).append(true).open(file_name) {
-            Ok(file) => {
-                return Ok(FileIo {
-                    fd: Arc::new(RwLock::new(file)),
-                });
+        // 不使用 append 模式，append 模式下 write_at 指定的位置无效
+        match OpenOptions::new().create(true).read(true).write(true).truncate(false).open(file_name) {
+            Ok(file) => Ok(FileIo {
+                fd: Arc::new(RwLock::new(file)),
+            }),
+            Err(e) => {
+                error!("{}", e);
+                Err(ErrDb::from(e))
             }
+        }
+    }
+
+    /// 只读打开已经存在的文件，可以用于只读的文件系统，写入时返回系统的错误
+    pub fn open_read_only(file_name: PathBuf) -> ResultDb<Self> {
+        match OpenOptions::new().read(true).open(file_name) {
+            Ok(file) => Ok(FileIo {
+                fd: Arc::new(RwLock::new(file)),
+            }),
             Err(e) => {
                 error!("{}", e);
-                return Err(ErrDb::IoErr(e));
+                Err(ErrDb::from(e))
             }
         }
     }
@@ -38,12 +50,12 @@ impl DbIo for FileIo {
         use std::os::unix::fs::FileExt;
         let read = self.fd.read();
         match read.read_at(buf, offset) {
-            Ok(n) => return Ok(n),
+            Ok(n) => Ok(n),
             Err(e) => {
                 error!("{}", e);
-                return Err(ErrDb::IoErr(e));
+                Err(ErrDb::from(e))
             }
-        };
+        }
     }
 
     #[cfg(windows)]
@@ -51,30 +63,86 @@ impl DbIo for FileIo {
         use std::os::windows::fs::FileExt;
         let read = self.fd.read();
         match read.seek_read(buf, offset) {
-            Ok(n) => return Ok(n),
+            Ok(n) => Ok(n),
             Err(e) => {
                 error!("{}", e);
-                return Err(ErrDb::IoErr(e));
+                Err(ErrDb::from(e))
             }
-        };
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn read_exact(&self, buf: &mut [u8], offset: u64) -> ResultDb<()> {
+        use std::os::unix::fs::FileExt;
+        let read = self.fd.read();
+        if let Err(e) = read.read_exact_at(buf, offset) {
+            error!("{}", e);
+            return Err(ErrDb::from(e));
+        }
+        Ok(())
     }
 
     fn write(&self, buf: &[u8]) -> ResultDb<usize> {
         let mut write = self.fd.write();
-        match write.write(buf) {
-            Ok(n) => return Ok(n),
+        match write.seek(SeekFrom::End(0)).and_then(|_| write.write(buf)) {
+            Ok(n) => Ok(n),
             Err(e) => {
                 error!("{}", e);
-                return Err(ErrDb::IoErr(e));
+                Err(ErrDb::from(e))
             }
         }
     }
 
+    #[cfg(not(windows))]
+    fn write_at(&self, buf: &[u8], offset: u64) -> ResultDb<usize> {
+        use std::os::unix::fs::FileExt;
+        let write = self.fd.write();
+        if let Err(e) = write.write_all_at(buf, offset) {
+            error!("{}", e);
+            return Err(ErrDb::from(e));
+        }
+        Ok(buf.len())
+    }
+
+    #[cfg(windows)]
+    fn write_at(&self, buf: &[u8], offset: u64) -> ResultDb<usize> {
+        use std::os::windows::fs::FileExt;
+        let write = self.fd.write();
+        let mut written = 0;
+        while written < buf.len() {
+            match write.seek_write(&buf[written..], offset + written as u64) {
+                Ok(0) => return Err(ErrDb::IoErr(std::io::Error::from(std::io::ErrorKind::WriteZero))),
+                Ok(n) => written += n,
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(ErrDb::from(e));
+                }
+            }
+        }
+        Ok(written)
+    }
+
+    fn preallocate(&self, size: u64) -> ResultDb<()> {
+        let write = self.fd.write();
+        if write.metadata()?.len() < size {
+            write.set_len(size)?;
+        }
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> ResultDb<()> {
+        let write = self.fd.write();
+        if write.metadata()?.len() > size {
+            write.set_len(size)?;
+        }
+        Ok(())
+    }
+
     fn sync(&self) -> ResultDb<()> {
         let read = self.fd.read();
         if let Err(e) = read.sync_all() {
             error!("failed to sync data file: {}", e);
-            return Err(ErrDb::IoErr(e));
+            return Err(ErrDb::from(e));
         }
         Ok(())
     }
@@ -93,6 +161,7 @@ mod tests {
 
     use function_name::named;
 
+    use crate::db::ErrDb;
     use crate::io_db::{DbIo, FileIo};
     use crate::kits;
 
@@ -157,6 +226,29 @@ mod tests {
         assert!(res3.is_ok());
     }
 
+    #[named]
+    #[test]
+    fn test_file_io_read_exact() {
+        let path = make_file_name(file!(), function_name!());
+        let _ = fs::remove_file(path.clone());
+        let file_io = FileIo::new(path.clone()).expect("failed to new file io");
+
+        let res1 = file_io.write("key-a".as_bytes());
+        assert!(res1.is_ok());
+
+        let mut buf = [0u8; 3];
+        let read_res1 = file_io.read_exact(&mut buf, 2);
+        assert!(read_res1.is_ok());
+        assert_eq!("y-a".as_bytes(), buf);
+
+        // only 2 bytes left
+        let read_res2 = file_io.read_exact(&mut buf, 3);
+        assert!(matches!(read_res2, Err(ErrDb::IoErr(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+
+        let res3 = fs::remove_file(path.clone());
+        assert!(res3.is_ok());
+    }
+
     #[named]
     #[test]
     fn test_file_io_sync() {
@@ -180,6 +272,37 @@ mod tests {
         assert!(res3.is_ok());
     }
 
+    #[named]
+    #[test]
+    fn test_file_io_truncate() {
+        let path = make_file_name(file!(), function_name!());
+        let _ = fs::remove_file(path.clone());
+        let file_io = FileIo::new(path.clone()).expect("failed to new file io");
+        file_io.write("key-a-key-b".as_bytes()).unwrap();
+
+        // 不大于 size 时不做任何事情
+        file_io.truncate(20).unwrap();
+        assert_eq!(11, file_io.size());
+
+        file_io.truncate(5).unwrap();
+        assert_eq!(5, file_io.size());
+        assert_eq!(5, fs::metadata(path.clone()).unwrap().len());
+        let mut buf = [0u8; 5];
+        file_io.read_exact(&mut buf, 0).unwrap();
+        assert_eq!("key-a".as_bytes(), buf);
+
+        // 截断之后的位置读取不到数据
+        assert_eq!(0, file_io.read(&mut buf, 5).unwrap());
+        let read_res = file_io.read_exact(&mut buf, 3);
+        assert!(matches!(read_res, Err(ErrDb::IoErr(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+
+        // 继续在末尾写入
+        file_io.write("-c".as_bytes()).unwrap();
+        assert_eq!(7, file_io.size());
+
+        fs::remove_file(path.clone()).expect("failed to remove file");
+    }
+
     #[named]
     #[test]
     fn test_file_io_size() {
@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::db::ResultDb;
+
+use super::DbIo;
+
+type ReadHook = dyn Fn(&dyn DbIo, &mut [u8], u64) -> ResultDb<usize> + Send + Sync;
+type WriteHook = dyn Fn(&dyn DbIo, &[u8], u64) -> ResultDb<usize> + Send + Sync;
+
+/// 测试用的 DbIo，包装另一个 DbIo，统计 sync 的次数，读写可以替换为钩子，用来模拟慢速、短读或者写入失败的 IO
+/// 钩子的第一个参数是被包装的 DbIo，write 和 write_at 一样经过写入的钩子
+pub(crate) struct HookIo {
+    inner: Arc<dyn DbIo>,
+    syncs: Arc<AtomicUsize>,
+    read: Option<Box<ReadHook>>,
+    write_at: Option<Box<WriteHook>>,
+}
+
+impl HookIo {
+    pub(crate) fn new(inner: impl DbIo + 'static) -> Self {
+        Self::shared(Arc::new(inner))
+    }
+
+    /// 多个 HookIo 共享同一个 DbIo 的数据，如按照文件名称多次创建的 IO
+    pub(crate) fn shared(inner: Arc<dyn DbIo>) -> Self {
+        HookIo {
+            inner,
+            syncs: Arc::new(AtomicUsize::new(0)),
+            read: None,
+            write_at: None,
+        }
+    }
+
+    /// sync 的次数累加到 syncs 中，多个 HookIo 可以共用一个计数
+    pub(crate) fn count_syncs(mut self, syncs: Arc<AtomicUsize>) -> Self {
+        self.syncs = syncs;
+        self
+    }
+
+    pub(crate) fn on_read(mut self, hook: impl Fn(&dyn DbIo, &mut [u8], u64) -> ResultDb<usize> + Send + Sync + 'static) -> Self {
+        self.read = Some(Box::new(hook));
+        self
+    }
+
+    pub(crate) fn on_write(mut self, hook: impl Fn(&dyn DbIo, &[u8], u64) -> ResultDb<usize> + Send + Sync + 'static) -> Self {
+        self.write_at = Some(Box::new(hook));
+        self
+    }
+}
+
+impl DbIo for HookIo {
+    fn read(&self, buf: &mut [u8], offset: u64) -> ResultDb<usize> {
+        match &self.read {
+            Some(hook) => hook(self.inner.as_ref(), buf, offset),
+            None => self.inner.read(buf, offset),
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> ResultDb<usize> {
+        self.write_at(buf, self.inner.size())
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> ResultDb<usize> {
+        match &self.write_at {
+            Some(hook) => hook(self.inner.as_ref(), buf, offset),
+            None => self.inner.write_at(buf, offset),
+        }
+    }
+
+    fn preallocate(&self, size: u64) -> ResultDb<()> {
+        self.inner.preallocate(size)
+    }
+
+    fn truncate(&self, size: u64) -> ResultDb<()> {
+        self.inner.truncate(size)
+    }
+
+    fn sync(&self) -> ResultDb<()> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        self.inner.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn read_shared(&self, offset: u64, len: usize) -> Option<Bytes> {
+        match &self.read {
+            Some(_) => None,
+            None => self.inner.read_shared(offset, len),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+
+use crate::db::{IoType, ResultDb};
+
+use super::{new_dbio, DbIo};
+
+/// 限制同时打开的文件数量，超过 max 时关闭最久没有使用的文件，再次读写时重新打开
+pub(crate) struct FileHandles {
+    max: usize,
+    // 每次读写递增，用来比较文件最后一次使用的先后
+    clock: AtomicU64,
+    open: Mutex<Vec<Arc<Handle>>>,
+}
+
+struct Handle {
+    file_name: PathBuf,
+    io_type: IoType,
+    io: RwLock<Option<Box<dyn DbIo>>>,
+    last_used: AtomicU64,
+    // 关闭时的文件大小，关闭的文件不需要重新打开就能返回大小
+    size: AtomicU64,
+}
+
+impl Handle {
+    fn close(&self) {
+        if let Some(io) = self.io.write().take() {
+            self.size.store(io.size(), Ordering::SeqCst);
+        }
+    }
+}
+
+impl FileHandles {
+    /// max 不能为 0
+    pub(crate) fn new(max: usize) -> FileHandles {
+        FileHandles {
+            max: max.max(1),
+            clock: AtomicU64::new(0),
+            open: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 当前打开的文件数量
+    #[cfg(test)]
+    pub(crate) fn open_count(&self) -> usize {
+        self.open.lock().len()
+    }
+
+    /// 按需打开文件的 DbIo，创建时不打开文件，size 为文件当前的大小
+    pub(crate) fn limit(self: &Arc<Self>, file_name: PathBuf, io_type: IoType, size: u64) -> LimitedIo {
+        LimitedIo {
+            handle: Arc::new(Handle {
+                file_name,
+                io_type,
+                io: RwLock::new(None),
+                last_used: AtomicU64::new(0),
+                size: AtomicU64::new(size),
+            }),
+            handles: self.clone(),
+        }
+    }
+
+    // 打开 handle 的文件，超过 max 时关闭最久没有使用的其它文件
+    // 先加 open 的锁再加文件的锁，读写文件时不会加 open 的锁
+    fn open(&self, handle: &Arc<Handle>) -> ResultDb<()> {
+        let mut open = self.open.lock();
+        {
+            let mut io = handle.io.write();
+            if io.is_some() {
+                return Ok(());
+            }
+            *io = Some(new_dbio(handle.file_name.clone(), handle.io_type.clone())?);
+        }
+        open.push(handle.clone());
+        while open.len() > self.max {
+            let lru = open
+                .iter()
+                .enumerate()
+                .filter(|(_, h)| !Arc::ptr_eq(h, handle))
+                .min_by_key(|(_, h)| h.last_used.load(Ordering::SeqCst))
+                .map(|(i, _)| i)
+                .unwrap();
+            open.swap_remove(lru).close();
+        }
+        Ok(())
+    }
+}
+
+/// 由 FileHandles 管理的 DbIo，文件被关闭之后读写时重新打开
+pub(crate) struct LimitedIo {
+    handle: Arc<Handle>,
+    handles: Arc<FileHandles>,
+}
+
+impl LimitedIo {
+    // 重新打开文件失败时返回错误
+    fn with_io<R>(&self, mut f: impl FnMut(&dyn DbIo) -> R) -> ResultDb<R> {
+        loop {
+            self.handle.last_used.store(self.handles.clock.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+            if let Some(io) = self.handle.io.read().as_ref() {
+                return Ok(f(io.as_ref()));
+            }
+            // 打开之后可能马上又被其它的文件关闭，重新检查
+            self.handles.open(&self.handle)?;
+        }
+    }
+}
+
+impl DbIo for LimitedIo {
+    fn read(&self, buf: &mut [u8], offset: u64) -> ResultDb<usize> {
+        self.with_io(|io| io.read(buf, offset))?
+    }
+
+    fn write(&self, buf: &[u8]) -> ResultDb<usize> {
+        self.with_io(|io| io.write(buf))?
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> ResultDb<usize> {
+        self.with_io(|io| io.write_at(buf, offset))?
+    }
+
+    fn preallocate(&self, size: u64) -> ResultDb<()> {
+        self.with_io(|io| io.preallocate(size))?
+    }
+
+    fn truncate(&self, size: u64) -> ResultDb<()> {
+        self.with_io(|io| io.truncate(size))?
+    }
+
+    fn sync(&self) -> ResultDb<()> {
+        self.with_io(|io| io.sync())?
+    }
+
+    fn read_shared(&self, offset: u64, len: usize) -> Option<Bytes> {
+        self.with_io(|io| io.read_shared(offset, len)).ok().flatten()
+    }
+
+    fn size(&self) -> u64 {
+        match self.handle.io.read().as_ref() {
+            Some(io) => io.size(),
+            None => self.handle.size.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl Drop for LimitedIo {
+    fn drop(&mut self) {
+        self.handles.open.lock().retain(|h| !Arc::ptr_eq(h, &self.handle));
+    }
+}
@@ -0,0 +1,92 @@
+use parking_lot::RwLock;
+
+use crate::db::ResultDb;
+
+use super::DbIo;
+
+/// 数据保存在内存中的 DbIo，用于内存模式
+#[derive(Default)]
+pub struct MemIo {
+    data: RwLock<Vec<u8>>,
+}
+
+impl MemIo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DbIo for MemIo {
+    fn read(&self, buf: &mut [u8], offset: u64) -> ResultDb<usize> {
+        let data = self.data.read();
+        let start = (offset as usize).min(data.len());
+        let end = (start + buf.len()).min(data.len());
+        buf[..end - start].copy_from_slice(&data[start..end]);
+        Ok(end - start)
+    }
+
+    fn write(&self, buf: &[u8]) -> ResultDb<usize> {
+        self.data.write().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> ResultDb<usize> {
+        let mut data = self.data.write();
+        let start = offset as usize;
+        if data.len() < start + buf.len() {
+            data.resize(start + buf.len(), 0);
+        }
+        data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn preallocate(&self, size: u64) -> ResultDb<()> {
+        let mut data = self.data.write();
+        if (data.len() as u64) < size {
+            data.resize(size as usize, 0);
+        }
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> ResultDb<()> {
+        self.data.write().truncate(size as usize);
+        Ok(())
+    }
+
+    fn sync(&self) -> ResultDb<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.data.read().len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io_db::{DbIo, MemIo};
+
+    #[test]
+    fn test_mem_io() {
+        let mem_io = MemIo::new();
+        assert_eq!(0, mem_io.size());
+
+        let re1 = mem_io.write("key-a".as_bytes());
+        assert_eq!(5, re1.unwrap());
+        let re2 = mem_io.write("key-b".as_bytes());
+        assert_eq!(5, re2.unwrap());
+        assert_eq!(10, mem_io.size());
+
+        let mut buf = [0u8; 5];
+        let re3 = mem_io.read(&mut buf, 5);
+        assert_eq!(5, re3.unwrap());
+        assert_eq!("key-b".as_bytes(), buf);
+
+        // read at the end
+        let re4 = mem_io.read(&mut buf, 8);
+        assert_eq!(2, re4.unwrap());
+        let re5 = mem_io.read_exact(&mut buf, 8);
+        assert!(re5.is_err());
+        assert!(mem_io.sync().is_ok());
+    }
+}
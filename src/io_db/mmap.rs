@@ -1,5 +1,7 @@
+use std::sync::Arc;
 use std::{fs, fs::OpenOptions, path::PathBuf};
 
+use bytes::Bytes;
 use log::error;
 use memmap2::{MmapMut, RemapOptions};
 use parking_lot::RwLock;
@@ -8,24 +10,83 @@ use crate::db::{ErrDb, ResultDb};
 
 use super::DbIo;
 
+/// 映射保存在 Arc 中，read_shared 返回的 Bytes 持有映射
+/// 映射被 Bytes 持有时，写入、扩大和截断都建立新的映射，旧的映射在 Bytes 释放之后释放
 pub struct MMapIo {
-    map: RwLock<MmapMut>,
+    map: RwLock<Arc<MmapMut>>,
     file: fs::File,
 }
 
+// 让 Bytes 持有映射，不复制数据
+struct SharedMap(Arc<MmapMut>);
+
+impl AsRef<[u8]> for SharedMap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
 impl MMapIo {
     pub fn new(file_name: PathBuf) -> ResultDb<Self> {
-        match OpenOptions::new().create(true).read(true).write(true).open(file_name) {
+        match OpenOptions::new().create(true).read(true).write(true).truncate(false).open(file_name) {
             Ok(file) => {
                 let map = unsafe { MmapMut::map_mut(&file)? };
-                return Ok(MMapIo { map: RwLock::new(map), file });
+                Ok(MMapIo {
+                    map: RwLock::new(Arc::new(map)),
+                    file,
+                })
             }
             Err(e) => {
                 error!("{}", e);
-                return Err(ErrDb::IoErr(e));
+                Err(ErrDb::from(e))
             }
         }
     }
+
+    // 持有映射的写锁时写入数据，超过映射的长度时先扩大
+    fn write_locked(&self, map: &mut Arc<MmapMut>, buf: &[u8], offset: usize) -> ResultDb<usize> {
+        if map.len() < offset + buf.len() {
+            self.grow(map, offset + buf.len())?;
+        }
+        self.unique(map)?[offset..offset + buf.len()].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    // 映射被 read_shared 返回的 Bytes 持有时建立新的映射，不修改 Bytes 正在使用的映射
+    // Bytes 引用的是已经写入的记录，新的映射不会写到这些位置
+    fn unique<'a>(&self, map: &'a mut Arc<MmapMut>) -> ResultDb<&'a mut MmapMut> {
+        if Arc::get_mut(map).is_none() {
+            *map = Arc::new(unsafe { MmapMut::map_mut(&self.file)? });
+        }
+        Ok(Arc::get_mut(map).unwrap())
+    }
+
+    // 扩大文件以及映射的长度
+    #[cfg(not(windows))]
+    fn grow(&self, map: &mut Arc<MmapMut>, len: usize) -> ResultDb<()> {
+        let file_len = self.file.metadata()?.len() as usize;
+        if file_len < len {
+            self.file.set_len(len as u64)?;
+        }
+        match Arc::get_mut(map) {
+            Some(map) => unsafe {
+                map.remap(len, RemapOptions::new().may_move(true))?;
+            },
+            None => *map = Arc::new(unsafe { MmapMut::map_mut(&self.file)? }),
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn grow(&self, map: &mut Arc<MmapMut>, len: usize) -> ResultDb<()> {
+        let file_len = self.file.metadata()?.len() as usize;
+        if file_len < len {
+            self.file.set_len(len as u64)?;
+        }
+        // os windows do not support the "remap"
+        *map = Arc::new(unsafe { MmapMut::map_mut(&self.file)? });
+        Ok(())
+    }
 }
 
 impl DbIo for MMapIo {
@@ -39,36 +100,53 @@ impl DbIo for MMapIo {
         buf.copy_from_slice(val);
         Ok(val.len())
     }
-    #[cfg(not(windows))]
-    fn write(&self, _buf: &[u8]) -> ResultDb<usize> {
+
+    // read 只会在数据足够的时候返回
+    fn read_exact(&self, buf: &mut [u8], offset: u64) -> ResultDb<()> {
+        self.read(buf, offset)?;
+        Ok(())
+    }
+
+    fn write(&self, buf: &[u8]) -> ResultDb<usize> {
         let mut w = self.map.write();
-        let old_len = w.len();
-        let file_len = self.file.metadata()?.len() as usize;
-        if file_len < old_len + _buf.len() {
-            self.file.set_len(old_len as u64 + _buf.len() as u64)?;
-        }
-        unsafe {
-            w.remap(old_len + _buf.len(), RemapOptions::new().may_move(true))?;
-        };
+        let offset = w.len();
+        self.write_locked(&mut w, buf, offset)
+    }
 
-        (&mut w[old_len..]).copy_from_slice(_buf);
-        Ok(_buf.len())
+    fn write_at(&self, buf: &[u8], offset: u64) -> ResultDb<usize> {
+        let mut w = self.map.write();
+        self.write_locked(&mut w, buf, offset as usize)
     }
-    #[cfg(windows)]
-    fn write(&self, _buf: &[u8]) -> ResultDb<usize> {
+
+    fn preallocate(&self, size: u64) -> ResultDb<()> {
         let mut w = self.map.write();
-        let old_len = w.len();
-        let file_len = self.file.metadata()?.len() as usize;
-        if file_len < old_len + _buf.len() {
-            self.file.set_len(old_len as u64 + _buf.len() as u64)?;
+        if (w.len() as u64) < size {
+            self.grow(&mut w, size as usize)?;
         }
-        // os windows do not support the "remap"
-        unsafe {
-            *(self.map.data_ptr()) = MmapMut::map_mut(&self.file)?;
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> ResultDb<()> {
+        let mut w = self.map.write();
+        if (w.len() as u64) <= size {
+            return Ok(());
         }
+        // 先释放旧的映射再截断文件，然后重新映射
+        // 只截断没有被索引的数据，Bytes 持有的旧的映射不会读取截断的部分
+        w.flush()?;
+        *w = Arc::new(MmapMut::map_anon(0)?);
+        self.file.set_len(size)?;
+        *w = Arc::new(unsafe { MmapMut::map_mut(&self.file)? });
+        Ok(())
+    }
 
-        (&mut w[old_len..]).copy_from_slice(_buf);
-        Ok(_buf.len())
+    fn read_shared(&self, offset: u64, len: usize) -> Option<Bytes> {
+        let map = self.map.read();
+        let end = (offset as usize).checked_add(len)?;
+        if end > map.len() {
+            return None;
+        }
+        Some(Bytes::from_owner(SharedMap(map.clone())).slice(offset as usize..end))
     }
 
     fn sync(&self) -> ResultDb<()> {
@@ -147,4 +225,61 @@ mod tests {
             assert!(re_remove.is_ok());
         }
     }
+
+    #[named]
+    #[test]
+    fn test_mmap_read_shared() {
+        let path = make_file_name(file!(), function_name!());
+        {
+            let _ = fs::remove_file(path.clone());
+        }
+        let mmap_io = MMapIo::new(path.clone()).unwrap();
+        mmap_io.write(&[1u8; 10]).unwrap();
+        let shared = mmap_io.read_shared(2, 6).unwrap();
+        assert_eq!(&[1u8; 6][..], &shared[..]);
+        assert_eq!(None, mmap_io.read_shared(8, 6));
+
+        // 持有旧的映射时写入、扩大和截断都使用新的映射
+        mmap_io.write(&[2u8; 4096]).unwrap();
+        mmap_io.write_at(&[3u8; 2], 0).unwrap();
+        mmap_io.truncate(4000).unwrap();
+        assert_eq!(&[1u8; 6][..], &shared[..]);
+        let mut buf = [0u8; 4];
+        mmap_io.read(&mut buf, 0).unwrap();
+        assert_eq!([3, 3, 1, 1], buf);
+
+        drop(mmap_io);
+        assert_eq!(&[1u8; 6][..], &shared[..]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[named]
+    #[test]
+    fn test_mmap_truncate() {
+        let path = make_file_name(file!(), function_name!());
+        {
+            let _ = fs::remove_file(path.clone());
+        }
+        let mmap_io = MMapIo::new(path.clone()).unwrap();
+        mmap_io.write(&[1u8; 10]).unwrap();
+        mmap_io.truncate(20).unwrap();
+        assert_eq!(10, mmap_io.size());
+
+        mmap_io.truncate(4).unwrap();
+        assert_eq!(4, mmap_io.size());
+        assert_eq!(4, fs::metadata(path.clone()).unwrap().len());
+        let mut buf = [0u8; 4];
+        assert_eq!(4, mmap_io.read(&mut buf, 0).unwrap());
+        assert_eq!([1u8; 4], buf);
+        let err = mmap_io.read(&mut buf, 2).unwrap_err();
+        assert!(matches!(err, ErrDb::IoErr(e) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+
+        mmap_io.truncate(0).unwrap();
+        assert_eq!(0, mmap_io.size());
+        mmap_io.write(&[2u8; 2]).unwrap();
+        assert_eq!(2, mmap_io.size());
+
+        drop(mmap_io);
+        fs::remove_file(path).unwrap();
+    }
 }
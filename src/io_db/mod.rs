@@ -1,21 +1,96 @@
+#[cfg(feature = "encryption")]
+pub use encrypted::*;
 pub use file_io::*;
+#[cfg(test)]
+pub(crate) use hook::*;
+pub(crate) use limited::*;
+pub use memory::*;
 pub use mmap::*;
 
-use crate::db::{IoType, ResultDb};
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
 
+use bytes::Bytes;
+
+use crate::db::{ErrDb, IoType, ResultDb};
+
+#[cfg(feature = "encryption")]
+mod encrypted;
 mod file_io;
+#[cfg(test)]
+mod hook;
+mod limited;
+mod memory;
 mod mmap;
 
 pub trait DbIo: Sync + Send {
     fn read(&self, buf: &mut [u8], offset: u64) -> ResultDb<usize>;
+    /// 读满整个 buf，数据不足时返回 UnexpectedEof 错误
+    fn read_exact(&self, mut buf: &mut [u8], mut offset: u64) -> ResultDb<()> {
+        while !buf.is_empty() {
+            match self.read(buf, offset)? {
+                0 => return Err(ErrDb::new_io_eof("failed to fill whole buffer")),
+                n => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
     fn write(&self, buf: &[u8]) -> ResultDb<usize>;
+    /// 在 offset 的位置写入数据，覆盖原有的数据，超过文件末尾时文件变大
+    fn write_at(&self, buf: &[u8], offset: u64) -> ResultDb<usize>;
+    /// 预先分配文件的大小，多出的部分填充 0，文件已经足够大时不做任何事情
+    fn preallocate(&self, size: u64) -> ResultDb<()>;
+    /// 把文件截断到 size，文件不大于 size 时不做任何事情
+    fn truncate(&self, size: u64) -> ResultDb<()>;
     fn sync(&self) -> ResultDb<()>;
     fn size(&self) -> u64;
+    /// 返回 offset 开始的 len 个字节，直接引用 IO 中的数据而不复制，如内存映射
+    /// 不支持或者数据不足时返回 None，由调用者使用 read 读取
+    fn read_shared(&self, _offset: u64, _len: usize) -> Option<Bytes> {
+        None
+    }
+}
+
+type CreateFn = dyn Fn(PathBuf) -> ResultDb<Box<dyn DbIo>> + Send + Sync;
+
+/// 用户自定义的 DbIo，按照文件名称创建，如加密、远程存储或者统计的 IO
+/// 同一个文件名称可能多次创建，需要返回同一个文件的数据
+#[derive(Clone)]
+pub struct DbIoFactory(Arc<CreateFn>);
+
+impl DbIoFactory {
+    pub fn new(create: impl Fn(PathBuf) -> ResultDb<Box<dyn DbIo>> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(create))
+    }
+
+    pub fn create(&self, file_name: PathBuf) -> ResultDb<Box<dyn DbIo>> {
+        (self.0)(file_name)
+    }
+}
+
+impl PartialEq for DbIoFactory {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
-pub fn new_dbio(file_name: std::path::PathBuf, io_type: IoType) -> Box<dyn DbIo> {
-    match io_type {
-        IoType::StdIo => Box::new(FileIo::new(file_name).unwrap()),
-        IoType::MemoryMap => Box::new(MMapIo::new(file_name).unwrap()),
+impl Debug for DbIoFactory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DbIoFactory")
     }
 }
+
+/// 按照 io_type 打开文件，打开失败时返回错误
+pub fn new_dbio(file_name: PathBuf, io_type: IoType) -> ResultDb<Box<dyn DbIo>> {
+    Ok(match io_type {
+        IoType::StdIo => Box::new(FileIo::new(file_name)?),
+        IoType::MemoryMap => Box::new(MMapIo::new(file_name)?),
+        IoType::Memory => Box::new(MemIo::new()),
+        IoType::ReadOnly => Box::new(FileIo::open_read_only(file_name)?),
+        IoType::Custom(factory) => factory.create(file_name)?,
+    })
+}
@@ -8,6 +8,5 @@ pub fn file_name(file: &str, name: &str, ex: &str) -> PathBuf {
 
 pub fn path_name(file: &str, name: &str) -> PathBuf {
     let file = file.replace(".", "_");
-    let path_buf = PathBuf::from(file).join(name);
-    path_buf
+    PathBuf::from(file).join(name)
 }
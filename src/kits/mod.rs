@@ -1,2 +1,17 @@
 pub mod com_names;
 pub mod rand_kv;
+
+#[cfg(test)]
+use crate::db::Config;
+
+/// 测试使用的配置，数据目录为 temp 下按照测试文件和名称生成的目录，重复运行测试时先删除旧的目录
+#[cfg(test)]
+pub fn test_config(file: &str, name: &str) -> Config {
+    let mut config = Config::default();
+    config.path_db = std::path::PathBuf::from("temp").join(com_names::path_name(file, name));
+    {
+        //repeat run test
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+    }
+    config
+}
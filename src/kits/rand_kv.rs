@@ -15,10 +15,10 @@ pub fn get_test_value(i: usize) -> Value {
 #[test]
 fn test_get_test_key_value() {
     for i in 0..=10 {
-        assert!(get_test_key(i).len() > 0)
+        assert!(!get_test_key(i).is_empty())
     }
 
     for i in 0..=10 {
-        assert!(get_test_value(i).len() > 0)
+        assert!(!get_test_value(i).is_empty())
     }
 }
@@ -1,3 +1,5 @@
+#![allow(clippy::module_inception, clippy::field_reassign_with_default)]
+
 pub mod db;
 pub mod index;
 pub mod io_db;
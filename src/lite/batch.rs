@@ -1,21 +1,31 @@
 use std::{
-    collections::HashMap,
-    sync::{atomic::Ordering, Arc},
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use bytes::{BufMut, Bytes, BytesMut};
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLockReadGuard};
 use prost::{decode_length_delimiter, encode_length_delimiter};
 
-use crate::db::{Db, ErrDb, LogDb, LogDbType, ResultDb, WriteBatchOptions};
-use crate::lite::LiteDb;
+use crate::db::{ErrDb, InvalidBatchReason, Key, LogDb, LogDbType, ResultDb, Value, WriteBatchOptions};
+use crate::lite::slow::SlowOp;
+use crate::lite::{ChangeKind, LiteDb};
 
-const TXN_FIN_KEY: &[u8] = "txn-fin".as_bytes();
+pub(crate) const TXN_FIN_KEY: &[u8] = "txn-fin".as_bytes();
 pub(crate) const NON_TRANSACTION_SEQ_NO: usize = 0;
 
 /// 批量写操作，保证原子性
+/// 提交之后可以继续使用，之后的 put 和 delete 属于下一次提交，使用新的事务序列号
+/// commit 持有暂存数据的锁直到提交完成，和提交并发的 put 和 delete 要么在这次提交中，要么在下一次提交中
 pub struct WriteBatch<'a> {
     pub(super) pending: Arc<Mutex<HashMap<Vec<u8>, LogDb>>>,
+    // 暂存数据的字节数，只在持有 pending 的锁时修改
+    pub(super) pending_bytes: AtomicUsize,
+    // 成功提交的次数，只在持有 pending 的锁时修改
+    pub(super) generation: AtomicUsize,
     // 暂存用户写入的数据
     pub(super) db: &'a LiteDb,
     pub(super) options: WriteBatchOptions,
@@ -36,10 +46,30 @@ impl WriteBatch<'_> {
         };
 
         let mut pending_writes = self.pending.lock();
+        // 超过批次的最大字节数时不暂存，之前暂存的数据不受影响
+        let old_bytes = pending_writes.get(key.as_ref()).map_or(0, pending_size);
+        let bytes = self.pending_bytes.load(Ordering::SeqCst) - old_bytes + pending_size(&log_db);
+        if bytes > self.options.max_batch_bytes {
+            return Err(ErrDb::InvalidBatch(InvalidBatchReason::TooManyBytes));
+        }
         pending_writes.insert(key.to_vec(), log_db);
+        self.pending_bytes.store(bytes, Ordering::SeqCst);
         Ok(())
     }
 
+    /// 暂存数据的 key 和 value 的字节数
+    pub fn pending_bytes(&self) -> usize {
+        let _pending_writes = self.pending.lock();
+        self.pending_bytes.load(Ordering::SeqCst)
+    }
+
+    /// 成功提交了数据的次数，没有暂存数据的提交不计算在内
+    /// 暂存的数据属于第 generation + 1 次提交
+    pub fn generation(&self) -> usize {
+        let _pending_writes = self.pending.lock();
+        self.generation.load(Ordering::SeqCst)
+    }
+
     /// 批量操作删除数据
     pub fn delete(&self, key: Bytes) -> ResultDb<()> {
         if key.is_empty() {
@@ -48,10 +78,10 @@ impl WriteBatch<'_> {
 
         let mut pending_writes = self.pending.lock();
         // 如果数据不存在则直接返回
-        let index_pos = self.db.index.get(key.to_vec());
+        let index_pos = self.db.index.try_get(key.to_vec())?;
         if index_pos.is_none() {
-            if pending_writes.contains_key(&key.to_vec()) {
-                pending_writes.remove(&key.to_vec());
+            if let Some(log_db) = pending_writes.remove(key.as_ref()) {
+                self.pending_bytes.fetch_sub(pending_size(&log_db), Ordering::SeqCst);
             }
             return Ok(());
         }
@@ -59,77 +89,181 @@ impl WriteBatch<'_> {
         // 暂存数据
         let log_db = LogDb {
             key: key.to_vec(),
-            value: Default::default(),
+            value: self.db.config.tombstone_value(),
             rec_type: LogDbType::DELETED,
         };
+        let old_bytes = pending_writes.get(key.as_ref()).map_or(0, pending_size);
+        let bytes = self.pending_bytes.load(Ordering::SeqCst) - old_bytes + pending_size(&log_db);
+        if bytes > self.options.max_batch_bytes {
+            return Err(ErrDb::InvalidBatch(InvalidBatchReason::TooManyBytes));
+        }
         pending_writes.insert(key.to_vec(), log_db);
+        self.pending_bytes.store(bytes, Ordering::SeqCst);
         Ok(())
     }
 
     /// 提交数据，将数据写到文件当中，并更新内存索引
     pub fn commit(&self) -> ResultDb<()> {
         let mut pending_writes = self.pending.lock();
-        if pending_writes.len() == 0 {
+        if pending_writes.is_empty() {
             return Ok(());
         }
-        if pending_writes.len() > self.options.max_batch_num {
-            return Err(ErrDb::InvalidBatch);
-        }
-
         // 加锁保证事务提交串行化
         let _lock = self.db.batch_commit_lock.lock();
+        self.commit_pending(&mut pending_writes, self.db.swap_lock.read())
+    }
+
+    // 和 commit 一样，调用者已经持有 batch_commit_lock
+    pub(super) fn commit_locked(&self) -> ResultDb<()> {
+        let mut pending_writes = self.pending.lock();
+        self.commit_pending(&mut pending_writes, self.db.swap_lock.read())
+    }
+
+    // 调用者已经持有 batch_commit_lock 和 swap_lock 的读锁，写入和更新索引之间不能替换数据文件
+    pub(super) fn commit_pending(&self, pending_writes: &mut HashMap<Vec<u8>, LogDb>, swap_lock: RwLockReadGuard<'_, ()>) -> ResultDb<()> {
+        if pending_writes.is_empty() {
+            return Ok(());
+        }
+        let _slow = SlowOp::start(&self.db.config, "commit");
+        if pending_writes.len() > self.options.max_batch_num {
+            return Err(ErrDb::InvalidBatch(InvalidBatchReason::TooManyRecords));
+        }
 
         // 获取全局事务序列号
         let seq_no = self.db.seq_no.fetch_add(1, Ordering::SeqCst);
 
-        let mut positions = HashMap::new();
-        // 开始写数据到数据文件当中
-        for (_, item) in pending_writes.iter() {
-            let mut log_db = LogDb {
+        // 批次的数据和最后一条标识事务完成的数据
+        let mut log_dbs: Vec<LogDb> = pending_writes
+            .values()
+            .map(|item| LogDb {
                 key: log_db_key_with_seq(item.key.clone(), seq_no),
                 value: item.value.clone(),
                 rec_type: item.rec_type,
-            };
-
-            let pos = self.db.append_log_db(&mut log_db)?;
-            positions.insert(item.key.clone(), pos);
-        }
-
-        // 写最后一条标识事务完成的数据
-        let mut finish_log_db = LogDb {
+            })
+            .collect();
+        log_dbs.push(LogDb {
             key: log_db_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no),
             value: Default::default(),
             rec_type: LogDbType::TXNFINISHED,
-        };
-        self.db.append_log_db(&mut finish_log_db)?;
+        });
+
+        // 开始写数据到数据文件当中，批次写完之后再统一持久化
+        // 写入失败时活跃文件回滚到批次开始的位置，暂存的数据保留，可以重试
+        // 没有记录留在数据文件中时序列号还给下一次提交，否则重试时使用新的序列号
+        let all_positions = self.db.append_txn_log_dbs(&log_dbs, seq_no)?;
+
+        let mut positions = HashMap::new();
+        // 批次写入过的数据文件，批次中间可能发生活跃文件的切换
+        let mut file_ids = BTreeSet::new();
+        let mut written = 0;
+        for (key, pos) in pending_writes.keys().zip(all_positions.iter()) {
+            positions.insert(key.clone(), *pos);
+        }
+        for pos in all_positions.iter() {
+            file_ids.insert(pos.file_id);
+            written += pos.size as usize;
+        }
+        // 事务完成的标识 merge 时不会重写
+        let fin_pos = all_positions.last().unwrap();
+        self.db.reclaim_size.fetch_add(fin_pos.size as usize, Ordering::SeqCst);
 
-        // 如果配置了持久化，则 sync
+        // 如果配置了持久化，则持久化批次写入过的所有文件，包括事务完成的标识
+        // 否则按照数据库的持久化策略，整个批次最多持久化一次
         if self.options.sync_writes {
-            self.db.sync()?;
+            self.db.sync_files(&file_ids)?;
+        } else {
+            self.db.sync_by_policy(written)?;
         }
 
         // 数据全部写完之后更新内存索引
         for (_, item) in pending_writes.iter() {
             if item.rec_type == LogDbType::NORMAL {
                 let log_db_pos = positions.get(&item.key).unwrap();
-                if let Some(old_pos) = self.db.index.put(item.key.clone(), *log_db_pos) {
+                self.db.metrics.on_put(log_db_pos.size as usize);
+                if let Some(old_pos) = self.db.index.try_put(item.key.clone(), *log_db_pos)? {
                     self.db.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
                 }
             }
             if item.rec_type == LogDbType::DELETED {
-                if let Some(old_pos) = self.db.index.delete(item.key.clone()) {
-                    self.db.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+                // 删除标记本身和被删除的数据都可以回收
+                let mut size = positions.get(&item.key).unwrap().size;
+                if let Some(old_pos) = self.db.index.try_delete(item.key.clone())? {
+                    size += old_pos.size;
                 }
+                self.db.reclaim_size.fetch_add(size as usize, Ordering::SeqCst);
             }
         }
 
-        // 清空暂存数据
+        for key in pending_writes.keys() {
+            self.db.value_cache.remove(key);
+            self.db.record_write(key);
+        }
+        drop(swap_lock);
+
+        // 索引更新完成之后通知监听者
+        for (_, item) in pending_writes.iter() {
+            let kind = if item.rec_type == LogDbType::DELETED {
+                ChangeKind::Delete
+            } else {
+                ChangeKind::Put
+            };
+            self.db.notify_change(&item.key, kind);
+        }
+
+        // 清空暂存数据，之后的写入属于下一次提交
         pending_writes.clear();
+        self.pending_bytes.store(0, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
 
         Ok(())
     }
 }
 
+impl WriteBatchOptions {
+    /// 根据数据库的配置生成批量配置，提交时是否 sync 和数据库的 sync_writes 一致
+    /// 数据库不支持批量操作时（BTree 索引下重新打开有数据的数据库），直接返回错误，而不是等到 new_write_batch 时才失败
+    pub fn for_engine(db: &LiteDb) -> ResultDb<Self> {
+        db.check_write_batch()?;
+        Ok(WriteBatchOptions {
+            sync_writes: db.config.sync_writes,
+            ..Default::default()
+        })
+    }
+}
+
+impl LiteDb {
+    /// 批量写入数据并原子提交，使用默认的批量配置
+    pub fn put_batch(&self, items: &[(Key, Value)]) -> ResultDb<()> {
+        let options = WriteBatchOptions::default();
+        if items.len() > options.max_batch_num {
+            return Err(ErrDb::InvalidBatch(InvalidBatchReason::TooManyRecords));
+        }
+        let wb = self.new_write_batch(options)?;
+        for (key, value) in items.iter() {
+            wb.put(key.clone(), value.clone())?;
+        }
+        wb.commit()
+    }
+
+    /// 批量删除数据并原子提交，使用默认的批量配置
+    pub fn delete_batch(&self, keys: &[Key]) -> ResultDb<()> {
+        let options = WriteBatchOptions::default();
+        if keys.len() > options.max_batch_num {
+            return Err(ErrDb::InvalidBatch(InvalidBatchReason::TooManyRecords));
+        }
+        let wb = self.new_write_batch(options)?;
+        for key in keys.iter() {
+            wb.delete(key.clone())?;
+        }
+        wb.commit()
+    }
+}
+
+// 暂存数据计入批次字节数的大小
+fn pending_size(log_db: &LogDb) -> usize {
+    log_db.key.len() + log_db.value.len()
+}
+
 // 编码 seq no 和 key
 pub(crate) fn log_db_key_with_seq(key: Vec<u8>, seq_no: usize) -> Vec<u8> {
     let mut enc_key = BytesMut::new();
@@ -138,23 +272,244 @@ pub(crate) fn log_db_key_with_seq(key: Vec<u8>, seq_no: usize) -> Vec<u8> {
     enc_key.to_vec()
 }
 
-// 解析 LogDb 的 key，拿到实际的 key 和 seq no
-pub(crate) fn parse_log_db_key(key: Vec<u8>) -> (Vec<u8>, usize) {
+// 解析 LogDb 的 key，拿到实际的 key 和 seq no，seq no 无法解析时返回错误
+pub(crate) fn parse_log_db_key(key: Vec<u8>) -> ResultDb<(Vec<u8>, usize)> {
     let mut buf = BytesMut::new();
     buf.put_slice(&key);
-    let seq_no = decode_length_delimiter(&mut buf).unwrap();
-    (buf.to_vec(), seq_no)
+    let seq_no = decode_length_delimiter(&mut buf).map_err(|e| ErrDb::CorruptLayout(format!("invalid seq no in the key: {}", e)))?;
+    Ok((buf.to_vec(), seq_no))
 }
 
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
-    use std::sync::atomic::Ordering;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use bytes::Bytes;
 
-    use crate::db::{Closer, Config, ErrDb, Getter, WriteBatchOptions};
+    use crate::db::{Adder, Closer, Config, ErrDb, FileDb, Getter, IndexType, InvalidBatchReason, Key, LogDb, LogDbType, Value, WriteBatchOptions};
+    use crate::io_db::{DbIoFactory, FileIo, HookIo};
     use crate::kits;
+    use crate::lite::batch::{log_db_key_with_seq, parse_log_db_key, TXN_FIN_KEY};
     use crate::lite::LiteDb;
 
+    // 写入若干次之后磁盘写满的 DbIo，写满时只写入一半的数据
+    fn fail_after_io(inner: FileIo, writes_left: Arc<AtomicUsize>) -> HookIo {
+        HookIo::new(inner).on_write(move |io, buf, offset| {
+            if writes_left.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_err() {
+                io.write_at(&buf[..buf.len() / 2], offset)?;
+                return Err(ErrDb::DiskFull);
+            }
+            io.write_at(buf, offset)
+        })
+    }
+
+    #[test]
+    fn test_write_batch_rollback() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-rollback");
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&kits::rand_kv::get_test_key(0), &kits::rand_kv::get_test_value(0)).unwrap();
+
+            let writes_left = Arc::new(AtomicUsize::new(0));
+            {
+                let mut active_file = lite_db.active_file.write();
+                let inner = FileIo::new(active_file.get_file_name().to_path_buf()).unwrap();
+                active_file.set_db_io(Box::new(fail_after_io(inner, writes_left.clone())));
+            }
+            let file_size = lite_db.active_file.read().file_size();
+            let seq_no = lite_db.seq_no();
+
+            // 批次只写入了一半，活跃文件回滚到提交之前的大小，序列号不变
+            let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            for i in 1..=10 {
+                wb.put(kits::rand_kv::get_test_key(i), kits::rand_kv::get_test_value(i)).unwrap();
+            }
+            assert_eq!(ErrDb::DiskFull, wb.commit().err().unwrap());
+            assert_eq!(file_size, lite_db.active_file.read().file_size());
+            assert_eq!(file_size, lite_db.active_file.read().get_write_off());
+            assert_eq!(seq_no, lite_db.seq_no());
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&kits::rand_kv::get_test_key(1)).err().unwrap());
+
+            // 暂存的数据还在，重试时使用同一个序列号
+            writes_left.store(usize::MAX, Ordering::SeqCst);
+            wb.commit().unwrap();
+            assert_eq!(seq_no + 1, lite_db.seq_no());
+            for i in 0..=10 {
+                assert_eq!(kits::rand_kv::get_test_value(i), lite_db.get(&kits::rand_kv::get_test_key(i)).unwrap());
+            }
+        }
+
+        // 重新打开之后只有提交成功的数据
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let (_, records) = lite_db.load_index_from_data_files().unwrap();
+        assert_eq!(1 + 10 + 1, records);
+        for i in 0..=10 {
+            assert_eq!(kits::rand_kv::get_test_value(i), lite_db.get(&kits::rand_kv::get_test_key(i)).unwrap());
+        }
+
+        drop(lite_db);
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_rollback_after_rotate() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-rollback-after-rotate");
+        config.file_size_db = 4 * 1024;
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        // 打开 armed 之后创建的文件写入时失败，直到 writes_left 被重新设置
+        let armed = Arc::new(AtomicBool::new(false));
+        let writes_left = Arc::new(AtomicUsize::new(0));
+        let (factory_armed, factory_writes_left) = (armed.clone(), writes_left.clone());
+        config.io_factory = Some(DbIoFactory::new(move |file_name| {
+            let writes_left = match factory_armed.load(Ordering::SeqCst) {
+                true => factory_writes_left.clone(),
+                false => Arc::new(AtomicUsize::new(usize::MAX)),
+            };
+            Ok(Box::new(fail_after_io(FileIo::new(file_name)?, writes_left)))
+        }));
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&kits::rand_kv::get_test_key(0), &kits::rand_kv::get_test_value(0)).unwrap();
+            let file_id = lite_db.active_file.read().get_file_id();
+            let seq_no = lite_db.seq_no();
+
+            // 批次的前一部分写入之后切换了活跃文件，在新的活跃文件中写入失败
+            armed.store(true, Ordering::SeqCst);
+            let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            for i in 1..=100 {
+                wb.put(kits::rand_kv::get_test_key(i), kits::rand_kv::get_test_value(i)).unwrap();
+            }
+            assert_eq!(ErrDb::DiskFull, wb.commit().err().unwrap());
+            assert!(lite_db.active_file.read().get_file_id() > file_id);
+            assert_eq!(0, lite_db.active_file.read().get_write_off());
+            // 封存的文件中留下了部分记录，序列号不能再使用
+            assert_eq!(seq_no + 1, lite_db.seq_no());
+            armed.store(false, Ordering::SeqCst);
+            writes_left.store(usize::MAX, Ordering::SeqCst);
+
+            // 之后的批次使用新的序列号，不会让之前留下的记录生效
+            let wb2 = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            wb2.put(kits::rand_kv::get_test_key(1000), kits::rand_kv::get_test_value(1000)).unwrap();
+            wb2.commit().unwrap();
+            assert_eq!(seq_no + 2, lite_db.seq_no());
+        }
+
+        // 重新打开之后失败的批次中的数据都不存在
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(kits::rand_kv::get_test_value(0), lite_db.get(&kits::rand_kv::get_test_key(0)).unwrap());
+        assert_eq!(kits::rand_kv::get_test_value(1000), lite_db.get(&kits::rand_kv::get_test_key(1000)).unwrap());
+        for i in 1..=100 {
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&kits::rand_kv::get_test_key(i)).err().unwrap());
+        }
+
+        drop(lite_db);
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_single_write() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-single-write");
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        lite_db.add(&kits::rand_kv::get_test_key(0), &kits::rand_kv::get_test_value(0)).unwrap();
+
+        let writes_left = Arc::new(AtomicUsize::new(usize::MAX));
+        {
+            let mut active_file = lite_db.active_file.write();
+            let inner = FileIo::new(active_file.get_file_name().to_path_buf()).unwrap();
+            active_file.set_db_io(Box::new(fail_after_io(inner, writes_left.clone())));
+        }
+        let write_off = lite_db.active_file.read().get_write_off();
+
+        // 整个批次包括事务完成的标识只写入一次
+        let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+        for i in 1..=1000 {
+            wb.put(kits::rand_kv::get_test_key(i), kits::rand_kv::get_test_value(i)).unwrap();
+        }
+        wb.commit().unwrap();
+        assert_eq!(1, usize::MAX - writes_left.load(Ordering::SeqCst));
+
+        // 每条记录的位置按照偏移计算，和逐条读取的一致
+        let active_file = lite_db.active_file.read();
+        let mut offset = write_off;
+        let mut positions = Vec::new();
+        while let Ok(re) = active_file.read_log_db(offset) {
+            positions.push((offset, re.size));
+            offset += re.size as u64;
+        }
+        assert_eq!(1000 + 1, positions.len());
+        assert_eq!(offset, active_file.get_write_off());
+        drop(active_file);
+        for i in 1..=1000 {
+            let pos = lite_db.index.get(kits::rand_kv::get_test_key(i).to_vec()).unwrap();
+            assert!(positions.contains(&(pos.offset, pos.size as usize)));
+            assert_eq!(kits::rand_kv::get_test_value(i), lite_db.get(&kits::rand_kv::get_test_key(i)).unwrap());
+        }
+
+        drop(lite_db);
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_delete_prefix_single_write() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-delete-prefix");
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            lite_db.add(&Bytes::from(format!("p:{}", i)), &kits::rand_kv::get_test_value(i)).unwrap();
+        }
+        lite_db.add(&Bytes::from("q:0"), &kits::rand_kv::get_test_value(0)).unwrap();
+
+        let writes_left = Arc::new(AtomicUsize::new(0));
+        {
+            let mut active_file = lite_db.active_file.write();
+            let inner = FileIo::new(active_file.get_file_name().to_path_buf()).unwrap();
+            active_file.set_db_io(Box::new(fail_after_io(inner, writes_left.clone())));
+        }
+        let write_off = lite_db.active_file.read().get_write_off();
+        let seq_no = lite_db.seq_no();
+
+        // 写入失败时回滚所有的删除标记，没有 key 被删除，序列号还给下一次提交
+        assert_eq!(ErrDb::DiskFull, lite_db.delete_prefix(b"p:").err().unwrap());
+        assert_eq!(write_off, lite_db.active_file.read().get_write_off());
+        assert_eq!(seq_no, lite_db.seq_no());
+        for i in 0..100 {
+            assert_eq!(kits::rand_kv::get_test_value(i), lite_db.get(&Bytes::from(format!("p:{}", i))).unwrap());
+        }
+
+        // 所有的删除标记和事务完成的标识只写入一次
+        writes_left.store(usize::MAX, Ordering::SeqCst);
+        assert_eq!(100, lite_db.delete_prefix(b"p:").unwrap());
+        assert_eq!(1, usize::MAX - writes_left.load(Ordering::SeqCst));
+        assert_eq!(seq_no + 1, lite_db.seq_no());
+        drop(lite_db);
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&Bytes::from(format!("p:{}", i))).err().unwrap());
+        }
+        assert_eq!(kits::rand_kv::get_test_value(0), lite_db.get(&Bytes::from("q:0")).unwrap());
+
+        drop(lite_db);
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_parse_log_db_key() {
+        let key = log_db_key_with_seq(b"key".to_vec(), 300);
+        assert_eq!((b"key".to_vec(), 300), parse_log_db_key(key).unwrap());
+        // seq no 的编码损坏时返回错误
+        assert!(matches!(parse_log_db_key(vec![0xff; 11]), Err(ErrDb::CorruptLayout(_))));
+        assert!(matches!(parse_log_db_key(Vec::new()), Err(ErrDb::CorruptLayout(_))));
+    }
+
     #[test]
     fn test_write_batch_1() {
         let mut config = Config::default();
@@ -180,7 +535,7 @@ mod tests {
         assert!(res2.is_ok());
 
         // 验证事务序列号
-        let seq_no = wb.db.seq_no.load(Ordering::SeqCst);
+        let seq_no = wb.db.seq_no();
         assert_eq!(2, seq_no);
 
         // 删除测试的文件夹
@@ -217,13 +572,287 @@ mod tests {
         // assert_eq!(2, keys.ok().unwrap().len());
 
         // 验证事务序列号
-        let seq_no = lite_db2.seq_no.load(Ordering::SeqCst);
+        let seq_no = lite_db2.seq_no();
         assert_eq!(3, seq_no);
 
         // 删除测试的文件夹
         std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
+    #[test]
+    fn test_write_batch_max_bytes() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-max-bytes");
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+        let mut options = WriteBatchOptions::default();
+        options.max_batch_bytes = 100;
+        let wb = lite_db.new_write_batch(options).expect("failed to create write batch");
+
+        // 刚好达到最大字节数
+        wb.put(Bytes::from("key-1"), Bytes::from(vec![1u8; 45])).unwrap();
+        wb.put(Bytes::from("key-2"), Bytes::from(vec![2u8; 45])).unwrap();
+        assert_eq!(100, wb.pending_bytes());
+
+        // 超过最大字节数，已经暂存的数据不受影响
+        let res = wb.put(Bytes::from("key-3"), Bytes::from(vec![3u8; 1]));
+        assert_eq!(ErrDb::InvalidBatch(InvalidBatchReason::TooManyBytes), res.err().unwrap());
+        assert_eq!(100, wb.pending_bytes());
+
+        // 覆盖已经暂存的 key，只计算新的 value
+        wb.put(Bytes::from("key-2"), Bytes::from(vec![2u8; 10])).unwrap();
+        assert_eq!(65, wb.pending_bytes());
+        let res = wb.put(Bytes::from("key-1"), Bytes::from(vec![1u8; 81]));
+        assert_eq!(ErrDb::InvalidBatch(InvalidBatchReason::TooManyBytes), res.err().unwrap());
+        wb.put(Bytes::from("key-3"), Bytes::from(vec![3u8; 30])).unwrap();
+        assert_eq!(100, wb.pending_bytes());
+
+        wb.commit().unwrap();
+        assert_eq!(0, wb.pending_bytes());
+        assert_eq!(Bytes::from(vec![1u8; 45]), lite_db.get(&Bytes::from("key-1")).unwrap());
+        assert_eq!(Bytes::from(vec![2u8; 10]), lite_db.get(&Bytes::from("key-2")).unwrap());
+        assert_eq!(Bytes::from(vec![3u8; 30]), lite_db.get(&Bytes::from("key-3")).unwrap());
+
+        // 单条数据超过最大字节数
+        let res = wb.put(Bytes::from("key-4"), Bytes::from(vec![4u8; 100]));
+        assert_eq!(ErrDb::InvalidBatch(InvalidBatchReason::TooManyBytes), res.err().unwrap());
+        assert_eq!(0, wb.pending_bytes());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_invalid_reason() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-invalid-reason");
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+            // 提交时数据的条数超过最大值
+            let mut options = WriteBatchOptions::default();
+            options.max_batch_num = 2;
+            let wb = lite_db.new_write_batch(options).expect("failed to create write batch");
+            for i in 0..3 {
+                wb.put(kits::rand_kv::get_test_key(i), kits::rand_kv::get_test_value(i)).unwrap();
+            }
+            assert_eq!(ErrDb::InvalidBatch(InvalidBatchReason::TooManyRecords), wb.commit().err().unwrap());
+
+            // 暂存的字节数超过最大值
+            let mut options = WriteBatchOptions::default();
+            options.max_batch_bytes = 10;
+            let wb = lite_db.new_write_batch(options).expect("failed to create write batch");
+            let res = wb.put(Bytes::from("key"), Bytes::from(vec![0u8; 10]));
+            assert_eq!(ErrDb::InvalidBatch(InvalidBatchReason::TooManyBytes), res.err().unwrap());
+
+            lite_db.add(&kits::rand_kv::get_test_key(1), &kits::rand_kv::get_test_value(1)).unwrap();
+        }
+
+        // BTree 索引下重新打开已经有数据的数据库，没有事务序列号
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(IndexType::BTree, config.index_type);
+        let res = lite_db.new_write_batch(WriteBatchOptions::default());
+        assert_eq!(ErrDb::InvalidBatch(InvalidBatchReason::SeqNoUnavailable), res.err().unwrap());
+        assert_eq!(
+            "invalid batch: seq no unavailable for a reopened BTree index, use the BPlusTree index for write batches",
+            ErrDb::InvalidBatch(InvalidBatchReason::SeqNoUnavailable).to_string()
+        );
+        // for_engine 提前返回错误
+        assert_eq!(
+            ErrDb::InvalidBatch(InvalidBatchReason::SeqNoUnavailable),
+            WriteBatchOptions::for_engine(&lite_db).err().unwrap()
+        );
+
+        drop(lite_db);
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_options_for_engine() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-options-for-engine");
+        config.sync_writes = false;
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        {
+            // 新建的数据库可以使用批量操作
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let options = WriteBatchOptions::for_engine(&lite_db).expect("failed to get options");
+            assert!(!options.sync_writes);
+            assert_eq!(WriteBatchOptions::default().max_batch_num, options.max_batch_num);
+            let wb = lite_db.new_write_batch(options).expect("failed to create write batch");
+            wb.put(kits::rand_kv::get_test_key(1), kits::rand_kv::get_test_value(1)).unwrap();
+            wb.commit().unwrap();
+        }
+
+        // BTree 索引下重新打开有数据的数据库，创建批次之前就返回错误
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let res = WriteBatchOptions::for_engine(&lite_db);
+        assert_eq!(ErrDb::InvalidBatch(InvalidBatchReason::SeqNoUnavailable), res.err().unwrap());
+        drop(lite_db);
+
+        // B+树索引下重新打开可以使用批量操作
+        config.index_type = IndexType::BPlusTree;
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&kits::rand_kv::get_test_key(1), &kits::rand_kv::get_test_value(1)).unwrap();
+        }
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let options = WriteBatchOptions::for_engine(&lite_db).expect("failed to get options");
+        assert!(lite_db.new_write_batch(options).is_ok());
+
+        drop(lite_db);
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_put_delete_batch() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-put-delete");
+        config.file_size_db = 64 * 1024 * 1024;
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+        // 超过批次的最大数量，所有的数据都不可见
+        let max = WriteBatchOptions::default().max_batch_num;
+        let items: Vec<(Key, Value)> = (0..=max).map(|i| (kits::rand_kv::get_test_key(i), kits::rand_kv::get_test_value(i))).collect();
+        let res1 = lite_db.put_batch(&items);
+        assert_eq!(ErrDb::InvalidBatch(InvalidBatchReason::TooManyRecords), res1.err().unwrap());
+        for (key, _) in items.iter() {
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(key).err().unwrap());
+        }
+
+        // 提交之后所有的数据都可见
+        let res2 = lite_db.put_batch(&items[..100]);
+        assert!(res2.is_ok());
+        for (key, value) in items[..100].iter() {
+            assert_eq!(*value, lite_db.get(key).unwrap());
+        }
+
+        let keys: Vec<Key> = items[..50].iter().map(|(k, _)| k.clone()).collect();
+        let res3 = lite_db.delete_batch(&keys);
+        assert!(res3.is_ok());
+        for key in keys.iter() {
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(key).err().unwrap());
+        }
+        for (key, value) in items[50..100].iter() {
+            assert_eq!(*value, lite_db.get(key).unwrap());
+        }
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_rollover() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-rollover");
+        config.file_size_db = 1024;
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+
+        let batch_items = |start: usize| -> Vec<(Key, Value)> {
+            (start..start + 50)
+                .map(|i| (kits::rand_kv::get_test_key(i), kits::rand_kv::get_test_value(i)))
+                .collect()
+        };
+        let items1 = batch_items(0);
+        let items2 = batch_items(100);
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.put_batch(&items1).unwrap();
+            // 批次跨越了多个数据文件
+            assert!(lite_db.active_file.read().get_file_id() > 1);
+
+            let before = lite_db.active_file.read().get_file_id();
+            lite_db.put_batch(&items2).unwrap();
+            assert!(lite_db.active_file.read().get_file_id() > before);
+        }
+
+        // 去掉第二个批次的事务完成标识，模拟提交过程中崩溃
+        {
+            let seq_no = 2;
+            let finish_log_db = LogDb {
+                key: log_db_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no),
+                value: Default::default(),
+                rec_type: LogDbType::TXNFINISHED,
+            };
+            let (_, last_file) = FileDb::find_data_files(&config.path_db).unwrap().pop().unwrap();
+            let file = std::fs::OpenOptions::new().write(true).open(last_file).unwrap();
+            let len = file.metadata().unwrap().len();
+            file.set_len(len - finish_log_db.encode().len() as u64).unwrap();
+        }
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for (key, value) in items1.iter() {
+                assert_eq!(*value, lite_db.get(key).unwrap());
+            }
+            for (key, _) in items2.iter() {
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(key).err().unwrap());
+            }
+        }
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_reuse_after_commit() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-batch-reuse");
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+        let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+        wb.put(kits::rand_kv::get_test_key(1), kits::rand_kv::get_test_value(1)).unwrap();
+        wb.commit().unwrap();
+        assert_eq!(1, wb.generation());
+        assert_eq!(0, wb.pending_bytes());
+
+        // 提交之后的写入属于下一次提交，提交之前不可见
+        wb.put(kits::rand_kv::get_test_key(2), kits::rand_kv::get_test_value(2)).unwrap();
+        wb.delete(kits::rand_kv::get_test_key(1)).unwrap();
+        assert_eq!(ErrDb::NotFindKey, lite_db.get(&kits::rand_kv::get_test_key(2)).err().unwrap());
+        assert_eq!(kits::rand_kv::get_test_value(1), lite_db.get(&kits::rand_kv::get_test_key(1)).unwrap());
+        wb.commit().unwrap();
+        assert_eq!(2, wb.generation());
+        assert_eq!(kits::rand_kv::get_test_value(2), lite_db.get(&kits::rand_kv::get_test_key(2)).unwrap());
+        assert_eq!(ErrDb::NotFindKey, lite_db.get(&kits::rand_kv::get_test_key(1)).err().unwrap());
+        // 没有暂存数据的提交不增加次数
+        wb.commit().unwrap();
+        assert_eq!(2, wb.generation());
+
+        // 和提交并发的写入不会丢失，要么在这次提交中，要么在下一次提交中
+        let done = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let (wb, done) = (&wb, &done);
+                s.spawn(move || {
+                    for i in 0..100 {
+                        let key = 100 + t * 100 + i;
+                        wb.put(kits::rand_kv::get_test_key(key), kits::rand_kv::get_test_value(key)).unwrap();
+                    }
+                    done.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            while done.load(Ordering::SeqCst) < 4 {
+                wb.commit().unwrap();
+            }
+        });
+        wb.commit().unwrap();
+        assert_eq!(0, wb.pending_bytes());
+        assert!(wb.generation() > 2);
+        for key in 100..500 {
+            assert_eq!(kits::rand_kv::get_test_value(key), lite_db.get(&kits::rand_kv::get_test_key(key)).unwrap());
+        }
+
+        drop(wb);
+        drop(lite_db);
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
     // #[test]
     // fn test_write_batch_3() {
     //     let mut opts = Options::default();
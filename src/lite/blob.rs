@@ -0,0 +1,221 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::db::{ChecksumKind, Config, ErrDb, FileDb, IoType, LogDb, LogDbPos, LogDbType, ResultDb};
+
+/// 保存大 value 的 blob 文件，数据文件中的记录只保存 blob 的位置
+/// 只追加写入，merge 时把仍然被引用的 blob 复制到新的 blob 文件中，再删除旧的 blob 文件
+pub(crate) struct BlobFiles {
+    dir_path: PathBuf,
+    file_size: u64,
+    threshold: usize,
+    checksum: ChecksumKind,
+    verify_crc: bool,
+    io_type: IoType,
+    inner: RwLock<BlobFilesInner>,
+}
+
+struct BlobFilesInner {
+    // 当前写入的 blob 文件 id，第一次写入时才创建文件
+    active_id: u32,
+    files: HashMap<u32, FileDb>,
+}
+
+impl BlobFiles {
+    pub(crate) fn open(config: &Config) -> ResultDb<BlobFiles> {
+        let blob_files = BlobFiles {
+            dir_path: config.path_db.clone(),
+            file_size: config.file_size_db,
+            threshold: match config.in_memory {
+                true => 0,
+                false => config.large_value_threshold,
+            },
+            checksum: config.checksum,
+            verify_crc: config.verify_crc_on_read,
+            io_type: config.data_io_type(),
+            inner: RwLock::new(BlobFilesInner {
+                active_id: 0,
+                files: HashMap::new(),
+            }),
+        };
+        if !config.in_memory {
+            blob_files.reload()?;
+        }
+        Ok(blob_files)
+    }
+
+    /// 是否需要把 value 写到 blob 文件中
+    pub(crate) fn accepts(&self, log_db: &LogDb) -> bool {
+        self.threshold > 0 && log_db.rec_type == LogDbType::NORMAL && log_db.value.len() > self.threshold
+    }
+
+    /// 追加写入 blob，返回 blob 在 blob 文件中的位置
+    pub(crate) fn write(&self, key: &[u8], value: &[u8]) -> ResultDb<LogDbPos> {
+        let blob = LogDb {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            rec_type: LogDbType::NORMAL,
+        };
+        let enc_blob = blob.encode_with(self.checksum);
+
+        let mut inner = self.inner.write();
+        let active_id = inner.active_id;
+        if let Some(active_file) = inner.files.get(&active_id) {
+            if active_file.get_write_off() > 0 && active_file.get_write_off() + enc_blob.len() as u64 > self.file_size {
+                active_file.sync()?;
+                inner.active_id += 1;
+            }
+        }
+        let active_id = inner.active_id;
+        if let Entry::Vacant(entry) = inner.files.entry(active_id) {
+            entry.insert(self.open_blob_file(&self.dir_path, active_id)?);
+        }
+
+        let active_file = inner.files.get(&active_id).unwrap();
+        let offset = active_file.get_write_off();
+        active_file.write(&enc_blob)?;
+        Ok(LogDbPos {
+            file_id: active_id,
+            offset,
+            size: enc_blob.len() as u32,
+        })
+    }
+
+    /// 读取 blob 的 value
+    pub(crate) fn read(&self, blob_pos: &LogDbPos) -> ResultDb<Bytes> {
+        let inner = self.inner.read();
+        match inner.files.get(&blob_pos.file_id) {
+            Some(blob_file) => Ok(blob_file.read_log_db(blob_pos.offset)?.log_db.value.into()),
+            None => Err(ErrDb::new_io_file_not_find("")),
+        }
+    }
+
+    pub(crate) fn sync(&self) -> ResultDb<()> {
+        let inner = self.inner.read();
+        match inner.files.get(&inner.active_id) {
+            Some(active_file) => active_file.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// 之后的 blob 写入新的文件，返回新的 blob 文件 id，小于这个 id 的 blob 文件都可以参与 merge
+    pub(crate) fn rotate(&self) -> ResultDb<u32> {
+        let mut inner = self.inner.write();
+        let active_id = inner.active_id;
+        if let Some(active_file) = inner.files.get(&active_id) {
+            if active_file.get_write_off() > 0 {
+                active_file.sync()?;
+                inner.active_id += 1;
+            }
+        }
+        Ok(inner.active_id)
+    }
+
+    /// 重新打开目录中所有的 blob 文件，merge 替换了 blob 文件之后调用
+    pub(crate) fn reload(&self) -> ResultDb<()> {
+        let mut inner = self.inner.write();
+        inner.files.clear();
+        inner.active_id = 0;
+        for (file_id, file_name) in FileDb::find_blob_files(&self.dir_path)? {
+            let mut blob_file = FileDb::new_with_file_name(file_name, file_id, self.io_type.clone())?;
+            blob_file.set_verify_crc(self.verify_crc);
+            inner.files.insert(file_id, blob_file);
+            inner.active_id = file_id;
+        }
+        Ok(())
+    }
+
+    /// 删除所有的 blob 文件
+    pub(crate) fn clear(&self) -> ResultDb<()> {
+        let mut inner = self.inner.write();
+        for (_, blob_file) in inner.files.drain() {
+            let file_name = blob_file.get_file_name().to_path_buf();
+            drop(blob_file);
+            fs::remove_file(file_name)?;
+        }
+        inner.active_id = 0;
+        Ok(())
+    }
+
+    pub(crate) fn open_blob_file(&self, dir_path: &Path, file_id: u32) -> ResultDb<FileDb> {
+        let mut blob_file = FileDb::new_with_file_name(FileDb::get_blob_file_name(dir_path, file_id), file_id, self.io_type.clone())?;
+        blob_file.set_verify_crc(self.verify_crc);
+        Ok(blob_file)
+    }
+}
+
+/// merge 时写入 merge 目录中的 blob 文件，文件 id 从 0 开始
+pub(crate) struct MergeBlobWriter<'a> {
+    blob_files: &'a BlobFiles,
+    merge_path: PathBuf,
+    file: Option<FileDb>,
+}
+
+impl MergeBlobWriter<'_> {
+    pub(crate) fn new(blob_files: &BlobFiles, merge_path: PathBuf) -> MergeBlobWriter<'_> {
+        MergeBlobWriter {
+            blob_files,
+            merge_path,
+            file: None,
+        }
+    }
+
+    /// 把旧 blob 文件中的 blob 复制到 merge 目录中，返回新的位置
+    pub(crate) fn copy(&mut self, key: &[u8], blob_pos: &LogDbPos) -> ResultDb<LogDbPos> {
+        let value = self.blob_files.read(blob_pos)?;
+        let blob = LogDb {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            rec_type: LogDbType::NORMAL,
+        };
+        let enc_blob = blob.encode_with(self.blob_files.checksum);
+
+        let file_id = match self.file.as_ref() {
+            None => 0,
+            Some(file) if file.get_write_off() > 0 && file.get_write_off() + enc_blob.len() as u64 > self.blob_files.file_size => {
+                file.sync()?;
+                file.get_file_id() + 1
+            }
+            Some(file) => file.get_file_id(),
+        };
+        if self.file.as_ref().map(|f| f.get_file_id()) != Some(file_id) {
+            self.file = Some(self.blob_files.open_blob_file(&self.merge_path, file_id)?);
+        }
+
+        let file = self.file.as_ref().unwrap();
+        let offset = file.get_write_off();
+        file.write(&enc_blob)?;
+        Ok(LogDbPos {
+            file_id,
+            offset,
+            size: enc_blob.len() as u32,
+        })
+    }
+
+    pub(crate) fn sync(&self) -> ResultDb<()> {
+        match self.file.as_ref() {
+            Some(file) => file.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// 最后一个写入的 blob 文件 id，没有复制过 blob 时为 None
+    pub(crate) fn last_file_id(&self) -> Option<u32> {
+        self.file.as_ref().map(|f| f.get_file_id())
+    }
+}
+
+/// 删除已经 merge 过的 blob 文件，merge 目录中的 blob 文件和其它文件一起移动到数据目录中
+pub(crate) fn remove_merged_blob_files(dir_path: &Path, non_merge_blob_fid: u32) -> ResultDb<()> {
+    for (file_id, file_name) in FileDb::find_blob_files(dir_path)? {
+        if file_id < non_merge_blob_fid {
+            fs::remove_file(file_name)?;
+        }
+    }
+    Ok(())
+}
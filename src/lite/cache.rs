@@ -0,0 +1,140 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::db::LogDbPos;
+
+/// 最近最少使用淘汰的 value 缓存，容量为 0 时不缓存
+/// 缓存中同时保存了 value 的位置，和索引中的位置一致时才命中，并发写入时不会读到旧的值
+pub(crate) struct ValueCache {
+    capacity: usize,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<Vec<u8>, CacheEntry>,
+    // 按照最近使用的顺序排列，第一个是最久没有使用的
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+struct CacheEntry {
+    pos: LogDbPos,
+    value: Bytes,
+    tick: u64,
+}
+
+impl ValueCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ValueCache {
+            capacity,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    /// pos 为索引中 key 的位置，和缓存的位置不一致时不命中
+    pub(crate) fn get(&self, key: &[u8], pos: &LogDbPos) -> Option<Bytes> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut inner = self.inner.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let entry = inner.entries.get_mut(key)?;
+        if entry.pos != *pos {
+            return None;
+        }
+        let old_tick = std::mem::replace(&mut entry.tick, tick);
+        let value = entry.value.clone();
+        if let Some(key) = inner.order.remove(&old_tick) {
+            inner.order.insert(tick, key);
+        }
+        Some(value)
+    }
+
+    pub(crate) fn insert(&self, key: &[u8], pos: LogDbPos, value: Bytes) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        inner.remove(key);
+        while inner.entries.len() >= self.capacity {
+            let (_, oldest) = inner.order.pop_first().unwrap();
+            inner.entries.remove(&oldest);
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.order.insert(tick, key.to_vec());
+        inner.entries.insert(key.to_vec(), CacheEntry { pos, value, tick });
+    }
+
+    /// key 被覆盖或者删除之后移除缓存
+    pub(crate) fn remove(&self, key: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.inner.lock().remove(key);
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.entries.clear();
+        inner.order.clear();
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().entries.len()
+    }
+}
+
+impl CacheInner {
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.tick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(offset: u64) -> LogDbPos {
+        LogDbPos { file_id: 0, offset, size: 10 }
+    }
+
+    #[test]
+    fn test_value_cache() {
+        let cache = ValueCache::new(2);
+        cache.insert(b"a", pos(0), Bytes::from("a0"));
+        cache.insert(b"b", pos(10), Bytes::from("b0"));
+        assert_eq!(Some(Bytes::from("a0")), cache.get(b"a", &pos(0)));
+        // 位置不一致时不命中
+        assert_eq!(None, cache.get(b"b", &pos(20)));
+
+        // b 最久没有使用，被淘汰
+        cache.insert(b"c", pos(20), Bytes::from("c0"));
+        assert_eq!(2, cache.len());
+        assert_eq!(None, cache.get(b"b", &pos(10)));
+        assert_eq!(Some(Bytes::from("a0")), cache.get(b"a", &pos(0)));
+        assert_eq!(Some(Bytes::from("c0")), cache.get(b"c", &pos(20)));
+
+        // 覆盖已有的 key 不会淘汰其它的 key
+        cache.insert(b"a", pos(30), Bytes::from("a1"));
+        assert_eq!(2, cache.len());
+        assert_eq!(Some(Bytes::from("a1")), cache.get(b"a", &pos(30)));
+
+        cache.remove(b"a");
+        assert_eq!(None, cache.get(b"a", &pos(30)));
+        cache.clear();
+        assert_eq!(0, cache.len());
+
+        // 容量为 0 时不缓存
+        let cache = ValueCache::new(0);
+        cache.insert(b"a", pos(0), Bytes::from("a0"));
+        assert_eq!(None, cache.get(b"a", &pos(0)));
+    }
+}
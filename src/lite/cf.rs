@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::db::{Adder, Closer, Config, ErrDb, Getter, IteratorOptions, Key, Remover, ResultDb, Value};
+use crate::lite::merge::get_merge_path;
+use crate::lite::{DbIterator, LiteDb, Stat};
+
+// 列族的目录名称的前缀，列族的目录在数据目录中
+const CF_DIR_PREFIX: &str = "cf-";
+
+// 打开的列族，按照名称保存
+pub(crate) type ColumnFamilies = Arc<Mutex<HashMap<String, Arc<LiteDb>>>>;
+
+/// 列族，有自己的数据文件和索引，数据文件在数据目录的 cf-<name> 子目录中
+/// 和数据库共享目录锁，数据库的自动 merge 同时 merge 打开的列族，列族之间的 merge 互不影响
+/// 可以 clone，关闭数据库或者删除列族之后不能再使用
+#[derive(Clone)]
+pub struct ColumnFamily {
+    name: String,
+    db: Arc<LiteDb>,
+}
+
+impl LiteDb {
+    /// 打开列族，不存在时创建，同一个名称返回同一个列族
+    /// 名称只能包含字母、数字、'-' 和 '_'
+    pub fn cf(&self, name: &str) -> ResultDb<ColumnFamily> {
+        check_cf_name(name)?;
+        let mut column_families = self.column_families.lock();
+        let db = match column_families.get(name) {
+            Some(db) => db.clone(),
+            None => {
+                let db = Arc::new(LiteDb::open_inner(self.cf_config(name), false)?);
+                column_families.insert(name.to_owned(), db.clone());
+                db
+            }
+        };
+        Ok(ColumnFamily { name: name.to_owned(), db })
+    }
+
+    /// 删除列族和它所有的数据，不影响其它的列族，列族不存在时什么也不做
+    pub fn drop_cf(&self, name: &str) -> ResultDb<()> {
+        check_cf_name(name)?;
+        self.check_writable()?;
+        let config = self.cf_config(name);
+        let mut column_families = self.column_families.lock();
+        if let Some(db) = column_families.remove(name) {
+            // 等待正在进行的 merge 完成
+            let _merging_lock = db.merging_lock.lock();
+            db.close()?;
+        }
+        if !config.in_memory {
+            for path in [config.path_db.clone(), get_merge_path(&config)] {
+                if path.is_dir() {
+                    fs::remove_dir_all(path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 列族使用数据库的配置，merge 由数据库的自动 merge 线程进行
+    fn cf_config(&self, name: &str) -> Config {
+        let mut config = self.config.clone();
+        config.path_db = self.config.path_db.join(format!("{}{}", CF_DIR_PREFIX, name));
+        config.auto_merge_interval = None;
+        config
+    }
+}
+
+impl ColumnFamily {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn iter(&self, options: IteratorOptions) -> DbIterator<'_> {
+        self.db.iter(options)
+    }
+
+    /// 只 merge 这个列族的数据文件
+    pub fn merge(&self) -> ResultDb<()> {
+        self.db.merge()
+    }
+
+    pub fn stat(&self) -> ResultDb<Stat> {
+        self.db.stat()
+    }
+}
+
+impl Getter for ColumnFamily {
+    fn get(&self, key: &Key) -> ResultDb<Value> {
+        self.db.get(key)
+    }
+}
+
+impl Adder for ColumnFamily {
+    fn add(&self, key: &Key, v: &Value) -> ResultDb<()> {
+        self.db.add(key, v)
+    }
+}
+
+impl Remover for ColumnFamily {
+    fn remove(&self, key: &Key) -> ResultDb<Option<Value>> {
+        self.db.remove(key)
+    }
+
+    fn remove_fast(&self, key: &Key) -> ResultDb<()> {
+        self.db.remove_fast(key)
+    }
+}
+
+fn check_cf_name(name: &str) -> ResultDb<()> {
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+        return Err(ErrDb::InvalidParameter);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bytes::Bytes;
+    use function_name::named;
+
+    use crate::db::{Adder, Config, ErrDb, FileDb, Getter, IteratorOptions, Remover};
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::LiteDb;
+
+    fn ready_config(file: &str, name: &str) -> Config {
+        let mut config = kits::test_config(file, name);
+        config.file_size_db = 64 * 1024;
+        config.merge_ratio = 0.0;
+        config
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_column_family() {
+        let config = ready_config(file!(), function_name!());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let users = lite_db.cf("users").unwrap();
+            let orders = lite_db.cf("orders").unwrap();
+            assert_eq!(ErrDb::InvalidParameter, lite_db.cf("a/b").err().unwrap());
+            assert_eq!(ErrDb::InvalidParameter, lite_db.cf("").err().unwrap());
+
+            // 同一个 key 在不同的列族和数据库中互不影响
+            let key = get_test_key(0);
+            lite_db.add(&key, &Bytes::from("db")).unwrap();
+            users.add(&key, &Bytes::from("users")).unwrap();
+            orders.add(&key, &Bytes::from("orders")).unwrap();
+            assert_eq!(Bytes::from("db"), lite_db.get(&key).unwrap());
+            assert_eq!(Bytes::from("users"), users.get(&key).unwrap());
+            assert_eq!(Bytes::from("orders"), lite_db.cf("orders").unwrap().get(&key).unwrap());
+
+            for i in 1..3000 {
+                users.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                orders.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 1..1000 {
+                users.remove(&get_test_key(i)).unwrap();
+            }
+            assert_eq!(2001, users.iter(IteratorOptions::default()).count());
+            assert_eq!(3000, orders.iter(IteratorOptions::default()).count());
+            assert_eq!(1, lite_db.iter(IteratorOptions::default()).count());
+
+            // 数据库的数据文件中没有列族的数据
+            let db_files = FileDb::find_data_files(&config.path_db).unwrap();
+            assert_eq!(1, db_files.len());
+            let orders_files = FileDb::find_data_files(&config.path_db.join("cf-orders")).unwrap();
+
+            // 只 merge 一个列族，其它列族的数据文件不变
+            users.merge().expect("failed to merge");
+            assert_eq!(orders_files, FileDb::find_data_files(&config.path_db.join("cf-orders")).unwrap());
+            assert_eq!(db_files, FileDb::find_data_files(&config.path_db).unwrap());
+            for i in 1..1000 {
+                assert_eq!(ErrDb::NotFindKey, users.get(&get_test_key(i)).err().unwrap());
+            }
+            for i in 1000..3000 {
+                assert_eq!(get_test_value(i), users.get(&get_test_key(i)).unwrap());
+                assert_eq!(get_test_value(i), orders.get(&get_test_key(i)).unwrap());
+            }
+            orders.merge().expect("failed to merge");
+            assert_eq!(3000, orders.stat().unwrap().key_num);
+        }
+
+        // 重新打开之后列族的数据还在
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(1, lite_db.iter(IteratorOptions::default()).count());
+        let users = lite_db.cf("users").unwrap();
+        let orders = lite_db.cf("orders").unwrap();
+        assert_eq!(Bytes::from("users"), users.get(&get_test_key(0)).unwrap());
+        assert_eq!(2001, users.stat().unwrap().key_num);
+
+        // 删除一个列族不影响其它的列族和数据库
+        lite_db.drop_cf("users").unwrap();
+        assert!(!config.path_db.join("cf-users").exists());
+        assert_eq!(3000, orders.stat().unwrap().key_num);
+        assert_eq!(get_test_value(1), orders.get(&get_test_key(1)).unwrap());
+        assert_eq!(Bytes::from("db"), lite_db.get(&get_test_key(0)).unwrap());
+        // 删除之后再打开是空的
+        let users = lite_db.cf("users").unwrap();
+        assert_eq!(ErrDb::NotFindKey, users.get(&get_test_key(0)).err().unwrap());
+        lite_db.drop_cf("missing").unwrap();
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_column_family_auto_merge() {
+        let mut config = ready_config(file!(), function_name!());
+        config.auto_merge_interval = Some(std::time::Duration::from_millis(10));
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let cf = lite_db.cf("auto").unwrap();
+        for i in 0..3000 {
+            cf.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        for i in 0..3000 {
+            cf.remove(&get_test_key(i)).unwrap();
+        }
+        let files = cf.stat().unwrap().data_file_num;
+        // 数据库的自动 merge 同时 merge 列族
+        let start = std::time::Instant::now();
+        while cf.stat().unwrap().data_file_num >= files && start.elapsed() < std::time::Duration::from_secs(10) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(cf.stat().unwrap().data_file_num < files);
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+}
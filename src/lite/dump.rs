@@ -0,0 +1,286 @@
+use std::cmp::Ordering;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
+use bytes::{Bytes, BytesMut};
+use prost::encode_length_delimiter;
+
+use crate::db::{Adder, ErrDb, InvalidBatchReason, ResultDb, WriteBatchOptions};
+use crate::lite::LiteDb;
+
+// 导出文件的开头，用于识别格式和版本
+const DUMP_MAGIC: &[u8] = b"LITEDB\x00\x01";
+
+impl LiteDb {
+    /// 按照 key 从小到大导出所有有效的数据
+    /// 格式为 magic 加上依次排列的记录，每条记录为变长编码的 key 长度、value 长度，然后是 key 和 value
+    pub fn export(&self, w: impl Write) -> ResultDb<()> {
+        let mut w = BufWriter::new(w);
+        w.write_all(DUMP_MAGIC)?;
+
+        // 在快照上导出，有快照时 merge 不替换数据文件，写入 w 的时候不持有锁
+        let snapshot = self.snapshot();
+        let mut header = BytesMut::new();
+        for item in snapshot.iter() {
+            let (key, value) = item?;
+            header.clear();
+            encode_length_delimiter(key.len(), &mut header).unwrap();
+            encode_length_delimiter(value.len(), &mut header).unwrap();
+            w.write_all(&header)?;
+            w.write_all(&key)?;
+            w.write_all(&value)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// 导入 export 导出的数据，返回读取的记录数量
+    /// 按照导出的顺序分批提交，批次受最大数量和最大字节数的限制，key 重复时后面的值覆盖前面的值
+    /// import_strict 时要求 key 按照索引的顺序严格递增，有重复或者顺序错误的 key 时返回错误，已经提交的批次不会回滚
+    /// BTree 索引下重新打开的非空数据库不能使用批量操作，这时逐条写入
+    pub fn import(&self, r: impl Read, import_strict: bool) -> ResultDb<usize> {
+        let mut r = BufReader::new(r);
+        let mut magic = [0u8; DUMP_MAGIC.len()];
+        r.read_exact(&mut magic)?;
+        if magic != DUMP_MAGIC {
+            return Err(ErrDb::Err("invalid dump, the magic is not matched".to_owned()));
+        }
+
+        let options = WriteBatchOptions::default();
+        let max_batch_num = options.max_batch_num;
+        let max_batch_bytes = options.max_batch_bytes;
+        let mut wb = match self.new_write_batch(options) {
+            Ok(wb) => Some(wb),
+            Err(ErrDb::InvalidBatch(InvalidBatchReason::SeqNoUnavailable)) => None,
+            Err(e) => return Err(e),
+        };
+        let mut pending = 0;
+        let mut records = 0;
+        let mut last_key: Option<Vec<u8>> = None;
+        // 在记录的边界上读到文件末尾说明导入完成
+        while let Some(key_len) = read_length(&mut r, true)? {
+            let value_len = read_length(&mut r, false)?.unwrap();
+            // 长度来自导入的数据，超过批次最大字节数的记录无法提交，分配内存之前先检查
+            if key_len.saturating_add(value_len) > max_batch_bytes {
+                return Err(ErrDb::Err(format!("invalid dump, the record {} is too large", records)));
+            }
+            let mut key = vec![0u8; key_len];
+            r.read_exact(&mut key)?;
+            let mut value = vec![0u8; value_len];
+            r.read_exact(&mut value)?;
+            if import_strict {
+                if let Some(last_key) = &last_key {
+                    let ordering = match &self.config.comparator {
+                        Some(c) => c.compare(last_key, &key),
+                        None => last_key.as_slice().cmp(&key),
+                    };
+                    match ordering {
+                        Ordering::Less => {}
+                        Ordering::Equal => return Err(ErrDb::Err(format!("invalid dump, the key of record {} is duplicated", records))),
+                        Ordering::Greater => return Err(ErrDb::Err(format!("invalid dump, the key of record {} is not sorted", records))),
+                    }
+                }
+                last_key = Some(key.clone());
+            }
+
+            let Some(batch) = wb.as_mut() else {
+                self.add(&Bytes::from(key), &Bytes::from(value))?;
+                records += 1;
+                continue;
+            };
+            // 批次的字节数不够时先提交之前的数据
+            if pending > 0 && batch.pending_bytes() + key_len + value_len > max_batch_bytes {
+                batch.commit()?;
+                *batch = self.new_write_batch(WriteBatchOptions::default())?;
+                pending = 0;
+            }
+            batch.put(Bytes::from(key), Bytes::from(value))?;
+            pending += 1;
+            records += 1;
+            if pending == max_batch_num {
+                batch.commit()?;
+                *batch = self.new_write_batch(WriteBatchOptions::default())?;
+                pending = 0;
+            }
+        }
+        if let Some(batch) = wb {
+            if pending > 0 {
+                batch.commit()?;
+            }
+        }
+        Ok(records)
+    }
+}
+
+// 读取变长编码的长度，allow_eof 时在第一个字节读到文件末尾返回 None
+fn read_length(r: &mut impl Read, allow_eof: bool) -> ResultDb<Option<usize>> {
+    let mut value = 0usize;
+    for i in 0..10 {
+        let mut byte = [0u8; 1];
+        if let Err(e) = r.read_exact(&mut byte) {
+            if i == 0 && allow_eof && e.kind() == ErrorKind::UnexpectedEof {
+                return Ok(None);
+            }
+            return Err(ErrDb::IoErr(e));
+        }
+        value |= ((byte[0] & 0x7f) as usize) << (i * 7);
+        if byte[0] < 0x80 {
+            return Ok(Some(value));
+        }
+    }
+    Err(ErrDb::Err("invalid dump, the length is too long".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use bytes::{Bytes, BytesMut};
+    use function_name::named;
+    use prost::encode_length_delimiter;
+
+    use crate::db::{Adder, ErrDb, Getter, IndexType, Remover, WriteBatchOptions};
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::LiteDb;
+
+    use super::DUMP_MAGIC;
+
+    #[named]
+    #[test]
+    fn test_lite_db_export_import() {
+        let config = kits::test_config(file!(), function_name!());
+        let mut config2 = config.clone();
+        config2.path_db = PathBuf::from(format!("{}-import", config.path_db.display()));
+        let _ = fs::remove_dir_all(config2.path_db.clone());
+
+        let count = 12000;
+        let mut dump = Vec::new();
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..count {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            lite_db.remove(&get_test_key(7)).unwrap();
+            lite_db.add(&get_test_key(8), &Bytes::new()).unwrap();
+            lite_db.export(&mut dump).expect("failed to export");
+
+            let lite_db2 = LiteDb::open(config2.clone()).expect("failed to open engine");
+            let records = lite_db2.import(dump.as_slice(), true).expect("failed to import");
+            assert_eq!(count - 1, records);
+
+            let keys = lite_db.index.list_keys().unwrap();
+            assert_eq!(keys, lite_db2.index.list_keys().unwrap());
+            for key in keys.iter() {
+                assert_eq!(lite_db.get(key).unwrap(), lite_db2.get(key).unwrap());
+            }
+            assert_eq!(ErrDb::NotFindKey, lite_db2.get(&get_test_key(7)).err().unwrap());
+            assert_eq!(Bytes::new(), lite_db2.get(&get_test_key(8)).unwrap());
+
+            // the dump of the imported db is the same
+            let mut dump2 = Vec::new();
+            lite_db2.export(&mut dump2).expect("failed to export");
+            assert_eq!(dump, dump2);
+        }
+
+        // invalid or truncated dump
+        {
+            let config3 = kits::test_config(file!(), "test_lite_db_export_import_invalid");
+            let lite_db3 = LiteDb::open(config3.clone()).expect("failed to open engine");
+            assert!(matches!(lite_db3.import(&b"not a dump"[..], false), Err(ErrDb::Err(_))));
+            for import_strict in [false, true] {
+                let re = lite_db3.import(&dump[..dump.len() - 1], import_strict);
+                assert!(matches!(re, Err(ErrDb::IoErr(_))));
+            }
+            // 长度超过批次最大字节数的记录不分配内存，直接返回错误
+            let mut huge = DUMP_MAGIC.to_vec();
+            let mut header = BytesMut::new();
+            encode_length_delimiter(1, &mut header).unwrap();
+            encode_length_delimiter(usize::MAX >> 1, &mut header).unwrap();
+            huge.extend_from_slice(&header);
+            huge.extend_from_slice(b"k");
+            let re = lite_db3.import(huge.as_slice(), false);
+            assert!(matches!(re, Err(ErrDb::Err(msg)) if msg.contains("too large")));
+            fs::remove_dir_all(config3.path_db.clone()).expect("failed to remove path");
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        fs::remove_dir_all(config2.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_import_reopened_btree() {
+        let config = kits::test_config(file!(), function_name!());
+        let count = 3000;
+        let mut dump = Vec::new();
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..count {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            lite_db.export(&mut dump).expect("failed to export");
+            lite_db.remove(&get_test_key(1)).unwrap();
+        }
+
+        // BTree 索引下重新打开的非空数据库不能使用批量操作，逐条导入
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(IndexType::BTree, config.index_type);
+        assert!(lite_db.new_write_batch(WriteBatchOptions::default()).is_err());
+        assert_eq!(count, lite_db.import(dump.as_slice(), true).expect("failed to import"));
+        for i in 0..count {
+            assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+        }
+        drop(lite_db);
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(get_test_value(1), lite_db.get(&get_test_key(1)).unwrap());
+        assert_eq!(count, lite_db.index.list_keys().unwrap().len());
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    // 按照导出的格式编码记录
+    fn encode_dump(records: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut dump = DUMP_MAGIC.to_vec();
+        for (key, value) in records {
+            let mut header = BytesMut::new();
+            encode_length_delimiter(key.len(), &mut header).unwrap();
+            encode_length_delimiter(value.len(), &mut header).unwrap();
+            dump.extend_from_slice(&header);
+            dump.extend_from_slice(key);
+            dump.extend_from_slice(value);
+        }
+        dump
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_import_strict() {
+        let config = kits::test_config(file!(), function_name!());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+        let clean = encode_dump(&[(b"a", b"1"), (b"b", b"2"), (b"c", b"3")]);
+        for import_strict in [false, true] {
+            assert_eq!(3, lite_db.import(clean.as_slice(), import_strict).unwrap());
+            assert_eq!(Bytes::from("2"), lite_db.get(&Bytes::from("b")).unwrap());
+        }
+
+        // 宽松模式下重复的 key 后面的值覆盖前面的值
+        let duplicated = encode_dump(&[(b"d", b"1"), (b"e", b"2"), (b"e", b"3")]);
+        assert_eq!(3, lite_db.import(duplicated.as_slice(), false).unwrap());
+        assert_eq!(Bytes::from("3"), lite_db.get(&Bytes::from("e")).unwrap());
+
+        // 严格模式下重复或者顺序错误的 key 返回错误
+        lite_db.remove(&Bytes::from("e")).unwrap();
+        let re = lite_db.import(duplicated.as_slice(), true);
+        assert!(matches!(re, Err(ErrDb::Err(msg)) if msg.contains("duplicated")));
+        assert_eq!(ErrDb::NotFindKey, lite_db.get(&Bytes::from("e")).err().unwrap());
+        let unsorted = encode_dump(&[(b"g", b"1"), (b"f", b"2")]);
+        let re = lite_db.import(unsorted.as_slice(), true);
+        assert!(matches!(re, Err(ErrDb::Err(msg)) if msg.contains("not sorted")));
+        assert_eq!(2, lite_db.import(unsorted.as_slice(), false).unwrap());
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+}
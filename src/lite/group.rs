@@ -0,0 +1,101 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::db::{ErrDb, LogDb, LogDbPos, ResultDb};
+use crate::lite::LiteDb;
+
+/// group commit，把并发的写入合并成一次写入和一次持久化
+/// 没有 leader 时到达的写入成为 leader，等待 window 或者收集到 max 条记录之后一起写入并持久化，然后唤醒其它的写入
+/// leader 写入的过程中到达的写入在下一组中，由其中的一个成为新的 leader
+pub(crate) struct GroupCommit {
+    window: Duration,
+    max: usize,
+    state: Mutex<GroupState>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct GroupState {
+    // 等待写入的记录和它们的编号，按照到达的顺序
+    pending: Vec<(u64, LogDb)>,
+    next_ticket: u64,
+    // 已经写入的结果，由各自的写入取走
+    results: HashMap<u64, ResultDb<LogDbPos>>,
+    // 是否有 leader 正在收集或者写入
+    leading: bool,
+}
+
+impl GroupCommit {
+    pub(crate) fn new(window: Duration, max: usize) -> GroupCommit {
+        GroupCommit {
+            window,
+            max,
+            state: Mutex::new(GroupState::default()),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// 写入一条记录，和同一组的记录一起持久化之后才返回记录的位置
+    pub(crate) fn commit(&self, db: &LiteDb, log_db: LogDb) -> ResultDb<LogDbPos> {
+        let mut state = self.state.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.pending.push((ticket, log_db));
+        // 收集够了，唤醒等待的 leader
+        if state.pending.len() >= self.max {
+            self.cond.notify_all();
+        }
+        loop {
+            // 等待 leader 写入，没有 leader 时成为 leader
+            loop {
+                if let Some(re) = state.results.remove(&ticket) {
+                    return re;
+                }
+                if !state.leading {
+                    break;
+                }
+                self.cond.wait(&mut state);
+            }
+            state.leading = true;
+            let deadline = Instant::now() + self.window;
+            while state.pending.len() < self.max && !self.cond.wait_until(&mut state, deadline).timed_out() {}
+
+            // 超过 max 时先写入最早到达的，自己的记录不在这一组时继续等待下一组
+            let len = state.pending.len().min(self.max);
+            let (tickets, log_dbs): (Vec<u64>, Vec<LogDb>) = state.pending.drain(..len).unzip();
+            drop(state);
+            let re = write_group(db, &log_dbs);
+
+            state = self.state.lock();
+            match re {
+                Ok(positions) => state.results.extend(tickets.into_iter().zip(positions.into_iter().map(Ok))),
+                Err(e) => {
+                    log::error!("failed to group commit {} records: {}", log_dbs.len(), e);
+                    state.results.extend(tickets.into_iter().map(|ticket| (ticket, Err(shared_err(&e)))));
+                }
+            }
+            state.leading = false;
+            self.cond.notify_all();
+        }
+    }
+}
+
+// 一次写入所有的记录，并且只持久化一次
+fn write_group(db: &LiteDb, log_dbs: &[LogDb]) -> ResultDb<Vec<LogDbPos>> {
+    let positions = db.append_log_dbs_atomic(log_dbs)?;
+    let file_ids: BTreeSet<u32> = positions.iter().map(|pos| pos.file_id).collect();
+    db.sync_files(&file_ids)?;
+    Ok(positions)
+}
+
+// 同一组的写入返回相同的错误，ErrDb 不能 clone，保留常见的错误类型
+fn shared_err(e: &ErrDb) -> ErrDb {
+    match e.root() {
+        ErrDb::DiskFull => ErrDb::DiskFull,
+        ErrDb::ReadOnly => ErrDb::ReadOnly,
+        ErrDb::InvalidParameter => ErrDb::InvalidParameter,
+        _ => ErrDb::Err(e.to_string()),
+    }
+}
@@ -0,0 +1,224 @@
+use bytes::Bytes;
+
+use crate::db::{ErrDb, Getter, IndexIterator, IteratorOptions, Key, ResultDb, Value};
+use crate::lite::LiteDb;
+
+/// 数据库的迭代器，按照索引的顺序遍历 key
+/// 创建时复制索引中的 key，value 在读取的时候才从数据文件中读取，读取的是当时最新的值
+pub struct DbIterator<'a> {
+    db: &'a LiteDb,
+    index_iter: Box<dyn IndexIterator>,
+}
+
+/// 迭代器当前 key 的 value，调用 get 时才读取数据文件
+pub struct ValueRef<'a> {
+    db: &'a LiteDb,
+    key: &'a [u8],
+}
+
+/// scan_chunk 返回的一页数据，以及下一页的 cursor
+pub type ScanChunk = (Vec<(Key, Value)>, Option<Vec<u8>>);
+
+impl LiteDb {
+    pub fn iter(&self, options: IteratorOptions) -> DbIterator<'_> {
+        DbIterator {
+            db: self,
+            index_iter: self.index.iterator(options),
+        }
+    }
+
+    /// 无状态的分页遍历，返回 cursor 之后最多 limit 个 key 和 value，以及下一页的 cursor，没有更多的数据时 cursor 为 None
+    /// cursor 为 None 时从第一个 key 开始，prefix 不为 None 时只返回以 prefix 开头的 key
+    /// 每一页按照读取时最新的数据返回，两页之间删除的 key 不会返回，写入的 key 在 cursor 之后时会返回
+    pub fn scan_chunk(&self, cursor: Option<Vec<u8>>, limit: usize, prefix: Option<Vec<u8>>) -> ResultDb<ScanChunk> {
+        if limit == 0 {
+            return Err(ErrDb::InvalidParameter);
+        }
+        let mut iter = self.index.iterator(IteratorOptions {
+            prefix: prefix.unwrap_or_default(),
+            reverse: false,
+        });
+        if let Some(cursor) = &cursor {
+            iter.seek(cursor.clone());
+        }
+        let mut items: Vec<(Key, Value)> = Vec::with_capacity(limit);
+        while let Some((key, _)) = iter.next() {
+            // seek 定位到大于等于 cursor 的位置，cursor 本身在上一页中已经返回
+            if cursor.as_ref() == Some(key) {
+                continue;
+            }
+            // 还有更多的 key 时才返回下一页的 cursor
+            if items.len() == limit {
+                let next = items.last().map(|(key, _)| key.to_vec());
+                return Ok((items, next));
+            }
+            let key = Bytes::copy_from_slice(key);
+            match self.get(&key) {
+                Ok(value) => items.push((key, value)),
+                // 遍历索引之后被删除了
+                Err(ErrDb::NotFindKey) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((items, None))
+    }
+}
+
+impl DbIterator<'_> {
+    /// 回到第一个 key
+    pub fn rewind(&mut self) {
+        self.index_iter.rewind();
+    }
+
+    /// 正向时定位到第一个大于等于 key 的位置，反向时定位到第一个小于等于 key 的位置
+    pub fn seek(&mut self, key: Vec<u8>) {
+        self.index_iter.seek(key);
+    }
+
+    /// 返回下一个 key 的引用，不复制 key，也不读取 value
+    pub fn next_ref(&mut self) -> Option<(&[u8], ValueRef<'_>)> {
+        let db = self.db;
+        self.index_iter.next().map(|(key, _)| (key.as_slice(), ValueRef { db, key: key.as_slice() }))
+    }
+}
+
+impl Iterator for DbIterator<'_> {
+    type Item = ResultDb<(Key, Value)>;
+
+    /// 复制 key 并读取 value
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.next_ref()?;
+        Some(value.get().map(|value| (Bytes::copy_from_slice(key), value)))
+    }
+}
+
+impl ValueRef<'_> {
+    /// 读取 value，key 在迭代之后被删除时返回 NotFindKey
+    pub fn get(&self) -> ResultDb<Bytes> {
+        self.db.get(&Bytes::copy_from_slice(self.key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::db::{Adder, Config, ErrDb, IteratorOptions, Remover};
+    use crate::io_db::MemIo;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::LiteDb;
+
+    #[test]
+    fn test_db_iterator() {
+        let mut config = Config::default();
+        config.in_memory = true;
+        let lite_db = LiteDb::open(config).expect("failed to open engine");
+        for i in 0..10 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+
+        let items: Vec<(Bytes, Bytes)> = lite_db.iter(IteratorOptions::default()).map(|item| item.unwrap()).collect();
+        assert_eq!(10, items.len());
+        for (i, (key, value)) in items.iter().enumerate() {
+            assert_eq!(get_test_key(i), key);
+            assert_eq!(get_test_value(i), value);
+        }
+
+        let mut iter = lite_db.iter(IteratorOptions {
+            prefix: Vec::new(),
+            reverse: true,
+        });
+        iter.seek(get_test_key(5).to_vec());
+        let (key, value) = iter.next_ref().unwrap();
+        assert_eq!(get_test_key(5).as_ref(), key);
+        assert_eq!(get_test_value(5), value.get().unwrap());
+
+        // 迭代之后删除的 key 读取 value 时返回 NotFindKey
+        let mut iter = lite_db.iter(IteratorOptions::default());
+        lite_db.remove(&get_test_key(0)).unwrap();
+        let (_, value) = iter.next_ref().unwrap();
+        assert_eq!(ErrDb::NotFindKey, value.get().err().unwrap());
+    }
+
+    #[test]
+    fn test_db_iterator_lazy_value() {
+        let mut config = Config::default();
+        config.in_memory = true;
+        let lite_db = LiteDb::open(config).expect("failed to open engine");
+        for i in 0..10 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        // 清空数据文件之后 value 不能再读取
+        lite_db.active_file.write().set_db_io(Box::new(MemIo::new()));
+
+        // 只遍历 key 时不读取 value
+        let mut iter = lite_db.iter(IteratorOptions::default());
+        let mut count = 0;
+        while let Some((key, _)) = iter.next_ref() {
+            assert_eq!(get_test_key(count).as_ref(), key);
+            count += 1;
+        }
+        assert_eq!(10, count);
+
+        // 读取 value 时才会出错
+        iter.rewind();
+        let (_, value) = iter.next_ref().unwrap();
+        assert!(value.get().is_err());
+        assert!(lite_db.iter(IteratorOptions::default()).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_db_scan_chunk() {
+        let mut config = Config::default();
+        config.in_memory = true;
+        let lite_db = LiteDb::open(config).expect("failed to open engine");
+        for i in 0..1050 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        for i in 0..30 {
+            lite_db.add(&Bytes::from(format!("prefix-{:03}", i)), &get_test_value(i)).unwrap();
+        }
+
+        let scan = |limit: usize, prefix: Option<Vec<u8>>| {
+            let mut items = Vec::new();
+            let mut cursor = None;
+            let mut pages = 0;
+            loop {
+                let (chunk, next) = lite_db.scan_chunk(cursor, limit, prefix.clone()).unwrap();
+                assert!(chunk.len() <= limit);
+                pages += 1;
+                items.extend(chunk);
+                match next {
+                    Some(next) => cursor = Some(next),
+                    None => return (items, pages),
+                }
+            }
+        };
+
+        // 所有的 key 都按照顺序返回，没有重复
+        let (items, pages) = scan(100, None);
+        assert_eq!(11, pages);
+        let all: Vec<(Bytes, Bytes)> = lite_db.iter(IteratorOptions::default()).map(|item| item.unwrap()).collect();
+        assert_eq!(1080, all.len());
+        assert_eq!(all, items);
+
+        // 刚好整页时最后一页的 cursor 为 None
+        let (items, pages) = scan(30, Some(b"prefix-".to_vec()));
+        assert_eq!(1, pages);
+        assert_eq!(30, items.len());
+        assert!(items.iter().all(|(key, _)| key.starts_with(b"prefix-")));
+        let (items, pages) = scan(7, Some(b"prefix-".to_vec()));
+        assert_eq!(5, pages);
+        assert_eq!(30, items.len());
+
+        // 两页之间删除的 key 不会返回
+        let (first, cursor) = lite_db.scan_chunk(None, 100, None).unwrap();
+        lite_db.remove(&get_test_key(100)).unwrap();
+        lite_db.remove(first.last().map(|(key, _)| key).unwrap()).unwrap();
+        let (second, _) = lite_db.scan_chunk(cursor, 100, None).unwrap();
+        assert_eq!(get_test_key(101), second[0].0);
+
+        assert_eq!(ErrDb::InvalidParameter, lite_db.scan_chunk(None, 0, None).err().unwrap());
+        assert!(lite_db.scan_chunk(None, 10, Some(b"missing".to_vec())).unwrap().0.is_empty());
+    }
+}
@@ -1,94 +1,195 @@
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use bytes::Bytes;
 use fs2::FileExt;
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 
 use crate::db::IndexType::BTree;
-use crate::db::{Adder, Closer, Config, Db, Editor, ErrDb, Getter, Indexer, IoType, Key, Remover, ResultDb, Value, DATA_FILE_NAME_SUFFIX};
-use crate::db::{FileDb, IndexType, LogDb, TransactionLogDb, WriteBatchOptions, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME};
-use crate::db::{LogDbPos, LogDbType};
+use crate::db::{is_footer_log_db, try_decode_log_db_pos, Durability, FileFooter, LogDbPos, LogDbType, Metrics, SyncPolicy};
+use crate::db::{Adder, Closer, Config, Db, Editor, ErrDb, Getter, Indexer, IoType, IteratorOptions, Key, Remover, ResultDb, Value};
+use crate::db::{
+    FileDb, IndexType, InvalidBatchReason, LogDb, TransactionLogDb, WriteBatchOptions, HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
+};
 use crate::index::new_indexer;
-use crate::lite::batch::{log_db_key_with_seq, parse_log_db_key, WriteBatch, NON_TRANSACTION_SEQ_NO};
-use crate::lite::Table;
+use crate::io_db::FileHandles;
+use crate::lite::batch::{log_db_key_with_seq, parse_log_db_key, WriteBatch, NON_TRANSACTION_SEQ_NO, TXN_FIN_KEY};
+use crate::lite::blob::BlobFiles;
+use crate::lite::cache::ValueCache;
+use crate::lite::group::GroupCommit;
+use crate::lite::merge::{get_non_merge_fid, load_merge_files, AutoMergeThread};
+use crate::lite::slow::SlowOp;
+use crate::lite::sync::SyncThread;
+use crate::lite::txn::KeyVersions;
+use crate::lite::watch::Watcher;
+use crate::lite::{ChangeKind, ColumnFamilies, Table};
 
 pub(crate) const FILE_LOCK_NAME: &str = "___lite_db_file_lock_name___";
 const SEQ_NO_KEY: &str = "___seq_no___";
 const INITIAL_FILE_ID: u32 = 0;
 
+/// 数据库的统计信息
+#[derive(Clone, Debug)]
+pub struct Stat {
+    // key 的总数量
+    pub key_num: usize,
+    // 数据文件的数量
+    pub data_file_num: usize,
+    // 可以 merge 回收的数据量
+    pub reclaim_size: usize,
+    // 数据目录占据的磁盘空间大小
+    pub disk_size: u64,
+    // merge 时没有有效数据而直接删除的文件数量
+    pub files_dropped: usize,
+}
+
+/// 单个数据文件中有效和无效数据的大小，用来判断是否需要 merge
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileStat {
+    pub file_id: u32,
+    // 文件中所有记录的大小
+    pub total_size: u64,
+    // 仍然被索引引用的记录大小
+    pub live_size: u64,
+    // merge 时可以回收的记录大小，包括被覆盖、删除的记录和事务标识等
+    pub dead_size: u64,
+}
+
 pub struct LiteDb {
     pub(crate) config: Config,
-    pub(crate) active_file: RwLock<FileDb>,
-    pub(crate) older_files: RwLock<HashMap<u32, FileDb>>,
-    pub(crate) index: Box<dyn Indexer>,
+    pub(crate) active_file: Arc<RwLock<FileDb>>,
+    pub(crate) older_files: Arc<RwLock<HashMap<u32, FileDb>>>,
+    pub(crate) index: Arc<dyn Indexer>,
     file_ids: Vec<u32>,
     pub(crate) batch_commit_lock: Mutex<()>,
     // 事务序列号，全局递增
     pub(crate) seq_no: AtomicUsize,
-    pub(crate) merging_lock: Mutex<()>,
+    pub(crate) merging_lock: Arc<Mutex<()>>,
+    // merge 替换数据文件时持有写锁，读写数据时持有读锁，保证索引中的位置和数据文件一致
+    // 加锁的顺序为 merging_lock、batch_commit_lock、swap_lock、active_file、older_files
+    pub(crate) swap_lock: Arc<RwLock<()>>,
     // 事务序列号文件是否存在
     pub(crate) seq_file_exists: bool,
+    // 打开时没有任何数据文件
     pub(crate) is_initial: bool,
-    lock_file: fs::File,
-    bytes_write: AtomicUsize,
-    pub(crate) reclaim_size: AtomicUsize, // 累计有多少空间可以 merge
+    // 内存模式下没有文件锁
+    lock_file: Option<fs::File>,
+    // 上次持久化之后写入的字节数
+    bytes_write: Arc<AtomicUsize>,
+    pub(crate) reclaim_size: Arc<AtomicUsize>, // 累计有多少空间可以 merge
+    pub(crate) files_dropped: Arc<AtomicUsize>,
+    // 存活的快照数量，有快照时 merge 不删除旧的数据文件
+    pub(crate) snapshots: Arc<AtomicUsize>,
+    pub(crate) watchers: Mutex<Vec<Watcher>>,
+    // 读取过的 value 的缓存
+    pub(crate) value_cache: Arc<ValueCache>,
+    // 保存大 value 的 blob 文件，加锁的顺序在活跃文件之后
+    pub(crate) blob_files: Arc<BlobFiles>,
+    // 是否已经关闭，关闭之后 Drop 不再重复关闭
+    pub(crate) closed: AtomicBool,
+    // 打开时截断了活跃文件末尾损坏的记录
+    pub(crate) repaired_tail: Mutex<Option<String>>,
+    // 最近一次 verify 发现的错误记录，没有错误时为 None
+    pub(crate) verify_failure: Mutex<Option<String>>,
+    // SyncPolicy::Interval 时的后台持久化线程
+    pub(crate) sync_thread: Mutex<Option<SyncThread>>,
+    // 配置了 auto_merge_interval 时的后台 merge 线程
+    pub(crate) auto_merge_thread: Mutex<Option<AutoMergeThread>>,
+    pub(crate) metrics: Arc<dyn Metrics>,
+    // 打开的列族，关闭数据库时一起关闭
+    pub(crate) column_families: ColumnFamilies,
+    // 配置了 group_commit_window 时合并并发的写入
+    group_commit: Option<GroupCommit>,
+    // 配置了 max_open_files 时限制打开的旧的数据文件
+    pub(crate) file_handles: Option<Arc<FileHandles>>,
+    // 进行中的事务读取过的 key 的版本
+    pub(crate) key_versions: Mutex<KeyVersions>,
 }
 
 impl LiteDb {
     pub fn open(config: Config) -> ResultDb<LiteDb> {
+        Self::open_inner(config, true)
+    }
+
+    // own_lock 为 false 时不加目录锁，用于数据目录中的列族，由数据库的锁保护
+    pub(crate) fn open_inner(config: Config, own_lock: bool) -> ResultDb<LiteDb> {
         if let Some(e) = config.check() {
             log::error!("{}", e.to_string());
             return Err(e);
         }
-        let mut is_initial = false;
         let path_db = &config.path_db;
-        if !path_db.is_dir() {
-            is_initial = true;
-            if let Err(e) = fs::create_dir_all(path_db.clone()) {
-                log::error!("{}", e.to_string());
-                return Err(ErrDb::IoErr(e));
-            }
-        }
-        // check whether the file opened
-        let lock_file = {
-            match fs::OpenOptions::new().read(true).write(true).create(true).open(path_db.join(FILE_LOCK_NAME)) {
-                Ok(f) => {
-                    if let Err(e) = f.try_lock_exclusive() {
-                        log::error!("{}", e.to_string());
-                        return Err(ErrDb::IoErr(e));
-                    }
-                    f
-                }
-                Err(e) => {
-                    log::error!("{}", e.to_string());
-                    return Err(ErrDb::IoErr(e));
+        let file_handles = match config.max_open_files {
+            0 => None,
+            max => Some(Arc::new(FileHandles::new(max))),
+        };
+        // 内存模式下不创建目录，也没有数据文件需要加载
+        let (lock_file, is_initial, merged, mut data_files) = if config.in_memory {
+            (None, true, false, Vec::new())
+        } else if config.read_only {
+            // 只读模式下不创建目录和锁文件，也不处理 merge 目录
+            let lock_file = open_db_dir_read_only(path_db)?;
+            let merged = load_merge_files(&config)?;
+            let data_files = load_data_files(&config, false, file_handles.as_ref())?;
+            (lock_file, data_files.is_empty(), merged, data_files)
+        } else {
+            let lock_file = match own_lock {
+                true => Some(open_db_dir(path_db, config.dir_mode)?),
+                false => {
+                    create_db_dir(path_db, config.dir_mode)?;
+                    None
                 }
-            }
+            };
+            // 加载 merge 数据目录
+            let merged = load_merge_files(&config)?;
+            let data_files = load_data_files(&config, false, file_handles.as_ref())?;
+            // 没有任何数据文件时是新建的数据库，目录是否存在不影响
+            (lock_file, data_files.is_empty(), merged, data_files)
         };
 
-        if let Ok(entries) = fs::read_dir(path_db.clone()) {
-            if entries.count() < 1 {
-                is_initial = true;
-            }
-        }
-
-        let mut data_files = load_data_files(path_db.clone(), false)?;
-
         let mut file_ids = Vec::new();
         for v in data_files.iter() {
             file_ids.push(v.get_file_id());
         }
 
+        // 已经有数据文件时 start_file_id 不再生效
+        let mut repaired_tail = None;
         let active_file = match data_files.pop() {
-            Some(v) => v,
-            None => FileDb::new(path_db.clone(), INITIAL_FILE_ID, IoType::StdIo)?,
+            Some(mut v) => {
+                // 预分配的活跃文件中间有损坏的记录时，修复时截断到最后一条正确的记录，否则返回错误
+                if let Some(valid) = v.recover_write_off(config.repair_on_open && !config.read_only)? {
+                    let reason = format!("repaired data file {}: truncated the corrupt records at offset {}", v.get_file_id(), valid);
+                    log::warn!("{}", reason);
+                    repaired_tail = Some(reason);
+                }
+                if config.preallocate && !config.read_only {
+                    v.preallocate(config.file_size_db)?;
+                }
+                // B+树索引打开时不一定扫描活跃文件，限制记录数量时需要先知道已有的数量
+                if config.max_records_per_file.is_some() && v.get_write_off() > 0 {
+                    v.set_footer(Some(v.scan_footer()?));
+                }
+                v
+            }
+            // 只读模式下没有数据文件时使用一个空的内存文件作为活跃文件
+            None if config.read_only => FileDb::new_with_file_name(
+                config.data_file_name(path_db, config.start_file_id.unwrap_or(INITIAL_FILE_ID)),
+                config.start_file_id.unwrap_or(INITIAL_FILE_ID),
+                IoType::Memory,
+            )?,
+            None => {
+                let file_id = config.start_file_id.unwrap_or(INITIAL_FILE_ID);
+                let data_file = open_data_file(&config, path_db, file_id, config.data_io_type())?;
+                // 新建的数据文件需要持久化目录，否则崩溃之后文件可能丢失
+                sync_data_file_dir(&config, data_file.get_file_name(), config.get_metrics().as_ref())?;
+                data_file
+            }
         };
         let older_files = {
-            if data_files.len() > 0 {
+            if !data_files.is_empty() {
                 // 将旧的数据文件放到后面，新的数据文件在第一个位置
                 data_files.into_iter().rev().map(|f| (f.get_file_id(), f)).collect()
             } else {
@@ -96,298 +197,438 @@ impl LiteDb {
             }
         };
 
-        let index = new_indexer(config.index_type.clone(), config.path_db.clone())?;
+        let index = Arc::from(new_indexer(
+            config.index_type.clone(),
+            config.path_db.clone(),
+            (&config.bptree_index_file_name, &config.bptree_bucket_name),
+            config.comparator.clone(),
+            config.bloom_filter_keys,
+        )?);
+        let metrics = config.get_metrics();
+        let config_group_commit = config.group_commit_window.map(|window| GroupCommit::new(window, config.group_commit_max));
+        let value_cache = Arc::new(ValueCache::new(config.value_cache_capacity));
+        let blob_files = Arc::new(BlobFiles::open(&config)?);
         let mut db = LiteDb {
             config,
-            active_file: RwLock::new(active_file),
-            older_files: RwLock::new(older_files),
+            active_file: Arc::new(RwLock::new(active_file)),
+            older_files: Arc::new(RwLock::new(older_files)),
             index,
             file_ids,
             batch_commit_lock: Mutex::new(()),
             seq_no: AtomicUsize::new(1),
-            merging_lock: Mutex::new(()),
+            merging_lock: Arc::new(Mutex::new(())),
+            swap_lock: Arc::new(RwLock::new(())),
             seq_file_exists: false,
             is_initial,
             lock_file,
-            bytes_write: AtomicUsize::new(0),
-            reclaim_size: AtomicUsize::new(0),
+            bytes_write: Arc::new(AtomicUsize::new(0)),
+            reclaim_size: Arc::new(AtomicUsize::new(0)),
+            files_dropped: Arc::new(AtomicUsize::new(0)),
+            snapshots: Arc::new(AtomicUsize::new(0)),
+            watchers: Mutex::new(Vec::new()),
+            value_cache,
+            blob_files,
+            closed: AtomicBool::new(false),
+            repaired_tail: Mutex::new(repaired_tail),
+            verify_failure: Mutex::new(None),
+            sync_thread: Mutex::new(None),
+            auto_merge_thread: Mutex::new(None),
+            metrics,
+            column_families: Default::default(),
+            group_commit: config_group_commit,
+            file_handles,
+            key_versions: Mutex::new(KeyVersions::default()),
         };
         // B+ 树则不需要从数据文件中加载索引
         if db.config.index_type != IndexType::BPlusTree {
-            // 从 hint 文件中加载索引
-            db.load_index_from_hint_file()?;
-
-            // 从数据文件中加载索引
-            let current_seq_no = db.load_index_from_data_files()?;
+            // 从 hint 文件和数据文件中加载索引，hint 文件损坏时从所有的数据文件中加载
+            let (current_seq_no, _) = match db.load_index_from_hint_file()? {
+                true => db.load_index_from_data_files()?,
+                false => db.load_index_from_data_files_after(None)?,
+            };
 
             // 更新当前事务序列号
             if current_seq_no > 0 {
                 db.seq_no.store(current_seq_no + 1, Ordering::SeqCst);
             }
 
-            // 重置 IO 类型
-            if db.config.mmap_at_startup {
-                db.reset_io_type();
+            // 重置 IO 类型，只读模式下已经是只读的 IO
+            if db.config.mmap_at_startup && !db.config.in_memory && !db.config.read_only {
+                db.reset_io_type()?;
             }
         }
 
         if db.config.index_type == IndexType::BPlusTree {
-            // 加载事务序列号
-            let (exists, seq_no) = db.load_seq_no();
+            // merge 之后旧的数据文件已经删除，从 hint 文件中更新索引的位置
+            // hint 文件损坏时索引中的位置不再可靠，从所有的数据文件中重建
+            if merged && !db.load_index_from_hint_file()? {
+                db.index.clear()?;
+                db.load_index_from_data_files_after(None)?;
+            }
+
+            // 加载事务序列号，没有正常关闭时从数据文件中恢复
+            let (exists, seq_no) = db.load_seq_no()?;
             if exists {
                 db.seq_no.store(seq_no, Ordering::SeqCst);
                 db.seq_file_exists = exists;
+            } else {
+                let max_seq_no = db.max_seq_no_from_data_files()?;
+                if max_seq_no > 0 {
+                    db.seq_no.store(max_seq_no + 1, Ordering::SeqCst);
+                }
             }
+        }
 
-            let active_file = db.active_file.write();
-            active_file.set_write_off(active_file.file_size());
+        // 只读模式下没有需要持久化或者 merge 的数据
+        if db.config.read_only {
+            return Ok(db);
+        }
+        if let SyncPolicy::Interval(interval) = db.config.get_sync_policy() {
+            let sync_thread = SyncThread::start(
+                db.active_file.clone(),
+                db.blob_files.clone(),
+                db.bytes_write.clone(),
+                db.metrics.clone(),
+                interval,
+            );
+            *db.sync_thread.lock() = Some(sync_thread);
+        }
+        if let Some(interval) = db.config.auto_merge_interval {
+            *db.auto_merge_thread.lock() = Some(AutoMergeThread::start(db.merger(), db.column_families.clone(), interval));
         }
         Ok(db)
     }
 
-    pub fn open_table(&self) -> ResultDb<Table> {
-        todo!()
+    /// 获取数据库的统计信息
+    pub fn stat(&self) -> ResultDb<Stat> {
+        self.check_open()?;
+        let keys = self.index.list_keys()?;
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        Ok(Stat {
+            key_num: keys.len(),
+            data_file_num: older_files.len() + 1,
+            reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
+            disk_size: active_file.file_size() + older_files.values().map(|f| f.file_size()).sum::<u64>(),
+            files_dropped: self.files_dropped.load(Ordering::SeqCst),
+        })
     }
 
-    fn get_value_by_pos(&self, log_db_pos: &LogDbPos) -> ResultDb<Bytes> {
+    /// 当前的全局事务序列号，下一个提交的批次使用这个序列号，每提交一个批次加一
+    /// 不在批次中的写入使用 NON_TRANSACTION_SEQ_NO，不改变序列号
+    pub fn seq_no(&self) -> usize {
+        self.seq_no.load(Ordering::SeqCst)
+    }
+
+    /// 逐条读取所有的数据文件，按照索引判断每条记录是否有效，按照文件 id 从小到大排列
+    /// 统计期间写入会被阻塞
+    pub fn file_stats(&self) -> ResultDb<Vec<FileStat>> {
+        self.check_open()?;
         let active_file = self.active_file.read();
-        let log_db = {
-            if active_file.get_file_id() == log_db_pos.file_id {
-                active_file.read_log_db(log_db_pos.offset)?.log_db
-            } else {
-                match self.older_files.read().get(&log_db_pos.file_id) {
-                    None => return Err(ErrDb::new_io_file_not_find("")),
-                    Some(data_file) => data_file.read_log_db(log_db_pos.offset)?.log_db,
-                }
-            }
-        };
+        let older_files = self.older_files.read();
 
-        if log_db.rec_type == LogDbType::DELETED {
-            return Err(ErrDb::NotFindKey);
-        }
+        let mut file_ids: Vec<u32> = older_files.keys().copied().collect();
+        file_ids.sort();
 
-        Ok(log_db.value.into())
+        let mut stats = Vec::with_capacity(file_ids.len() + 1);
+        for file_id in file_ids.iter() {
+            stats.push(self.file_stat(older_files.get(file_id).unwrap())?);
+        }
+        stats.push(self.file_stat(&active_file)?);
+        Ok(stats)
     }
 
-    pub(crate) fn append_log_db(&self, log_db: &mut LogDb) -> ResultDb<LogDbPos> {
-        let dir_path = self.config.path_db.clone();
-
-        let enc_log_db = log_db.encode();
-        let log_db_len = enc_log_db.len() as u64;
-
-        let mut active_file = self.active_file.write();
+    fn file_stat(&self, data_file: &FileDb) -> ResultDb<FileStat> {
+        let file_id = data_file.get_file_id();
+        let mut stat = FileStat {
+            file_id,
+            total_size: 0,
+            live_size: 0,
+            dead_size: 0,
+        };
+        let mut offset = 0;
+        for log_db in read_log_dbs(data_file) {
+            let (log_db, size) = log_db?;
+            // 只有索引指向的数据记录是有效的，系统数据和事务标识 merge 时都不会重写
+            let live = match log_db.rec_type {
+                LogDbType::NORMAL | LogDbType::BLOB => {
+                    let (real_key, _) = parse_log_db_key(log_db.key)?;
+                    self.index.get(real_key).is_some_and(|pos| pos.file_id == file_id && pos.offset == offset)
+                }
+                _ => false,
+            };
+            match live {
+                true => stat.live_size += size as u64,
+                false => stat.dead_size += size as u64,
+            }
+            stat.total_size += size as u64;
+            offset += size as u64;
+        }
+        Ok(stat)
+    }
 
-        // 判断当前活跃文件是否达到了阈值
-        if active_file.get_write_off() + log_db_len > self.config.file_size_db {
-            active_file.sync()?;
+    /// 返回 false 时 key 一定不存在，配置了布隆过滤器时不需要查找索引
+    pub fn probably_contains(&self, key: &Key) -> bool {
+        self.index.probably_contains(key)
+    }
 
-            let current_fid = active_file.get_file_id();
-            // 旧的数据文件存储到 map 中
-            let mut older_files = self.older_files.write();
-            let old_file = FileDb::new(dir_path.clone(), current_fid, IoType::StdIo)?;
-            older_files.insert(current_fid, old_file);
+    /// key 是否存在，不读取数据文件
+    /// 只读取内存中的索引，不检查是否已经关闭，reopen 失败之后索引是空的，返回 false
+    pub fn exists(&self, key: &Key) -> bool {
+        self.index.probably_contains(key) && self.index.get(key.to_vec()).is_some()
+    }
 
-            let new_file = FileDb::new(dir_path.clone(), current_fid + 1, IoType::StdIo)?;
-            *active_file = new_file;
+    /// 获取 key 的值以及记录在数据文件中的位置
+    pub fn get_with_pos(&self, key: &Key) -> ResultDb<(Value, LogDbPos)> {
+        self.check_open()?;
+        if !self.index.probably_contains(key) {
+            return Err(ErrDb::NotFindKey);
         }
+        let _swap_lock = self.swap_lock.read();
+        let pos = match self.index.try_get(key.to_vec())? {
+            Some(pos) => pos,
+            None => return Err(ErrDb::NotFindKey),
+        };
+        let value = self.get_value_cached(key, &pos)?;
+        Ok((value, pos))
+    }
 
-        // 追加写数据到当前活跃文件中
-        let write_off = active_file.get_write_off();
-        active_file.write(&enc_log_db)?;
-
-        let previous = self.bytes_write.fetch_add(enc_log_db.len(), Ordering::SeqCst);
-        // 根据配置项决定是否持久化
-        let mut need_sync = self.config.sync_writes;
-        if !need_sync && self.config.bytes_per_sync > 0 && previous + enc_log_db.len() >= self.config.bytes_per_sync {
-            need_sync = true;
+    /// 和 get 一样，数据文件的 IO 支持 read_shared 时（如 MMapIo）返回的 value 直接引用映射的内存，不复制
+    /// value 持有映射，之后的写入、merge 删除文件都不影响已经返回的 value；其它的 IO 和 blob 中的 value 仍然复制
+    /// 不加入 value 缓存，避免缓存一直持有旧的映射
+    pub fn get_zero_copy(&self, key: &Key) -> ResultDb<Value> {
+        let _slow = SlowOp::start(&self.config, "get");
+        self.check_open()?;
+        if !self.index.probably_contains(key) {
+            self.metrics.on_get(false);
+            return Err(ErrDb::NotFindKey);
         }
+        let _swap_lock = self.swap_lock.read();
+        let pos = match self.index.try_get(key.to_vec())? {
+            Some(pos) => pos,
+            None => {
+                self.metrics.on_get(false);
+                return Err(ErrDb::NotFindKey);
+            }
+        };
+        let re = match self.value_cache.get(key, &pos) {
+            Some(value) => Ok(value),
+            None => self.get_value_shared(&pos),
+        };
+        self.metrics.on_get(re.is_ok());
+        re
+    }
 
-        if need_sync {
-            active_file.sync()?;
-            self.bytes_write.store(0, Ordering::SeqCst);
+    fn get_value_shared(&self, pos: &LogDbPos) -> ResultDb<Bytes> {
+        let shared = {
+            let active_file = self.active_file.read();
+            if active_file.get_file_id() == pos.file_id {
+                active_file.read_value_shared(pos)
+            } else {
+                self.with_older_file(&active_file, pos.file_id, |data_file| Ok(data_file.read_value_shared(pos)))?
+            }
+        };
+        match shared {
+            None => self.get_value_by_pos(pos),
+            Some(re) => match re? {
+                (LogDbType::DELETED | LogDbType::SYSTEM, _) => Err(ErrDb::NotFindKey),
+                (LogDbType::BLOB, value) => self.blob_files.read(&try_decode_log_db_pos(&value)?),
+                (_, value) => Ok(value),
+            },
         }
-
-        Ok(LogDbPos {
-            file_id: active_file.get_file_id(),
-            offset: write_off,
-            size: enc_log_db.len() as u32,
-        })
     }
 
-    /// 从数据文件中加载内存索引
-    /// 遍历数据文件中的内容，并依次处理其中的记录
-    fn load_index_from_data_files(&self) -> ResultDb<usize> {
-        let mut current_seq_no = NON_TRANSACTION_SEQ_NO;
-
-        // 数据文件为空，直接返回
-        if self.file_ids.is_empty() {
-            return Ok(current_seq_no);
+    /// 读取 key 在数据文件中编码之后的整条记录，包括 header、key、value 和校验值，可以用于复制和调试
+    /// 记录中的 key 带有事务序列号的前缀，value 在 blob 文件中时记录中保存的是 blob 的位置
+    pub fn get_raw(&self, key: &Key) -> ResultDb<Vec<u8>> {
+        self.check_open()?;
+        let _swap_lock = self.swap_lock.read();
+        let pos = match self.index.try_get(key.to_vec())? {
+            Some(pos) => pos,
+            None => return Err(ErrDb::NotFindKey),
+        };
+        let active_file = self.active_file.read();
+        if active_file.get_file_id() == pos.file_id {
+            return active_file.read_raw(pos.offset, pos.size as usize);
         }
+        self.with_older_file(&active_file, pos.file_id, |data_file| data_file.read_raw(pos.offset, pos.size as usize))
+    }
 
-        // 拿到最近未参与 merge 的文件 id
-        let mut has_merge = false;
-        let mut non_merge_fid = 0;
-        let merge_fin_file = self.config.path_db.join(MERGE_FINISHED_FILE_NAME);
-        if merge_fin_file.is_file() {
-            let merge_fin_file = FileDb::new_merge_fin_file(self.config.path_db.clone())?;
-            let merge_fin_record = merge_fin_file.read_log_db(0)?;
-            let v = String::from_utf8(merge_fin_record.log_db.value).unwrap();
+    pub fn open_table(&self) -> ResultDb<Table> {
+        todo!()
+    }
 
-            non_merge_fid = v.parse::<u32>().unwrap();
-            has_merge = true;
+    // 先从缓存中读取，没有命中时读取数据文件并放入缓存
+    fn get_value_cached(&self, key: &Key, pos: &LogDbPos) -> ResultDb<Bytes> {
+        if let Some(value) = self.value_cache.get(key, pos) {
+            return Ok(value);
         }
+        let value = self.get_value_by_pos(pos)?;
+        self.value_cache.insert(key, *pos, value.clone());
+        Ok(value)
+    }
 
-        // 暂存事务相关的数据
-        let mut transaction_log_dbs = HashMap::new();
-
+    pub(crate) fn get_value_by_pos(&self, log_db_pos: &LogDbPos) -> ResultDb<Bytes> {
         let active_file = self.active_file.read();
-        let older_files = self.older_files.read();
-
-        // 遍历每个文件 id，取出对应的数据文件，并加载其中的数据
-        for (i, file_id) in self.file_ids.iter().enumerate() {
-            // 如果比最近未参与 merge 的文件 id 更小，则已经从 hint 文件中加载索引了
-            if has_merge && *file_id < non_merge_fid {
-                continue;
+        let log_db = {
+            if active_file.get_file_id() == log_db_pos.file_id {
+                active_file.read_log_db(log_db_pos.offset)?.log_db
+            } else {
+                self.with_older_file(&active_file, log_db_pos.file_id, |data_file| data_file.read_log_db(log_db_pos.offset))?
+                    .log_db
             }
+        };
 
-            let mut offset = 0;
-            loop {
-                let log_record_res = match *file_id == active_file.get_file_id() {
-                    true => active_file.read_log_db(offset),
-                    false => {
-                        let data_file = older_files.get(file_id).unwrap();
-                        data_file.read_log_db(offset)
-                    }
-                };
-
-                let (mut log_db, size) = match log_record_res {
-                    Ok(result) => (result.log_db, result.size),
-                    Err(e) => {
-                        if e == ErrDb::new_io_eof("") {
-                            break;
-                        }
-                        return Err(e);
-                    }
-                };
-
-                // 构建内存索引
-                let log_db_pos = LogDbPos {
-                    file_id: *file_id,
-                    offset,
-                    size: size as u32,
-                };
+        if log_db.rec_type == LogDbType::DELETED || log_db.rec_type == LogDbType::SYSTEM {
+            return Err(ErrDb::NotFindKey);
+        }
+        // value 在 blob 文件中
+        if log_db.rec_type == LogDbType::BLOB {
+            return self.blob_files.read(&try_decode_log_db_pos(&log_db.value)?);
+        }
 
-                // 解析 key，拿到实际的 key 和 seq no
-                let (real_key, seq_no) = parse_log_db_key(log_db.key.clone());
-                // 非事务提交的情况，直接更新内存索引
-                if seq_no == NON_TRANSACTION_SEQ_NO {
-                    self.update_index(real_key, log_db.rec_type, log_db_pos);
-                } else {
-                    // 事务有提交的标识，更新内存索引
-                    if log_db.rec_type == LogDbType::TXNFINISHED {
-                        let records: &Vec<TransactionLogDb> = transaction_log_dbs.get(&seq_no).unwrap();
-                        for txn_record in records.iter() {
-                            self.update_index(txn_record.log_db.key.clone(), txn_record.log_db.rec_type, txn_record.pos);
-                        }
-                        transaction_log_dbs.remove(&seq_no);
-                    } else {
-                        log_db.key = real_key;
-                        transaction_log_dbs
-                            .entry(seq_no)
-                            .or_insert(Vec::new())
-                            .push(TransactionLogDb { log_db, pos: log_db_pos });
-                    }
-                }
+        Ok(log_db.value.into())
+    }
 
-                // 更新当前事务序列号
-                if seq_no > current_seq_no {
-                    current_seq_no = seq_no;
-                }
+    // 用 file_id 对应的旧数据文件执行 f，调用者持有活跃文件的读锁
+    // 文件不在 older_files 中时，如被外部的工具替换过，尝试按照 id 从数据目录中打开，打开之后放入 older_files
+    // 只打开 id 小于活跃文件的文件，数据目录中也没有时返回文件不存在的错误
+    fn with_older_file<R>(&self, active_file: &FileDb, file_id: u32, f: impl FnOnce(&FileDb) -> ResultDb<R>) -> ResultDb<R> {
+        if let Some(data_file) = self.older_files.read().get(&file_id) {
+            return f(data_file);
+        }
+        let file_name = self.config.data_file_name(&self.config.path_db, file_id);
+        if file_id >= active_file.get_file_id() || !file_name.is_file() {
+            return Err(ErrDb::new_io_file_not_find(&file_name.display().to_string()));
+        }
+        let mut older_files = self.older_files.write();
+        if let Entry::Vacant(entry) = older_files.entry(file_id) {
+            log::warn!("the data file {} is not loaded, open it from the data dir", file_name.display());
+            let mut data_file = FileDb::new_with_file_name(file_name, file_id, self.config.data_io_type())?;
+            data_file.set_verify_crc(self.config.verify_crc_on_read);
+            limit_open_file(self.file_handles.as_ref(), &mut data_file, self.config.data_io_type());
+            entry.insert(data_file);
+        }
+        let older_files = RwLockWriteGuard::downgrade(older_files);
+        f(older_files.get(&file_id).unwrap())
+    }
 
-                // 递增 offset，下一次读取的时候从新的位置开始
-                offset += size as u64;
-            }
+    /// 持久化并封存当前的活跃文件，之后的数据写入新的活跃文件，返回新的活跃文件 id
+    /// 可以用于备份时确定文件边界，不需要等待活跃文件写满
+    pub fn rotate(&self) -> ResultDb<u32> {
+        self.check_writable()?;
+        let mut active_file = self.active_file.write();
+        rotate_active_file(
+            &self.config,
+            &mut active_file,
+            &self.older_files,
+            &self.reclaim_size,
+            self.metrics.as_ref(),
+            self.file_handles.as_ref(),
+        )
+    }
 
-            // 设置活跃文件的 offset
-            if i == self.file_ids.len() - 1 {
-                active_file.set_write_off(offset);
-            }
+    /// 把 key 当前的值重新写到活跃文件中，旧的版本都可以被 merge 回收
+    /// 只重写一个 key，比整个 merge 的代价小得多，key 不存在时返回 NotFindKey
+    pub fn compact_key(&self, key: &Key) -> ResultDb<()> {
+        if key.is_empty() {
+            return Err(ErrDb::InvalidParameter);
         }
-        Ok(current_seq_no)
+        // 读取和重写之间不能有其它的写入，否则会覆盖掉新写入的值
+        let _swap_lock = self.swap_lock.write();
+        let old_pos = match self.index.try_get(key.to_vec())? {
+            Some(pos) => pos,
+            None => return Err(ErrDb::NotFindKey),
+        };
+        let value = self.get_value_by_pos(&old_pos)?;
+        let mut log_db = LogDb {
+            key: log_db_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
+            value: value.to_vec(),
+            rec_type: LogDbType::NORMAL,
+        };
+        let pos = self.append_log_db(&mut log_db)?;
+        self.index.try_put(key.to_vec(), pos)?;
+        self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        Ok(())
     }
 
-    //batch
-    pub fn new_write_batch(&self, options: WriteBatchOptions) -> ResultDb<WriteBatch> {
-        if self.config.index_type == BTree && !self.seq_file_exists && !self.is_initial {
-            return Err(ErrDb::InvalidBatch);
-        }
-        Ok(WriteBatch {
-            pending: Arc::new(Mutex::new(HashMap::new())),
-            db: self,
-            options,
-        })
+    /// 打开时是否是新建的数据库，即没有任何数据文件，可以用于判断是否需要写入初始数据
+    pub fn is_new(&self) -> bool {
+        self.is_initial
     }
 
-    fn update_index(&self, key: Vec<u8>, rec_type: LogDbType, pos: LogDbPos) {
-        if rec_type == LogDbType::NORMAL {
-            if let Some(old_pos) = self.index.put(key.clone(), pos) {
-                self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+    /// 删除所有的数据，数据库保持打开，之后的数据写入新的活跃文件
+    /// 有存活的快照时返回错误，快照还需要读取旧的数据文件
+    pub fn clear(&self) -> ResultDb<()> {
+        self.check_writable()?;
+        // 和 merge、批量提交互斥，锁的顺序和 merge 一致
+        let _merging_lock = self.merging_lock.lock();
+        let _batch_lock = self.batch_commit_lock.lock();
+        let _swap_lock = self.swap_lock.write();
+        if self.snapshots.load(Ordering::SeqCst) > 0 {
+            return Err(ErrDb::Err("can not clear the db while there are snapshots".to_owned()));
+        }
+
+        let mut active_file = self.active_file.write();
+        let mut older_files = self.older_files.write();
+        let in_memory = self.config.in_memory;
+
+        // 先删除所有的数据文件，新的活跃文件可能和旧的文件 id 相同
+        for (_, data_file) in older_files.drain() {
+            let file_name = data_file.get_file_name().to_path_buf();
+            drop(data_file);
+            if !in_memory {
+                fs::remove_file(file_name)?;
             }
         }
-        if rec_type == LogDbType::DELETED {
-            let mut size = pos.size;
-            if let Some(old_pos) = self.index.delete(key) {
-                size += old_pos.size;
+        if !in_memory {
+            fs::remove_file(active_file.get_file_name())?;
+            for name in [HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME] {
+                let file_name = self.config.path_db.join(name);
+                if file_name.is_file() {
+                    fs::remove_file(file_name)?;
+                }
             }
-            self.reclaim_size.fetch_add(size as usize, Ordering::SeqCst);
         }
-    }
+        let file_id = self.config.start_file_id.unwrap_or(INITIAL_FILE_ID);
+        *active_file = open_data_file(&self.config, &self.config.path_db, file_id, self.config.data_io_type())?;
 
-    // B+树索引模式下加载事务序列号
-    fn load_seq_no(&self) -> (bool, usize) {
-        let file_name = self.config.path_db.join(SEQ_NO_FILE_NAME);
-        if !file_name.is_file() {
-            return (false, 0);
+        if !in_memory {
+            self.blob_files.clear()?;
         }
+        self.index.clear()?;
+        self.value_cache.clear();
+        self.record_clear();
+        self.seq_no.store(1, Ordering::SeqCst);
+        self.reclaim_size.store(0, Ordering::SeqCst);
+        self.bytes_write.store(0, Ordering::SeqCst);
+        Ok(())
+    }
 
-        let seq_no_file = FileDb::new_seq_no_file(self.config.path_db.clone()).unwrap();
-        let log_db = match seq_no_file.read_log_db(0) {
-            Ok(re) => re.log_db,
-            Err(e) => panic!("failed to read seq no: {}", e),
-        };
-        let v = String::from_utf8(log_db.value).unwrap();
-        let seq_no = v.parse::<usize>().unwrap();
-
-        // 加载后删除掉，避免追加写入
-        fs::remove_file(file_name).unwrap();
+    /// 和 add 一样，但是不管 sync_writes 的配置，返回之前都持久化这次写入
+    pub fn add_synced(&self, k: &Key, v: &Value) -> ResultDb<()> {
+        self.add_with_sync(k, v, true, false).map(|_| ())
+    }
 
-        (true, seq_no)
+    /// 和 add 一样，返回这次写入是否已经持久化，由持久化策略决定
+    /// 返回 Buffered 时这次写入也可能已经被之后的持久化一起持久化了
+    pub fn add_durable(&self, k: &Key, v: &Value) -> ResultDb<Durability> {
+        self.add_with_sync(k, v, false, false).map(|(durability, _)| durability)
     }
 
-    fn reset_io_type(&self) {
-        let mut active_file = self.active_file.write();
-        active_file.set_io_manager(self.config.path_db.clone(), IoType::StdIo);
-        let mut older_files = self.older_files.write();
-        for (_, file) in older_files.iter_mut() {
-            file.set_io_manager(self.config.path_db.clone(), IoType::StdIo);
-        }
+    /// 和 add 一样，同时返回写入之前的值，key 不存在或者已经删除时返回 None
+    pub fn swap(&self, k: &Key, v: &Value) -> ResultDb<Option<Value>> {
+        self.add_with_sync(k, v, false, true).map(|(_, old)| old)
     }
-}
 
-impl Getter for LiteDb {
-    fn get(&self, key: &Key) -> ResultDb<Value> {
-        let p = {
-            match self.index.get(key.to_vec()) {
-                Some(p) => p,
-                None => return Err(ErrDb::NotFindKey),
-            }
-        };
-        self.get_value_by_pos(&p)
+    /// 和 remove 一样，但是不管 sync_writes 的配置，返回之前都持久化这次删除
+    pub fn remove_synced(&self, key: &Key) -> ResultDb<Option<Value>> {
+        self.remove_with_sync(key, true)
     }
-}
 
-impl Adder for LiteDb {
-    fn add(&self, k: &Key, v: &Value) -> ResultDb<()> {
+    // read_old 为 true 时在写入之前读取旧的值
+    fn add_with_sync(&self, k: &Key, v: &Value, force_sync: bool, read_old: bool) -> ResultDb<(Durability, Option<Value>)> {
+        let _slow = SlowOp::start(&self.config, "add");
         // 判断 key 的有效性
         if k.is_empty() {
             return Err(ErrDb::InvalidParameter);
@@ -400,24 +641,39 @@ impl Adder for LiteDb {
             rec_type: LogDbType::NORMAL,
         };
 
-        let log_db_pos = self.append_log_db(&mut log_db)?;
-
-        if let Some(old_pos) = self.index.put(k.to_vec(), log_db_pos) {
-            self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
-        }
+        let (durability, old) = {
+            // 写入数据和更新索引之间不能替换数据文件
+            let _swap_lock = self.swap_lock.read();
+            let old = match read_old {
+                true => match self.index.try_get(k.to_vec())? {
+                    Some(pos) => Some(self.get_value_cached(k, &pos)?),
+                    None => None,
+                },
+                false => None,
+            };
+            let (log_db_pos, durability) = self.append_log_db_with_sync(&mut log_db, force_sync)?;
+            self.metrics.on_put(log_db_pos.size as usize);
+
+            if let Some(old_pos) = self.index.try_put(k.to_vec(), log_db_pos)? {
+                self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+                self.value_cache.remove(k);
+            }
+            self.record_write(k);
+            (durability, old)
+        };
+        self.notify_change(k, ChangeKind::Put);
 
-        Ok(())
+        Ok((durability, old))
     }
-}
 
-impl Remover for LiteDb {
-    fn remove(&self, key: &Key) -> ResultDb<Option<Value>> {
+    fn remove_with_sync(&self, key: &Key, force_sync: bool) -> ResultDb<Option<Value>> {
         if key.is_empty() {
             return Err(ErrDb::InvalidParameter);
         }
 
+        let swap_lock = self.swap_lock.read();
         let p = {
-            match self.index.get(key.to_vec()) {
+            match self.index.try_get(key.to_vec())? {
                 Some(p) => p,
                 None => return Ok(None),
             }
@@ -426,414 +682,3816 @@ impl Remover for LiteDb {
 
         let mut log_db = LogDb {
             key: log_db_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
-            value: Default::default(),
+            value: self.config.tombstone_value(),
             rec_type: LogDbType::DELETED,
         };
 
-        let pos = self.append_log_db(&mut log_db)?;
+        let (pos, _) = self.append_log_db_with_sync(&mut log_db, force_sync)?;
         self.reclaim_size.fetch_add(pos.size as usize, Ordering::SeqCst);
 
         // delete the key in indexes
-        if let Some(old_pos) = self.index.delete(key.to_vec()) {
+        if let Some(old_pos) = self.index.try_delete(key.to_vec())? {
             self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
         }
+        self.value_cache.remove(key);
+        self.record_write(key);
+        drop(swap_lock);
+        self.notify_change(key, ChangeKind::Delete);
 
         Ok(Some(value))
     }
 
-    fn remove_fast(&self, key: &Key) -> ResultDb<()> {
-        if key.is_empty() {
-            return Err(ErrDb::InvalidParameter);
-        }
+    pub(crate) fn append_log_db(&self, log_db: &mut LogDb) -> ResultDb<LogDbPos> {
+        let pos = self.append_log_db_without_sync(log_db)?;
+        self.sync_by_policy(pos.size as usize)?;
+        Ok(pos)
+    }
 
-        let pos = self.index.get(key.to_vec());
-        if pos.is_none() {
-            return Ok(());
+    // force_sync 为 true 时不管持久化策略，都持久化这次写入的文件，同时返回这次写入是否已经持久化
+    // 配置了 group commit 时和并发的写入一起写入，并且总是持久化
+    fn append_log_db_with_sync(&self, log_db: &mut LogDb, force_sync: bool) -> ResultDb<(LogDbPos, Durability)> {
+        if let Some(group_commit) = &self.group_commit {
+            self.check_writable()?;
+            let log_db = LogDb {
+                key: std::mem::take(&mut log_db.key),
+                value: std::mem::take(&mut log_db.value),
+                rec_type: log_db.rec_type,
+            };
+            return Ok((group_commit.commit(self, log_db)?, Durability::Synced));
         }
-
-        let mut log_db = LogDb {
-            key: log_db_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
-            value: Default::default(),
-            rec_type: LogDbType::DELETED,
-        };
-
-        let pos = self.append_log_db(&mut log_db)?;
-        self.reclaim_size.fetch_add(pos.size as usize, Ordering::SeqCst);
-
-        // delete the key in indexes
-        if let Some(old_pos) = self.index.delete(key.to_vec()) {
-            self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        let pos = self.append_log_db_without_sync(log_db)?;
+        if force_sync {
+            self.sync_files(&BTreeSet::from([pos.file_id]))?;
+            return Ok((pos, Durability::Synced));
         }
-
-        Ok(())
+        let durability = self.sync_by_policy(pos.size as usize)?;
+        Ok((pos, durability))
     }
-}
 
-impl Closer for LiteDb {
-    fn close(&self) -> ResultDb<()> {
-        if !self.config.path_db.is_dir() {
-            return Ok(());
+    // 追加写数据，不根据持久化策略持久化，由调用者决定什么时候持久化
+    pub(crate) fn append_log_db_without_sync(&self, log_db: &mut LogDb) -> ResultDb<LogDbPos> {
+        self.check_writable()?;
+        if self.blob_files.accepts(log_db) {
+            return self.append_blob_log_db(log_db);
         }
-        let seq_no_file = FileDb::new_seq_no_file(self.config.path_db.clone())?;
-        let seq_no = self.seq_no.load(Ordering::SeqCst);
-        let log_db = LogDb {
-            key: SEQ_NO_KEY.as_bytes().to_vec(),
-            value: seq_no.to_string().into_bytes(),
-            rec_type: LogDbType::NORMAL,
-        };
-        seq_no_file.write(&log_db.encode())?;
-        seq_no_file.sync()?;
+        // 编码和计算 crc 不需要持有活跃文件的锁，多个写入可以并行执行
+        let enc_log_db = log_db.encode_with(self.config.checksum);
+        self.append_encoded(&enc_log_db, log_db.seq_no())
+    }
 
-        let read_guard = self.active_file.read();
-        read_guard.sync()?;
-        self.lock_file.unlock()?;
-        Ok(())
+    // value 先写到 blob 文件中，记录中只保存 blob 的位置
+    // blob 和记录在同一个活跃文件的写锁中写入，merge 切换活跃文件时也会切换 blob 文件，参与 merge 的记录引用的 blob 都在参与 merge 的 blob 文件中
+    fn append_blob_log_db(&self, log_db: &LogDb) -> ResultDb<LogDbPos> {
+        let mut active_file = self.active_file.write();
+        self.append_blob_locked(&mut active_file, log_db)
     }
-}
 
-impl Editor for LiteDb {}
+    fn append_blob_locked(&self, active_file: &mut FileDb, log_db: &LogDb) -> ResultDb<LogDbPos> {
+        let enc_log_db = self.encode_blob_log_db(log_db)?;
+        self.append_to_active_file(active_file, &enc_log_db, log_db.seq_no())
+    }
 
-impl Db for LiteDb {
-    fn sync(&self) -> ResultDb<()> {
-        let read_guard = self.active_file.read();
-        read_guard.sync()
+    // 写入 blob 并返回编码之后的 blob 记录，需要持有活跃文件的写锁
+    fn encode_blob_log_db(&self, log_db: &LogDb) -> ResultDb<Vec<u8>> {
+        let blob_pos = self.blob_files.write(&log_db.key, &log_db.value)?;
+        let blob_log_db = LogDb {
+            key: log_db.key.clone(),
+            value: blob_pos.encode(),
+            rec_type: LogDbType::BLOB,
+        };
+        Ok(blob_log_db.encode_with(self.config.checksum))
     }
-}
 
-impl Drop for LiteDb {
-    fn drop(&mut self) {
-        if let Err(e) = self.close() {
-            log::error!("error whiling close engine: {}", e);
+    // 在一次活跃文件的写锁中追加多条记录，中间不会有其它的写入
+    // 记录编码到同一个缓冲区中一次写入，只有需要切换活跃文件时才分多次写入，位置按照缓冲区中的偏移计算
+    // 任何一次写入失败时把活跃文件截断到这些记录开始的位置，不会留下写了一半的数据，已经封存的文件中的数据不会回滚
+    pub(crate) fn append_log_dbs_atomic(&self, log_dbs: &[LogDb]) -> ResultDb<Vec<LogDbPos>> {
+        self.append_log_dbs_rollback(log_dbs, &mut false)
+    }
+
+    // 追加一个事务的记录，seq_no 是事务已经取得的序列号
+    // 失败时只有所有的记录都已经回滚才把序列号还给下一次提交
+    // 有记录已经在封存的文件中或者回滚失败时不能重复使用序列号，否则下一个事务完成的标识会让这些记录在恢复时生效
+    pub(crate) fn append_txn_log_dbs(&self, log_dbs: &[LogDb], seq_no: usize) -> ResultDb<Vec<LogDbPos>> {
+        let mut rolled_back = false;
+        let re = self.append_log_dbs_rollback(log_dbs, &mut rolled_back);
+        if re.is_err() && rolled_back {
+            self.seq_no.store(seq_no, Ordering::SeqCst);
         }
+        re
     }
-}
 
-fn load_data_files(dir_path: PathBuf, use_mmap: bool) -> ResultDb<Vec<FileDb>> {
-    let dir = fs::read_dir(dir_path.clone())?;
+    // 和 append_log_dbs_atomic 一样，失败时 rolled_back 表示这些记录是否都已经从数据文件中去掉
+    fn append_log_dbs_rollback(&self, log_dbs: &[LogDb], rolled_back: &mut bool) -> ResultDb<Vec<LogDbPos>> {
+        *rolled_back = true;
+        self.check_writable()?;
+        // 编码和计算 crc 不需要持有活跃文件的锁，blob 记录在写入 blob 之后才能编码
+        let enc_log_dbs: Vec<Option<Vec<u8>>> = log_dbs
+            .iter()
+            .map(|log_db| match self.blob_files.accepts(log_db) {
+                true => None,
+                false => Some(log_db.encode_with(self.config.checksum)),
+            })
+            .collect();
 
-    let mut file_ids: Vec<u32> = Vec::new();
+        let mut active_file = self.active_file.write();
+        let mut start = active_file.get_write_off();
+        let mut positions = Vec::with_capacity(log_dbs.len());
+        if let Err(e) = self.append_encoded_batch(&mut active_file, log_dbs, enc_log_dbs, &mut start, &mut positions, rolled_back) {
+            if let Err(re) = self.rollback_active_file(&mut active_file, start) {
+                log::error!("failed to roll back the active file: {}", re);
+                *rolled_back = false;
+            }
+            return Err(e);
+        }
+        Ok(positions)
+    }
+
+    // start 是这些记录在活跃文件中开始的位置，切换了活跃文件之后新的活跃文件中只有这些记录
+    // 有记录写入之后切换了活跃文件时，这些记录已经封存，rolled_back 设为 false
+    fn append_encoded_batch(
+        &self,
+        active_file: &mut FileDb,
+        log_dbs: &[LogDb],
+        enc_log_dbs: Vec<Option<Vec<u8>>>,
+        start: &mut u64,
+        positions: &mut Vec<LogDbPos>,
+        rolled_back: &mut bool,
+    ) -> ResultDb<()> {
+        let mut buf = Vec::new();
+        let mut seq_nos = Vec::new();
+        for (log_db, enc_log_db) in log_dbs.iter().zip(enc_log_dbs) {
+            let enc_log_db = match enc_log_db {
+                Some(enc_log_db) => enc_log_db,
+                None => self.encode_blob_log_db(log_db)?,
+            };
+            // 当前活跃文件放不下时，先写入缓冲区中的记录再切换
+            if self
+                .config
+                .exceeds_file_limit(active_file, (buf.len() + enc_log_db.len()) as u64, seq_nos.len() as u64 + 1)
+            {
+                write_buffered(active_file, &mut buf, &mut seq_nos)?;
+                rotate_active_file(
+                    &self.config,
+                    active_file,
+                    &self.older_files,
+                    &self.reclaim_size,
+                    self.metrics.as_ref(),
+                    self.file_handles.as_ref(),
+                )?;
+                if !positions.is_empty() {
+                    *rolled_back = false;
+                }
+                *start = 0;
+            }
+            positions.push(LogDbPos {
+                file_id: active_file.get_file_id(),
+                offset: active_file.get_write_off() + buf.len() as u64,
+                size: enc_log_db.len() as u32,
+            });
+            buf.extend_from_slice(&enc_log_db);
+            seq_nos.push(log_db.seq_no());
+        }
+        write_buffered(active_file, &mut buf, &mut seq_nos)
+    }
+
+    // 把活跃文件截断到 offset，预分配的文件重新分配
+    fn rollback_active_file(&self, active_file: &mut FileDb, offset: u64) -> ResultDb<()> {
+        active_file.truncate(offset)?;
+        if self.config.preallocate && !self.config.in_memory {
+            active_file.preallocate(self.config.file_size_db)?;
+        }
+        Ok(())
+    }
+
+    // 追加写已经编码好的数据，只有文件切换和写入在活跃文件的写锁中，保证 offset 的正确
+    fn append_encoded(&self, enc_log_db: &[u8], seq_no: usize) -> ResultDb<LogDbPos> {
+        let mut active_file = self.active_file.write();
+        self.append_to_active_file(&mut active_file, enc_log_db, seq_no)
+    }
+
+    fn append_to_active_file(&self, active_file: &mut FileDb, enc_log_db: &[u8], seq_no: usize) -> ResultDb<LogDbPos> {
+        let log_db_len = enc_log_db.len() as u64;
+
+        // 判断当前活跃文件是否达到了阈值
+        if self.config.exceeds_file_limit(active_file, log_db_len, 1) {
+            rotate_active_file(
+                &self.config,
+                active_file,
+                &self.older_files,
+                &self.reclaim_size,
+                self.metrics.as_ref(),
+                self.file_handles.as_ref(),
+            )?;
+        }
+
+        // 追加写数据到当前活跃文件中
+        let write_off = active_file.get_write_off();
+        active_file.write(enc_log_db)?;
+        active_file.add_footer_record(seq_no);
+
+        Ok(LogDbPos {
+            file_id: active_file.get_file_id(),
+            offset: write_off,
+            size: enc_log_db.len() as u32,
+        })
+    }
+
+    // 累计写入的字节数，并根据持久化策略决定是否持久化活跃文件，Interval 由后台线程持久化，返回是否持久化了
+    pub(crate) fn sync_by_policy(&self, written: usize) -> ResultDb<Durability> {
+        let previous = self.bytes_write.fetch_add(written, Ordering::SeqCst);
+        let need_sync = match self.config.get_sync_policy() {
+            SyncPolicy::Never | SyncPolicy::Interval(_) => false,
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::EveryBytes(bytes) => previous + written >= bytes,
+        };
+
+        if need_sync {
+            self.blob_files.sync()?;
+            self.active_file.read().sync()?;
+            self.metrics.on_sync();
+            self.bytes_write.store(0, Ordering::SeqCst);
+            return Ok(Durability::Synced);
+        }
+        Ok(Durability::Buffered)
+    }
+
+    /// 持久化活跃文件和所有打开的旧数据文件，Db::sync 只持久化活跃文件
+    pub fn sync_all(&self) -> ResultDb<()> {
+        self.blob_files.sync()?;
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        active_file.sync()?;
+        self.bytes_write.store(0, Ordering::SeqCst);
+        for data_file in older_files.values() {
+            data_file.sync()?;
+        }
+        self.metrics.on_sync();
+        Ok(())
+    }
+
+    // 持久化写入过的数据文件，每个文件只持久化一次
+    pub(crate) fn sync_files(&self, file_ids: &BTreeSet<u32>) -> ResultDb<()> {
+        self.blob_files.sync()?;
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        for file_id in file_ids.iter() {
+            if *file_id == active_file.get_file_id() {
+                active_file.sync()?;
+                self.bytes_write.store(0, Ordering::SeqCst);
+            } else if let Some(data_file) = older_files.get(file_id) {
+                data_file.sync()?;
+            } else {
+                continue;
+            }
+            self.metrics.on_sync();
+        }
+        Ok(())
+    }
+
+    /// 从数据文件中加载内存索引
+    /// 遍历数据文件中的内容，并依次处理其中的记录，返回最大的事务序列号和读取的记录数
+    pub(crate) fn load_index_from_data_files(&self) -> ResultDb<(usize, usize)> {
+        // 拿到最近未参与 merge 的文件 id
+        let non_merge_fid = get_non_merge_fid(&self.config.path_db)?;
+        self.load_index_from_data_files_after(non_merge_fid)
+    }
+
+    /// 只加载 non_merge_fid 及之后的数据文件，之前的已经从 hint 文件中加载了，为 None 时加载所有的数据文件
+    pub(crate) fn load_index_from_data_files_after(&self, non_merge_fid: Option<u32>) -> ResultDb<(usize, usize)> {
+        let mut current_seq_no = NON_TRANSACTION_SEQ_NO;
+        let mut records = 0;
+
+        // 数据文件为空，直接返回
+        if self.file_ids.is_empty() {
+            return Ok((current_seq_no, records));
+        }
+
+        // 暂存事务相关的数据，按照 estimated_keys 预先分配
+        let mut transaction_log_dbs = new_transaction_log_dbs(&self.config);
+
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+
+        // 如果比最近未参与 merge 的文件 id 更小，则已经从 hint 文件中加载索引了
+        let file_ids: Vec<u32> = self.file_ids.iter().copied().filter(|id| non_merge_fid.is_none_or(|fid| *id >= fid)).collect();
+        // 并行恢复时每一批文件中封存的文件在多个线程中读取，之后仍然按照文件 id 的顺序更新索引，结果和串行的一样
+        let threads = match self.config.parallel_recovery {
+            true => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            false => 1,
+        };
+
+        // file_ids 中的封存文件在打开时都已经加载，找不到时数据目录在打开之后被修改了
+        let older_file = |file_id: &u32| {
+            older_files
+                .get(file_id)
+                .ok_or_else(|| ErrDb::CorruptLayout(format!("data file {} is not loaded", file_id)))
+        };
+        for chunk in file_ids.chunks(threads) {
+            let mut scanned = HashMap::new();
+            if self.config.parallel_recovery {
+                let data_files = chunk
+                    .iter()
+                    .filter(|file_id| **file_id != active_file.get_file_id())
+                    .map(|file_id| Ok((*file_id, older_file(file_id)?)))
+                    .collect::<ResultDb<Vec<_>>>()?;
+                std::thread::scope(|s| {
+                    let handles: Vec<_> = data_files
+                        .into_iter()
+                        .map(|(file_id, data_file)| (file_id, s.spawn(move || read_log_dbs(data_file).collect::<ResultDb<Vec<_>>>())))
+                        .collect();
+                    for (file_id, handle) in handles {
+                        scanned.insert(file_id, handle.join().expect("failed to join the recovery thread"));
+                    }
+                });
+            }
+
+            // 遍历每个文件 id，取出对应的数据文件，并加载其中的数据
+            for file_id in chunk.iter() {
+                let data_file = match *file_id == active_file.get_file_id() {
+                    true => &*active_file,
+                    false => older_file(file_id)?,
+                };
+                // 修复时先把活跃文件截断到最后一条正确的记录，之后的读取不会遇到损坏的记录
+                if data_file.get_file_id() == active_file.get_file_id() && self.config.repair_on_open && !self.config.read_only {
+                    self.repair_corrupt_tail(data_file)?;
+                }
+                let log_dbs: Box<dyn Iterator<Item = ResultDb<(LogDb, usize)>>> = match scanned.remove(file_id) {
+                    Some(log_dbs) => Box::new(log_dbs?.into_iter().map(Ok)),
+                    None => Box::new(read_log_dbs(data_file)),
+                };
+
+                let mut offset = 0;
+                // 活跃文件的统计，封存时写到 footer 中
+                let mut footer = FileFooter::default();
+                for log_db in log_dbs {
+                    let (mut log_db, size) = match log_db {
+                        Ok(log_db) => log_db,
+                        // 活跃文件末尾的记录损坏，可能是写入的时候崩溃了
+                        Err(e) if data_file.get_file_id() == active_file.get_file_id() => {
+                            return Err(ErrDb::CorruptLayout(format!("{}, open with repair_on_open to truncate the corrupt tail", e)));
+                        }
+                        Err(e) => return Err(e),
+                    };
+
+                    // 构建内存索引
+                    let log_db_pos = LogDbPos {
+                        file_id: *file_id,
+                        offset,
+                        size: size as u32,
+                    };
+
+                    // 系统数据的 key 没有 seq no，也不属于用户的索引
+                    if log_db.rec_type == LogDbType::SYSTEM {
+                        self.reclaim_size.fetch_add(size, Ordering::SeqCst);
+                        offset += size as u64;
+                        records += 1;
+                        if !is_footer_log_db(&log_db) {
+                            footer.record_count += 1;
+                        }
+                        continue;
+                    }
+
+                    // 解析 key，拿到实际的 key 和 seq no
+                    let (real_key, seq_no) = parse_log_db_key(log_db.key.clone())?;
+                    // 非事务提交的情况，直接更新内存索引
+                    if seq_no == NON_TRANSACTION_SEQ_NO {
+                        self.update_index(real_key, log_db.rec_type, log_db_pos)?;
+                    } else {
+                        // 事务有提交的标识，按照写入的顺序更新内存索引，同一个事务中相同的 key 以最后一条记录为准
+                        // 完成的标识之前没有这个事务的记录时不需要更新索引
+                        if log_db.rec_type == LogDbType::TXNFINISHED {
+                            self.reclaim_size.fetch_add(size, Ordering::SeqCst);
+                            let records: Vec<TransactionLogDb> = transaction_log_dbs.remove(&seq_no).unwrap_or_default();
+                            for txn_record in records.into_iter() {
+                                self.update_index(txn_record.log_db.key, txn_record.log_db.rec_type, txn_record.pos)?;
+                            }
+                        } else {
+                            log_db.key = real_key;
+                            transaction_log_dbs
+                                .entry(seq_no)
+                                .or_default()
+                                .push(TransactionLogDb { log_db, pos: log_db_pos });
+                        }
+                    }
+
+                    // 更新当前事务序列号
+                    if seq_no > current_seq_no {
+                        current_seq_no = seq_no;
+                    }
+
+                    // 递增 offset，下一次读取的时候从新的位置开始
+                    offset += size as u64;
+                    records += 1;
+                    footer.record_count += 1;
+                    footer.max_seq_no = footer.max_seq_no.max(seq_no as u64);
+                }
+
+                // 设置活跃文件的 offset
+                if *file_id == active_file.get_file_id() {
+                    active_file.set_write_off(offset);
+                    active_file.set_footer(Some(footer));
+                }
+            }
+        }
+        // 没有提交标识的事务数据不会生效
+        let uncommitted: usize = transaction_log_dbs.values().flatten().map(|r: &TransactionLogDb| r.pos.size as usize).sum();
+        self.reclaim_size.fetch_add(uncommitted, Ordering::SeqCst);
+        Ok((current_seq_no, records))
+    }
+
+    // 把活跃文件截断到最后一条 crc 正确的记录，包括写入时崩溃留下的不完整的记录
+    fn repair_corrupt_tail(&self, active_file: &FileDb) -> ResultDb<()> {
+        let valid = active_file.last_valid_offset();
+        let write_off = active_file.get_write_off();
+        if valid >= write_off {
+            return Ok(());
+        }
+        active_file.truncate(valid)?;
+        let reason = format!(
+            "repaired data file {}: truncated {} corrupt bytes at offset {}",
+            active_file.get_file_id(),
+            write_off - valid,
+            valid
+        );
+        log::warn!("{}", reason);
+        *self.repaired_tail.lock() = Some(reason);
+        Ok(())
+    }
+
+    //batch
+    pub fn new_write_batch(&self, options: WriteBatchOptions) -> ResultDb<WriteBatch<'_>> {
+        self.check_write_batch()?;
+        Ok(WriteBatch {
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            pending_bytes: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+            db: self,
+            options,
+        })
+    }
+
+    // BTree 索引下重新打开有数据的数据库时没有事务序列号，不能使用批量操作
+    // 只读模式下所有的写入都返回 ReadOnly
+    // 关闭之后的读写返回 Closed，reopen 失败时索引是空的，不能返回 NotFindKey
+    pub(crate) fn check_open(&self) -> ResultDb<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(ErrDb::Closed);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_writable(&self) -> ResultDb<()> {
+        self.check_open()?;
+        if self.config.read_only {
+            return Err(ErrDb::ReadOnly);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_write_batch(&self) -> ResultDb<()> {
+        self.check_writable()?;
+        if self.config.index_type == BTree && !self.seq_file_exists && !self.is_initial {
+            return Err(ErrDb::InvalidBatch(InvalidBatchReason::SeqNoUnavailable));
+        }
+        Ok(())
+    }
+
+    /// 是否有以 prefix 开头的 key，prefix 为空时判断是否有 key，只读取索引，不读取 value
+    /// 和 exists 一样不检查是否已经关闭，reopen 失败之后返回 false
+    pub fn contains_prefix(&self, prefix: &[u8]) -> bool {
+        let mut iter = self.index.iterator(IteratorOptions {
+            prefix: prefix.to_vec(),
+            reverse: false,
+        });
+        iter.seek(prefix.to_vec());
+        iter.next().is_some()
+    }
+
+    /// 按照索引的顺序最小的 key，没有 key 时返回 None，不读取 value
+    /// 不检查是否已经关闭，reopen 失败之后返回 None
+    pub fn first_key(&self) -> Option<Bytes> {
+        self.index.first_key().map(Bytes::from)
+    }
+
+    /// 按照索引的顺序最大的 key，没有 key 时返回 None，不读取 value
+    /// 不检查是否已经关闭，reopen 失败之后返回 None
+    pub fn last_key(&self) -> Option<Bytes> {
+        self.index.last_key().map(Bytes::from)
+    }
+
+    /// 以 prefix 开头的 key 的数量，prefix 为空时返回所有 key 的数量，只读取索引，不读取 value
+    /// 不检查是否已经关闭，reopen 失败之后返回 0
+    pub fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let mut iter = self.index.iterator(IteratorOptions {
+            prefix: prefix.to_vec(),
+            reverse: false,
+        });
+        let mut count = 0;
+        while iter.next().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    /// 和 count_prefix 相同，保留旧的名称
+    pub fn count_matching_prefix(&self, prefix: &[u8]) -> usize {
+        self.count_prefix(prefix)
+    }
+
+    /// 删除所有以 prefix 开头的 key，作为一个批次原子提交，返回删除的数量
+    /// prefix 为空时删除所有的 key
+    pub fn delete_prefix(&self, prefix: &[u8]) -> ResultDb<usize> {
+        // 加锁保证事务提交串行化
+        let _lock = self.batch_commit_lock.lock();
+        // 写入和更新索引之间不能替换数据文件，否则 merge 可能把删除的 key 重新放回索引
+        let swap_lock = self.swap_lock.read();
+
+        let mut keys = Vec::new();
+        {
+            let mut iter = self.index.iterator(IteratorOptions {
+                prefix: prefix.to_vec(),
+                reverse: false,
+            });
+            while let Some((key, _)) = iter.next() {
+                keys.push(key.clone());
+            }
+        }
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        // 所有的删除标记和事务完成的标识一次写入，和批次一样失败时回滚，不会留下部分删除
+        let seq_no = self.seq_no.fetch_add(1, Ordering::SeqCst);
+        let mut log_dbs: Vec<LogDb> = keys
+            .iter()
+            .map(|key| LogDb {
+                key: log_db_key_with_seq(key.clone(), seq_no),
+                value: self.config.tombstone_value(),
+                rec_type: LogDbType::DELETED,
+            })
+            .collect();
+        log_dbs.push(LogDb {
+            key: log_db_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no),
+            value: Default::default(),
+            rec_type: LogDbType::TXNFINISHED,
+        });
+        let positions = self.append_txn_log_dbs(&log_dbs, seq_no)?;
+
+        // 删除标记和事务完成的标识都可以回收，整个删除最多持久化一次
+        let mut file_ids = BTreeSet::new();
+        let mut written = 0;
+        for pos in positions.iter() {
+            file_ids.insert(pos.file_id);
+            written += pos.size as usize;
+        }
+        self.reclaim_size.fetch_add(written, Ordering::SeqCst);
+        if self.config.sync_writes {
+            self.sync_files(&file_ids)?;
+        } else {
+            self.sync_by_policy(written)?;
+        }
+
+        // 数据全部写完之后更新内存索引
+        for key in keys.iter() {
+            if let Some(old_pos) = self.index.try_delete(key.clone())? {
+                self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+            }
+            self.value_cache.remove(key);
+            self.record_write(key);
+        }
+        drop(swap_lock);
+        for key in keys.iter() {
+            self.notify_change(key, ChangeKind::Delete);
+        }
+
+        Ok(keys.len())
+    }
+
+    /// 把 old 的值移动到 new，写入 new 和删除 old 在一个事务中原子提交，old 不存在时返回 false
+    /// new 已经存在时覆盖它的值，old 和 new 相同时不写入
+    pub fn rename(&self, old: &Key, new: &Key) -> ResultDb<bool> {
+        if old.is_empty() || new.is_empty() {
+            return Err(ErrDb::InvalidParameter);
+        }
+        let batch = self.new_write_batch(WriteBatchOptions::for_engine(self)?)?;
+        // 读取和提交之间没有其它的事务提交
+        let _lock = self.batch_commit_lock.lock();
+        let value = match self.get(old) {
+            Ok(value) => value,
+            Err(ErrDb::NotFindKey) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if old == new {
+            return Ok(true);
+        }
+        batch.put(new.clone(), value)?;
+        batch.delete(old.clone())?;
+        batch.commit_locked()?;
+        Ok(true)
+    }
+
+    fn update_index(&self, key: Vec<u8>, rec_type: LogDbType, pos: LogDbPos) -> ResultDb<()> {
+        if rec_type == LogDbType::NORMAL || rec_type == LogDbType::BLOB {
+            if let Some(old_pos) = self.index.try_put(key.clone(), pos)? {
+                self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+            }
+        }
+        if rec_type == LogDbType::DELETED {
+            let mut size = pos.size;
+            if let Some(old_pos) = self.index.try_delete(key)? {
+                size += old_pos.size;
+            }
+            self.reclaim_size.fetch_add(size as usize, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    // 从数据文件中找到最大的事务序列号，有 footer 的文件不需要扫描
+    fn max_seq_no_from_data_files(&self) -> ResultDb<usize> {
+        let mut max_seq_no = 0;
+        for data_file in self.older_files.read().values() {
+            let footer = match data_file.read_footer() {
+                Some(footer) => footer,
+                None => data_file.scan_footer()?,
+            };
+            max_seq_no = max_seq_no.max(footer.max_seq_no);
+        }
+        // 活跃文件没有 footer，扫描之后封存时可以写入 footer
+        let active_file = self.active_file.read();
+        let footer = active_file.scan_footer()?;
+        active_file.set_footer(Some(footer));
+        Ok(max_seq_no.max(footer.max_seq_no) as usize)
+    }
+
+    // B+树索引模式下加载事务序列号
+    fn load_seq_no(&self) -> ResultDb<(bool, usize)> {
+        let file_name = self.config.path_db.join(SEQ_NO_FILE_NAME);
+        if !file_name.is_file() {
+            return Ok((false, 0));
+        }
+
+        let seq_no_file = FileDb::new_seq_no_file(self.config.path_db.clone())?;
+        let log_db = seq_no_file.read_log_db(0).map_err(|e| e.context("failed to read seq no"))?.log_db;
+        let seq_no = String::from_utf8_lossy(&log_db.value).parse::<usize>()?;
+
+        // 加载后删除掉，避免追加写入
+        fs::remove_file(file_name)?;
+
+        Ok((true, seq_no))
+    }
+
+    fn reset_io_type(&self) -> ResultDb<()> {
+        let io_type = self.config.data_io_type();
+        let mut active_file = self.active_file.write();
+        active_file.set_io_manager(io_type.clone())?;
+        let mut older_files = self.older_files.write();
+        for (_, file) in older_files.iter_mut() {
+            if !limit_open_file(self.file_handles.as_ref(), file, io_type.clone()) {
+                file.set_io_manager(io_type.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 关闭之后按照同样的配置重新打开，重新查找数据文件并重建索引，可以看到导入、从备份恢复等在数据目录中的修改
+    /// 需要 &mut self，重新打开的过程中没有其他的读写，watch 的订阅不会保留
+    /// B+ 树的索引保存在索引文件中，不会从数据文件中重建，打开失败时数据库保持关闭的状态，之后的读写返回 Closed
+    pub fn reopen(&mut self) -> ResultDb<()> {
+        // 内存模式下数据只在内存中，重新打开会丢失所有的数据
+        if self.config.in_memory {
+            return Err(ErrDb::Err("reopen is not supported in memory mode".to_owned()));
+        }
+        self.close()?;
+        // B+ 树的索引文件有文件锁，先释放旧的索引再打开
+        self.index = Arc::from(new_indexer(BTree, PathBuf::new(), ("", ""), None, None)?);
+        *self = LiteDb::open(self.config.clone())?;
+        Ok(())
+    }
+
+    /// 关闭数据库并返回关闭过程中的错误，之后 Drop 不会再次关闭
+    /// Drop 中的关闭只能记录日志，需要确认数据已经持久化时使用该方法
+    pub fn close_checked(self) -> ResultDb<()> {
+        let re = self.close();
+        self.closed.store(true, Ordering::SeqCst);
+        re
+    }
+
+    fn close_inner(&self) -> ResultDb<()> {
+        if self.config.in_memory || !self.config.path_db.is_dir() {
+            return Ok(());
+        }
+        // 只读模式下没有写入，不保存事务序列号
+        if self.config.read_only {
+            if let Some(lock_file) = &self.lock_file {
+                lock_file.unlock()?;
+            }
+            return Ok(());
+        }
+        let seq_no_file = FileDb::new_seq_no_file(self.config.path_db.clone())?;
+        let seq_no = self.seq_no.load(Ordering::SeqCst);
+        let log_db = LogDb {
+            key: SEQ_NO_KEY.as_bytes().to_vec(),
+            value: seq_no.to_string().into_bytes(),
+            rec_type: LogDbType::SYSTEM,
+        };
+        seq_no_file.write(&log_db.encode())?;
+        seq_no_file.sync()?;
+
+        self.blob_files.sync()?;
+        let read_guard = self.active_file.read();
+        read_guard.sync()?;
+        self.metrics.on_sync();
+        if let Some(lock_file) = &self.lock_file {
+            lock_file.unlock()?;
+        }
+        Ok(())
+    }
+}
+
+impl Getter for LiteDb {
+    fn get(&self, key: &Key) -> ResultDb<Value> {
+        let _slow = SlowOp::start(&self.config, "get");
+        self.check_open()?;
+        if !self.index.probably_contains(key) {
+            self.metrics.on_get(false);
+            return Err(ErrDb::NotFindKey);
+        }
+        let _swap_lock = self.swap_lock.read();
+        let p = {
+            match self.index.try_get(key.to_vec())? {
+                Some(p) => p,
+                None => {
+                    self.metrics.on_get(false);
+                    return Err(ErrDb::NotFindKey);
+                }
+            }
+        };
+        let re = self.get_value_cached(key, &p);
+        self.metrics.on_get(re.is_ok());
+        re
+    }
+}
+
+impl Adder for LiteDb {
+    fn add(&self, k: &Key, v: &Value) -> ResultDb<()> {
+        self.add_with_sync(k, v, false, false).map(|_| ())
+    }
+}
+
+impl Remover for LiteDb {
+    fn remove(&self, key: &Key) -> ResultDb<Option<Value>> {
+        self.remove_with_sync(key, false)
+    }
+
+    fn remove_fast(&self, key: &Key) -> ResultDb<()> {
+        if key.is_empty() {
+            return Err(ErrDb::InvalidParameter);
+        }
+
+        let swap_lock = self.swap_lock.read();
+        let pos = self.index.try_get(key.to_vec())?;
+        if pos.is_none() {
+            return Ok(());
+        }
+
+        let mut log_db = LogDb {
+            key: log_db_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
+            value: self.config.tombstone_value(),
+            rec_type: LogDbType::DELETED,
+        };
+
+        let pos = self.append_log_db(&mut log_db)?;
+        self.reclaim_size.fetch_add(pos.size as usize, Ordering::SeqCst);
+
+        // delete the key in indexes
+        if let Some(old_pos) = self.index.try_delete(key.to_vec())? {
+            self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        }
+        self.value_cache.remove(key);
+        self.record_write(key);
+        drop(swap_lock);
+        self.notify_change(key, ChangeKind::Delete);
+
+        Ok(())
+    }
+}
+
+impl Closer for LiteDb {
+    fn close(&self) -> ResultDb<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        // 先停止后台线程，关闭时会持久化活跃文件
+        if let Some(mut auto_merge_thread) = self.auto_merge_thread.lock().take() {
+            auto_merge_thread.stop();
+        }
+        if let Some(mut sync_thread) = self.sync_thread.lock().take() {
+            sync_thread.stop();
+        }
+        // 列族在数据库的目录中，先于数据库关闭
+        let column_families: Vec<_> = self.column_families.lock().drain().map(|(_, cf)| cf).collect();
+        for cf in column_families {
+            cf.close()?;
+        }
+        self.close_inner()?;
+        self.closed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl Editor for LiteDb {}
+
+impl Db for LiteDb {
+    fn sync(&self) -> ResultDb<()> {
+        self.blob_files.sync()?;
+        let read_guard = self.active_file.read();
+        read_guard.sync()?;
+        self.metrics.on_sync();
+        Ok(())
+    }
+}
+
+impl Drop for LiteDb {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("error whiling close engine: {}", e);
+        }
+    }
+}
+
+/// 持久化当前的活跃文件并存储到旧的数据文件中，然后打开新的活跃文件，返回新的文件 id
+/// 调用者需要持有活跃文件的写锁，加锁的顺序是先活跃文件再旧的数据文件
+pub(crate) fn rotate_active_file(
+    config: &Config,
+    active_file: &mut FileDb,
+    older_files: &RwLock<HashMap<u32, FileDb>>,
+    reclaim_size: &AtomicUsize,
+    metrics: &dyn Metrics,
+    file_handles: Option<&Arc<FileHandles>>,
+) -> ResultDb<u32> {
+    // 封存之前写入 footer，merge 时不会重写
+    let footer_size = active_file.write_footer()?;
+    reclaim_size.fetch_add(footer_size, Ordering::SeqCst);
+    active_file.trim_preallocated()?;
+    active_file.sync()?;
+    metrics.on_sync();
+
+    let current_fid = active_file.get_file_id();
+    let new_file = open_data_file(config, &config.path_db, current_fid + 1, config.data_io_type())?;
+    sync_data_file_dir(config, new_file.get_file_name(), metrics)?;
+    // 旧的数据文件存储到 map 中
+    let mut old_file = std::mem::replace(active_file, new_file);
+    limit_open_file(file_handles, &mut old_file, config.data_io_type());
+    older_files.write().insert(current_fid, old_file);
+    Ok(current_fid + 1)
+}
+
+// 持久化数据文件所在的目录，分片的子目录也是新建的，同时持久化数据目录，内存模式下不需要
+fn sync_data_file_dir(config: &Config, file_name: &Path, metrics: &dyn Metrics) -> ResultDb<()> {
+    if config.in_memory || !cfg!(unix) {
+        return Ok(());
+    }
+    if let Some(parent) = file_name.parent() {
+        sync_dir(parent)?;
+        if parent != config.path_db {
+            sync_dir(&config.path_db)?;
+        }
+        metrics.on_dir_sync();
+    }
+    Ok(())
+}
+
+/// 持久化目录本身，新建或者重命名的文件在崩溃之后不会丢失，Windows 上不需要也不支持
+pub(crate) fn sync_dir(dir_path: &Path) -> ResultDb<()> {
+    #[cfg(unix)]
+    fs::File::open(dir_path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir_path;
+    Ok(())
+}
+
+// 只读打开已经存在的数据目录，锁文件存在时加共享锁，和其它只读的实例共存，有写入的实例时返回 DatabaseLocked
+// 只读的快照中可能没有锁文件，这时不加锁
+fn open_db_dir_read_only(path_db: &Path) -> ResultDb<Option<fs::File>> {
+    if !path_db.is_dir() {
+        return Err(ErrDb::new_io_file_not_find(&path_db.display().to_string()));
+    }
+    let lock_file = match fs::File::open(path_db.join(FILE_LOCK_NAME)) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ErrDb::IoErr(e)),
+    };
+    if let Err(e) = FileExt::try_lock_shared(&lock_file) {
+        log::error!("{}", e.to_string());
+        if e.kind() == std::io::ErrorKind::WouldBlock || e.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+            return Err(ErrDb::DatabaseLocked);
+        }
+        return Err(ErrDb::IoErr(e));
+    }
+    Ok(Some(lock_file))
+}
+
+// 数据目录不存在时创建，并设置目录的权限
+fn create_db_dir(path_db: &Path, dir_mode: Option<u32>) -> ResultDb<()> {
+    if !path_db.is_dir() {
+        if let Err(e) = fs::create_dir_all(path_db) {
+            log::error!("{}", e.to_string());
+            return Err(ErrDb::IoErr(e));
+        }
+        #[cfg(unix)]
+        if let Some(mode) = dir_mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path_db, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = dir_mode;
+    Ok(())
+}
+
+/// 创建数据目录并加上文件锁，返回文件锁，其它实例持有锁时返回 DatabaseLocked
+fn open_db_dir(path_db: &Path, dir_mode: Option<u32>) -> ResultDb<fs::File> {
+    create_db_dir(path_db, dir_mode)?;
+    // check whether the file opened
+    // 锁文件不会被删除，也不能截断，否则和同时打开的实例竞争；进程退出或者崩溃之后锁由系统释放，残留的锁文件不影响再次打开
+    let lock_file = {
+        match fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path_db.join(FILE_LOCK_NAME))
+        {
+            Ok(f) => {
+                if let Err(e) = f.try_lock_exclusive() {
+                    log::error!("{}", e.to_string());
+                    if e.kind() == std::io::ErrorKind::WouldBlock || e.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+                        return Err(ErrDb::DatabaseLocked);
+                    }
+                    return Err(ErrDb::IoErr(e));
+                }
+                f
+            }
+            Err(e) => {
+                log::error!("{}", e.to_string());
+                return Err(ErrDb::IoErr(e));
+            }
+        }
+    };
+    Ok(lock_file)
+}
+
+// 从头依次读取数据文件中的记录和记录的大小，读到文件末尾时结束，出错之后不再读取
+fn read_log_dbs(data_file: &FileDb) -> impl Iterator<Item = ResultDb<(LogDb, usize)>> + '_ {
+    let mut offset = 0;
+    let mut failed = false;
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        match data_file.read_log_db(offset) {
+            Ok(result) => {
+                offset += result.size as u64;
+                Some(Ok((result.log_db, result.size)))
+            }
+            Err(e) if e == ErrDb::new_io_eof("") => None,
+            Err(e) => {
+                failed = true;
+                Some(Err(e.context(format!(
+                    "corrupt record in file {} at offset {}",
+                    data_file.get_file_id(),
+                    offset
+                ))))
+            }
+        }
+    })
+}
+
+// 一次写入缓冲区中的多条记录，并更新 footer 的统计
+fn write_buffered(active_file: &FileDb, buf: &mut Vec<u8>, seq_nos: &mut Vec<usize>) -> ResultDb<()> {
+    if buf.is_empty() {
+        return Ok(());
+    }
+    active_file.write(buf)?;
+    for seq_no in seq_nos.drain(..) {
+        active_file.add_footer_record(seq_no);
+    }
+    buf.clear();
+    Ok(())
+}
+
+/// 按照配置的命名方式打开数据文件
+pub(crate) fn open_data_file(config: &Config, dir_path: &Path, file_id: u32, io_type: IoType) -> ResultDb<FileDb> {
+    let mut data_file = FileDb::new_with_file_name(config.data_file_name(dir_path, file_id), file_id, io_type.clone())?;
+    data_file.set_verify_crc(config.verify_crc_on_read);
+    if config.preallocate && io_type != IoType::Memory {
+        data_file.preallocate(config.file_size_db)?;
+    }
+    Ok(data_file)
+}
+
+/// 配置了 max_open_files 时旧的数据文件改为按需打开，现在打开的文件马上关闭，返回是否修改了文件的 IO
+/// 内存中的文件关闭之后数据会丢失，不修改
+pub(crate) fn limit_open_file(file_handles: Option<&Arc<FileHandles>>, data_file: &mut FileDb, io_type: IoType) -> bool {
+    match file_handles {
+        Some(file_handles) if io_type != IoType::Memory => {
+            let io = file_handles.limit(data_file.get_file_name().to_path_buf(), io_type, data_file.file_size());
+            data_file.set_db_io(Box::new(io));
+            true
+        }
+        _ => false,
+    }
+}
+
+// 恢复索引时暂存还没有完成的事务的记录，预先分配 estimated_keys 个事务的空间，每个事务至少有一个 key
+pub(crate) fn new_transaction_log_dbs(config: &Config) -> HashMap<usize, Vec<TransactionLogDb>> {
+    HashMap::with_capacity(config.estimated_keys.unwrap_or_default())
+}
+
+// 最后一个文件是活跃文件，一直打开，其它的文件在 file_handles 不为 None 时按需打开
+fn load_data_files(config: &Config, use_mmap: bool, file_handles: Option<&Arc<FileHandles>>) -> ResultDb<Vec<FileDb>> {
     let mut data_files: Vec<FileDb> = Vec::new();
-    for file in dir {
-        if let Ok(entry) = file {
-            let file_os_str = entry.file_name();
-            let file_name = file_os_str.to_str().unwrap();
+    // 数据文件可能在子目录中，按照文件 id 从小到大排序
+    let files = check_duplicate_file_ids(config, FileDb::find_data_files(&config.path_db)?)?;
+
+    // 遍历所有的文件id，依次打开对应的数据文件
+    for (file_id, file_name) in files.into_iter() {
+        let mut io_type = config.data_io_type();
+        if use_mmap {
+            io_type = IoType::MemoryMap;
+        }
+        // 打开下一个文件之前关闭前一个，同时打开的文件不超过 max_open_files
+        if let Some(last) = data_files.last_mut() {
+            limit_open_file(file_handles, last, io_type.clone());
+        }
+        let mut data_file = FileDb::new_with_file_name(file_name, file_id, io_type)?;
+        data_file.set_verify_crc(config.verify_crc_on_read);
+        data_files.push(data_file);
+    }
+
+    Ok(data_files)
+}
+
+// 同一个 id 有多个数据文件时，不修复就返回 CorruptLayout，否则把多余的文件改为新的 id
+// 新的 id 比所有的文件都大，这些文件中的记录会覆盖其它文件中相同 key 的记录
+fn check_duplicate_file_ids(config: &Config, files: Vec<(u32, PathBuf)>) -> ResultDb<Vec<(u32, PathBuf)>> {
+    let mut unique: Vec<(u32, PathBuf)> = Vec::with_capacity(files.len());
+    let mut duplicates = Vec::new();
+    for (file_id, mut file_name) in files {
+        match unique.last_mut() {
+            Some((last_id, last_name)) if *last_id == file_id => {
+                // 按照配置命名的文件保留原来的 id
+                if file_name == config.data_file_name(&config.path_db, file_id) {
+                    std::mem::swap(last_name, &mut file_name);
+                }
+                duplicates.push((file_id, file_name));
+            }
+            _ => unique.push((file_id, file_name)),
+        }
+    }
+    if duplicates.is_empty() {
+        return Ok(unique);
+    }
+    if !config.repair_duplicate_file_ids || config.read_only {
+        let names: Vec<String> = duplicates.iter().map(|(id, f)| format!("{}: {}", id, f.display())).collect();
+        return Err(ErrDb::CorruptLayout(format!("duplicate data file ids, {}", names.join(", "))));
+    }
+
+    let mut next_id = unique.last().map(|(id, _)| *id).unwrap_or_default();
+    for (file_id, file_name) in duplicates {
+        next_id = next_id
+            .checked_add(1)
+            .ok_or_else(|| ErrDb::CorruptLayout("no file id left for duplicate data files".to_owned()))?;
+        let new_name = config.data_file_name(&config.path_db, next_id);
+        let parent = new_name.parent().unwrap_or(&config.path_db);
+        fs::create_dir_all(parent)?;
+        log::warn!("duplicate data file id {}, rename {} to {}", file_id, file_name.display(), new_name.display());
+        fs::rename(&file_name, &new_name)?;
+        sync_dir(parent)?;
+        unique.push((next_id, new_name));
+    }
+    Ok(unique)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+    use std::fs;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use function_name::named;
+    use parking_lot::Mutex;
+
+    use crate::db::{
+        Adder, ChecksumKind, Closer, Config, Db, Durability, ErrDb, FileDb, Getter, IndexType, IoType, IteratorOptions, Key, LogDb, LogDbType, Metrics,
+        Remover, Value, WriteBatchOptions, HINT_FILE_NAME, SEQ_NO_FILE_NAME,
+    };
+    use crate::io_db::{DbIo, DbIoFactory, HookIo, MMapIo, MemIo};
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::batch::{log_db_key_with_seq, parse_log_db_key, NON_TRANSACTION_SEQ_NO, TXN_FIN_KEY};
+    use crate::lite::lite::{sync_dir, FILE_LOCK_NAME, SEQ_NO_KEY};
+    use crate::lite::{FileStat, Health, LiteDb};
+
+    fn ready_config(file: &str, name: &str) -> Config {
+        let mut config = kits::test_config(file, name);
+        config.file_size_db = 64u64 * 1024 * 1024;
+        config
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_put() {
+        let config = ready_config(file!(), function_name!());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+            {
+                let re = lite_db.add(&get_test_key(11), &get_test_value(11));
+                assert!(re.is_ok());
+                let re2 = lite_db.get(&get_test_key(11));
+                assert_eq!(re2.unwrap(), get_test_value(11));
+            }
+
+            // 2. add the same key
+            {
+                let re = lite_db.add(&get_test_key(22), &get_test_value(22));
+                assert!(re.is_ok());
+                let re2 = lite_db.add(&get_test_key(22), &Bytes::from("a new value"));
+                assert!(re2.is_ok());
+                let re3 = lite_db.get(&get_test_key(22));
+                assert_eq!(re3.unwrap(), Bytes::from("a new value"));
+            }
+
+            // 3.key is empty
+            {
+                let re = lite_db.add(&Bytes::new(), &get_test_value(123));
+                assert_eq!(ErrDb::InvalidParameter, re.err().unwrap());
+            }
+
+            // 4.value is empty
+            {
+                let re = lite_db.add(&get_test_key(33), &Bytes::new());
+                assert!(re.is_ok());
+                let re2 = lite_db.get(&get_test_key(33));
+                assert_eq!(0, re2.ok().unwrap().len());
+            }
+
+            // 5. write data till the make new data file
+            for i in 0..=1000000 {
+                let re = lite_db.add(&get_test_key(i), &get_test_value(i));
+                assert!(re.is_ok());
+            }
+        }
+
+        // 6. reopen db, and then Put data
+        //must drop the db
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let re = lite_db.add(&get_test_key(55), &get_test_value(55));
+            assert!(re.is_ok());
+
+            let re2 = lite_db.get(&get_test_key(55));
+            assert_eq!(re2.unwrap(), &get_test_value(55));
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_get() {
+        let config = ready_config(file!(), function_name!());
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+            // 1. normal
+            {
+                let re = lite_db.add(&get_test_key(111), &get_test_value(111));
+                assert!(re.is_ok());
+                let re2 = lite_db.get(&get_test_key(111));
+                assert_eq!(re2.unwrap(), get_test_value(111));
+            }
+
+            // 2.get the key that not exist
+            {
+                let re = lite_db.get(&Bytes::from("not existed key"));
+                assert!(matches!(re, Err(ErrDb::NotFindKey)));
+            }
+
+            // 3. repeat to add same key
+            {
+                let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
+                assert!(re1.is_ok());
+                let re2 = lite_db.add(&get_test_key(222), &Bytes::from("a new value"));
+                assert!(re2.is_ok());
+                let re3 = lite_db.get(&get_test_key(222));
+                assert_eq!(Bytes::from("a new value"), re3.unwrap());
+            }
+
+            // 4. get after remove
+            {
+                let re1 = lite_db.add(&get_test_key(333), &get_test_value(333));
+                assert!(re1.is_ok());
+                let re2 = lite_db.remove(&get_test_key(333));
+                assert!(re2.is_ok());
+                let re3 = lite_db.get(&get_test_key(333));
+                assert!(matches!(re3, Err(ErrDb::NotFindKey)));
+            }
+
+            // 5. to old file ，get value from the old file
+            {
+                for i in 500..=1000000 {
+                    let re = lite_db.add(&get_test_key(i), &get_test_value(i));
+                    assert!(re.is_ok());
+                }
+                let re2 = lite_db.get(&get_test_key(505));
+                assert_eq!(get_test_value(505), re2.unwrap());
+            }
+        }
+
+        // 6. reopen db, and then Put data
+        //must drop the db
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let re = lite_db.get(&get_test_key(111));
+            assert_eq!(get_test_value(111), re.unwrap());
+            let re2 = lite_db.get(&get_test_key(222));
+            assert_eq!(Bytes::from("a new value"), re2.unwrap());
+            let re3 = lite_db.get(&get_test_key(333));
+            assert!(matches!(re3, Err(ErrDb::NotFindKey)));
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_delete() {
+        let config = ready_config(file!(), function_name!());
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+            // 1.remove key
+            {
+                let re1 = lite_db.add(&get_test_key(111), &get_test_value(111));
+                assert!(re1.is_ok());
+                let re2 = lite_db.remove(&get_test_key(111));
+                assert_eq!(re2.unwrap(), Some(get_test_value(111)));
+                let re3 = lite_db.get(&get_test_key(111));
+                assert!(matches!(re3, Err(ErrDb::NotFindKey)));
+            }
+
+            // 2.remove key that do not exist
+            {
+                let re = lite_db.remove(&Bytes::from("not-existed-key"));
+                assert!(re.is_ok());
+            }
+
+            // 3. remove a empty key
+            {
+                let re = lite_db.remove(&Bytes::new());
+                assert!(matches!(re, Err(ErrDb::InvalidParameter)));
+            }
+
+            // 4.add after remove
+            {
+                let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
+                assert!(re1.is_ok());
+                let re2 = lite_db.remove(&get_test_key(222));
+                assert!(re2.is_ok());
+                let re3 = lite_db.add(&get_test_key(222), &Bytes::from("a new value"));
+                assert!(re3.is_ok());
+                let re4 = lite_db.get(&get_test_key(222));
+                assert_eq!(Bytes::from("a new value"), re4.unwrap());
+            }
+        }
+
+        // 5.reopen db
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let re1 = lite_db.get(&get_test_key(111));
+            assert!(matches!(re1, Err(ErrDb::NotFindKey)));
+            let re2 = lite_db.get(&get_test_key(222));
+            assert_eq!(Bytes::from("a new value"), re2.unwrap());
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_close() {
+        let config = ready_config(file!(), function_name!());
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+        let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
+        assert!(re1.is_ok());
+
+        let close_re = lite_db.close();
+        assert!(close_re.is_ok());
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_close_checked() {
+        let config = ready_config(file!(), function_name!());
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
+        assert!(re1.is_ok());
+
+        // the db is dropped in close_checked, drop must not close it again
+        let close_re = lite_db.close_checked();
+        assert!(close_re.is_ok());
+
+        // only one seq no record is written
+        let seq_no_file = FileDb::new_seq_no_file(config.path_db.clone()).expect("failed to open seq no file");
+        let re2 = seq_no_file.read_log_db(0);
+        assert!(re2.is_ok());
+        assert_eq!(re2.unwrap().size as u64, seq_no_file.file_size());
+
+        // the file lock is released
+        let re3 = LiteDb::open(config.clone());
+        assert!(re3.is_ok());
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_sync() {
+        let config = ready_config(file!(), function_name!());
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
+        assert!(re1.is_ok());
+
+        let close_re = lite_db.sync();
+        assert!(close_re.is_ok());
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_file_lock() {
+        let config = ready_config(file!(), function_name!());
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+        let lite_db2 = LiteDb::open(config.clone());
+        assert_eq!(ErrDb::DatabaseLocked, lite_db2.err().unwrap());
+
+        let re2 = lite_db.close();
+        assert!(matches!(re2, Ok(())));
+        // std::mem::drop(lite_db);
+
+        let re3 = LiteDb::open(config.clone());
+        assert!(re3.is_ok());
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_file_lock_race() {
+        let config = ready_config(file!(), function_name!());
+        // 残留的锁文件没有加锁，不影响打开
+        fs::create_dir_all(&config.path_db).unwrap();
+        fs::write(config.path_db.join(FILE_LOCK_NAME), b"stale").unwrap();
+
+        for _ in 0..10 {
+            let barrier = std::sync::Barrier::new(2);
+            let results: Vec<_> = std::thread::scope(|s| {
+                let handles: Vec<_> = (0..2)
+                    .map(|_| {
+                        s.spawn(|| {
+                            barrier.wait();
+                            LiteDb::open(config.clone())
+                        })
+                    })
+                    .collect();
+                handles.into_iter().map(|h| h.join().unwrap()).collect()
+            });
+            // 只有一个打开成功，另一个返回 DatabaseLocked
+            assert_eq!(1, results.iter().filter(|r| r.is_ok()).count());
+            assert!(results.iter().any(|r| matches!(r, Err(ErrDb::DatabaseLocked))));
+            assert!(config.path_db.join(FILE_LOCK_NAME).is_file());
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_stat() {
+        let config = ready_config(file!(), function_name!());
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+        for i in 0..=10000 {
+            let re = lite_db.add(&get_test_key(i), &get_test_value(i));
+            assert!(re.is_ok());
+        }
+        for i in 0..=1000 {
+            let re = lite_db.add(&get_test_key(i), &get_test_value(i));
+            assert!(re.is_ok());
+        }
+        for i in 2000..=5000 {
+            let re = lite_db.remove(&get_test_key(i));
+            assert!(re.is_ok());
+        }
+
+        let stat = lite_db.stat().unwrap();
+        assert!(stat.reclaim_size > 0);
+        assert_eq!(10001 - 3001, stat.key_num);
+        assert_eq!(1, stat.data_file_num);
+        assert!(stat.disk_size > 0);
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_delete_prefix() {
+        let config = ready_config(file!(), function_name!());
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..100 {
+                let re1 = lite_db.add(&Bytes::from(format!("user-{}", i)), &get_test_value(i));
+                assert!(re1.is_ok());
+                let re2 = lite_db.add(&Bytes::from(format!("order-{}", i)), &get_test_value(i));
+                assert!(re2.is_ok());
+            }
+
+            let re = lite_db.delete_prefix("user-".as_bytes());
+            assert_eq!(100, re.unwrap());
+            let re2 = lite_db.delete_prefix("user-".as_bytes());
+            assert_eq!(0, re2.unwrap());
+
+            for i in 0..100 {
+                let re1 = lite_db.get(&Bytes::from(format!("user-{}", i)));
+                assert!(matches!(re1, Err(ErrDb::NotFindKey)));
+                let re2 = lite_db.get(&Bytes::from(format!("order-{}", i)));
+                assert_eq!(get_test_value(i), re2.unwrap());
+            }
+        }
+
+        // reopen db
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let re1 = lite_db.get(&Bytes::from("user-1"));
+            assert!(matches!(re1, Err(ErrDb::NotFindKey)));
+            let re2 = lite_db.get(&Bytes::from("order-1"));
+            assert_eq!(get_test_value(1), re2.unwrap());
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_rename() {
+        let config = ready_config(file!(), function_name!());
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..3 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+
+            // 存在的 key
+            assert!(lite_db.rename(&get_test_key(0), &get_test_key(10)).unwrap());
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(0)).err().unwrap());
+            assert_eq!(get_test_value(0), lite_db.get(&get_test_key(10)).unwrap());
+
+            // 不存在的 key
+            assert!(!lite_db.rename(&get_test_key(0), &get_test_key(11)).unwrap());
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(11)).err().unwrap());
+
+            // new 已经存在时覆盖
+            assert!(lite_db.rename(&get_test_key(1), &get_test_key(2)).unwrap());
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(1)).err().unwrap());
+            assert_eq!(get_test_value(1), lite_db.get(&get_test_key(2)).unwrap());
+
+            // 相同的 key 不变
+            assert!(lite_db.rename(&get_test_key(2), &get_test_key(2)).unwrap());
+            assert_eq!(get_test_value(1), lite_db.get(&get_test_key(2)).unwrap());
+            assert_eq!(ErrDb::InvalidParameter, lite_db.rename(&Bytes::new(), &get_test_key(2)).err().unwrap());
+            assert_eq!(2, lite_db.stat().unwrap().key_num);
+        }
+
+        // 重新打开之后和提交时一样
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(2, lite_db.stat().unwrap().key_num);
+            assert_eq!(get_test_value(0), lite_db.get(&get_test_key(10)).unwrap());
+            assert_eq!(get_test_value(1), lite_db.get(&get_test_key(2)).unwrap());
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_swap() {
+        let config = ready_config(file!(), function_name!());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let key = get_test_key(1);
+
+        // 第一次写入
+        assert_eq!(None, lite_db.swap(&key, &get_test_value(1)).unwrap());
+        // 覆盖时返回旧的值
+        assert_eq!(Some(get_test_value(1)), lite_db.swap(&key, &get_test_value(2)).unwrap());
+        assert_eq!(get_test_value(2), lite_db.get(&key).unwrap());
+        // 删除之后再写入
+        lite_db.remove(&key).unwrap();
+        assert_eq!(None, lite_db.swap(&key, &get_test_value(3)).unwrap());
+        assert_eq!(get_test_value(3), lite_db.get(&key).unwrap());
+        assert_eq!(ErrDb::InvalidParameter, lite_db.swap(&Bytes::new(), &get_test_value(3)).err().unwrap());
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_file_shard() {
+        let mut config = ready_config(file!(), function_name!());
+        // one record per data file
+        config.file_size_db = 100;
+        config.file_name_width = 4;
+        config.file_shard_size = 500;
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..1200 {
+                let re = lite_db.add(&get_test_key(i), &get_test_value(i));
+                assert!(re.is_ok());
+            }
+            assert!(lite_db.older_files.read().len() > 1000);
+        }
+
+        assert_eq!(config.path_db.join("0000").join("0001.data"), config.data_file_name(&config.path_db, 1));
+        assert_eq!(config.path_db.join("0002").join("1001.data"), config.data_file_name(&config.path_db, 1001));
+        assert!(config.data_file_name(&config.path_db, 1001).is_file());
+        assert!(!config.path_db.join("0000.data").exists());
+
+        // reopen db
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..1200 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+            let re = lite_db.add(&get_test_key(1200), &get_test_value(1200));
+            assert!(re.is_ok());
+            assert_eq!(get_test_value(1200), lite_db.get(&get_test_key(1200)).unwrap());
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[derive(Default)]
+    struct CountMetrics {
+        hits: AtomicUsize,
+        misses: AtomicUsize,
+        puts: AtomicUsize,
+        put_bytes: AtomicUsize,
+        syncs: AtomicUsize,
+        dir_syncs: AtomicUsize,
+        merges: AtomicUsize,
+        reclaimed: AtomicUsize,
+    }
+
+    impl Metrics for CountMetrics {
+        fn on_get(&self, hit: bool) {
+            match hit {
+                true => self.hits.fetch_add(1, Ordering::SeqCst),
+                false => self.misses.fetch_add(1, Ordering::SeqCst),
+            };
+        }
+
+        fn on_put(&self, bytes: usize) {
+            self.puts.fetch_add(1, Ordering::SeqCst);
+            self.put_bytes.fetch_add(bytes, Ordering::SeqCst);
+        }
+
+        fn on_sync(&self) {
+            self.syncs.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_dir_sync(&self) {
+            self.dir_syncs.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_merge(&self, reclaimed: usize) {
+            self.merges.fetch_add(1, Ordering::SeqCst);
+            self.reclaimed.fetch_add(reclaimed, Ordering::SeqCst);
+        }
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_metrics() {
+        let mut config = ready_config(file!(), function_name!());
+        config.merge_ratio = 0.3;
+        let metrics = Arc::new(CountMetrics::default());
+        config.metrics = Some(metrics.clone());
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..10 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 0..10 {
+                lite_db.add(&get_test_key(i), &get_test_value(i + 100)).unwrap();
+            }
+            for i in 0..12 {
+                let _ = lite_db.get(&get_test_key(i));
+            }
+            lite_db.sync().unwrap();
+
+            let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            for i in 20..23 {
+                wb.put(get_test_key(i), get_test_value(i)).unwrap();
+            }
+            wb.commit().unwrap();
+
+            assert_eq!(10, metrics.hits.load(Ordering::SeqCst));
+            assert_eq!(2, metrics.misses.load(Ordering::SeqCst));
+            assert_eq!(23, metrics.puts.load(Ordering::SeqCst));
+            // the disk size also includes the transaction finished record
+            assert!(metrics.put_bytes.load(Ordering::SeqCst) < lite_db.stat().unwrap().disk_size as usize);
+            // sync and the batch commit
+            assert_eq!(2, metrics.syncs.load(Ordering::SeqCst));
+
+            lite_db.merge().expect("failed to merge");
+            assert_eq!(1, metrics.merges.load(Ordering::SeqCst));
+            assert!(metrics.reclaimed.load(Ordering::SeqCst) > 0);
+            // merge rotates the active file
+            assert_eq!(3, metrics.syncs.load(Ordering::SeqCst));
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_dir_sync() {
+        let mut config = ready_config(file!(), function_name!());
+        config.dir_mode = Some(0o700);
+        config.file_shard_size = 2;
+        let metrics = Arc::new(CountMetrics::default());
+        config.metrics = Some(metrics.clone());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.rotate().unwrap();
+            lite_db.rotate().unwrap();
+            lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
+        }
+        // 已经有数据文件时重新打开不需要持久化目录
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(get_test_value(1), lite_db.get(&get_test_key(1)).unwrap());
+        // 新建数据库和每次切换活跃文件都会持久化目录，Windows 上不需要
+        let dir_syncs = metrics.dir_syncs.load(Ordering::SeqCst);
+        match cfg!(unix) {
+            true => assert_eq!(3, dir_syncs),
+            false => assert_eq!(0, dir_syncs),
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&config.path_db).unwrap().permissions().mode();
+            assert_eq!(0o700, mode & 0o777);
+        }
+        assert!(sync_dir(&config.path_db).is_ok());
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_parallel_recovery() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for round in 0..3 {
+                for i in 0..1000 {
+                    lite_db.add(&get_test_key(i), &Bytes::from(format!("{}-{}", round, i))).unwrap();
+                }
+                // 跨越多个文件的批次
+                let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+                for i in 1000..1500 {
+                    wb.put(get_test_key(i), Bytes::from(format!("{}-{}", round, i))).unwrap();
+                }
+                wb.commit().unwrap();
+                for i in (round * 100)..(round * 100 + 50) {
+                    lite_db.remove(&get_test_key(i)).unwrap();
+                }
+            }
+            assert!(lite_db.stat().unwrap().data_file_num > 10);
+        }
+
+        let load = |parallel_recovery: bool| {
+            let mut config = config.clone();
+            config.parallel_recovery = parallel_recovery;
+            let lite_db = LiteDb::open(config).expect("failed to open engine");
+            let mut items = Vec::new();
+            let mut iter = lite_db.index.iterator(IteratorOptions::default());
+            while let Some((key, pos)) = iter.next() {
+                items.push((key.clone(), *pos));
+            }
+            let stat = lite_db.stat().unwrap();
+            (items, lite_db.seq_no(), stat.reclaim_size)
+        };
+        let serial = load(false);
+        let parallel = load(true);
+        assert_eq!(1500 - 50, serial.0.len());
+        assert_eq!(serial, parallel);
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_txn_last_write_wins() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        let record = |key: Key, value: &str, rec_type: LogDbType, seq_no: usize| LogDb {
+            key: log_db_key_with_seq(key.to_vec(), seq_no),
+            value: value.as_bytes().to_vec(),
+            rec_type,
+        };
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..200 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            // WriteBatch 中相同的 key 只保留一条，直接写入同一个事务中有重复 key 的记录
+            let seq_no = lite_db.seq_no.fetch_add(1, Ordering::SeqCst);
+            let mut log_dbs = vec![record(get_test_key(0), "first", LogDbType::NORMAL, seq_no)];
+            // 两次写入之间切换数据文件
+            for i in 1000..1100 {
+                log_dbs.push(record(get_test_key(i), &"filler".repeat(16), LogDbType::NORMAL, seq_no));
+            }
+            log_dbs.push(record(get_test_key(0), "last", LogDbType::NORMAL, seq_no));
+            log_dbs.push(record(get_test_key(1), "", LogDbType::DELETED, seq_no));
+            log_dbs.push(record(get_test_key(1), "put-after-delete", LogDbType::NORMAL, seq_no));
+            log_dbs.push(record(get_test_key(2), "delete-after-put", LogDbType::NORMAL, seq_no));
+            log_dbs.push(record(get_test_key(2), "", LogDbType::DELETED, seq_no));
+            log_dbs.push(record(TXN_FIN_KEY.to_vec().into(), "", LogDbType::TXNFINISHED, seq_no));
+            let positions = lite_db.append_log_dbs_atomic(&log_dbs).unwrap();
+            assert_ne!(positions[0].file_id, positions[101].file_id);
+        }
+
+        for parallel_recovery in [false, true] {
+            let mut config = config.clone();
+            config.parallel_recovery = parallel_recovery;
+            let lite_db = LiteDb::open(config).expect("failed to open engine");
+            assert_eq!(Bytes::from("last"), lite_db.get(&get_test_key(0)).unwrap());
+            assert_eq!(Bytes::from("put-after-delete"), lite_db.get(&get_test_key(1)).unwrap());
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(2)).err().unwrap());
+            // 被覆盖的记录都计入可以回收的大小
+            let dead_size: u64 = lite_db.file_stats().unwrap().iter().map(|s| s.dead_size).sum();
+            assert_eq!(dead_size as usize, lite_db.stat().unwrap().reclaim_size);
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_get_with_pos() {
+        let config = ready_config(file!(), function_name!());
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
+            let offset = lite_db.active_file.read().get_write_off();
+            lite_db.add(&get_test_key(2), &get_test_value(2)).unwrap();
+            let size = lite_db.active_file.read().get_write_off() - offset;
+            lite_db.rotate().unwrap();
+            lite_db.add(&get_test_key(3), &get_test_value(3)).unwrap();
+
+            let (value, pos) = lite_db.get_with_pos(&get_test_key(2)).unwrap();
+            assert_eq!(get_test_value(2), value);
+            assert_eq!(0, pos.file_id());
+            assert_eq!(offset, pos.offset());
+            assert_eq!(size, pos.size() as u64);
+
+            let (value, pos) = lite_db.get_with_pos(&get_test_key(3)).unwrap();
+            assert_eq!(get_test_value(3), value);
+            assert_eq!(1, pos.file_id());
+            assert_eq!(0, pos.offset());
+
+            let re = lite_db.get_with_pos(&get_test_key(4));
+            assert_eq!(ErrDb::NotFindKey, re.err().unwrap());
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_get_zero_copy() {
+        let base = ready_config(file!(), function_name!());
+        let big = Bytes::from(vec![7u8; 64 * 1024]);
+
+        // 标准的文件 IO 不支持 read_shared，和 get 一样复制 value
+        {
+            let mut config = base.clone();
+            config.path_db = base.path_db.join("std");
+            let lite_db = LiteDb::open(config).expect("failed to open engine");
+            lite_db.add(&Bytes::from("big"), &big).unwrap();
+            assert_eq!(big, lite_db.get_zero_copy(&Bytes::from("big")).unwrap());
+            assert_eq!(Err(ErrDb::NotFindKey), lite_db.get_zero_copy(&Bytes::from("none")));
+        }
+
+        let mut config = base.clone();
+        config.path_db = base.path_db.join("mmap");
+        config.file_size_db = 128 * 1024;
+        config.merge_ratio = 0.0;
+        config.io_factory = Some(DbIoFactory::new(|file_name| Ok(Box::new(MMapIo::new(file_name)?))));
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        lite_db.add(&Bytes::from("big"), &big).unwrap();
+        lite_db.add(&Bytes::from("small"), &Bytes::from("small-value")).unwrap();
+
+        let value = lite_db.get_zero_copy(&Bytes::from("big")).unwrap();
+        assert_eq!(big, value);
+        // 两次读取引用同一块映射的内存，get 复制到新的内存中
+        assert_eq!(value.as_ptr(), lite_db.get_zero_copy(&Bytes::from("big")).unwrap().as_ptr());
+        assert_ne!(value.as_ptr(), lite_db.get(&Bytes::from("big")).unwrap().as_ptr());
+        let small = lite_db.get_zero_copy(&Bytes::from("small")).unwrap();
+        assert_eq!(Bytes::from("small-value"), small);
+
+        // 写入同一个文件和其它的文件，merge 删除了旧的文件之后，已经返回的 value 仍然有效
+        for i in 0..3000 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        lite_db.remove(&Bytes::from("small")).unwrap();
+        assert!(lite_db.stat().unwrap().data_file_num >= 2);
+        lite_db.merge().unwrap();
+        assert_eq!(big, value);
+        assert_eq!(Bytes::from("small-value"), small);
+        assert_eq!(big, lite_db.get_zero_copy(&Bytes::from("big")).unwrap());
+        assert_eq!(Err(ErrDb::NotFindKey), lite_db.get_zero_copy(&Bytes::from("small")));
+        for i in 0..3000 {
+            assert_eq!(get_test_value(i), lite_db.get_zero_copy(&get_test_key(i)).unwrap());
+        }
+
+        drop(lite_db);
+        assert_eq!(big, value);
+        fs::remove_dir_all(base.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_get_raw() {
+        let config = ready_config(file!(), function_name!());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
+        lite_db.rotate().unwrap();
+        lite_db.add(&get_test_key(2), &get_test_value(2)).unwrap();
+
+        // 旧的数据文件和活跃文件中的记录
+        for i in 1..=2 {
+            let raw = lite_db.get_raw(&get_test_key(i)).unwrap();
+            let (_, pos) = lite_db.get_with_pos(&get_test_key(i)).unwrap();
+            assert_eq!(pos.size() as usize, raw.len());
+
+            // 写到另一个文件中按照记录的格式解码
+            let file = FileDb::new(PathBuf::new(), 0, IoType::Memory).unwrap();
+            file.write(&raw).unwrap();
+            let re = file.read_log_db(0).unwrap();
+            assert_eq!(raw.len(), re.size);
+            assert_eq!(LogDbType::NORMAL, re.log_db.rec_type);
+            assert_eq!((get_test_key(i).to_vec(), NON_TRANSACTION_SEQ_NO), parse_log_db_key(re.log_db.key).unwrap());
+            assert_eq!(get_test_value(i).to_vec(), re.log_db.value);
+        }
+        assert_eq!(ErrDb::NotFindKey, lite_db.get_raw(&get_test_key(3)).err().unwrap());
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_concurrent_add() {
+        let mut config = ready_config(file!(), function_name!());
+        // 小文件，写入的过程中会切换活跃文件
+        config.file_size_db = 64 * 1024;
+        let threads = 8;
+        let count = 2000;
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            std::thread::scope(|s| {
+                for t in 0..threads {
+                    let lite_db = &lite_db;
+                    s.spawn(move || {
+                        for i in 0..count {
+                            let n = t * count + i;
+                            lite_db.add(&get_test_key(n), &get_test_value(n)).unwrap();
+                        }
+                    });
+                }
+            });
+
+            assert!(lite_db.older_files.read().len() > 1);
+            assert_eq!(threads * count, lite_db.index.list_keys().unwrap().len());
+            for n in 0..threads * count {
+                assert_eq!(get_test_value(n), lite_db.get(&get_test_key(n)).unwrap());
+            }
+        }
+
+        // reopen db, all the records are readable
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(threads * count, lite_db.index.list_keys().unwrap().len());
+            for n in 0..threads * count {
+                assert_eq!(get_test_value(n), lite_db.get(&get_test_key(n)).unwrap());
+            }
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_clear() {
+        let config = ready_config(file!(), function_name!());
+        let mut config_bptree = config.clone();
+        config_bptree.path_db = PathBuf::from(format!("{}-bptree", config.path_db.display()));
+        config_bptree.index_type = IndexType::BPlusTree;
+        let _ = fs::remove_dir_all(config_bptree.path_db.clone());
+        let mut config_memory = config.clone();
+        config_memory.in_memory = true;
+
+        for mut config in [config, config_bptree, config_memory] {
+            config.file_size_db = 32 * 1024;
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..1000 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+                assert!(!lite_db.older_files.read().is_empty());
+
+                lite_db.clear().expect("failed to clear");
+                assert!(lite_db.index.list_keys().unwrap().is_empty());
+                assert!(lite_db.older_files.read().is_empty());
+                assert_eq!(0, lite_db.active_file.read().get_file_id());
+                assert_eq!(0, lite_db.active_file.read().get_write_off());
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(1)).err().unwrap());
+
+                lite_db.add(&get_test_key(1), &get_test_value(10)).unwrap();
+                assert_eq!(get_test_value(10), lite_db.get(&get_test_key(1)).unwrap());
+
+                // can not clear while there is a snapshot
+                let snapshot = lite_db.snapshot();
+                assert!(lite_db.clear().is_err());
+                drop(snapshot);
+            }
+            if config.in_memory {
+                continue;
+            }
+
+            // reopen db, only the data written after clear exists
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                assert_eq!(1, lite_db.index.list_keys().unwrap().len());
+                assert_eq!(get_test_value(10), lite_db.get(&get_test_key(1)).unwrap());
+            }
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        }
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_bloom_filter() {
+        let config = ready_config(file!(), function_name!());
+        let mut config_bptree = config.clone();
+        config_bptree.path_db = PathBuf::from(format!("{}-bptree", config.path_db.display()));
+        config_bptree.index_type = IndexType::BPlusTree;
+        let _ = fs::remove_dir_all(config_bptree.path_db.clone());
+
+        for mut config in [config, config_bptree] {
+            config.bloom_filter_keys = Some(1000);
+            config.merge_ratio = 0.0;
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..1000 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+                for i in 0..100 {
+                    lite_db.remove(&get_test_key(i)).unwrap();
+                }
+                let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+                wb.put(get_test_key(1000), get_test_value(1000)).unwrap();
+                wb.delete(get_test_key(100)).unwrap();
+                wb.commit().unwrap();
+                lite_db.merge().unwrap();
+
+                for i in 101..=1000 {
+                    assert!(lite_db.probably_contains(&get_test_key(i)));
+                    assert!(lite_db.exists(&get_test_key(i)));
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+                for i in 0..=100 {
+                    assert!(!lite_db.exists(&get_test_key(i)));
+                    assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+                }
+                // most of the absent keys are filtered without index lookup
+                let absent = (2000..3000).filter(|i| lite_db.probably_contains(&get_test_key(*i))).count();
+                assert!(absent < 100, "false positives: {}", absent);
+            }
+
+            // reopen db, the bloom filter is rebuilt from the index
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 101..=1000 {
+                    assert!(lite_db.exists(&get_test_key(i)));
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+                for i in 0..=100 {
+                    assert!(!lite_db.exists(&get_test_key(i)));
+                }
+            }
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        }
+
+        let mut config = ready_config(file!(), function_name!());
+        config.bloom_filter_keys = Some(0);
+        assert!(LiteDb::open(config).is_err());
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_preallocate() {
+        let config = ready_config(file!(), function_name!());
+        let mut config_bptree = config.clone();
+        config_bptree.path_db = PathBuf::from(format!("{}-bptree", config.path_db.display()));
+        config_bptree.index_type = IndexType::BPlusTree;
+        let _ = fs::remove_dir_all(config_bptree.path_db.clone());
+
+        for mut config in [config, config_bptree] {
+            config.preallocate = true;
+            config.file_size_db = 32 * 1024;
+            config.merge_ratio = 0.0;
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..2000 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+                assert!(!lite_db.older_files.read().is_empty());
+                assert_eq!(config.file_size_db, lite_db.active_file.read().file_size());
+                // 封存的文件截断了预分配的空间
+                for (_, file) in lite_db.older_files.read().iter() {
+                    assert!(file.file_size() < config.file_size_db);
+                    assert_eq!(file.get_write_off(), file.file_size());
+                }
+            }
+
+            // reopen db, continue writing after the written data
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..2000 {
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+                for i in 2000..2100 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+                lite_db.remove(&get_test_key(0)).unwrap();
+            }
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                assert_eq!(2099, lite_db.index.list_keys().unwrap().len());
+                for i in 1..2100 {
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+                lite_db.merge().unwrap();
+            }
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                assert_eq!(2099, lite_db.index.list_keys().unwrap().len());
+                for i in 1..2100 {
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+                // merge 之后的文件同样截断了预分配的空间
+                for (_, file) in lite_db.older_files.read().iter() {
+                    assert_eq!(file.get_write_off(), file.file_size());
+                }
+            }
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        }
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_checksum_kind() {
+        let config = ready_config(file!(), function_name!());
+        let middle = Bytes::from("checksum-value");
+        for (kind, other) in [(ChecksumKind::Crc32, ChecksumKind::XxHash64), (ChecksumKind::XxHash64, ChecksumKind::Crc32)] {
+            let mut config = config.clone();
+            config.checksum = kind;
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..100 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+                lite_db.add(&get_test_key(100), &middle).unwrap();
+            }
+
+            // 使用另一种算法打开，旧的记录仍然按照记录中的算法校验
+            let mut config_other = config.clone();
+            config_other.checksum = other;
+            {
+                let lite_db = LiteDb::open(config_other.clone()).expect("failed to open engine");
+                for i in 0..100 {
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+                for i in 200..300 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+            }
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in (0..100).chain(200..300) {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+            assert_eq!(middle, lite_db.get(&get_test_key(100)).unwrap());
+
+            // 修改 value 之后校验失败
+            let pos = lite_db.index.get(get_test_key(100).to_vec()).unwrap();
+            let file_name = FileDb::get_data_file_name(config.path_db.clone(), pos.file_id);
+            let mut file = fs::OpenOptions::new().write(true).open(file_name).unwrap();
+            let value_offset = pos.offset + pos.size as u64 - kind.size() as u64 - middle.len() as u64;
+            file.seek(SeekFrom::Start(value_offset)).unwrap();
+            file.write_all(b"CHECKSUM").unwrap();
+            drop(file);
+            let err = lite_db.get(&get_test_key(100)).err().unwrap();
+            assert!(matches!(err, ErrDb::CrcMismatch { file_id, offset, .. } if file_id == pos.file_id && offset == pos.offset));
+
+            drop(lite_db);
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        }
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_get_opt() {
+        let config = ready_config(file!(), function_name!());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        lite_db.remove(&get_test_key(9)).unwrap();
+
+        // 不存在或者已经删除的 key 返回 None
+        assert_eq!(Some(get_test_value(0)), lite_db.get_opt(&get_test_key(0)).unwrap());
+        assert_eq!(None, lite_db.get_opt(&get_test_key(9)).unwrap());
+        assert_eq!(None, lite_db.get_opt(&get_test_key(100)).unwrap());
+
+        // 损坏的记录返回错误
+        let value = get_test_value(5);
+        let pos = lite_db.index.get(get_test_key(5).to_vec()).unwrap();
+        let file_name = FileDb::get_data_file_name(config.path_db.clone(), pos.file_id);
+        let mut file = fs::OpenOptions::new().write(true).open(file_name).unwrap();
+        file.seek(SeekFrom::Start(pos.offset + pos.size as u64 - 4 - value.len() as u64)).unwrap();
+        file.write_all(b"corrupt").unwrap();
+        drop(file);
+        assert!(matches!(lite_db.get_opt(&get_test_key(5)), Err(ErrDb::CrcMismatch { .. })));
+        assert_eq!(Some(get_test_value(4)), lite_db.get_opt(&get_test_key(4)).unwrap());
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_count_matching_prefix() {
+        let config = ready_config(file!(), function_name!());
+        let mut config_bptree = config.clone();
+        config_bptree.path_db = PathBuf::from(format!("{}-bptree", config.path_db.display()));
+        config_bptree.index_type = IndexType::BPlusTree;
+        let _ = fs::remove_dir_all(config_bptree.path_db.clone());
+
+        for config in [config, config_bptree] {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(0, lite_db.count_matching_prefix(b""));
+            for key in ["user-1", "user-2", "user-10", "users", "view", "a"] {
+                lite_db.add(&Bytes::from(key), &Bytes::from("value")).unwrap();
+            }
+            assert_eq!(6, lite_db.count_matching_prefix(b""));
+            assert_eq!(3, lite_db.count_matching_prefix(b"user-"));
+            assert_eq!(4, lite_db.count_matching_prefix(b"user"));
+            assert_eq!(1, lite_db.count_matching_prefix(b"user-10"));
+            assert_eq!(0, lite_db.count_matching_prefix(b"user-100"));
+            assert_eq!(1, lite_db.count_matching_prefix(b"a"));
+            assert_eq!(0, lite_db.count_matching_prefix(b"0"));
+            assert_eq!(0, lite_db.count_matching_prefix(b"z"));
+
+            lite_db.remove(&Bytes::from("user-2")).unwrap();
+            assert_eq!(2, lite_db.count_matching_prefix(b"user-"));
+
+            drop(lite_db);
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        }
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_binary_keys() {
+        let base = ready_config(file!(), function_name!());
+        // 包含 0x00、0xff 和不合法的 UTF-8 的 key，以及互为前缀的 key
+        let keys: Vec<Bytes> = [
+            &[0x00][..],
+            &[0x00, 0x00],
+            &[0x00, 0xff, 0x00],
+            b"a\x00b",
+            &[0xc3, 0x28],
+            &[0xf0, 0x28, 0x8c, 0xbc],
+            &[0xff],
+            &[0xff, 0x00],
+            &[0xff, 0xff],
+            &[0xff, 0xff, 0xff],
+        ]
+        .iter()
+        .map(|key| Bytes::copy_from_slice(key))
+        .collect();
+        let value = |key: &Bytes| Bytes::from([&[0xfe, 0x00], key.as_ref()].concat());
+
+        for (i, index_type) in [IndexType::BTree, IndexType::BPlusTree].into_iter().enumerate() {
+            let mut config = base.clone();
+            config.path_db = base.path_db.join(i.to_string());
+            config.index_type = index_type;
+            config.merge_ratio = 0.0;
+
+            let check = |lite_db: &LiteDb| {
+                for key in keys.iter() {
+                    assert_eq!(value(key), lite_db.get(key).unwrap());
+                }
+                assert_eq!(keys, lite_db.index.list_keys().unwrap());
+                let items: Vec<(Bytes, Bytes)> = lite_db.iter(IteratorOptions::default()).map(|item| item.unwrap()).collect();
+                assert_eq!(keys.iter().map(|key| (key.clone(), value(key))).collect::<Vec<_>>(), items);
+                let reversed: Vec<Bytes> = lite_db
+                    .iter(IteratorOptions {
+                        prefix: Vec::new(),
+                        reverse: true,
+                    })
+                    .map(|item| item.unwrap().0)
+                    .collect();
+                assert_eq!(keys.iter().rev().cloned().collect::<Vec<_>>(), reversed);
+                for prefix in [vec![0x00], vec![0xff], vec![0xff, 0xff]] {
+                    let expected: Vec<Bytes> = keys.iter().filter(|key| key.starts_with(&prefix)).cloned().collect();
+                    let actual: Vec<Bytes> = lite_db
+                        .iter(IteratorOptions {
+                            prefix: prefix.clone(),
+                            reverse: false,
+                        })
+                        .map(|item| item.unwrap().0)
+                        .collect();
+                    assert_eq!(expected, actual);
+                    assert_eq!(expected.len(), lite_db.count_prefix(&prefix));
+                }
+                assert_eq!(Some(keys[0].clone()), lite_db.first_key());
+                assert_eq!(keys.last().cloned(), lite_db.last_key());
+            };
+
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for key in keys.iter().rev() {
+                    lite_db.add(key, &value(key)).unwrap();
+                }
+                check(&lite_db);
+            }
+            // 从数据文件中加载索引
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                check(&lite_db);
+                lite_db.add(&keys[0], &value(&keys[0])).unwrap();
+                lite_db.merge().unwrap();
+            }
+            // 从 hint 文件中加载索引
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            check(&lite_db);
+            drop(lite_db);
+        }
+
+        fs::remove_dir_all(base.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_seq_no() {
+        let mut config = ready_config(file!(), function_name!());
+        config.index_type = IndexType::BPlusTree;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let seq_no = lite_db.seq_no();
+
+            // 不在批次中的写入不改变序列号
+            lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
+            lite_db.remove(&get_test_key(1)).unwrap();
+            assert_eq!(seq_no, lite_db.seq_no());
+
+            // 每提交一个批次加一，空的批次不提交
+            for i in 1..=3 {
+                let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+                wb.put(get_test_key(i), get_test_value(i)).unwrap();
+                wb.commit().unwrap();
+                assert_eq!(seq_no + i, lite_db.seq_no());
+            }
+            let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            wb.commit().unwrap();
+            assert_eq!(seq_no + 3, lite_db.seq_no());
+            lite_db.close().unwrap();
+        }
+        // 重新打开之后从之前的序列号继续
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let seq_no = lite_db.seq_no();
+            let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            wb.put(get_test_key(4), get_test_value(4)).unwrap();
+            wb.commit().unwrap();
+            assert_eq!(seq_no + 1, lite_db.seq_no());
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_first_last_key() {
+        let config = ready_config(file!(), function_name!());
+        let mut config_bptree = config.clone();
+        config_bptree.path_db = PathBuf::from(format!("{}-bptree", config.path_db.display()));
+        config_bptree.index_type = IndexType::BPlusTree;
+        let _ = fs::remove_dir_all(config_bptree.path_db.clone());
 
-            if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
-                let split_names: Vec<&str> = file_name.split(".").collect();
-                let file_id = split_names[0].parse::<u32>()?;
+        for config in [config, config_bptree] {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(None, lite_db.first_key());
+            assert_eq!(None, lite_db.last_key());
 
-                file_ids.push(file_id);
+            for key in ["m", "b", "x", "c"] {
+                lite_db.add(&Bytes::from(key), &Bytes::from("value")).unwrap();
             }
+            assert_eq!(Some(Bytes::from("b")), lite_db.first_key());
+            assert_eq!(Some(Bytes::from("x")), lite_db.last_key());
+
+            lite_db.remove(&Bytes::from("b")).unwrap();
+            lite_db.remove(&Bytes::from("x")).unwrap();
+            assert_eq!(Some(Bytes::from("c")), lite_db.first_key());
+            assert_eq!(Some(Bytes::from("m")), lite_db.last_key());
+
+            drop(lite_db);
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
         }
     }
 
-    // 如果没有数据文件，则直接返回
-    if file_ids.is_empty() {
-        return Ok(data_files);
+    #[named]
+    #[test]
+    fn test_lite_db_contains_prefix() {
+        let config = ready_config(file!(), function_name!());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert!(!lite_db.contains_prefix(b""));
+        assert_eq!(0, lite_db.count_prefix(b""));
+        for key in ["order-1", "order-2", "order", "user-1"] {
+            lite_db.add(&Bytes::from(key), &Bytes::from("value")).unwrap();
+        }
+
+        // 空的 prefix 匹配所有的 key
+        assert!(lite_db.contains_prefix(b""));
+        assert_eq!(4, lite_db.count_prefix(b""));
+        // 和 key 完全相同的 prefix
+        assert!(lite_db.contains_prefix(b"order"));
+        assert_eq!(3, lite_db.count_prefix(b"order"));
+        assert!(lite_db.contains_prefix(b"user-1"));
+        assert_eq!(1, lite_db.count_prefix(b"user-1"));
+        // 没有匹配的 key，包括比所有 key 都小和都大的 prefix
+        for prefix in [&b"order-3"[..], b"user-10", b"a", b"z"] {
+            assert!(!lite_db.contains_prefix(prefix));
+            assert_eq!(0, lite_db.count_prefix(prefix));
+        }
+
+        lite_db.remove(&Bytes::from("user-1")).unwrap();
+        assert!(!lite_db.contains_prefix(b"user"));
+        assert_eq!(0, lite_db.count_prefix(b"user"));
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
-    file_ids.sort();
-    // 遍历所有的文件id，依次打开对应的数据文件
-    for file_id in file_ids.iter() {
-        let mut io_type = IoType::StdIo;
-        if use_mmap {
-            io_type = IoType::MemoryMap;
+    #[named]
+    #[test]
+    fn test_lite_db_file_footer() {
+        let config = ready_config(file!(), function_name!());
+        let mut config_bptree = config.clone();
+        config_bptree.path_db = PathBuf::from(format!("{}-bptree", config.path_db.display()));
+        config_bptree.index_type = IndexType::BPlusTree;
+        let _ = fs::remove_dir_all(config_bptree.path_db.clone());
+
+        for mut config in [config, config_bptree] {
+            config.file_size_db = 16 * 1024;
+            let seq_no;
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..300 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+                let items: Vec<(Key, Value)> = (300..310).map(|i| (get_test_key(i), get_test_value(i))).collect();
+                lite_db.put_batch(&items).unwrap();
+                lite_db.rotate().unwrap();
+                seq_no = lite_db.seq_no();
+            }
+
+            // 封存的文件都有 footer，和扫描的结果一致
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                let older_files = lite_db.older_files.read();
+                assert!(older_files.len() > 1);
+                for data_file in older_files.values() {
+                    let footer = data_file.read_footer().expect("no footer");
+                    assert!(footer.record_count > 0);
+                    assert_eq!(footer, data_file.scan_footer().unwrap());
+                }
+                let max_seq_no = older_files.values().map(|f| f.read_footer().unwrap().max_seq_no).max().unwrap();
+                assert_eq!(seq_no - 1, max_seq_no as usize);
+                for i in 0..310 {
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+            }
+
+            // 没有事务序列号文件时从 footer 中恢复
+            if config.index_type == IndexType::BPlusTree {
+                fs::remove_file(config.path_db.join(SEQ_NO_FILE_NAME)).unwrap();
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                assert_eq!(seq_no, lite_db.seq_no());
+            }
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
         }
-        let data_file = FileDb::new(dir_path.clone(), *file_id, io_type)?;
-        data_files.push(data_file);
     }
 
-    Ok(data_files)
-}
+    #[named]
+    #[test]
+    fn test_lite_db_value_cache() {
+        let mut config = ready_config(file!(), function_name!());
+        config.value_cache_capacity = 10;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let key = get_test_key(1);
+        let value = Bytes::from("cached-value");
+        lite_db.add(&key, &value).unwrap();
+        assert_eq!(value, lite_db.get(&key).unwrap());
+        assert_eq!(1, lite_db.value_cache.len());
+
+        // 修改数据文件中的 value，命中缓存时不读取数据文件
+        let pos = lite_db.index.get(key.to_vec()).unwrap();
+        {
+            let file_name = FileDb::get_data_file_name(config.path_db.clone(), pos.file_id);
+            let mut file = fs::OpenOptions::new().write(true).open(file_name).unwrap();
+            file.seek(SeekFrom::Start(pos.offset + pos.size as u64 - 4 - value.len() as u64)).unwrap();
+            file.write_all(b"CACHED").unwrap();
+        }
+        assert_eq!(value, lite_db.get(&key).unwrap());
+        assert_eq!(value, lite_db.get_with_pos(&key).unwrap().0);
+
+        // 覆盖写入之后读取到新的值
+        lite_db.add(&key, &Bytes::from("new-value")).unwrap();
+        assert_eq!(0, lite_db.value_cache.len());
+        assert_eq!(Bytes::from("new-value"), lite_db.get(&key).unwrap());
+        assert_eq!(1, lite_db.value_cache.len());
+
+        // 批量提交之后读取到新的值
+        lite_db.put_batch(&[(key.clone(), Bytes::from("batch-value"))]).unwrap();
+        assert_eq!(Bytes::from("batch-value"), lite_db.get(&key).unwrap());
+
+        // 删除之后读取不到
+        lite_db.remove(&key).unwrap();
+        assert_eq!(0, lite_db.value_cache.len());
+        assert_eq!(ErrDb::NotFindKey, lite_db.get(&key).err().unwrap());
+
+        // 超过容量之后淘汰最久没有读取的
+        for i in 0..20 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+        }
+        assert_eq!(10, lite_db.value_cache.len());
 
-#[cfg(test)]
-mod tests {
-    use std::fs;
-    use std::path::PathBuf;
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
 
-    use bytes::Bytes;
-    use function_name::named;
+    #[named]
+    #[test]
+    fn test_lite_db_compact_key() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 16 * 1024;
+        let key = get_test_key(1);
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..1000 {
+                lite_db.add(&key, &get_test_value(i)).unwrap();
+            }
+            lite_db.add(&get_test_key(2), &get_test_value(2)).unwrap();
+            let old_pos = lite_db.index.get(key.to_vec()).unwrap();
+            let reclaim_size = lite_db.stat().unwrap().reclaim_size;
+
+            lite_db.compact_key(&key).unwrap();
+            let pos = lite_db.index.get(key.to_vec()).unwrap();
+            assert!(pos.offset > old_pos.offset || pos.file_id > old_pos.file_id);
+            assert_eq!(reclaim_size + old_pos.size as usize, lite_db.stat().unwrap().reclaim_size);
+            assert_eq!(get_test_value(999), lite_db.get(&key).unwrap());
+
+            assert_eq!(ErrDb::NotFindKey, lite_db.compact_key(&get_test_key(3)).err().unwrap());
+            assert_eq!(ErrDb::InvalidParameter, lite_db.compact_key(&Bytes::new()).err().unwrap());
+
+            // 重写之后旧的文件都可以被 merge 回收
+            lite_db.rotate().unwrap();
+            lite_db.compact_key(&key).unwrap();
+            lite_db.compact_key(&get_test_key(2)).unwrap();
+            lite_db.merge().unwrap();
+            assert!(lite_db.stat().unwrap().files_dropped > 0);
+        }
 
-    use crate::db::{Adder, Closer, Config, Db, ErrDb, Getter, Remover};
-    use crate::kits;
-    use crate::kits::rand_kv::{get_test_key, get_test_value};
-    use crate::lite::LiteDb;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(get_test_value(999), lite_db.get(&key).unwrap());
+        assert_eq!(get_test_value(2), lite_db.get(&get_test_key(2)).unwrap());
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
 
-    fn ready_config(file: &str, name: &str) -> Config {
-        let mut config = Config::default();
-        config.path_db = PathBuf::from("temp").join(kits::com_names::path_name(file, name));
-        config.file_size_db = 64u64 * 1024 * 1024;
+    #[named]
+    #[test]
+    fn test_lite_db_large_value() {
+        let mut config = ready_config(file!(), function_name!());
+        config.large_value_threshold = 100;
+        let under = Bytes::from(vec![1u8; 100]);
+        let over = Bytes::from(vec![2u8; 101]);
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&get_test_key(1), &under).unwrap();
+            lite_db.add(&get_test_key(2), &over).unwrap();
+            assert_eq!(under, lite_db.get(&get_test_key(1)).unwrap());
+            assert_eq!(over, lite_db.get(&get_test_key(2)).unwrap());
+
+            // 只有超过阈值的 value 写到 blob 文件中，数据文件中只有 blob 的位置
+            let blob_files = FileDb::find_blob_files(&config.path_db).unwrap();
+            assert_eq!(1, blob_files.len());
+            assert!(lite_db.index.get(get_test_key(1).to_vec()).unwrap().size > 100);
+            assert!(lite_db.index.get(get_test_key(2).to_vec()).unwrap().size < 100);
+
+            // 快照的迭代器同样读取 blob
+            let snapshot = lite_db.snapshot();
+            let items: Vec<(Key, Value)> = snapshot.iter().map(|item| item.unwrap()).collect();
+            assert_eq!(vec![(get_test_key(1), under.clone()), (get_test_key(2), over.clone())], items);
+        }
+
+        // 重新打开之后仍然可以读取
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(under, lite_db.get(&get_test_key(1)).unwrap());
+        assert_eq!(over, lite_db.get(&get_test_key(2)).unwrap());
+        assert_eq!(Some(over), lite_db.remove(&get_test_key(2)).unwrap());
+        assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(2)).err().unwrap());
+
+        lite_db.clear().unwrap();
+        assert!(FileDb::find_blob_files(&config.path_db).unwrap().is_empty());
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_is_new() {
+        let config = ready_config(file!(), function_name!());
+
+        // the dir does not exist
+        {
+            assert!(!config.path_db.exists());
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert!(lite_db.is_new());
+        }
+        // the dir exists but is empty
         {
-            //repeat run test
-            let _ = fs::remove_dir_all(config.path_db.clone());
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+            fs::create_dir_all(config.path_db.clone()).expect("failed to create dir");
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert!(lite_db.is_new());
+            lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
         }
-        config
+        // the dir contains data files
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert!(!lite_db.is_new());
+        }
+        // in memory db is always new
+        {
+            let mut config_memory = config.clone();
+            config_memory.in_memory = true;
+            let lite_db = LiteDb::open(config_memory).expect("failed to open engine");
+            assert!(lite_db.is_new());
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
     #[named]
     #[test]
-    fn test_lite_db_put() {
+    fn test_lite_db_start_file_id() {
+        let mut config = ready_config(file!(), function_name!());
+        config.start_file_id = Some(100);
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(100, lite_db.active_file.read().get_file_id());
+            lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
+            lite_db.rotate().unwrap();
+            lite_db.add(&get_test_key(2), &get_test_value(2)).unwrap();
+        }
+        assert!(config.path_db.join("000000100.data").is_file());
+        assert!(config.path_db.join("000000101.data").is_file());
+        assert!(!config.path_db.join("000000000.data").exists());
+
+        // reopen db, the existing data files are used
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(101, lite_db.active_file.read().get_file_id());
+            assert_eq!(get_test_value(1), lite_db.get(&get_test_key(1)).unwrap());
+            assert_eq!(get_test_value(2), lite_db.get(&get_test_key(2)).unwrap());
+        }
+
+        config.start_file_id = Some(u32::MAX);
+        assert!(LiteDb::open(config.clone()).is_err());
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_rotate() {
         let config = ready_config(file!(), function_name!());
+
         {
             let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..30 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                if i == 9 || i == 19 {
+                    let file_id = lite_db.rotate().expect("failed to rotate");
+                    assert_eq!(file_id, lite_db.active_file.read().get_file_id());
+                }
+            }
+            assert_eq!(2, lite_db.active_file.read().get_file_id());
+            assert_eq!(3, lite_db.stat().unwrap().data_file_num);
+            for i in 0..30 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+        }
 
-            {
-                let re = lite_db.add(&get_test_key(11), &get_test_value(11));
-                assert!(re.is_ok());
-                let re2 = lite_db.get(&get_test_key(11));
-                assert_eq!(re2.unwrap(), get_test_value(11));
+        assert_eq!(3, FileDb::find_data_files(&config.path_db).unwrap().len());
+
+        // reopen db
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..30 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
             }
+        }
 
-            // 2. add the same key
-            {
-                let re = lite_db.add(&get_test_key(22), &get_test_value(22));
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_in_memory() {
+        let mut config = ready_config(file!(), function_name!());
+        config.in_memory = true;
+        config.file_size_db = 4 * 1024;
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..1000 {
+                let re = lite_db.add(&get_test_key(i), &get_test_value(i));
                 assert!(re.is_ok());
-                let re2 = lite_db.add(&get_test_key(22), &Bytes::from("a new value"));
-                assert!(re2.is_ok());
-                let re3 = lite_db.get(&get_test_key(22));
-                assert_eq!(re3.unwrap(), Bytes::from("a new value"));
+            }
+            // rotate to the older files
+            assert!(!lite_db.older_files.read().is_empty());
+            for i in 0..1000 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
             }
 
-            // 3.key is empty
-            {
-                let re = lite_db.add(&Bytes::new(), &get_test_value(123));
-                assert_eq!(ErrDb::InvalidParameter, re.err().unwrap());
+            for i in 0..500 {
+                let re = lite_db.remove(&get_test_key(i));
+                assert_eq!(Some(get_test_value(i)), re.unwrap());
+            }
+            assert!(matches!(lite_db.get(&get_test_key(1)), Err(ErrDb::NotFindKey)));
+
+            let mut iter = lite_db.index.iterator(IteratorOptions::default());
+            let mut count = 0;
+            while let Some((key, pos)) = iter.next() {
+                assert_eq!(get_test_value(500 + count), lite_db.get_value_by_pos(pos).unwrap());
+                assert_eq!(get_test_key(500 + count).to_vec(), *key);
+                count += 1;
             }
+            assert_eq!(500, count);
 
-            // 4.value is empty
-            {
-                let re = lite_db.add(&get_test_key(33), &Bytes::new());
-                assert!(re.is_ok());
-                let re2 = lite_db.get(&get_test_key(33));
-                assert_eq!(0, re2.ok().unwrap().len());
+            assert!(lite_db.merge().is_err());
+            assert!(lite_db.close().is_ok());
+        }
+
+        // no file is written
+        assert!(!config.path_db.exists());
+
+        // the data is gone after reopen
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert!(matches!(lite_db.get(&get_test_key(600)), Err(ErrDb::NotFindKey)));
+        }
+        assert!(!config.path_db.exists());
+
+        // in memory mode do not support bplus tree
+        config.index_type = IndexType::BPlusTree;
+        assert!(LiteDb::open(config.clone()).is_err());
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_open_corrupt_bptree() {
+        let mut config = ready_config(file!(), function_name!());
+        config.index_type = IndexType::BPlusTree;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
+            lite_db.close().unwrap();
+        }
+        // 损坏的序列号文件和索引文件打开时返回错误而不是 panic
+        let seq_no_file = config.path_db.join(SEQ_NO_FILE_NAME);
+        let seq_no = fs::read(&seq_no_file).unwrap();
+        fs::write(&seq_no_file, vec![0xab; seq_no.len()]).unwrap();
+        assert!(LiteDb::open(config.clone()).is_err());
+        fs::write(&seq_no_file, &seq_no).unwrap();
+
+        let index_file = config.path_db.join(&config.bptree_index_file_name);
+        fs::write(&index_file, vec![0xab; 8192]).unwrap();
+        assert!(LiteDb::open(config.clone()).is_err());
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_corrupt_blob_pos() {
+        let mut config = ready_config(file!(), function_name!());
+        config.large_value_threshold = 100;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        // blob 的位置损坏时返回错误而不是 panic
+        let mut log_db = LogDb {
+            key: log_db_key_with_seq(get_test_key(1).to_vec(), NON_TRANSACTION_SEQ_NO),
+            value: vec![0xff; 3],
+            rec_type: LogDbType::BLOB,
+        };
+        let pos = lite_db.append_log_db(&mut log_db).unwrap();
+        assert!(lite_db.index.put(get_test_key(1).to_vec(), pos).is_none());
+        assert!(matches!(lite_db.get(&get_test_key(1)), Err(ErrDb::Err(_))));
+        assert!(matches!(lite_db.get_value_by_pos(&pos), Err(ErrDb::Err(_))));
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_bptree_custom_names() {
+        let mut config = ready_config(file!(), function_name!());
+        config.index_type = IndexType::BPlusTree;
+        config.bptree_index_file_name = "custom-index".to_owned();
+        config.bptree_bucket_name = "custom-bucket".to_owned();
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
+            lite_db.close().unwrap();
+        }
+        assert!(config.path_db.join("custom-index").is_file());
+        assert!(!config.path_db.join("bptree-index").exists());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(get_test_value(1), lite_db.get(&get_test_key(1)).unwrap());
+            lite_db.close().unwrap();
+        }
+
+        config.bptree_bucket_name = String::new();
+        assert!(LiteDb::open(config.clone()).is_err());
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_system_key() {
+        let mut config = ready_config(file!(), function_name!());
+        config.merge_ratio = 0.0;
+        let seq_no_key = Bytes::from(SEQ_NO_KEY);
+        let merge_fin_key = Bytes::from("merge.finished");
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert!(lite_db.add(&seq_no_key, &Bytes::from("user seq no")).is_ok());
+            assert!(lite_db.add(&merge_fin_key, &Bytes::from("user merge")).is_ok());
+            assert!(lite_db.add(&get_test_key(1), &get_test_value(1)).is_ok());
+            assert!(lite_db.add(&get_test_key(1), &get_test_value(2)).is_ok());
+            assert!(lite_db.merge().is_ok());
+            assert!(lite_db.close().is_ok());
+
+            let seq_no_file = FileDb::new_seq_no_file(config.path_db.clone()).unwrap();
+            let re = seq_no_file.read_log_db(0).unwrap();
+            assert_eq!(LogDbType::SYSTEM, re.log_db.rec_type);
+        }
+
+        // reopen db
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(Bytes::from("user seq no"), lite_db.get(&seq_no_key).unwrap());
+            assert_eq!(Bytes::from("user merge"), lite_db.get(&merge_fin_key).unwrap());
+            assert_eq!(get_test_value(2), lite_db.get(&get_test_key(1)).unwrap());
+            assert_eq!(3, lite_db.index.list_keys().unwrap().len());
+        }
+
+        // remove the test file
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_file_stats() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        config.merge_ratio = 0.0;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        let dead_size = |stats: &Vec<FileStat>| stats.iter().map(|s| s.dead_size).sum::<u64>();
+        let stats = lite_db.file_stats().unwrap();
+        assert!(stats.len() > 1);
+        // 封存的文件中只有 footer 是无效的
+        assert_eq!(0, stats.last().unwrap().dead_size);
+        assert!(dead_size(&stats) < 100 * stats.len() as u64);
+
+        // 覆盖和删除之后旧的记录变为无效
+        for i in 0..500 {
+            lite_db.add(&get_test_key(i), &Bytes::from(format!("new-{}", i))).unwrap();
+        }
+        let overwritten = lite_db.file_stats().unwrap();
+        assert!(overwritten[0].dead_size > stats[0].dead_size);
+        assert!(dead_size(&overwritten) > dead_size(&stats) + 500 * 10);
+        lite_db.remove(&get_test_key(999)).unwrap();
+        let removed = lite_db.file_stats().unwrap();
+        assert!(dead_size(&removed) > dead_size(&overwritten));
+        for stat in removed.iter() {
+            assert_eq!(stat.total_size, stat.live_size + stat.dead_size);
+        }
+
+        // merge 之后重写的文件中只有有效的记录
+        lite_db.merge().expect("failed to merge");
+        let merged = lite_db.file_stats().unwrap();
+        assert_eq!(0, dead_size(&merged));
+        let live_size: u64 = merged.iter().map(|s| s.live_size).sum();
+        assert!(live_size > 0);
+        assert!(live_size < removed.iter().map(|s| s.live_size + s.dead_size).sum::<u64>());
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_io_factory() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        config.large_value_threshold = 1024;
+        let files: Arc<Mutex<HashMap<PathBuf, Arc<MemIo>>>> = Arc::new(Mutex::new(HashMap::new()));
+        let factory_files = files.clone();
+        config.io_factory = Some(DbIoFactory::new(move |file_name| {
+            // 所有的数据都保存在内存中，同一个文件名称返回同一份数据
+            let mem_io = factory_files.lock().entry(file_name).or_default().clone();
+            Ok(Box::new(HookIo::shared(mem_io)))
+        }));
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        let large = Bytes::from(vec![1u8; 2048]);
+        lite_db.add(&get_test_key(1000), &large).unwrap();
+        for i in 0..100 {
+            lite_db.remove(&get_test_key(i)).unwrap();
+        }
+        let batch = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+        batch.put(get_test_key(0), get_test_value(0)).unwrap();
+        batch.commit().unwrap();
+        lite_db.sync().unwrap();
+
+        assert_eq!(get_test_value(0), lite_db.get(&get_test_key(0)).unwrap());
+        for i in 1..100 {
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+        }
+        for i in 100..1000 {
+            assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+        }
+        assert_eq!(large, lite_db.get(&get_test_key(1000)).unwrap());
+        assert_eq!(902, lite_db.stat().unwrap().key_num);
+
+        // 数据文件和 blob 文件都由自定义的 IO 创建，磁盘上的文件没有数据
+        let stat = lite_db.stat().unwrap();
+        assert!(stat.data_file_num > 1);
+        let files = files.lock();
+        assert_eq!(stat.data_file_num + 1, files.len());
+        assert!(files.keys().any(|f| f.extension() == Some("blob".as_ref())));
+        assert!(files.values().all(|f| f.size() > 0));
+        for (_, file_name) in FileDb::find_data_files(&config.path_db).unwrap() {
+            assert_eq!(0, fs::metadata(file_name).unwrap().len());
+        }
+
+        drop(files);
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_duplicate_file_ids() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        let mut other_config = config.clone();
+        other_config.path_db = config.path_db.join("other");
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..300 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            let other_db = LiteDb::open(other_config.clone()).expect("failed to open engine");
+            for i in 1000..1010 {
+                other_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+        }
+        // 另一个数据库的 0 号文件换一个名称放到数据目录中，和已有的 0 号文件 id 相同
+        let planted = config.path_db.join("0.data");
+        fs::rename(other_config.data_file_name(&other_config.path_db, 0), &planted).unwrap();
+        fs::remove_dir_all(&other_config.path_db).unwrap();
+        let file_num = FileDb::find_data_files(&config.path_db).unwrap().len();
+
+        let err = LiteDb::open(config.clone()).err().expect("duplicate file ids are not detected");
+        assert!(matches!(err, ErrDb::CorruptLayout(_)), "{}", err);
+        assert!(planted.exists());
+
+        // 修复时多余的文件改为新的 id，两个文件中的数据都可以读取
+        config.repair_duplicate_file_ids = true;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to repair engine");
+        assert!(!planted.exists());
+        let files = FileDb::find_data_files(&config.path_db).unwrap();
+        assert_eq!(file_num, files.len());
+        let ids: BTreeSet<u32> = files.iter().map(|(id, _)| *id).collect();
+        assert_eq!(file_num, ids.len());
+        for i in (0..300).chain(1000..1010) {
+            assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+        }
+        drop(lite_db);
+
+        // 修复之后不需要再修复
+        config.repair_duplicate_file_ids = false;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(310, lite_db.stat().unwrap().key_num);
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_repair_on_open() {
+        let mut config = ready_config(file!(), function_name!());
+        let tail = {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..100 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            // 校验值错误的记录，和写入的时候崩溃留下的一样
+            let mut tail = lite_db.get_raw(&get_test_key(99)).unwrap();
+            let len = tail.len();
+            tail[len - 5] ^= 0xff;
+            tail
+        };
+        let file_name = config.data_file_name(&config.path_db, 0);
+        let size = fs::metadata(&file_name).unwrap().len();
+        let mut file = fs::OpenOptions::new().append(true).open(&file_name).unwrap();
+        file.write_all(&tail).unwrap();
+        drop(file);
+        let corrupt = fs::read(&file_name).unwrap();
+
+        // 默认返回错误，不修改文件
+        let err = LiteDb::open(config.clone()).err().expect("the corrupt tail is not detected");
+        assert!(matches!(err, ErrDb::CorruptLayout(_)), "{}", err);
+        assert_eq!(corrupt, fs::read(&file_name).unwrap());
+
+        // 修复时截断损坏的记录，之前的数据都可以读取
+        config.repair_on_open = true;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to repair engine");
+            assert_eq!(size, fs::metadata(&file_name).unwrap().len());
+            for i in 0..100 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+            lite_db.add(&get_test_key(100), &get_test_value(100)).unwrap();
+        }
+
+        // 修复之后不需要再修复
+        config.repair_on_open = false;
+        let size = {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(101, lite_db.stat().unwrap().key_num);
+            fs::metadata(&file_name).unwrap().len()
+        };
+
+        // 写入时崩溃留下的不完整的记录同样从最后一条正确的记录之后截断
+        let torn = &tail[..tail.len() / 2];
+        let mut file = fs::OpenOptions::new().append(true).open(&file_name).unwrap();
+        file.write_all(torn).unwrap();
+        drop(file);
+        let err = LiteDb::open(config.clone()).err().expect("the torn tail is not detected");
+        assert!(matches!(err, ErrDb::CorruptLayout(_)), "{}", err);
+        assert_eq!(size + torn.len() as u64, fs::metadata(&file_name).unwrap().len());
+        config.repair_on_open = true;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to repair engine");
+        assert_eq!(size, fs::metadata(&file_name).unwrap().len());
+        assert_eq!(101, lite_db.stat().unwrap().key_num);
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_repair_on_open_preallocated() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 64 * 1024;
+        config.preallocate = true;
+        let pos = {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..100 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
             }
+            lite_db.index.get(get_test_key(50).to_vec()).unwrap()
+        };
+        // 预分配的活跃文件中间一条记录的类型损坏
+        let file_name = config.data_file_name(&config.path_db, pos.file_id);
+        let mut file = fs::OpenOptions::new().write(true).open(&file_name).unwrap();
+        file.seek(SeekFrom::Start(pos.offset)).unwrap();
+        file.write_all(&[0x0f]).unwrap();
+        drop(file);
+
+        // 默认返回错误，不从文件大小开始写入
+        let err = LiteDb::open(config.clone()).err().expect("the corrupt record is not detected");
+        assert!(matches!(err, ErrDb::CorruptLayout(_)), "{}", err);
+
+        // 修复时截断到损坏的记录之前
+        config.repair_on_open = true;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to repair engine");
+        assert!(matches!(lite_db.health(), Health::Corrupt(reason) if reason.contains("truncated")));
+        assert_eq!(50, lite_db.stat().unwrap().key_num);
+        assert_eq!(get_test_value(49), lite_db.get(&get_test_key(49)).unwrap());
+        lite_db.add(&get_test_key(50), &get_test_value(50)).unwrap();
+        assert_eq!(pos.offset, lite_db.index.get(get_test_key(50).to_vec()).unwrap().offset);
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
 
-            // 5. write data till the make new data file
-            for i in 0..=1000000 {
-                let re = lite_db.add(&get_test_key(i), &get_test_value(i));
-                assert!(re.is_ok());
+    #[named]
+    #[test]
+    fn test_lite_db_max_open_files() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 4 * 1024;
+        config.max_open_files = 3;
+        config.merge_ratio = 0.0;
+        let count = 1000;
+        let check = |lite_db: &LiteDb| {
+            for i in (0..count).rev() {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+            let open = lite_db.file_handles.as_ref().unwrap().open_count();
+            assert!(open > 0 && open <= 3, "{} files are open", open);
+        };
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..count {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
             }
+            assert!(lite_db.stat().unwrap().data_file_num > 10);
+            check(&lite_db);
+            lite_db.sync_all().unwrap();
         }
 
-        // 6. reopen db, and then Put data
-        //must drop the db
+        // 重新打开时从所有的数据文件中加载索引
         {
             let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
-            let re = lite_db.add(&get_test_key(55), &get_test_value(55));
-            assert!(re.is_ok());
-
-            let re2 = lite_db.get(&get_test_key(55));
-            assert_eq!(re2.unwrap(), &get_test_value(55));
+            check(&lite_db);
+            for i in 0..count / 2 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            lite_db.merge().unwrap();
+            check(&lite_db);
         }
 
-        // remove the test file
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        check(&lite_db);
+        assert_eq!(count, lite_db.iter(IteratorOptions::default()).count());
+
+        drop(lite_db);
         fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
     #[named]
     #[test]
-    fn test_lite_db_get() {
-        let config = ready_config(file!(), function_name!());
-
-        {
-            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+    fn test_lite_db_reopen_unloaded_file() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 4 * 1024;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..200 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        let pos = lite_db.index.get(get_test_key(0).to_vec()).unwrap();
+        assert!(pos.file_id < lite_db.active_file.read().get_file_id());
+        let raw = lite_db.get_raw(&get_test_key(0)).unwrap();
+
+        // 文件不在 older_files 中时从数据目录中重新打开
+        assert!(lite_db.older_files.write().remove(&pos.file_id).is_some());
+        assert_eq!(get_test_value(0), lite_db.get(&get_test_key(0)).unwrap());
+        assert!(lite_db.older_files.read().contains_key(&pos.file_id));
+        lite_db.older_files.write().remove(&pos.file_id);
+        assert_eq!(raw, lite_db.get_raw(&get_test_key(0)).unwrap());
+        lite_db.older_files.write().remove(&pos.file_id);
+        assert_eq!(get_test_value(0), lite_db.get_zero_copy(&get_test_key(0)).unwrap());
+
+        // 数据目录中也没有时返回文件不存在
+        lite_db.older_files.write().remove(&pos.file_id);
+        fs::remove_file(config.data_file_name(&config.path_db, pos.file_id)).unwrap();
+        let re = lite_db.get(&get_test_key(0));
+        assert!(matches!(re, Err(ErrDb::IoErr(e)) if e.kind() == std::io::ErrorKind::NotFound));
+        assert_eq!(get_test_value(199), lite_db.get(&get_test_key(199)).unwrap());
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
 
-            // 1. normal
+    #[named]
+    #[test]
+    fn test_lite_db_max_records_per_file() {
+        let base = ready_config(file!(), function_name!());
+        for (i, index_type) in [IndexType::BTree, IndexType::BPlusTree].into_iter().enumerate() {
+            let mut config = base.clone();
+            config.path_db = base.path_db.join(i.to_string());
+            config.index_type = index_type;
+            config.max_records_per_file = Some(10);
             {
-                let re = lite_db.add(&get_test_key(111), &get_test_value(111));
-                assert!(re.is_ok());
-                let re2 = lite_db.get(&get_test_key(111));
-                assert_eq!(re2.unwrap(), get_test_value(111));
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..25 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+                assert_eq!(3, lite_db.stat().unwrap().data_file_num);
+                assert_eq!(5, lite_db.active_file.read().record_count());
+
+                // 批量写入的 4 条记录和事务标识刚好写满活跃文件
+                let batch = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+                for i in 25..29 {
+                    batch.put(get_test_key(i), get_test_value(i)).unwrap();
+                }
+                batch.commit().unwrap();
+                assert_eq!(3, lite_db.stat().unwrap().data_file_num);
+                assert_eq!(10, lite_db.active_file.read().record_count());
             }
 
-            // 2.get the key that not exist
-            {
-                let re = lite_db.get(&Bytes::from("not existed key"));
-                assert!(matches!(re, Err(ErrDb::NotFindKey)));
+            // 重新打开之后继续按照已有的记录数量切换
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&get_test_key(29), &get_test_value(29)).unwrap();
+            assert_eq!(4, lite_db.stat().unwrap().data_file_num);
+            assert_eq!(1, lite_db.active_file.read().record_count());
+            for file in lite_db.older_files.read().values() {
+                assert_eq!(10, file.scan_footer().unwrap().record_count);
             }
+            for i in 0..30 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+        }
 
-            // 3. repeat to add same key
-            {
-                let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
-                assert!(re1.is_ok());
-                let re2 = lite_db.add(&get_test_key(222), &Bytes::from("a new value"));
-                assert!(re2.is_ok());
-                let re3 = lite_db.get(&get_test_key(222));
-                assert_eq!(Bytes::from("a new value"), re3.unwrap());
+        fs::remove_dir_all(base.path_db.clone()).expect("failed to remove path");
+    }
+
+    // 数据目录中所有的文件和内容
+    fn dir_files(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(dir_files(&path));
+            } else {
+                files.push((path.clone(), fs::read(path).unwrap()));
             }
+        }
+        files.sort();
+        files
+    }
 
-            // 4. get after remove
-            {
-                let re1 = lite_db.add(&get_test_key(333), &get_test_value(333));
-                assert!(re1.is_ok());
-                let re2 = lite_db.remove(&get_test_key(333));
-                assert!(re2.is_ok());
-                let re3 = lite_db.get(&get_test_key(333));
-                assert!(matches!(re3, Err(ErrDb::NotFindKey)));
+    // 尽量把目录和其中的文件都设置为只读，root 用户仍然可以写入
+    #[allow(clippy::permissions_set_readonly_false)]
+    fn set_dir_readonly(dir: &Path, readonly: bool) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                set_dir_readonly(&path, readonly);
+            } else {
+                let mut perm = fs::metadata(&path).unwrap().permissions();
+                perm.set_readonly(readonly);
+                fs::set_permissions(&path, perm).unwrap();
             }
+        }
+        let mut perm = fs::metadata(dir).unwrap().permissions();
+        perm.set_readonly(readonly);
+        fs::set_permissions(dir, perm).unwrap();
+    }
 
-            // 5. to old file ，get value from the old file
-            {
-                for i in 500..=1000000 {
-                    let re = lite_db.add(&get_test_key(i), &get_test_value(i));
-                    assert!(re.is_ok());
-                }
-                let re2 = lite_db.get(&get_test_key(505));
-                assert_eq!(get_test_value(505), re2.unwrap());
+    #[named]
+    #[test]
+    fn test_lite_db_read_only() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        config.large_value_threshold = 1024;
+        config.merge_ratio = 0.0;
+        let large = Bytes::from(vec![7u8; 2048]);
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..300 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
             }
+            lite_db.add(&get_test_key(300), &large).unwrap();
+            for i in 0..100 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            // 只读打开时也从 hint 文件中加载索引
+            lite_db.merge().expect("failed to merge");
+            lite_db.add(&get_test_key(0), &get_test_value(0)).unwrap();
+            lite_db.remove(&get_test_key(0)).unwrap();
         }
+        assert!(config.path_db.join(HINT_FILE_NAME).is_file());
 
-        // 6. reopen db, and then Put data
-        //must drop the db
+        // 只读的快照中没有锁文件
+        fs::remove_file(config.path_db.join(FILE_LOCK_NAME)).unwrap();
+        set_dir_readonly(&config.path_db, true);
+        let before = dir_files(&config.path_db);
+
+        let mut ro_config = config.clone();
+        ro_config.read_only = true;
+        {
+            let lite_db = LiteDb::open(ro_config.clone()).expect("failed to open read only");
+            // 多个只读的实例可以同时打开
+            let other = LiteDb::open(ro_config.clone()).expect("failed to open read only");
+            for i in 0..100 {
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+            }
+            for i in 100..300 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+            assert_eq!(large, other.get(&get_test_key(300)).unwrap());
+
+            // 所有的写入都返回 ReadOnly
+            assert_eq!(ErrDb::ReadOnly, lite_db.add(&get_test_key(0), &get_test_value(0)).err().unwrap());
+            assert_eq!(ErrDb::ReadOnly, lite_db.add(&get_test_key(301), &large).err().unwrap());
+            assert_eq!(ErrDb::ReadOnly, lite_db.remove(&get_test_key(100)).err().unwrap());
+            assert_eq!(ErrDb::ReadOnly, lite_db.new_write_batch(WriteBatchOptions::default()).err().unwrap());
+            assert_eq!(ErrDb::ReadOnly, lite_db.merge().err().unwrap());
+            assert_eq!(ErrDb::ReadOnly, lite_db.rotate().err().unwrap());
+            assert_eq!(ErrDb::ReadOnly, lite_db.clear().err().unwrap());
+            assert_eq!(get_test_value(100), lite_db.get(&get_test_key(100)).unwrap());
+        }
+        // 没有创建、修改或者删除任何文件
+        assert_eq!(before, dir_files(&config.path_db));
+        set_dir_readonly(&config.path_db, false);
+
+        // 有锁文件时，只读的实例和写入的实例互斥
         {
             let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
-            let re = lite_db.get(&get_test_key(111));
-            assert_eq!(get_test_value(111), re.unwrap());
-            let re2 = lite_db.get(&get_test_key(222));
-            assert_eq!(Bytes::from("a new value"), re2.unwrap());
-            let re3 = lite_db.get(&get_test_key(333));
-            assert!(matches!(re3, Err(ErrDb::NotFindKey)));
+            assert_eq!(ErrDb::DatabaseLocked, LiteDb::open(ro_config.clone()).err().unwrap());
+            drop(lite_db);
+            let ro_db = LiteDb::open(ro_config.clone()).expect("failed to open read only");
+            assert_eq!(ErrDb::DatabaseLocked, LiteDb::open(config.clone()).err().unwrap());
+            assert_eq!(large, ro_db.get(&get_test_key(300)).unwrap());
         }
 
-        // remove the test file
+        // 只读模式下不创建数据目录
+        let mut missing = ro_config.clone();
+        missing.path_db = config.path_db.join("missing");
+        assert!(LiteDb::open(missing.clone()).is_err());
+        assert!(!missing.path_db.exists());
+
         fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
     #[named]
     #[test]
-    fn test_lite_db_delete() {
-        let config = ready_config(file!(), function_name!());
-
-        {
-            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
-
-            // 1.remove key
-            {
-                let re1 = lite_db.add(&get_test_key(111), &get_test_value(111));
-                assert!(re1.is_ok());
-                let re2 = lite_db.remove(&get_test_key(111));
-                assert_eq!(re2.unwrap(), Some(get_test_value(111)));
-                let re3 = lite_db.get(&get_test_key(111));
-                assert!(matches!(re3, Err(ErrDb::NotFindKey)));
+    fn test_lite_db_reopen() {
+        let base = ready_config(file!(), function_name!());
+        for index_type in [IndexType::BTree, IndexType::BPlusTree] {
+            let mut config = base.clone();
+            config.path_db = base.path_db.join(format!("{:?}", index_type));
+            config.index_type = index_type.clone();
+            let mut lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..100 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            lite_db.remove(&get_test_key(0)).unwrap();
+
+            // 在数据目录中追加一条记录，重新打开之前看不到
+            let external = Bytes::from("external-key");
+            if index_type == IndexType::BTree {
+                let file_id = lite_db.active_file.read().get_file_id();
+                let data_file = FileDb::new_with_file_name(config.data_file_name(&config.path_db, file_id), file_id, IoType::StdIo).unwrap();
+                let log_db = LogDb {
+                    key: log_db_key_with_seq(external.to_vec(), NON_TRANSACTION_SEQ_NO),
+                    value: b"external-value".to_vec(),
+                    rec_type: LogDbType::NORMAL,
+                };
+                data_file.write(&log_db.encode()).unwrap();
+                data_file.sync().unwrap();
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(&external).err().unwrap());
             }
 
-            // 2.remove key that do not exist
-            {
-                let re = lite_db.remove(&Bytes::from("not-existed-key"));
-                assert!(re.is_ok());
+            lite_db.reopen().expect("failed to reopen");
+            if index_type == IndexType::BTree {
+                assert_eq!(Bytes::from("external-value"), lite_db.get(&external).unwrap());
+            }
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(0)).err().unwrap());
+            for i in 1..100 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
             }
+            // 重新打开之后可以继续写入
+            lite_db.add(&get_test_key(100), &get_test_value(100)).unwrap();
+            lite_db.reopen().expect("failed to reopen");
+            assert_eq!(get_test_value(100), lite_db.get(&get_test_key(100)).unwrap());
+        }
 
-            // 3. remove a empty key
-            {
-                let re = lite_db.remove(&Bytes::new());
-                assert!(matches!(re, Err(ErrDb::InvalidParameter)));
+        // 重新打开失败时数据库保持关闭的状态，读写返回 Closed，不会返回 NotFindKey
+        {
+            let mut config = base.clone();
+            config.path_db = base.path_db.join("failed");
+            let mut lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            lite_db.add(&get_test_key(0), &get_test_value(0)).unwrap();
+            let file_name = config.data_file_name(&config.path_db, lite_db.active_file.read().get_file_id());
+            let data = fs::read(&file_name).unwrap();
+            // 活跃文件的末尾是一条 crc 错误的记录
+            let mut corrupt = LogDb {
+                key: log_db_key_with_seq(get_test_key(1).to_vec(), NON_TRANSACTION_SEQ_NO),
+                value: get_test_value(1).to_vec(),
+                rec_type: LogDbType::NORMAL,
             }
+            .encode();
+            let last = corrupt.len() - 1;
+            corrupt[last] ^= 0xff;
+            fs::write(&file_name, [data.clone(), corrupt].concat()).unwrap();
+
+            assert!(lite_db.reopen().is_err());
+            assert_eq!(ErrDb::Closed, lite_db.get(&get_test_key(0)).err().unwrap());
+            assert_eq!(ErrDb::Closed, lite_db.get_with_pos(&get_test_key(0)).err().unwrap());
+            assert_eq!(ErrDb::Closed, lite_db.add(&get_test_key(2), &get_test_value(2)).err().unwrap());
+            assert_eq!(ErrDb::Closed, lite_db.stat().err().unwrap());
+            assert_eq!(ErrDb::Closed, lite_db.file_stats().err().unwrap());
+            // 只读取索引的方法不返回错误，索引是空的
+            assert!(!lite_db.exists(&get_test_key(0)));
+            assert!(!lite_db.contains_prefix(b""));
+            assert_eq!(0, lite_db.count_prefix(b""));
+            assert_eq!(None, lite_db.first_key());
+            assert_eq!(None, lite_db.last_key());
+
+            // 修复数据文件之后可以再次重新打开
+            fs::write(&file_name, data).unwrap();
+            lite_db.reopen().expect("failed to reopen");
+            assert_eq!(get_test_value(0), lite_db.get(&get_test_key(0)).unwrap());
+        }
 
-            // 4.add after remove
-            {
-                let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
-                assert!(re1.is_ok());
-                let re2 = lite_db.remove(&get_test_key(222));
-                assert!(re2.is_ok());
-                let re3 = lite_db.add(&get_test_key(222), &Bytes::from("a new value"));
-                assert!(re3.is_ok());
-                let re4 = lite_db.get(&get_test_key(222));
-                assert_eq!(Bytes::from("a new value"), re4.unwrap());
+        let mut config = Config::default();
+        config.in_memory = true;
+        let mut lite_db = LiteDb::open(config).expect("failed to open engine");
+        assert!(lite_db.reopen().is_err());
+
+        fs::remove_dir_all(base.path_db.clone()).expect("failed to remove path");
+    }
+
+    // 设置了 slow 之后每次读写都等待一段时间的 IO
+    fn slow_io(slow: Arc<AtomicBool>) -> HookIo {
+        let wait = move || {
+            if slow.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(5));
             }
+        };
+        let write_wait = wait.clone();
+        HookIo::new(MemIo::new())
+            .on_read(move |io, buf, offset| {
+                wait();
+                io.read(buf, offset)
+            })
+            .on_write(move |io, buf, offset| {
+                write_wait();
+                io.write_at(buf, offset)
+            })
+    }
+
+    // 保存所有输出的日志，同一个进程中只能设置一次
+    struct CaptureLog(std::sync::Mutex<Vec<String>>);
+
+    impl log::Log for CaptureLog {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
         }
 
-        // 5.reopen db
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE_LOG: CaptureLog = CaptureLog(std::sync::Mutex::new(Vec::new()));
+
+    fn captured_logs(prefix: &str) -> Vec<String> {
+        CAPTURE_LOG.0.lock().unwrap().iter().filter(|line| line.starts_with(prefix)).cloned().collect()
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_slow_op_threshold() {
+        if log::set_logger(&CAPTURE_LOG).is_ok() {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+        let mut config = ready_config(file!(), function_name!());
+        let slow = Arc::new(AtomicBool::new(false));
+        let factory_slow = slow.clone();
+        config.io_factory = Some(DbIoFactory::new(move |_| Ok(Box::new(slow_io(factory_slow.clone())))));
+
+        // 没有超过阈值时不输出日志
+        config.slow_op_threshold = Some(Duration::from_secs(3600));
         {
             let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
-            let re1 = lite_db.get(&get_test_key(111));
-            assert!(matches!(re1, Err(ErrDb::NotFindKey)));
-            let re2 = lite_db.get(&get_test_key(222));
-            assert_eq!(Bytes::from("a new value"), re2.unwrap());
+            slow.store(true, Ordering::SeqCst);
+            lite_db.add(&get_test_key(0), &get_test_value(0)).unwrap();
+            assert_eq!(get_test_value(0), lite_db.get(&get_test_key(0)).unwrap());
+            slow.store(false, Ordering::SeqCst);
         }
+        assert!(captured_logs("slow operation").is_empty());
 
-        // remove the test file
+        config.slow_op_threshold = Some(Duration::from_millis(1));
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        slow.store(true, Ordering::SeqCst);
+        lite_db.add(&get_test_key(1), &get_test_value(1)).unwrap();
+        assert_eq!(get_test_value(1), lite_db.get(&get_test_key(1)).unwrap());
+        let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+        wb.put(get_test_key(2), get_test_value(2)).unwrap();
+        wb.commit().unwrap();
+        slow.store(false, Ordering::SeqCst);
+
+        for op in ["add", "get", "commit"] {
+            let logs = captured_logs(&format!("slow operation {}:", op));
+            assert!(!logs.is_empty(), "no warning for {}", op);
+            assert!(logs[0].contains("threshold 1ms"), "{}", logs[0]);
+        }
+
+        drop(lite_db);
         fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
     #[named]
     #[test]
-    fn test_lite_db_close() {
-        let config = ready_config(file!(), function_name!());
+    fn test_lite_db_add_synced() {
+        let mut config = ready_config(file!(), function_name!());
+        config.sync_writes = false;
+        config.bytes_per_sync = 0;
+        let syncs = Arc::new(AtomicUsize::new(0));
+        let factory_syncs = syncs.clone();
+        config.io_factory = Some(DbIoFactory::new(move |_| {
+            Ok(Box::new(HookIo::new(MemIo::new()).count_syncs(factory_syncs.clone())))
+        }));
 
         let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let before = syncs.load(Ordering::SeqCst);
+        for i in 0..10 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        lite_db.remove(&get_test_key(0)).unwrap();
+        assert_eq!(before, syncs.load(Ordering::SeqCst));
+
+        lite_db.add_synced(&get_test_key(10), &get_test_value(10)).unwrap();
+        let after_add = syncs.load(Ordering::SeqCst);
+        assert!(after_add > before);
+        assert_eq!(get_test_value(10), lite_db.get(&get_test_key(10)).unwrap());
+
+        assert_eq!(Some(get_test_value(1)), lite_db.remove_synced(&get_test_key(1)).unwrap());
+        assert!(syncs.load(Ordering::SeqCst) > after_add);
+        assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(1)).err().unwrap());
+        // 不存在的 key 不写入也不持久化
+        let after_remove = syncs.load(Ordering::SeqCst);
+        assert_eq!(None, lite_db.remove_synced(&get_test_key(1)).unwrap());
+        assert_eq!(after_remove, syncs.load(Ordering::SeqCst));
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
 
-        let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
-        assert!(re1.is_ok());
+    #[named]
+    #[test]
+    fn test_lite_db_sync_all() {
+        let mut config = ready_config(file!(), function_name!());
+        config.sync_writes = false;
+        config.bytes_per_sync = 0;
+        config.file_size_db = 8 * 1024;
+        // 每个文件单独记录 sync 的次数
+        let syncs: Arc<Mutex<HashMap<PathBuf, Arc<AtomicUsize>>>> = Default::default();
+        let factory_syncs = syncs.clone();
+        config.io_factory = Some(DbIoFactory::new(move |file_name| {
+            let count = factory_syncs.lock().entry(file_name).or_default().clone();
+            Ok(Box::new(HookIo::new(MemIo::new()).count_syncs(count)))
+        }));
+        let count = |file_name: &Path| syncs.lock().get(file_name).map_or(0, |count| count.load(Ordering::SeqCst));
 
-        let close_re = lite_db.close();
-        assert!(close_re.is_ok());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        let mut file_names: Vec<PathBuf> = lite_db.older_files.read().values().map(|f| f.get_file_name().to_path_buf()).collect();
+        assert!(file_names.len() > 2);
+        file_names.push(lite_db.active_file.read().get_file_name().to_path_buf());
+
+        let before: Vec<usize> = file_names.iter().map(|f| count(f)).collect();
+        lite_db.sync_all().unwrap();
+        for (file_name, before) in file_names.iter().zip(before) {
+            assert_eq!(before + 1, count(file_name), "{}", file_name.display());
+        }
 
-        // remove the test file
+        drop(lite_db);
         fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
     #[named]
     #[test]
-    fn test_lite_db_sync() {
-        let config = ready_config(file!(), function_name!());
+    fn test_lite_db_add_durable() {
+        let mut config = ready_config(file!(), function_name!());
+        let syncs = Arc::new(AtomicUsize::new(0));
+        let factory_syncs = syncs.clone();
+        config.io_factory = Some(DbIoFactory::new(move |_| {
+            Ok(Box::new(HookIo::new(MemIo::new()).count_syncs(factory_syncs.clone())))
+        }));
+        let value = Bytes::from(vec![b'v'; 1000]);
+
+        // 每次写入都持久化
+        config.sync_writes = true;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(Durability::Synced, lite_db.add_durable(&get_test_key(0), &value).unwrap());
+        drop(lite_db);
 
+        // 不主动持久化
+        config.sync_writes = false;
+        config.bytes_per_sync = 0;
         let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
-        let re1 = lite_db.add(&get_test_key(222), &get_test_value(222));
-        assert!(re1.is_ok());
+        let before = syncs.load(Ordering::SeqCst);
+        for i in 0..10 {
+            assert_eq!(Durability::Buffered, lite_db.add_durable(&get_test_key(i), &value).unwrap());
+        }
+        assert_eq!(before, syncs.load(Ordering::SeqCst));
+        drop(lite_db);
 
-        let close_re = lite_db.sync();
-        assert!(close_re.is_ok());
+        // 累计写入 bytes_per_sync 之后持久化，只有真正持久化的写入返回 Synced
+        config.bytes_per_sync = 4096;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(Durability::Buffered, lite_db.add_durable(&get_test_key(0), &value).unwrap());
+        let mut synced = 0;
+        for i in 1..40 {
+            let before = syncs.load(Ordering::SeqCst);
+            let durability = lite_db.add_durable(&get_test_key(i), &value).unwrap();
+            assert_eq!(syncs.load(Ordering::SeqCst) > before, durability == Durability::Synced);
+            if durability == Durability::Synced {
+                synced += 1;
+            }
+        }
+        // 每 4 到 5 次写入持久化一次
+        assert!((8..=10).contains(&synced));
 
-        // remove the test file
+        drop(lite_db);
         fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
     #[named]
     #[test]
-    fn test_lite_db_file_lock() {
-        let config = ready_config(file!(), function_name!());
-
+    fn test_lite_db_group_commit() {
+        let mut config = ready_config(file!(), function_name!());
+        config.sync_writes = false;
+        config.bytes_per_sync = 0;
+        config.group_commit_window = Some(Duration::from_millis(300));
+        config.group_commit_max = 1000;
+        let syncs = Arc::new(AtomicUsize::new(0));
+        let factory_syncs = syncs.clone();
+        config.io_factory = Some(DbIoFactory::new(move |_| {
+            Ok(Box::new(HookIo::new(MemIo::new()).count_syncs(factory_syncs.clone())))
+        }));
+
+        // 窗口内并发的写入共享一次持久化
+        let threads = 16;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let before = syncs.load(Ordering::SeqCst);
+        let barrier = Barrier::new(threads);
+        std::thread::scope(|s| {
+            for i in 0..threads {
+                let (lite_db, barrier) = (&lite_db, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                });
+            }
+        });
+        assert_eq!(before + 1, syncs.load(Ordering::SeqCst));
+        for i in 0..threads {
+            assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+        }
+        // 单独的写入等待窗口之后也会持久化
+        let before = syncs.load(Ordering::SeqCst);
+        assert_eq!(Some(get_test_value(0)), lite_db.remove(&get_test_key(0)).unwrap());
+        assert_eq!(before + 1, syncs.load(Ordering::SeqCst));
+        drop(lite_db);
+
+        // 收集到 group_commit_max 条之后不等待窗口结束
+        config.group_commit_window = Some(Duration::from_secs(10));
+        config.group_commit_max = 4;
         let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let before = syncs.load(Ordering::SeqCst);
+        let barrier = Barrier::new(8);
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let (lite_db, barrier) = (&lite_db, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    lite_db.add(&get_test_key(100 + i), &get_test_value(i)).unwrap();
+                });
+            }
+        });
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(before + 2, syncs.load(Ordering::SeqCst));
+        for i in 0..8 {
+            assert_eq!(get_test_value(i), lite_db.get(&get_test_key(100 + i)).unwrap());
+        }
 
-        let lite_db2 = LiteDb::open(config.clone());
-        let _eof = ErrDb::new_io_eof("");
-        assert!(matches!(lite_db2, Err(_eof)));
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
 
-        let re2 = lite_db.close();
-        assert!(matches!(re2, Ok(())));
-        // std::mem::drop(lite_db);
+    #[cfg(feature = "encryption")]
+    #[named]
+    #[test]
+    fn test_lite_db_encryption() {
+        use crate::io_db::EncryptionKey;
+
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        config.merge_ratio = 0.0;
+        config.preallocate = true;
+        config.encryption_key = Some(EncryptionKey::new([1u8; 32]));
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..500 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 0..100 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            // merge 之后的数据文件和 hint 文件同样是加密的
+            lite_db.merge().expect("failed to merge");
+            lite_db.add(&get_test_key(0), &get_test_value(0)).unwrap();
+        }
 
-        let re3 = LiteDb::open(config.clone());
-        assert!(re3.is_ok());
+        // 磁盘上的文件中没有明文的 key 和 value
+        let mut files = 0;
+        for entry in fs::read_dir(&config.path_db).unwrap() {
+            let content = fs::read(entry.unwrap().path()).unwrap();
+            assert!(!content.windows(b"lite-db-".len()).any(|w| w == b"lite-db-"));
+            files += 1;
+        }
+        assert!(files > 2);
+
+        // 使用正确的密钥重新打开之后可以读取
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(get_test_value(0), lite_db.get(&get_test_key(0)).unwrap());
+            for i in 1..100 {
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+            }
+            for i in 100..500 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+        }
+
+        // 密钥错误时解密出的记录校验失败，不会返回错误的数据
+        let mut wrong = config.clone();
+        wrong.encryption_key = Some(EncryptionKey::new([2u8; 32]));
+        let err = LiteDb::open(wrong).err().expect("opened with a wrong key");
+        assert!(err.to_string().contains("corrupt record"), "{}", err);
+        let mut plain = config.clone();
+        plain.encryption_key = None;
+        assert!(LiteDb::open(plain).is_err());
 
-        // remove the test file
         fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
     #[named]
     #[test]
-    fn test_lite_db_stat() {
-        let config = ready_config(file!(), function_name!());
+    fn test_lite_db_reclaim_size() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        config.merge_ratio = 0.0;
+        let dead_size = |lite_db: &LiteDb| lite_db.file_stats().unwrap().iter().map(|s| s.dead_size as usize).sum::<usize>();
+        let reclaim_size = |lite_db: &LiteDb| lite_db.stat().unwrap().reclaim_size;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            // 覆盖之后删除，两个版本的数据和删除标记都可以回收
+            let key = Bytes::from("reclaim-key");
+            lite_db.add(&key, &Bytes::from("v1")).unwrap();
+            let first = lite_db.index.get(key.to_vec()).unwrap().size as usize;
+            lite_db.add(&key, &Bytes::from("value 2")).unwrap();
+            let second = lite_db.index.get(key.to_vec()).unwrap().size as usize;
+            let write_off = lite_db.active_file.read().get_write_off();
+            lite_db.remove(&key).unwrap();
+            let tombstone = (lite_db.active_file.read().get_write_off() - write_off) as usize;
+            assert_eq!(first + second + tombstone, reclaim_size(&lite_db));
+            assert_eq!(dead_size(&lite_db), reclaim_size(&lite_db));
+
+            for i in 0..300 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 0..150 {
+                lite_db.add(&get_test_key(i), &Bytes::from(format!("new-{}", i))).unwrap();
+            }
+            for i in 100..120 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            // 批次中的删除标记和事务完成的标识
+            let batch = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            for i in 200..210 {
+                batch.put(get_test_key(i), Bytes::from(format!("batch-{}", i))).unwrap();
+            }
+            for i in 210..220 {
+                batch.delete(get_test_key(i)).unwrap();
+            }
+            batch.delete(get_test_key(1000)).unwrap();
+            batch.commit().unwrap();
+            // delete_prefix 的事务完成标识，以及封存文件时的 footer
+            assert_eq!(90, lite_db.delete_prefix(b"lite-db-key--0000002").unwrap());
+            lite_db.rotate().unwrap();
+            assert!(reclaim_size(&lite_db) > 0);
+            assert_eq!(dead_size(&lite_db), reclaim_size(&lite_db));
+        }
 
+        // 重新打开之后从数据文件中得到相同的结果
         let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(dead_size(&lite_db), reclaim_size(&lite_db));
 
-        for i in 0..=10000 {
-            let re = lite_db.add(&get_test_key(i), &get_test_value(i));
-            assert!(re.is_ok());
-        }
-        for i in 0..=1000 {
-            let re = lite_db.add(&get_test_key(i), &get_test_value(i));
-            assert!(re.is_ok());
-        }
-        for i in 2000..=5000 {
-            let re = lite_db.remove(&get_test_key(i));
-            assert!(re.is_ok());
-        }
-
-        // let stat = lite_db.stat().unwrap();
-        // assert!(stat.reclaim_size > 0);
+        // merge 之后只剩下没有参与 merge 的文件中无效的数据
+        lite_db.merge().expect("failed to merge");
+        lite_db.remove(&get_test_key(0)).unwrap();
+        assert_eq!(dead_size(&lite_db), reclaim_size(&lite_db));
 
-        // remove the test file
+        drop(lite_db);
         fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
     }
 
@@ -1,34 +1,1509 @@
-use crate::db::{decode_log_db_pos, ErrDb, FileDb, ResultDb, HINT_FILE_NAME};
-use crate::lite::LiteDb;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, MutexGuard, RwLock};
+
+use crate::db::{
+    try_decode_log_db_pos, Config, ErrDb, FileDb, IndexType, Indexer, IteratorOptions, LogDb, LogDbPos, LogDbType, MergeProgress, MergeStrategy, Metrics,
+    ResultDb,
+};
+use crate::db::{HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME};
+use crate::io_db::FileHandles;
+use crate::lite::batch::{log_db_key_with_seq, parse_log_db_key, NON_TRANSACTION_SEQ_NO};
+use crate::lite::blob::{remove_merged_blob_files, BlobFiles, MergeBlobWriter};
+use crate::lite::cache::ValueCache;
+use crate::lite::lite::{limit_open_file, open_data_file, rotate_active_file, FILE_LOCK_NAME};
+use crate::lite::slow::SlowOp;
+use crate::lite::{ColumnFamilies, LiteDb};
+
+const MERGE_DIR_NAME: &str = "merge";
+const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
 
 impl LiteDb {
-    /// 从 hint 索引文件中加载索引
-    pub(crate) fn load_index_from_hint_file(&self) -> ResultDb<()> {
-        let hint_file_name = self.config.path_db.join(HINT_FILE_NAME);
-        // 如果 hint 文件不存在则返回
-        if !hint_file_name.is_file() {
+    /// merge 数据目录，清理无效的数据，并生成 hint 索引文件
+    /// 重写数据时不阻塞读写，完成之后短暂阻塞读写，用 merge 的文件替换旧的数据文件
+    /// 有存活的快照时不替换，merge 的结果在下一次 open 的时候生效
+    /// 参与 merge 的文件由 merge_strategy 决定，SizeTiered 没有可以 merge 的文件时返回错误
+    pub fn merge(&self) -> ResultDb<()> {
+        self.merger().merge()
+    }
+
+    /// 达到 merge 的比例并且没有正在进行的 merge 时才 merge，返回是否进行了 merge
+    pub fn merge_if_needed(&self) -> ResultDb<bool> {
+        self.merger().merge_if_needed()
+    }
+
+    /// 只 merge id 不大于 up_to_file_id 的旧数据文件，之后的文件不变，不检查 merge 的比例
+    /// 已经 merge 过的文件总是一起重新 merge，多次部分的 merge 可以组合；包含活跃文件时和 merge 一样
+    /// blob 文件不回收，由完整的 merge 回收
+    pub fn merge_range(&self, up_to_file_id: u32) -> ResultDb<()> {
+        self.merger().merge_range(up_to_file_id)
+    }
+
+    pub(crate) fn merger(&self) -> Merger {
+        Merger {
+            config: self.config.clone(),
+            active_file: self.active_file.clone(),
+            older_files: self.older_files.clone(),
+            index: self.index.clone(),
+            merging_lock: self.merging_lock.clone(),
+            swap_lock: self.swap_lock.clone(),
+            value_cache: self.value_cache.clone(),
+            blob_files: self.blob_files.clone(),
+            reclaim_size: self.reclaim_size.clone(),
+            files_dropped: self.files_dropped.clone(),
+            snapshots: self.snapshots.clone(),
+            metrics: self.metrics.clone(),
+            file_handles: self.file_handles.clone(),
+        }
+    }
+}
+
+/// merge 需要用到的数据库状态，可以在后台线程中使用
+pub(crate) struct Merger {
+    config: Config,
+    active_file: Arc<RwLock<FileDb>>,
+    older_files: Arc<RwLock<HashMap<u32, FileDb>>>,
+    index: Arc<dyn Indexer>,
+    merging_lock: Arc<Mutex<()>>,
+    swap_lock: Arc<RwLock<()>>,
+    value_cache: Arc<ValueCache>,
+    blob_files: Arc<BlobFiles>,
+    reclaim_size: Arc<AtomicUsize>,
+    files_dropped: Arc<AtomicUsize>,
+    snapshots: Arc<AtomicUsize>,
+    metrics: Arc<dyn Metrics>,
+    file_handles: Option<Arc<FileHandles>>,
+}
+
+impl Merger {
+    fn merge(&self) -> ResultDb<()> {
+        let _lock = self.lock_merge()?;
+        if !self.reach_merge_ratio()? {
+            return Err(ErrDb::Err("the merge ratio is unreached".to_owned()));
+        }
+        if !self.merge_by_strategy()? {
+            return Err(ErrDb::Err("no files are eligible for the size tiered merge".to_owned()));
+        }
+        Ok(())
+    }
+
+    fn merge_range(&self, up_to_file_id: u32) -> ResultDb<()> {
+        let _lock = self.lock_merge()?;
+        self.merge_range_locked(up_to_file_id)
+    }
+
+    // 和 merge_range 一样，调用者需要持有 merging_lock
+    fn merge_range_locked(&self, up_to_file_id: u32) -> ResultDb<()> {
+        // hint 文件需要包含所有已经 merge 的文件中的 key，已经 merge 过的文件一起重新 merge
+        let up_to_file_id = match get_non_merge_fid(&self.config.path_db)? {
+            Some(non_merge_fid) if non_merge_fid > 0 => up_to_file_id.max(non_merge_fid - 1),
+            _ => up_to_file_id,
+        };
+        if up_to_file_id >= self.active_file.read().get_file_id() {
+            return self.merge_files();
+        }
+
+        let merge_files = self.older_merge_files(up_to_file_id + 1)?;
+        if merge_files.is_empty() {
             return Ok(());
         }
+        self.rewrite_files(merge_files, up_to_file_id + 1, None)
+    }
+
+    // 检查是否可以 merge，返回 merging_lock，同一时刻只能有一个 merge
+    fn lock_merge(&self) -> ResultDb<MutexGuard<'_, ()>> {
+        if self.config.read_only {
+            return Err(ErrDb::ReadOnly);
+        }
+        // 内存模式下没有可以回收的磁盘空间
+        if self.config.in_memory {
+            return Err(ErrDb::Err("merge is not supported in memory mode".to_owned()));
+        }
+        self.merging_lock
+            .try_lock()
+            .ok_or_else(|| ErrDb::Err("merge is in progress, try again later".to_owned()))
+    }
+
+    fn merge_if_needed(&self) -> ResultDb<bool> {
+        if self.config.in_memory || self.config.read_only {
+            return Ok(false);
+        }
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() || !self.reach_merge_ratio()? {
+            return Ok(false);
+        }
+        self.merge_by_strategy()
+    }
+
+    // 按照配置的 merge 策略选择参与 merge 的文件，返回是否进行了 merge，调用者需要持有 merging_lock
+    fn merge_by_strategy(&self) -> ResultDb<bool> {
+        match self.config.merge_strategy {
+            MergeStrategy::Full => self.merge_files()?,
+            MergeStrategy::SizeTiered => match self.size_tiered_group()? {
+                Some(up_to_file_id) => self.merge_range_locked(up_to_file_id)?,
+                None => return Ok(false),
+            },
+        }
+        Ok(true)
+    }
+
+    // merge 的文件总是从 id 0 开始连续的，已经 merge 的文件作为最老的一层
+    // 按照 id 依次加入之后没有 merge 的旧数据文件，加入的大小不小于已经 merge 的大小时，返回这一组最后的文件 id
+    // 新的数据比已经 merge 的少时不 merge，每条数据被重写的次数和数据量成对数关系
+    fn size_tiered_group(&self) -> ResultDb<Option<u32>> {
+        let non_merge_fid = get_non_merge_fid(&self.config.path_db)?.unwrap_or_default();
+        let older_files = self.older_files.read();
+        let mut file_ids: Vec<u32> = older_files.keys().copied().collect();
+        file_ids.sort();
+        let merged_size: u64 = file_ids
+            .iter()
+            .filter(|id| **id < non_merge_fid)
+            .map(|id| older_files.get(id).unwrap().get_write_off())
+            .sum();
+        let mut new_size = 0;
+        for file_id in file_ids.into_iter().filter(|id| *id >= non_merge_fid) {
+            new_size += older_files.get(&file_id).unwrap().get_write_off();
+            if new_size >= merged_size {
+                return Ok(Some(file_id));
+            }
+        }
+        Ok(None)
+    }
+
+    // 判断是否达到了 merge 的比例
+    fn reach_merge_ratio(&self) -> ResultDb<bool> {
+        let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
+        let total_size = data_files_size(&self.config.path_db)?;
+        Ok(total_size > 0 && (reclaim_size as f32 / total_size as f32) >= self.config.merge_ratio)
+    }
+
+    // 重写所有旧数据文件中的有效数据，调用者需要持有 merging_lock
+    fn merge_files(&self) -> ResultDb<()> {
+        let (merge_files, non_merge_fid, non_merge_blob_fid) = self.rotate_merge_files()?;
+        self.rewrite_files(merge_files, non_merge_fid, Some(non_merge_blob_fid))
+    }
+
+    // 重写 merge_files 中的有效数据，merge_files 是 non_merge_fid 之前所有的数据文件
+    // non_merge_blob_fid 为 None 时不复制 blob，记录仍然引用原来的 blob 文件
+    fn rewrite_files(&self, merge_files: Vec<FileDb>, non_merge_fid: u32, non_merge_blob_fid: Option<u32>) -> ResultDb<()> {
+        let _slow = SlowOp::start(&self.config, "merge");
+        // 如果 merge 目录存在，说明上一次 merge 没有完成，直接删除
+        let merge_path = get_merge_path(&self.config);
+        if merge_path.is_dir() {
+            fs::remove_dir_all(merge_path.clone())?;
+        }
+        fs::create_dir_all(merge_path.clone())?;
+
+        // 参与 merge 的数据量和其中有效数据的量，两者的差就是回收的数据量
+        // 重写的记录去掉了事务标识，大小可能和原来的不同，回收的数据量按照原来的大小计算
+        let merge_size: u64 = merge_files.iter().map(|f| f.get_write_off()).sum();
+        let mut dead_size = 0;
+        let mut live_size = 0;
+        let mut rewrite_size = 0;
+        // 保留的删除标记重写之后仍然是可以回收的数据
+        let mut rewrite_tombstone_size = 0;
+        // 事务中的删除标记等到事务完成的标识之后再保留，没有完成的事务中的删除标记直接丢弃
+        let mut txn_tombstones: HashMap<usize, Vec<_>> = HashMap::new();
 
-        let hint_file = FileDb::new_hint_file(self.config.path_db.clone())?;
-        let mut offset = 0;
-        loop {
-            let (log_record, size) = match hint_file.read_log_db(offset) {
-                Ok(result) => (result.log_db, result.size),
-                Err(e) => {
-                    if e == ErrDb::new_io_eof("") {
-                        break;
+        let mut merge_file_id = 0;
+        let mut merge_file = open_data_file(&self.config, &merge_path, merge_file_id, self.config.data_io_type())?;
+        let hint_file = FileDb::new_hint_file(merge_path.clone(), self.config.data_io_type())?;
+        // 有效数据引用的 blob 复制到 merge 目录中的 blob 文件
+        let mut blob_writer = MergeBlobWriter::new(&self.blob_files, merge_path.clone());
+
+        // 内存索引中引用到的文件，没有被引用的文件中没有有效的数据
+        let mut live_file_ids = HashSet::new();
+        {
+            let mut iter = self.index.iterator(IteratorOptions::default());
+            while let Some((_, pos)) = iter.next() {
+                live_file_ids.insert(pos.file_id);
+            }
+        }
+        let mut dead_file_ids = Vec::new();
+
+        let files_total = merge_files.len();
+        let report = |files_done: usize, bytes_written: u64| {
+            if let Some(progress) = &self.config.merge_progress {
+                progress.call(MergeProgress {
+                    files_done,
+                    files_total,
+                    bytes_written,
+                });
+            }
+        };
+        report(0, 0);
+
+        // 依次处理每个数据文件，重写有效的数据
+        for (i, data_file) in merge_files.iter().enumerate() {
+            // 没有有效数据的文件不需要读取，merge 完成之后直接删除，需要保留删除标记时仍然要读取
+            if self.config.tombstone_ttl.is_none() && !live_file_ids.contains(&data_file.get_file_id()) {
+                dead_file_ids.push(data_file.get_file_id());
+                dead_size += data_file.get_write_off();
+                report(i + 1, rewrite_size);
+                continue;
+            }
+            let mut offset = 0;
+            loop {
+                let (log_db, size) = match data_file.read_log_db(offset) {
+                    Ok(result) => (result.log_db, result.size),
+                    Err(e) => {
+                        if e == ErrDb::new_io_eof("") {
+                            break;
+                        }
+                        return Err(e.context(format!("corrupt record in file {} at offset {}", data_file.get_file_id(), offset)));
+                    }
+                };
+
+                // 和内存索引中的位置一致，说明是有效的数据，布隆过滤器可以跳过已经删除的 key
+                let (real_key, seq_no) = parse_log_db_key(log_db.key.clone())?;
+                let index_pos = match self.index.probably_contains(&real_key) {
+                    true => self.index.get(real_key.clone()),
+                    false => None,
+                };
+                if let Some(index_pos) = index_pos {
+                    if index_pos.file_id == data_file.get_file_id() && index_pos.offset == offset {
+                        // 已经提交的事务数据，重写时去除事务标识
+                        let key = log_db_key_with_seq(real_key.clone(), NON_TRANSACTION_SEQ_NO);
+                        let log_db = match log_db.rec_type {
+                            LogDbType::BLOB if non_merge_blob_fid.is_some() => {
+                                let blob_pos = blob_writer.copy(&key, &try_decode_log_db_pos(&log_db.value)?)?;
+                                LogDb {
+                                    key,
+                                    value: blob_pos.encode(),
+                                    rec_type: LogDbType::BLOB,
+                                }
+                            }
+                            LogDbType::BLOB => LogDb {
+                                key,
+                                value: log_db.value,
+                                rec_type: LogDbType::BLOB,
+                            },
+                            _ => LogDb {
+                                key,
+                                value: log_db.value,
+                                rec_type: LogDbType::NORMAL,
+                            },
+                        };
+                        let pos = self.write_merge_record(&merge_path, &mut merge_file, &mut merge_file_id, &log_db)?;
+                        rewrite_size += pos.size as u64;
+                        live_size += size as u64;
+
+                        // 将新的位置写到 hint 文件中
+                        hint_file.write_hint_log_db(real_key, pos)?;
+                    }
+                } else if log_db.rec_type == LogDbType::DELETED && !self.config.tombstone_expired(&log_db.value) {
+                    // 索引中没有这个 key，删除标记还在保留的时间内，重写时去除事务标识，不写到 hint 文件中
+                    match seq_no {
+                        NON_TRANSACTION_SEQ_NO => {
+                            let pos = self.write_tombstone(&merge_path, &mut merge_file, &mut merge_file_id, real_key, log_db.value)?;
+                            rewrite_size += pos.size as u64;
+                            rewrite_tombstone_size += pos.size as u64;
+                        }
+                        _ => txn_tombstones.entry(seq_no).or_default().push((real_key, log_db.value)),
+                    }
+                } else if log_db.rec_type == LogDbType::TXNFINISHED {
+                    for (real_key, value) in txn_tombstones.remove(&seq_no).unwrap_or_default() {
+                        let pos = self.write_tombstone(&merge_path, &mut merge_file, &mut merge_file_id, real_key, value)?;
+                        rewrite_size += pos.size as u64;
+                        rewrite_tombstone_size += pos.size as u64;
                     }
-                    return Err(e);
                 }
+                offset += size as u64;
+            }
+            report(i + 1, rewrite_size);
+        }
+
+        blob_writer.sync()?;
+        merge_file.trim_preallocated()?;
+        merge_file.sync()?;
+        hint_file.sync()?;
+        // merge 之后的文件 id 不能和没有参与 merge 的文件重复
+        if merge_file_id >= non_merge_fid {
+            fs::remove_dir_all(merge_path)?;
+            return Err(ErrDb::Err(format!("the merged files exceed the file id {}", non_merge_fid)));
+        }
+        // blob 文件也一样，否则移动时会覆盖 merge 开始之后写入的 blob 文件
+        if let Some(non_merge_blob_fid) = non_merge_blob_fid {
+            if blob_writer.last_file_id().is_some_and(|id| id >= non_merge_blob_fid) {
+                fs::remove_dir_all(merge_path)?;
+                return Err(ErrDb::Err(format!("the merged blob files exceed the file id {}", non_merge_blob_fid)));
+            }
+        }
+
+        // 写标识 merge 完成的文件，记录最近未参与 merge 的文件 id 和 blob 文件 id
+        let merge_fin_file = FileDb::new_merge_fin_file(merge_path.clone())?;
+        let merge_fin_log_db = LogDb {
+            key: MERGE_FIN_KEY.to_vec(),
+            value: format!("{} {}", non_merge_fid, non_merge_blob_fid.unwrap_or_default()).into_bytes(),
+            rec_type: LogDbType::SYSTEM,
+        };
+        merge_fin_file.write(&merge_fin_log_db.encode())?;
+        merge_fin_file.sync()?;
+
+        // merge 已经完成，删除没有有效数据的文件，
+        // 不能在 merge 完成之前删除，否则 merge 失败之后文件中的删除标记会丢失
+        self.drop_dead_files(&dead_file_ids)?;
+
+        // 替换之后旧文件中无效的数据都已经回收，没有数据的文件在删除的时候已经减去了
+        if self.swap_merge_files(non_merge_fid)? {
+            // 保留的删除标记按照重写之后的大小继续计入可以回收的数据
+            let reclaimed = merge_size.saturating_sub(dead_size).saturating_sub(live_size) as usize;
+            let _ = self.reclaim_size.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| {
+                Some(v.saturating_sub(reclaimed) + rewrite_tombstone_size as usize)
+            });
+        }
+
+        self.metrics.on_merge(merge_size.saturating_sub(rewrite_size) as usize);
+        Ok(())
+    }
+
+    // 把记录写到 merge 目录中的数据文件，超过限制时换到下一个文件，返回写入的位置
+    fn write_merge_record(&self, merge_path: &Path, merge_file: &mut FileDb, merge_file_id: &mut u32, log_db: &LogDb) -> ResultDb<LogDbPos> {
+        let enc_log_db = log_db.encode_with(self.config.checksum);
+        if self.config.exceeds_file_limit(merge_file, enc_log_db.len() as u64, 1) {
+            merge_file.trim_preallocated()?;
+            merge_file.sync()?;
+            *merge_file_id += 1;
+            *merge_file = open_data_file(&self.config, merge_path, *merge_file_id, self.config.data_io_type())?;
+        }
+        let pos = LogDbPos {
+            file_id: *merge_file_id,
+            offset: merge_file.get_write_off(),
+            size: enc_log_db.len() as u32,
+        };
+        merge_file.write(&enc_log_db)?;
+        merge_file.add_footer_record(NON_TRANSACTION_SEQ_NO);
+        Ok(pos)
+    }
+
+    // 重写保留的删除标记，value 是删除时的时间戳
+    fn write_tombstone(&self, merge_path: &Path, merge_file: &mut FileDb, merge_file_id: &mut u32, real_key: Vec<u8>, value: Vec<u8>) -> ResultDb<LogDbPos> {
+        let log_db = LogDb {
+            key: log_db_key_with_seq(real_key, NON_TRANSACTION_SEQ_NO),
+            value,
+            rec_type: LogDbType::DELETED,
+        };
+        self.write_merge_record(merge_path, merge_file, merge_file_id, &log_db)
+    }
+
+    // 用 merge 目录中的文件替换已经 merge 的数据文件，并更新索引，返回是否替换了
+    // 持有 swap_lock 的写锁，替换的过程中没有读写，读写看到的索引位置和数据文件一致
+    fn swap_merge_files(&self, non_merge_fid: u32) -> ResultDb<bool> {
+        let _swap_lock = self.swap_lock.write();
+        // 快照中的位置需要一直有效，下一次 open 的时候再替换
+        if self.snapshots.load(Ordering::SeqCst) > 0 {
+            return Ok(false);
+        }
+
+        let mut older_files = self.older_files.write();
+        // 先关闭已经 merge 的数据文件，再和 open 的时候一样移动 merge 目录中的文件
+        older_files.retain(|file_id, _| *file_id >= non_merge_fid);
+        load_merge_files(&self.config)?;
+        self.blob_files.reload()?;
+        for (file_id, file_name) in FileDb::find_data_files(&self.config.path_db)? {
+            if file_id < non_merge_fid {
+                let mut data_file = FileDb::new_with_file_name(file_name, file_id, self.config.data_io_type())?;
+                data_file.set_verify_crc(self.config.verify_crc_on_read);
+                limit_open_file(self.file_handles.as_ref(), &mut data_file, self.config.data_io_type());
+                older_files.insert(file_id, data_file);
+            }
+        }
+
+        // merge 开始之后写入或删除的 key 以索引中的为准
+        if !load_hint_file(&self.config, self.index.as_ref(), Some(non_merge_fid))? {
+            return Err(ErrDb::Err("the hint file written by merge is corrupt".to_owned()));
+        }
+        // 文件 id 会重复使用，缓存中的位置不再可靠
+        self.value_cache.clear();
+        Ok(true)
+    }
+
+    fn drop_dead_files(&self, file_ids: &[u32]) -> ResultDb<()> {
+        // 快照可能还在读取这些文件，下一次 open 的时候再删除
+        if self.snapshots.load(Ordering::SeqCst) > 0 {
+            return Ok(());
+        }
+        let mut older_files = self.older_files.write();
+        for file_id in file_ids.iter() {
+            let (file_name, size) = match older_files.remove(file_id) {
+                Some(file) => (file.get_file_name().to_path_buf(), file.get_write_off() as usize),
+                None => continue,
             };
+            fs::remove_file(file_name)?;
 
-            // 解码 value，拿到位置索引信息
-            let log_record_pos = decode_log_db_pos(log_record.value);
-            // 存储到内存索引中
-            self.index.put(log_record.key, log_record_pos);
-            offset += size as u64;
+            self.files_dropped.fetch_add(1, Ordering::SeqCst);
+            // 文件中的数据都是可以回收的，已经被回收了
+            let _ = self
+                .reclaim_size
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| Some(v.saturating_sub(size)));
         }
         Ok(())
     }
+
+    // 将当前活跃文件转为旧的数据文件，返回所有需要 merge 的文件，以及最近未参与 merge 的文件 id 和 blob 文件 id
+    // 在活跃文件的写锁中同时切换 blob 文件，参与 merge 的记录引用的 blob 都在参与 merge 的 blob 文件中
+    fn rotate_merge_files(&self) -> ResultDb<(Vec<FileDb>, u32, u32)> {
+        let mut active_file = self.active_file.write();
+        let non_merge_fid = rotate_active_file(
+            &self.config,
+            &mut active_file,
+            &self.older_files,
+            &self.reclaim_size,
+            self.metrics.as_ref(),
+            self.file_handles.as_ref(),
+        )?;
+        let non_merge_blob_fid = self.blob_files.rotate()?;
+        let merge_files = self.older_merge_files(non_merge_fid)?;
+        Ok((merge_files, non_merge_fid, non_merge_blob_fid))
+    }
+
+    // 返回 id 小于 non_merge_fid 的旧数据文件，从小到大依次 merge
+    fn older_merge_files(&self, non_merge_fid: u32) -> ResultDb<Vec<FileDb>> {
+        let older_files = self.older_files.read();
+        let mut merge_file_ids: Vec<u32> = older_files.keys().copied().filter(|id| *id < non_merge_fid).collect();
+        merge_file_ids.sort();
+        let mut merge_files = Vec::with_capacity(merge_file_ids.len());
+        for file_id in merge_file_ids.iter() {
+            let file_name = older_files.get(file_id).unwrap().get_file_name().to_path_buf();
+            let mut merge_file = FileDb::new_with_file_name(file_name, *file_id, self.config.data_io_type())?;
+            merge_file.set_verify_crc(self.config.verify_crc_on_read);
+            limit_open_file(self.file_handles.as_ref(), &mut merge_file, self.config.data_io_type());
+            merge_files.push(merge_file);
+        }
+        Ok(merge_files)
+    }
+}
+
+/// 后台自动 merge 的线程，按照时间间隔检查是否需要 merge
+pub(crate) struct AutoMergeThread {
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl AutoMergeThread {
+    /// 同时 merge 数据库和其中打开的列族
+    pub(crate) fn start(merger: Merger, column_families: ColumnFamilies, interval: Duration) -> AutoMergeThread {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = shutdown.clone();
+        let handle = std::thread::Builder::new()
+            .name("lite_db_merge".to_owned())
+            .spawn(move || {
+                let mut next = Instant::now() + interval;
+                while !thread_shutdown.load(Ordering::SeqCst) {
+                    // 等待时不持有任何锁，stop 时会唤醒线程
+                    let now = Instant::now();
+                    if now < next {
+                        std::thread::park_timeout(next - now);
+                        continue;
+                    }
+                    if let Err(e) = merger.merge_if_needed() {
+                        log::error!("failed to auto merge: {}", e);
+                    }
+                    // 不持有列族的锁 merge，merge 的过程中可以打开或者删除列族
+                    let cfs: Vec<_> = column_families.lock().iter().map(|(name, cf)| (name.clone(), cf.clone())).collect();
+                    for (name, cf) in cfs {
+                        if let Err(e) = cf.merge_if_needed() {
+                            log::error!("failed to auto merge the column family {}: {}", name, e);
+                        }
+                    }
+                    next = Instant::now() + interval;
+                }
+            })
+            .expect("failed to spawn merge thread");
+        AutoMergeThread {
+            shutdown,
+            handle: Some(handle),
+        }
+    }
+
+    /// 通知后台线程退出，并等待正在进行的 merge 完成
+    pub(crate) fn stop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for AutoMergeThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl LiteDb {
+    /// 从 hint 索引文件中加载索引
+    /// hint 文件损坏时不更新索引并返回 false，需要从所有的数据文件中重建索引
+    pub(crate) fn load_index_from_hint_file(&self) -> ResultDb<bool> {
+        // B+ 树的索引是持久化的，只更新仍然指向已 merge 文件的 key，
+        // 之后写入或删除的 key 以 B+ 树中的为准
+        let non_merge_fid = match self.config.index_type {
+            IndexType::BPlusTree => get_non_merge_fid(&self.config.path_db)?,
+            _ => None,
+        };
+        load_hint_file(&self.config, self.index.as_ref(), non_merge_fid)
+    }
+}
+
+// 从 hint 文件中加载索引，non_merge_fid 不为 None 时只更新仍然指向已 merge 文件的 key
+// 先逐条读取并校验整个 hint 文件，有损坏的记录时不更新索引，返回 false，不部分地使用 hint 文件
+// 校验通过之后再逐条读取一次更新索引，不在内存中保存整个 hint 文件
+// 损坏的 hint 文件不会删除，否则下一次 open 会跳过已经 merge 的数据文件
+fn load_hint_file(config: &Config, index: &dyn Indexer, non_merge_fid: Option<u32>) -> ResultDb<bool> {
+    let hint_file_name = config.path_db.join(HINT_FILE_NAME);
+    // 如果 hint 文件不存在则返回
+    if !hint_file_name.is_file() {
+        return Ok(true);
+    }
+
+    let hint_file = FileDb::new_hint_file(config.path_db.clone(), config.data_io_type())?;
+    let mut offset = 0;
+    loop {
+        match hint_file.read_log_db(offset) {
+            Ok(result) => offset += result.size as u64,
+            Err(e) => {
+                if e == ErrDb::new_io_eof("") {
+                    break;
+                }
+                let corrupt = match &e {
+                    ErrDb::CrcMismatch { .. } | ErrDb::InvalidRecordType(_) => true,
+                    ErrDb::IoErr(io_err) => io_err.kind() == std::io::ErrorKind::UnexpectedEof,
+                    _ => false,
+                };
+                if !corrupt {
+                    return Err(e.context(format!("failed to read the hint file at offset {}", offset)));
+                }
+                log::warn!("corrupt record in hint file at offset {}: {}, rebuild the index from data files", offset, e);
+                return Ok(false);
+            }
+        }
+    }
+
+    // 第二次读取只到校验过的位置，之后的读取错误不是文件损坏，直接返回
+    let end = offset;
+    let mut offset = 0;
+    while offset < end {
+        let result = hint_file
+            .read_log_db(offset)
+            .map_err(|e| e.context(format!("failed to read the hint file at offset {}", offset)))?;
+        offset += result.size as u64;
+        let log_record = result.log_db;
+        // 解码 value，拿到位置索引信息
+        let log_record_pos = try_decode_log_db_pos(&log_record.value)?;
+        // 存储到索引中
+        match non_merge_fid {
+            Some(fid) => {
+                if let Some(pos) = index.get(log_record.key.clone()) {
+                    if pos.file_id < fid {
+                        index.put(log_record.key, log_record_pos);
+                    }
+                }
+            }
+            None => {
+                index.put(log_record.key, log_record_pos);
+            }
+        }
+    }
+    Ok(true)
+}
+
+// 获取 merge 临时目录，没有配置 merge_dir 时和数据目录在同一级
+pub(crate) fn get_merge_path(config: &Config) -> PathBuf {
+    let dir_path = config.path_db.as_path();
+    let file_name = dir_path.file_name().unwrap();
+    let merge_name = std::format!("{}-{}", file_name.to_str().unwrap(), MERGE_DIR_NAME);
+    match &config.merge_dir {
+        Some(merge_dir) => merge_dir.join(merge_name),
+        None => dir_path.parent().unwrap().join(merge_name),
+    }
+}
+
+// 移动 merge 目录中的文件，不在同一个文件系统时复制之后再删除
+fn move_file(from: &Path, to: &Path) -> ResultDb<()> {
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::File::open(to)?.sync_all()?;
+            fs::remove_file(from)?;
+            Ok(())
+        }
+        Err(e) => Err(ErrDb::IoErr(e)),
+    }
+}
+
+// 所有数据文件的大小
+fn data_files_size(dir_path: &Path) -> ResultDb<u64> {
+    let mut size = 0;
+    for (_, file_name) in FileDb::find_data_files(dir_path)? {
+        size += fs::metadata(file_name)?.len();
+    }
+    Ok(size)
+}
+
+/// 读取 merge 完成文件中记录的最近未参与 merge 的文件 id，没有 merge 过则返回 None
+pub(crate) fn get_non_merge_fid(dir_path: &Path) -> ResultDb<Option<u32>> {
+    Ok(read_merge_fin(dir_path)?.map(|(fid, _)| fid))
+}
+
+// merge 完成文件中记录的文件 id 和 blob 文件 id，旧版本的 merge 没有 blob 文件 id，为 0
+fn read_merge_fin(dir_path: &Path) -> ResultDb<Option<(u32, u32)>> {
+    let merge_fin_file = dir_path.join(MERGE_FINISHED_FILE_NAME);
+    if !merge_fin_file.is_file() {
+        return Ok(None);
+    }
+    let merge_fin_file = FileDb::new_merge_fin_file(dir_path.to_path_buf())?;
+    let merge_fin_record = merge_fin_file.read_log_db(0)?;
+    let v = String::from_utf8_lossy(&merge_fin_record.log_db.value).into_owned();
+    let mut ids = v.split_whitespace();
+    let fid = ids.next().unwrap_or_default().parse::<u32>()?;
+    let blob_fid = match ids.next() {
+        Some(blob_fid) => blob_fid.parse::<u32>()?,
+        None => 0,
+    };
+    Ok(Some((fid, blob_fid)))
+}
+
+/// 加载 merge 目录中的数据文件，在 open 的时候调用，返回是否加载了 merge 的数据
+pub(crate) fn load_merge_files(config: &Config) -> ResultDb<bool> {
+    let dir_path = config.path_db.as_path();
+    let merge_path = get_merge_path(config);
+    // 没有发生过 merge 则直接返回
+    if !merge_path.is_dir() {
+        return Ok(false);
+    }
+
+    // 只读模式下不能移动文件，没有完成的 merge 直接忽略，已经完成但是没有生效的 merge 需要先以读写模式打开一次
+    if config.read_only {
+        if merge_path.join(MERGE_FINISHED_FILE_NAME).is_file() {
+            return Err(ErrDb::Err("a finished merge is not applied, open the db in read-write mode first".to_owned()));
+        }
+        return Ok(false);
+    }
+
+    // merge 没有完成，直接删除 merge 目录
+    let (non_merge_fid, non_merge_blob_fid) = match read_merge_fin(&merge_path)? {
+        Some(ids) => ids,
+        None => {
+            fs::remove_dir_all(merge_path)?;
+            return Ok(false);
+        }
+    };
+
+    // 删除已经 merge 过的旧数据文件
+    for (file_id, file_name) in FileDb::find_data_files(dir_path)? {
+        if file_id < non_merge_fid {
+            fs::remove_file(file_name.clone())?;
+            // 子目录为空时一起删除
+            if let Some(parent) = file_name.parent() {
+                if parent != dir_path {
+                    let _ = fs::remove_dir(parent);
+                }
+            }
+        }
+    }
+
+    remove_merged_blob_files(dir_path, non_merge_blob_fid)?;
+
+    // 将 merge 目录中的数据文件按照配置的命名方式移动到数据目录中
+    for (file_id, file_name) in FileDb::find_data_files(&merge_path)? {
+        let new_file_name = config.data_file_name(dir_path, file_id);
+        if let Some(parent) = new_file_name.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_file(&file_name, &new_file_name)?;
+    }
+    // 再移动 blob 文件、hint 文件和 merge 完成的文件
+    for entry in fs::read_dir(merge_path.clone())?.flatten() {
+        let file_os_str = entry.file_name();
+        let file_name = file_os_str.to_str().unwrap();
+        if !entry.path().is_file() || file_name == SEQ_NO_FILE_NAME || file_name == FILE_LOCK_NAME {
+            continue;
+        }
+        move_file(&merge_path.join(file_name), &dir_path.join(file_name))?;
+    }
+
+    fs::remove_dir_all(merge_path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use function_name::named;
+    use parking_lot::Mutex;
+
+    use crate::db::{
+        Adder, Closer, Config, ErrDb, FileDb, Getter, IndexType, LogDbType, MergeProgressFn, MergeStrategy, Remover, WriteBatchOptions, HINT_FILE_NAME,
+    };
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::lite::new_transaction_log_dbs;
+    use crate::lite::merge::{get_merge_path, get_non_merge_fid, MERGE_DIR_NAME};
+    use crate::lite::LiteDb;
+
+    fn ready_config(file: &str, name: &str) -> Config {
+        let mut config = kits::test_config(file, name);
+        config.file_size_db = 32 * 1024 * 1024;
+        config.merge_ratio = 0.0;
+        config
+    }
+
+    fn write_data(lite_db: &LiteDb) {
+        for round in 0..3 {
+            for i in 0..50000 {
+                let re = lite_db.add(&get_test_key(i), &Bytes::from(format!("{}-{}", round, i)));
+                assert!(re.is_ok());
+            }
+        }
+        for i in 0..10000 {
+            let re = lite_db.remove(&get_test_key(i));
+            assert!(re.is_ok());
+        }
+    }
+
+    fn check_data(lite_db: &LiteDb) {
+        for i in 0..10000 {
+            let re = lite_db.get(&get_test_key(i));
+            assert_eq!(ErrDb::NotFindKey, re.err().unwrap());
+        }
+        for i in 10000..50000 {
+            let re = lite_db.get(&get_test_key(i));
+            assert_eq!(Bytes::from(format!("2-{}", i)), re.unwrap());
+        }
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge() {
+        let config = ready_config(file!(), function_name!());
+
+        // 1. merge a empty db
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let re = lite_db.merge();
+            assert!(re.is_err());
+        }
+
+        // 2. reopen without merge, all records are read from the data files
+        let records_without_merge = {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            write_data(&lite_db);
+            lite_db.close().expect("failed to close");
+            std::mem::drop(lite_db);
+
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            check_data(&lite_db);
+            let (_, records) = lite_db.load_index_from_data_files().unwrap();
+            records
+        };
+        assert!(records_without_merge >= 160000);
+
+        // 3. merge, write some data after merge, and then reopen
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let re = lite_db.merge();
+            assert!(re.is_ok());
+            // the data is still readable before reopen
+            check_data(&lite_db);
+
+            let re2 = lite_db.add(&get_test_key(50000), &get_test_value(50000));
+            assert!(re2.is_ok());
+            let re3 = lite_db.remove(&get_test_key(49999));
+            assert!(re3.is_ok());
+        }
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(get_test_value(50000), lite_db.get(&get_test_key(50000)).unwrap());
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(49999)).err().unwrap());
+            assert_eq!(Bytes::from("2-49998"), lite_db.get(&get_test_key(49998)).unwrap());
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(1)).err().unwrap());
+
+            // only the records written after merge are read from the data files
+            let (_, records) = lite_db.load_index_from_data_files().unwrap();
+            assert_eq!(2, records);
+            assert!(records * 1000 < records_without_merge);
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_large_value() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 64 * 1024;
+        config.large_value_threshold = 1024;
+        let value = |round: usize, i: usize| Bytes::from(format!("{}-{}", round, i).repeat(500));
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for round in 0..3 {
+                for i in 0..100 {
+                    lite_db.add(&get_test_key(i), &value(round, i)).unwrap();
+                }
+            }
+            for i in 0..10 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            let blob_files = FileDb::find_blob_files(&config.path_db).unwrap().len();
+            assert!(blob_files > 3);
+
+            // merge 只复制仍然被引用的 blob，旧的 blob 文件被删除
+            lite_db.merge().unwrap();
+            assert!(FileDb::find_blob_files(&config.path_db).unwrap().len() < blob_files);
+            for i in 10..100 {
+                assert_eq!(value(2, i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+
+            // merge 之后的写入在新的 blob 文件中
+            lite_db.add(&get_test_key(0), &value(3, 0)).unwrap();
+            assert_eq!(value(3, 0), lite_db.get(&get_test_key(0)).unwrap());
+        }
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(value(3, 0), lite_db.get(&get_test_key(0)).unwrap());
+        for i in 1..10 {
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+        }
+        for i in 10..100 {
+            assert_eq!(value(2, i), lite_db.get(&get_test_key(i)).unwrap());
+        }
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_blob_file_id_overflow() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 64 * 1024;
+        config.large_value_threshold = 1024;
+        let value = |i: usize| Bytes::from(format!("{}", i).repeat(2000));
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..100 {
+                lite_db.add(&get_test_key(i), &value(i)).unwrap();
+            }
+        }
+
+        // 文件变小之后 merge 需要更多的 blob 文件，超过没有参与 merge 的 blob 文件 id 时放弃 merge
+        config.file_size_db = 8 * 1024;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert!(lite_db.merge().is_err());
+        assert!(!get_merge_path(&config).exists());
+        lite_db.add(&get_test_key(100), &value(100)).unwrap();
+        drop(lite_db);
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..=100 {
+            assert_eq!(value(i), lite_db.get(&get_test_key(i)).unwrap());
+        }
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_drop_dead_files() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 1024 * 1024;
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            // fill the first file, then overwrite all of its keys
+            let mut n = 0;
+            while lite_db.active_file.read().get_file_id() == 0 {
+                lite_db.add(&get_test_key(n), &get_test_value(n)).unwrap();
+                n += 1;
+            }
+            for i in 0..n {
+                lite_db.add(&get_test_key(i), &Bytes::from(format!("new-{}", i))).unwrap();
+            }
+            let oldest = FileDb::get_data_file_name(config.path_db.clone(), 0);
+            assert!(oldest.is_file());
+            let reclaim_size = lite_db.stat().unwrap().reclaim_size;
+
+            lite_db.merge().expect("failed to merge");
+            let stat = lite_db.stat().unwrap();
+            assert_eq!(1, stat.files_dropped);
+            assert!(stat.reclaim_size < reclaim_size);
+            // the oldest file is removed, and it's records are not rewritten,
+            // the merged file takes the place of it after merge
+            assert!(!get_merge_path(&config).exists());
+            assert!(fs::metadata(oldest).unwrap().len() < config.file_size_db);
+
+            for i in 0..n {
+                assert_eq!(Bytes::from(format!("new-{}", i)), lite_db.get(&get_test_key(i)).unwrap());
+            }
+        }
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(Bytes::from("new-0"), lite_db.get(&get_test_key(0)).unwrap());
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_concurrent() {
+        let config = ready_config(file!(), function_name!());
+        let mut config_bptree = config.clone();
+        config_bptree.path_db = PathBuf::from(format!("{}-bptree", config.path_db.display()));
+        config_bptree.index_type = IndexType::BPlusTree;
+        let _ = fs::remove_dir_all(config_bptree.path_db.clone());
+
+        let count = 500;
+        let rounds = 4;
+        for mut config in [config, config_bptree] {
+            config.file_size_db = 16 * 1024;
+            config.merge_ratio = 0.0;
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..count * 2 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+
+                let done = AtomicBool::new(false);
+                let merges = std::thread::scope(|s| {
+                    // 前一半的 key 不断覆盖写入，写入之后立即读取
+                    s.spawn(|| {
+                        for round in 0..rounds {
+                            for i in 0..count {
+                                let value = Bytes::from(format!("{}-{}", round, i));
+                                lite_db.add(&get_test_key(i), &value).unwrap();
+                                assert_eq!(value, lite_db.get(&get_test_key(i)).unwrap());
+                                // 后一半的 key 没有变化
+                                let j = count + i;
+                                assert_eq!(get_test_value(j), lite_db.get(&get_test_key(j)).unwrap());
+                            }
+                        }
+                        for i in 0..rounds {
+                            lite_db.remove(&get_test_key(i)).unwrap();
+                        }
+                        done.store(true, Ordering::SeqCst);
+                    });
+                    let merge = s.spawn(|| {
+                        let mut merges = 0;
+                        while !done.load(Ordering::SeqCst) {
+                            lite_db.merge().expect("failed to merge");
+                            merges += 1;
+                        }
+                        merges
+                    });
+                    merge.join().unwrap()
+                });
+                assert!(merges > 1);
+
+                for i in 0..count {
+                    match i < rounds {
+                        true => assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap()),
+                        false => assert_eq!(Bytes::from(format!("{}-{}", rounds - 1, i)), lite_db.get(&get_test_key(i)).unwrap()),
+                    }
+                }
+                lite_db.merge().expect("failed to merge");
+            }
+
+            // reopen db, no write is lost
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                assert_eq!(count * 2 - rounds, lite_db.index.list_keys().unwrap().len());
+                for i in rounds..count {
+                    assert_eq!(Bytes::from(format!("{}-{}", rounds - 1, i)), lite_db.get(&get_test_key(i)).unwrap());
+                }
+                for i in count..count * 2 {
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+            }
+            fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        }
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_auto_merge() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 64 * 1024;
+        config.auto_merge_interval = Some(Duration::from_millis(20));
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let mut n = 0;
+            while lite_db.active_file.read().get_file_id() < 2 {
+                lite_db.add(&get_test_key(n), &get_test_value(n)).unwrap();
+                n += 1;
+            }
+            let disk_size = lite_db.stat().unwrap().disk_size;
+            for i in 0..n {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+
+            // 等待后台线程 merge，删除的过程中可能已经 merge 过，需要等到不再达到 merge 的比例
+            let merger = lite_db.merger();
+            let mut waited = 0;
+            while (lite_db.stat().unwrap().files_dropped == 0 || merger.reach_merge_ratio().unwrap()) && waited < 500 {
+                std::thread::sleep(Duration::from_millis(20));
+                waited += 1;
+            }
+            let stat = lite_db.stat().unwrap();
+            assert!(stat.files_dropped > 0);
+            assert!(stat.disk_size < disk_size);
+            assert_eq!(0, stat.key_num);
+            assert!(!lite_db.merge_if_needed().unwrap());
+
+            lite_db.close().unwrap();
+            assert!(lite_db.auto_merge_thread.lock().is_none());
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        let _ = fs::remove_dir_all(get_merge_path(&config));
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_bptree() {
+        let mut config = ready_config(file!(), function_name!());
+        config.index_type = IndexType::BPlusTree;
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..1000 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                lite_db.add(&get_test_key(i), &get_test_value(i + 1)).unwrap();
+            }
+            lite_db.merge().expect("failed to merge");
+            lite_db.add(&get_test_key(1), &get_test_value(100)).unwrap();
+            lite_db.remove(&get_test_key(2)).unwrap();
+        }
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(get_test_value(100), lite_db.get(&get_test_key(1)).unwrap());
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(2)).err().unwrap());
+            for i in 3..1000 {
+                assert_eq!(get_test_value(i + 1), lite_db.get(&get_test_key(i)).unwrap());
+            }
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_progress() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 64 * 1024;
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let progress_updates = updates.clone();
+        config.merge_progress = Some(MergeProgressFn::new(move |progress| progress_updates.lock().push(progress)));
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        for i in 0..3000 {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        for i in 0..1000 {
+            lite_db.remove(&get_test_key(i)).unwrap();
+        }
+        let files_total = lite_db.stat().unwrap().data_file_num;
+        assert!(files_total > 2);
+        lite_db.merge().expect("failed to merge");
+
+        // 从 0 开始，每个参与 merge 的文件报告一次，写入的字节数只增不减
+        let updates = updates.lock();
+        assert_eq!(files_total + 1, updates.len());
+        for (i, progress) in updates.iter().enumerate() {
+            assert_eq!(i, progress.files_done);
+            assert_eq!(files_total, progress.files_total);
+        }
+        assert!(updates.windows(2).all(|w| w[0].bytes_written <= w[1].bytes_written));
+        assert!(updates.last().unwrap().bytes_written > 0);
+        for i in 1000..3000 {
+            assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+        }
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_dir() {
+        let base = ready_config(file!(), function_name!());
+        let mut merge_dirs = vec![std::env::temp_dir().join("lite-db-merge-dir")];
+        // 不同的文件系统上移动时复制文件
+        if cfg!(target_os = "linux") && PathBuf::from("/dev/shm").is_dir() {
+            merge_dirs.push(PathBuf::from("/dev/shm/lite-db-merge-dir"));
+        }
+        for (i, merge_dir) in merge_dirs.into_iter().enumerate() {
+            let mut config = base.clone();
+            config.path_db = base.path_db.join(i.to_string());
+            config.file_size_db = 64 * 1024;
+            config.large_value_threshold = 1024;
+            config.merge_dir = Some(merge_dir.clone());
+            let large = Bytes::from(vec![3u8; 2048]);
+            {
+                let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+                for i in 0..3000 {
+                    lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+                }
+                lite_db.add(&get_test_key(3000), &large).unwrap();
+                for i in 0..1000 {
+                    lite_db.remove(&get_test_key(i)).unwrap();
+                }
+                lite_db.merge().expect("failed to merge");
+
+                // merge 目录在 merge_dir 中，完成之后删除，merge 的文件都在数据目录中
+                let merge_path = get_merge_path(&config);
+                assert!(merge_path.starts_with(&merge_dir));
+                assert!(!merge_path.exists());
+                assert!(!base.path_db.join(format!("{}-{}", i, MERGE_DIR_NAME)).exists());
+                assert!(config.path_db.join(HINT_FILE_NAME).is_file());
+                for i in 1000..3000 {
+                    assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+                }
+                assert_eq!(large, lite_db.get(&get_test_key(3000)).unwrap());
+            }
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(2001, lite_db.stat().unwrap().key_num);
+            assert_eq!(large, lite_db.get(&get_test_key(3000)).unwrap());
+            drop(lite_db);
+            let _ = fs::remove_dir_all(merge_dir);
+        }
+
+        fs::remove_dir_all(base.path_db.clone()).expect("failed to remove path");
+    }
+
+    // 数据文件中删除标记的数量
+    fn count_tombstones(lite_db: &LiteDb) -> usize {
+        let count = |data_file: &FileDb| {
+            let mut offset = 0;
+            let mut count = 0;
+            while let Ok(re) = data_file.read_log_db(offset) {
+                if re.log_db.rec_type == LogDbType::DELETED {
+                    count += 1;
+                }
+                offset += re.size as u64;
+            }
+            count
+        };
+        lite_db.older_files.read().values().map(count).sum::<usize>() + count(&lite_db.active_file.read())
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_tombstone_ttl() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 8 * 1024;
+        config.tombstone_ttl = Some(Duration::from_millis(500));
+        let dead_size = |lite_db: &LiteDb| lite_db.file_stats().unwrap().iter().map(|s| s.dead_size as usize).sum::<usize>();
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..300 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 0..10 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            // 事务中的删除标记
+            let batch = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            for i in 10..15 {
+                batch.delete(get_test_key(i)).unwrap();
+            }
+            batch.commit().unwrap();
+            // 删除之后又写入的 key，删除标记不需要保留
+            lite_db.remove(&get_test_key(15)).unwrap();
+            lite_db.add(&get_test_key(15), &get_test_value(15)).unwrap();
+            assert_eq!(16, count_tombstones(&lite_db));
+
+            // 保留时间内 merge，删除标记保留下来，多次 merge 也一样
+            for _ in 0..2 {
+                lite_db.merge().expect("failed to merge");
+                assert_eq!(15, count_tombstones(&lite_db));
+                assert_eq!(dead_size(&lite_db), lite_db.stat().unwrap().reclaim_size);
+            }
+            for i in 0..15 {
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+            }
+            for i in 15..300 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+
+            // 超过保留时间之后 merge，删除标记被丢弃
+            std::thread::sleep(Duration::from_millis(600));
+            lite_db.merge().expect("failed to merge");
+            assert_eq!(0, count_tombstones(&lite_db));
+            assert_eq!(dead_size(&lite_db), lite_db.stat().unwrap().reclaim_size);
+        }
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(285, lite_db.stat().unwrap().key_num);
+        for i in 0..15 {
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+        }
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_corrupt_hint_file() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 64 * 1024;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..3000 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 0..1000 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            lite_db.merge().expect("failed to merge");
+            // merge 之后的写入在没有参与 merge 的文件中
+            lite_db.add(&get_test_key(0), &Bytes::from("after merge")).unwrap();
+            lite_db.remove(&get_test_key(2999)).unwrap();
+        }
+        let check = |lite_db: &LiteDb| {
+            assert_eq!(Bytes::from("after merge"), lite_db.get(&get_test_key(0)).unwrap());
+            for i in 1..1000 {
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+            }
+            for i in 1000..2999 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(2999)).err().unwrap());
+            assert_eq!(2000, lite_db.stat().unwrap().key_num);
+        };
+
+        let hint_file = config.path_db.join(HINT_FILE_NAME);
+        let hint = fs::read(&hint_file).unwrap();
+        // 修改中间的一个字节，crc 校验失败，整个 hint 文件都不使用
+        let mut corrupt = hint.clone();
+        corrupt[hint.len() / 2] ^= 0xff;
+        // 最后一条记录损坏，之前的记录也不会更新到索引中
+        let mut corrupt_last = hint.clone();
+        corrupt_last[hint.len() - 2] ^= 0xff;
+        // 截断的 hint 文件
+        let truncated = hint[..hint.len() - 3].to_vec();
+        for data in [corrupt, corrupt_last, truncated] {
+            fs::write(&hint_file, &data).unwrap();
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            check(&lite_db);
+            drop(lite_db);
+            // 损坏的 hint 文件保留，下一次 open 同样从数据文件中重建
+            assert_eq!(data, fs::read(&hint_file).unwrap());
+        }
+
+        fs::write(&hint_file, &hint).unwrap();
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        check(&lite_db);
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_range() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 16 * 1024;
+        let check = |lite_db: &LiteDb, removed: usize, total: usize| {
+            for i in 0..removed {
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+            }
+            for i in removed..total {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+        };
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..3000 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 0..1000 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            let files = FileDb::find_data_files(&config.path_db).unwrap();
+            assert!(files.len() > 6);
+
+            // 只 merge 前 3 个文件，之后的文件不变
+            lite_db.merge_range(2).expect("failed to merge");
+            let merged = FileDb::find_data_files(&config.path_db).unwrap();
+            assert_eq!(files[3..], merged[merged.len() - (files.len() - 3)..]);
+            assert!(merged.len() < files.len());
+            check(&lite_db, 1000, 3000);
+
+            // 再 merge 更多的文件，已经 merge 的文件一起重新 merge
+            for i in 3000..4000 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 1000..1500 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            lite_db.merge_range(5).expect("failed to merge");
+            check(&lite_db, 1500, 4000);
+            assert_eq!(2500, lite_db.stat().unwrap().key_num);
+        }
+
+        // 重新打开之后从 hint 文件和没有 merge 的文件中加载索引
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        check(&lite_db, 1500, 4000);
+        assert_eq!(2500, lite_db.stat().unwrap().key_num);
+        // 包含活跃文件时和 merge 一样
+        lite_db.merge_range(u32::MAX).expect("failed to merge");
+        check(&lite_db, 1500, 4000);
+        drop(lite_db);
+
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        check(&lite_db, 1500, 4000);
+        let (_, records) = lite_db.load_index_from_data_files_after(get_non_merge_fid(&config.path_db).unwrap()).unwrap();
+        assert_eq!(0, records);
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_estimated_keys() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 64 * 1024;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..3000 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in 0..1000 {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+            lite_db.merge().expect("failed to merge");
+            // merge 之后的写入和事务从数据文件中加载
+            let batch = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            batch.put(get_test_key(0), get_test_value(0)).unwrap();
+            batch.delete(get_test_key(2999)).unwrap();
+            batch.commit().unwrap();
+        }
+
+        // 预计的数量比实际的少、多或者没有设置，加载的索引都一样
+        for estimated_keys in [None, Some(10), Some(100_000)] {
+            config.estimated_keys = estimated_keys;
+            // 恢复索引时暂存事务数据的空间按照 estimated_keys 预先分配
+            assert!(new_transaction_log_dbs(&config).capacity() >= estimated_keys.unwrap_or_default());
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(2000, lite_db.stat().unwrap().key_num);
+            assert_eq!(get_test_value(0), lite_db.get(&get_test_key(0)).unwrap());
+            for i in 1..1000 {
+                assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap());
+            }
+            for i in 1000..2999 {
+                assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap());
+            }
+            assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(2999)).err().unwrap());
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    // 数据文件的 id 和内容
+    fn read_data_files(config: &Config) -> HashMap<u32, Vec<u8>> {
+        let files = FileDb::find_data_files(&config.path_db).unwrap();
+        files.into_iter().map(|(file_id, file_name)| (file_id, fs::read(file_name).unwrap())).collect()
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_merge_strategy() {
+        let mut config = ready_config(file!(), function_name!());
+        config.file_size_db = 16 * 1024;
+        config.merge_strategy = MergeStrategy::SizeTiered;
+        let mut config_full = config.clone();
+        config_full.path_db = PathBuf::from(format!("{}-full", config.path_db.display()));
+        config_full.merge_strategy = MergeStrategy::Full;
+        let _ = fs::remove_dir_all(config_full.path_db.clone());
+
+        // 前面的文件中大部分数据被删除，后面的文件中少部分数据被删除
+        let write = |lite_db: &LiteDb| {
+            for i in 0..3000 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            for i in (0..3000).filter(|i| i < &400 || i % 10 == 0) {
+                lite_db.remove(&get_test_key(i)).unwrap();
+            }
+        };
+        let check = |lite_db: &LiteDb| {
+            for i in 0..3000 {
+                match i < 400 || i % 10 == 0 {
+                    true => assert_eq!(ErrDb::NotFindKey, lite_db.get(&get_test_key(i)).err().unwrap()),
+                    false => assert_eq!(get_test_value(i), lite_db.get(&get_test_key(i)).unwrap()),
+                }
+            }
+        };
+
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            write(&lite_db);
+            let files = read_data_files(&config);
+            assert!(files.len() > 6);
+
+            // 没有 merge 过时只 merge 第一个文件
+            lite_db.merge().expect("failed to merge");
+            let merged = read_data_files(&config);
+            assert_ne!(files[&0], merged[&0]);
+            assert!(merged[&0].len() < files[&0].len());
+            for (file_id, data) in files.iter().filter(|(file_id, _)| **file_id > 0) {
+                assert_eq!(data, &merged[file_id]);
+            }
+            check(&lite_db);
+
+            // 第二个文件不比已经 merge 的文件小，两个一起 merge，之后的文件不变
+            lite_db.merge().expect("failed to merge");
+            let merged2 = read_data_files(&config);
+            assert_ne!(Some(&merged[&1]), merged2.get(&1));
+            for (file_id, data) in files.iter().filter(|(file_id, _)| **file_id > 1) {
+                assert_eq!(data, &merged2[file_id]);
+            }
+            check(&lite_db);
+        }
+
+        // Full 重写所有的文件，之后所有的旧数据文件都已经 merge，SizeTiered 没有可以 merge 的文件
+        {
+            let lite_db = LiteDb::open(config_full.clone()).expect("failed to open engine");
+            write(&lite_db);
+            let files = read_data_files(&config_full);
+            lite_db.merge().expect("failed to merge");
+            let merged = read_data_files(&config_full);
+            for (file_id, data) in files.iter() {
+                assert_ne!(Some(data), merged.get(file_id));
+            }
+            check(&lite_db);
+            drop(lite_db);
+
+            let mut config_tiered = config_full.clone();
+            config_tiered.merge_strategy = MergeStrategy::SizeTiered;
+            let lite_db = LiteDb::open(config_tiered).expect("failed to open engine");
+            check(&lite_db);
+            assert!(lite_db.merge().is_err());
+            assert!(!lite_db.merge_if_needed().unwrap());
+        }
+
+        // 重新打开之后从 hint 文件和没有 merge 的文件中加载索引
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        check(&lite_db);
+        drop(lite_db);
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+        fs::remove_dir_all(config_full.path_db.clone()).expect("failed to remove path");
+    }
 }
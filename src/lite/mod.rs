@@ -1,7 +1,29 @@
+pub use cf::*;
+pub use iterator::*;
 pub use lite::*;
+pub use snapshot::*;
 pub use table::*;
+pub use txn::*;
+#[cfg(feature = "serde")]
+pub use typed::*;
+pub use verify::*;
+pub use watch::*;
 
 mod batch;
+mod blob;
+mod cache;
+mod cf;
+mod dump;
+mod group;
+mod iterator;
 mod lite;
 mod merge;
+mod slow;
+mod snapshot;
+mod sync;
 mod table;
+mod txn;
+#[cfg(feature = "serde")]
+mod typed;
+mod verify;
+mod watch;
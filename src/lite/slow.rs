@@ -0,0 +1,31 @@
+use std::time::{Duration, Instant};
+
+use crate::db::Config;
+
+/// 记录操作开始的时间，结束时耗时超过 slow_op_threshold 则输出警告日志
+/// 在操作的开头创建，提前返回或者返回错误时同样会检查
+pub(crate) struct SlowOp {
+    op: &'static str,
+    threshold: Duration,
+    start: Instant,
+}
+
+impl SlowOp {
+    /// 没有配置 slow_op_threshold 时返回 None，不读取时间
+    pub(crate) fn start(config: &Config, op: &'static str) -> Option<SlowOp> {
+        config.slow_op_threshold.map(|threshold| SlowOp {
+            op,
+            threshold,
+            start: Instant::now(),
+        })
+    }
+}
+
+impl Drop for SlowOp {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        if elapsed > self.threshold {
+            log::warn!("slow operation {}: took {:?}, threshold {:?}", self.op, elapsed, self.threshold);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+
+use crate::db::{ErrDb, IteratorOptions, Key, KeyComparator, LogDbPos, ResultDb, Value};
+use crate::lite::LiteDb;
+
+/// 数据库在某个事务序列号时的只读快照
+/// 创建快照时复制当前的索引，之后的写入和删除对快照不可见
+pub struct Snapshot<'a> {
+    db: &'a LiteDb,
+    seq_no: usize,
+    // 按照索引的顺序排列
+    items: Vec<(Vec<u8>, LogDbPos)>,
+    comparator: Option<KeyComparator>,
+}
+
+impl LiteDb {
+    /// 创建快照，复制索引的开销和 key 的数量成正比
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        // 加锁保证不会看到提交了一半的事务
+        let _lock = self.batch_commit_lock.lock();
+        // 有快照时 merge 不替换数据文件，快照中的位置一直有效
+        let _swap_lock = self.swap_lock.read();
+        self.snapshots.fetch_add(1, Ordering::SeqCst);
+
+        let mut items = Vec::new();
+        let mut iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = iter.next() {
+            items.push((key.clone(), *pos));
+        }
+        Snapshot {
+            db: self,
+            seq_no: self.seq_no.load(Ordering::SeqCst),
+            items,
+            comparator: self.config.comparator.clone(),
+        }
+    }
+}
+
+impl Snapshot<'_> {
+    /// 快照可见的最大事务序列号，之后提交的事务对快照不可见
+    pub fn seq_no(&self) -> usize {
+        self.seq_no
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> ResultDb<Value> {
+        let found = self.items.binary_search_by(|(x, _)| match &self.comparator {
+            Some(c) => c.compare(x, key),
+            None => x.as_slice().cmp(key),
+        });
+        match found {
+            Ok(i) => self.db.get_value_by_pos(&self.items[i].1),
+            Err(_) => Err(ErrDb::NotFindKey),
+        }
+    }
+
+    /// 按照索引中 key 的顺序遍历快照中的数据
+    pub fn iter(&self) -> impl Iterator<Item = ResultDb<(Key, Value)>> + '_ {
+        self.items
+            .iter()
+            .map(|(key, pos)| self.db.get_value_by_pos(pos).map(|value| (Bytes::copy_from_slice(key), value)))
+    }
+}
+
+impl Drop for Snapshot<'_> {
+    fn drop(&mut self) {
+        self.db.snapshots.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use std::sync::atomic::Ordering;
+
+    use bytes::Bytes;
+    use function_name::named;
+
+    use crate::db::{Adder, ErrDb, Getter, Remover, WriteBatchOptions};
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::LiteDb;
+
+    #[named]
+    #[test]
+    fn test_lite_db_snapshot() {
+        let config = kits::test_config(file!(), function_name!());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            for i in 0..10 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+
+            let snapshot = lite_db.snapshot();
+            assert_eq!(10, snapshot.len());
+            assert_eq!(1, lite_db.snapshots.load(Ordering::SeqCst));
+
+            // 快照之后的写入
+            lite_db.add(&get_test_key(0), &Bytes::from("new-0")).unwrap();
+            lite_db.remove(&get_test_key(1)).unwrap();
+            lite_db.add(&get_test_key(100), &get_test_value(100)).unwrap();
+            let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            wb.put(get_test_key(2), Bytes::from("new-2")).unwrap();
+            wb.commit().unwrap();
+
+            assert_eq!(Bytes::from("new-0"), lite_db.get(&get_test_key(0)).unwrap());
+            assert_eq!(Bytes::from("new-2"), lite_db.get(&get_test_key(2)).unwrap());
+
+            // 快照中仍然是之前的数据
+            assert!(snapshot.seq_no() < lite_db.seq_no());
+            assert_eq!(get_test_value(0), snapshot.get(&get_test_key(0)).unwrap());
+            assert_eq!(get_test_value(1), snapshot.get(&get_test_key(1)).unwrap());
+            assert_eq!(get_test_value(2), snapshot.get(&get_test_key(2)).unwrap());
+            assert_eq!(ErrDb::NotFindKey, snapshot.get(&get_test_key(100)).err().unwrap());
+
+            let items: Vec<(Bytes, Bytes)> = snapshot.iter().map(|r| r.unwrap()).collect();
+            assert_eq!(10, items.len());
+            for (key, value) in items.iter() {
+                let i: usize = (0..10).find(|i| get_test_key(*i) == key).unwrap();
+                assert_eq!(get_test_value(i), value);
+            }
+
+            std::mem::drop(snapshot);
+            assert_eq!(0, lite_db.snapshots.load(Ordering::SeqCst));
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,203 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+
+use crate::db::{FileDb, Metrics};
+use crate::lite::blob::BlobFiles;
+
+/// 按照时间间隔持久化 blob 文件和活跃文件的后台线程，停止或者 Drop 时结束线程
+pub(crate) struct SyncThread {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SyncThread {
+    pub(crate) fn start(
+        active_file: Arc<RwLock<FileDb>>,
+        blob_files: Arc<BlobFiles>,
+        bytes_write: Arc<AtomicUsize>,
+        metrics: Arc<dyn Metrics>,
+        interval: Duration,
+    ) -> SyncThread {
+        let (stop, receiver) = channel::<()>();
+        let handle = std::thread::Builder::new()
+            .name("lite_db_sync".to_owned())
+            .spawn(move || {
+                // 收到停止信号或者发送端被丢弃时退出
+                while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                    // 没有新写入的数据时不需要持久化
+                    let written = bytes_write.swap(0, Ordering::SeqCst);
+                    if written == 0 {
+                        continue;
+                    }
+                    // 和 sync_by_policy 一样先持久化 blob 文件，持久化的记录引用的 blob 一定已经持久化
+                    // 失败时不持久化活跃文件，下一次重试
+                    if let Err(e) = blob_files.sync() {
+                        log::error!("failed to sync blob files: {}", e);
+                        bytes_write.fetch_add(written, Ordering::SeqCst);
+                        continue;
+                    }
+                    match active_file.read().sync() {
+                        Ok(_) => metrics.on_sync(),
+                        Err(e) => log::error!("failed to sync active file: {}", e),
+                    }
+                }
+            })
+            .expect("failed to spawn sync thread");
+        SyncThread {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    /// 通知后台线程退出，并等待线程结束
+    pub(crate) fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for SyncThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::db::BLOB_FILE_NAME_SUFFIX;
+    use crate::db::{Adder, Closer, Config, ErrDb, SyncPolicy};
+    use crate::io_db::{DbIoFactory, FileIo, HookIo, MemIo};
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::LiteDb;
+
+    fn open_counted(policy: Option<SyncPolicy>, sync_writes: bool, bytes_per_sync: usize) -> (LiteDb, Arc<AtomicUsize>) {
+        let mut config = Config::default();
+        config.in_memory = true;
+        config.sync_policy = policy;
+        config.sync_writes = sync_writes;
+        config.bytes_per_sync = bytes_per_sync;
+        let lite_db = LiteDb::open(config).expect("failed to open engine");
+        let syncs = Arc::new(AtomicUsize::new(0));
+        let db_io = HookIo::new(MemIo::new()).count_syncs(syncs.clone());
+        lite_db.active_file.write().set_db_io(Box::new(db_io));
+        (lite_db, syncs)
+    }
+
+    fn put_n(lite_db: &LiteDb, n: usize) {
+        for i in 0..n {
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sync_policy() {
+        let (lite_db, syncs) = open_counted(Some(SyncPolicy::Never), true, 0);
+        put_n(&lite_db, 10);
+        assert_eq!(0, syncs.load(Ordering::SeqCst));
+
+        let (lite_db, syncs) = open_counted(Some(SyncPolicy::EveryWrite), false, 0);
+        put_n(&lite_db, 10);
+        assert_eq!(10, syncs.load(Ordering::SeqCst));
+
+        let (lite_db, syncs) = open_counted(Some(SyncPolicy::EveryBytes(1024)), false, 0);
+        let value = Bytes::from(vec![1u8; 300]);
+        for i in 0..10 {
+            lite_db.add(&get_test_key(i), &value).unwrap();
+        }
+        // 每条记录超过 300 字节，4 条记录持久化一次
+        assert_eq!(2, syncs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_sync_policy_compatible() {
+        let (lite_db, syncs) = open_counted(None, false, 0);
+        assert_eq!(SyncPolicy::Never, lite_db.config.get_sync_policy());
+        put_n(&lite_db, 10);
+        assert_eq!(0, syncs.load(Ordering::SeqCst));
+
+        let (lite_db, syncs) = open_counted(None, true, 0);
+        assert_eq!(SyncPolicy::EveryWrite, lite_db.config.get_sync_policy());
+        put_n(&lite_db, 10);
+        assert_eq!(10, syncs.load(Ordering::SeqCst));
+
+        let (lite_db, _) = open_counted(None, false, 1024);
+        assert_eq!(SyncPolicy::EveryBytes(1024), lite_db.config.get_sync_policy());
+
+        let mut config = Config::default();
+        config.in_memory = true;
+        config.sync_policy = Some(SyncPolicy::EveryBytes(0));
+        assert!(LiteDb::open(config).is_err());
+    }
+
+    #[test]
+    fn test_sync_policy_interval() {
+        let (lite_db, syncs) = open_counted(Some(SyncPolicy::Interval(Duration::from_millis(10))), false, 0);
+        put_n(&lite_db, 10);
+        // 写入时不持久化，由后台线程持久化
+        let mut waited = 0;
+        while syncs.load(Ordering::SeqCst) == 0 && waited < 500 {
+            std::thread::sleep(Duration::from_millis(10));
+            waited += 1;
+        }
+        assert!(syncs.load(Ordering::SeqCst) > 0);
+
+        // 没有新的写入时不再持久化
+        std::thread::sleep(Duration::from_millis(50));
+        let count = syncs.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(count, syncs.load(Ordering::SeqCst));
+
+        // 关闭之后后台线程退出，写入返回 Closed
+        lite_db.close().unwrap();
+        assert!(lite_db.sync_thread.lock().is_none());
+        assert_eq!(ErrDb::Closed, lite_db.add(&get_test_key(0), &get_test_value(0)).err().unwrap());
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(count, syncs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_sync_policy_interval_blob() {
+        let mut config = Config::default();
+        config.path_db = PathBuf::from("/tmp/bitcask-rs-sync-interval-blob");
+        let _ = std::fs::remove_dir_all(config.path_db.clone());
+        config.sync_policy = Some(SyncPolicy::Interval(Duration::from_millis(10)));
+        config.large_value_threshold = 1024;
+        let blob_syncs = Arc::new(AtomicUsize::new(0));
+        let factory_syncs = blob_syncs.clone();
+        config.io_factory = Some(DbIoFactory::new(move |file_name| {
+            match file_name.to_string_lossy().ends_with(BLOB_FILE_NAME_SUFFIX) {
+                true => Ok(Box::new(HookIo::new(MemIo::new()).count_syncs(factory_syncs.clone()))),
+                false => Ok(Box::new(FileIo::new(file_name)?)),
+            }
+        }));
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+
+        // 后台线程同样持久化 blob 文件
+        lite_db.add(&get_test_key(0), &Bytes::from(vec![1u8; 4096])).unwrap();
+        let mut waited = 0;
+        while blob_syncs.load(Ordering::SeqCst) == 0 && waited < 500 {
+            std::thread::sleep(Duration::from_millis(10));
+            waited += 1;
+        }
+        assert!(blob_syncs.load(Ordering::SeqCst) > 0);
+
+        drop(lite_db);
+        std::fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+}
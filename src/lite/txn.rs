@@ -0,0 +1,332 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLockWriteGuard};
+
+use crate::db::{ErrDb, Key, LogDbType, ResultDb, Value, WriteBatchOptions};
+use crate::lite::batch::WriteBatch;
+use crate::lite::LiteDb;
+
+/// 乐观事务，写入先暂存在批次中，提交时检查读取过的 key 有没有被修改
+/// key 的版本是单调递增的写入计数，每次写入和删除都使用新的版本，merge 移动数据不改变版本
+pub struct Transaction<'a> {
+    db: &'a LiteDb,
+    batch: WriteBatch<'a>,
+    // 事务开始时的版本，事务结束之前比它小的写入记录不会被清除
+    start: u64,
+    // 第一次读取之前 key 的版本，之后的写入版本不小于它时冲突
+    reads: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+/// 事务冲突检测使用的 key 的版本，只在有进行中的事务时记录写入过的 key
+#[derive(Default)]
+pub(crate) struct KeyVersions {
+    // 下一次写入使用的版本
+    next: u64,
+    // 进行中的事务开始时的版本和数量
+    active: BTreeMap<u64, usize>,
+    // 最早的事务开始之后写入过的 key 的最新版本
+    written: HashMap<Vec<u8>, u64>,
+    // 最近一次 clear 的版本，clear 修改了所有的 key
+    cleared: Option<u64>,
+}
+
+impl KeyVersions {
+    // 写入之前读取的版本，之后的写入的版本都不小于它
+    fn current(&self) -> u64 {
+        self.next
+    }
+
+    fn is_modified(&self, key: &[u8], version: u64) -> bool {
+        self.cleared.is_some_and(|v| v >= version) || self.written.get(key).is_some_and(|v| *v >= version)
+    }
+
+    fn begin(&mut self) -> u64 {
+        *self.active.entry(self.next).or_default() += 1;
+        self.next
+    }
+
+    // 事务结束之后去掉比所有进行中的事务都早的写入记录
+    fn end(&mut self, start: u64) {
+        if let Some(count) = self.active.get_mut(&start) {
+            *count -= 1;
+            if *count == 0 {
+                self.active.remove(&start);
+            }
+        }
+        match self.active.keys().next().copied() {
+            None => {
+                self.written.clear();
+                self.cleared = None;
+            }
+            Some(oldest) if oldest > start => self.written.retain(|_, v| *v >= oldest),
+            Some(_) => {}
+        }
+    }
+}
+
+impl LiteDb {
+    /// 在乐观事务中执行 f，f 返回错误时丢弃暂存的写入，返回 f 的错误
+    /// 提交时如果 f 读取过的 key 已经被其它的写入修改，不写入任何数据，返回 Conflict，调用者可以重试整个事务
+    /// 写入相同的值也算修改，merge 不会导致冲突
+    pub fn transact<F>(&self, f: F) -> ResultDb<()>
+    where
+        F: FnOnce(&Transaction) -> ResultDb<()>,
+    {
+        let txn = Transaction {
+            db: self,
+            batch: self.new_write_batch(WriteBatchOptions::default())?,
+            start: self.key_versions.lock().begin(),
+            reads: Mutex::new(HashMap::new()),
+        };
+        f(&txn)?;
+        txn.commit()
+    }
+
+    // 更新索引之后记录 key 的版本，需要在持有 swap_lock 的读锁时调用，提交事务时持有写锁检查
+    pub(crate) fn record_write(&self, key: &[u8]) {
+        let mut versions = self.key_versions.lock();
+        if versions.active.is_empty() {
+            return;
+        }
+        let version = versions.next;
+        versions.written.insert(key.to_vec(), version);
+        versions.next += 1;
+    }
+
+    // clear 删除了所有的 key，进行中的事务读取过的 key 都已经修改
+    pub(crate) fn record_clear(&self) {
+        let mut versions = self.key_versions.lock();
+        if versions.active.is_empty() {
+            return;
+        }
+        versions.cleared = Some(versions.next);
+        versions.next += 1;
+    }
+}
+
+impl Transaction<'_> {
+    /// 读取 key，先读取事务中暂存的写入，并记录 key 当前的版本
+    pub fn get(&self, key: &Key) -> ResultDb<Value> {
+        if let Some(log_db) = self.batch.pending.lock().get(key.as_ref()) {
+            return match log_db.rec_type {
+                LogDbType::DELETED => Err(ErrDb::NotFindKey),
+                _ => Ok(Bytes::from(log_db.value.clone())),
+            };
+        }
+
+        let _swap_lock = self.db.swap_lock.read();
+        // 先取版本再读取索引，读取之后的写入的版本都不小于它
+        let version = self.db.key_versions.lock().current();
+        let pos = self.db.index.try_get(key.to_vec())?;
+        self.reads.lock().entry(key.to_vec()).or_insert(version);
+        match pos {
+            Some(pos) => self.db.get_value_by_pos(&pos),
+            None => Err(ErrDb::NotFindKey),
+        }
+    }
+
+    pub fn put(&self, key: Key, value: Value) -> ResultDb<()> {
+        self.batch.put(key, value)
+    }
+
+    pub fn delete(&self, key: Key) -> ResultDb<()> {
+        self.batch.delete(key)
+    }
+
+    // 持有 swap_lock 的写锁检查读取过的 key，检查时没有进行中的写入，检查之后降级为读锁提交
+    fn commit(&self) -> ResultDb<()> {
+        let mut pending_writes = self.batch.pending.lock();
+        let _lock = self.db.batch_commit_lock.lock();
+        let swap_lock = self.db.swap_lock.write();
+        {
+            let versions = self.db.key_versions.lock();
+            if self.reads.lock().iter().any(|(key, version)| versions.is_modified(key, *version)) {
+                return Err(ErrDb::Conflict);
+            }
+        }
+        self.batch.commit_pending(&mut pending_writes, RwLockWriteGuard::downgrade(swap_lock))
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.db.key_versions.lock().end(self.start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use std::sync::Barrier;
+    use std::thread;
+
+    use bytes::Bytes;
+    use function_name::named;
+
+    use crate::db::{Adder, ErrDb, Getter, Remover};
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::LiteDb;
+
+    // 读取计数加一之后写回
+    fn increment(lite_db: &LiteDb, key: &Bytes, barrier: Option<&Barrier>) -> Result<(), ErrDb> {
+        lite_db.transact(|txn| {
+            let count: u64 = String::from_utf8(txn.get(key)?.to_vec()).unwrap().parse().unwrap();
+            if let Some(barrier) = barrier {
+                barrier.wait();
+            }
+            txn.put(key.clone(), Bytes::from((count + 1).to_string()))
+        })
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_transact_conflict() {
+        let config = kits::test_config(file!(), function_name!());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let key = Bytes::from("counter");
+        lite_db.add(&key, &Bytes::from("0")).unwrap();
+
+        // 两个事务都读取之后才提交，只有一个成功，另一个返回 Conflict
+        let barrier = Barrier::new(2);
+        let results: Vec<Result<(), ErrDb>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..2).map(|_| s.spawn(|| increment(&lite_db, &key, Some(&barrier)))).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(1, results.iter().filter(|re| re.is_ok()).count());
+        assert_eq!(1, results.iter().filter(|re| **re == Err(ErrDb::Conflict)).count());
+        assert_eq!(Bytes::from("1"), lite_db.get(&key).unwrap());
+
+        // 冲突时重试，所有的加一都不会丢失
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        while increment(&lite_db, &key, None) == Err(ErrDb::Conflict) {}
+                    }
+                });
+            }
+        });
+        assert_eq!(Bytes::from("41"), lite_db.get(&key).unwrap());
+
+        // 不在批次中的写入也会导致冲突
+        let re = lite_db.transact(|txn| {
+            txn.get(&key)?;
+            lite_db.add(&key, &Bytes::from("100")).unwrap();
+            txn.put(key.clone(), Bytes::from("42"))
+        });
+        assert_eq!(Err(ErrDb::Conflict), re);
+        assert_eq!(Bytes::from("100"), lite_db.get(&key).unwrap());
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_transact() {
+        let config = kits::test_config(file!(), function_name!());
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let (a, b) = (Bytes::from("a"), Bytes::from("b"));
+        lite_db.add(&a, &Bytes::from("1")).unwrap();
+
+        // 事务中可以读取自己暂存的写入，提交之前其它的读取看不到
+        lite_db
+            .transact(|txn| {
+                txn.put(b.clone(), Bytes::from("2"))?;
+                assert_eq!(Bytes::from("2"), txn.get(&b)?);
+                txn.delete(a.clone())?;
+                assert_eq!(Err(ErrDb::NotFindKey), txn.get(&a));
+                assert_eq!(Err(ErrDb::NotFindKey), lite_db.get(&b));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(Err(ErrDb::NotFindKey), lite_db.get(&a));
+        assert_eq!(Bytes::from("2"), lite_db.get(&b).unwrap());
+
+        // 读取不存在的 key 也会记录版本，之后被写入时冲突
+        let re = lite_db.transact(|txn| {
+            assert_eq!(Err(ErrDb::NotFindKey), txn.get(&a));
+            lite_db.add(&a, &Bytes::from("3")).unwrap();
+            txn.put(b.clone(), Bytes::from("4"))
+        });
+        assert_eq!(Err(ErrDb::Conflict), re);
+        assert_eq!(Bytes::from("2"), lite_db.get(&b).unwrap());
+
+        // f 返回错误时丢弃暂存的写入
+        let re = lite_db.transact(|txn| {
+            txn.put(b.clone(), Bytes::from("5"))?;
+            Err(ErrDb::InvalidParameter)
+        });
+        assert_eq!(Err(ErrDb::InvalidParameter), re);
+        assert_eq!(Bytes::from("2"), lite_db.get(&b).unwrap());
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_transact_version() {
+        let mut config = kits::test_config(file!(), function_name!());
+        config.file_size_db = 4 * 1024;
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        let key = Bytes::from("a");
+        // 之前的数据被删除，merge 之后 key 的位置改变
+        for i in 0..200 {
+            if i == 100 {
+                lite_db.add(&key, &Bytes::from("1")).unwrap();
+            }
+            lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+        }
+        for i in 0..100 {
+            lite_db.remove(&get_test_key(i)).unwrap();
+        }
+
+        // merge 移动了 key 的位置，没有修改 key，不会冲突
+        let pos = lite_db.index.get(key.to_vec()).unwrap();
+        lite_db
+            .transact(|txn| {
+                assert_eq!(Bytes::from("1"), txn.get(&key)?);
+                lite_db.merge()?;
+                assert_ne!(Some(pos), lite_db.index.get(key.to_vec()));
+                txn.put(key.clone(), Bytes::from("2"))
+            })
+            .unwrap();
+        assert_eq!(Bytes::from("2"), lite_db.get(&key).unwrap());
+
+        // 写入相同的值也是修改
+        let re = lite_db.transact(|txn| {
+            txn.get(&key)?;
+            lite_db.add(&key, &Bytes::from("2")).unwrap();
+            txn.put(key.clone(), Bytes::from("3"))
+        });
+        assert_eq!(Err(ErrDb::Conflict), re);
+
+        // 修改之后又删除，key 回到读取时的状态也冲突
+        let b = Bytes::from("b");
+        let re = lite_db.transact(|txn| {
+            assert_eq!(Err(ErrDb::NotFindKey), txn.get(&b));
+            lite_db.add(&b, &Bytes::from("1")).unwrap();
+            lite_db.remove(&b).unwrap();
+            txn.put(key.clone(), Bytes::from("3"))
+        });
+        assert_eq!(Err(ErrDb::Conflict), re);
+
+        // clear 修改了所有的 key
+        let re = lite_db.transact(|txn| {
+            txn.get(&key)?;
+            lite_db.clear().unwrap();
+            txn.put(key.clone(), Bytes::from("3"))
+        });
+        assert_eq!(Err(ErrDb::Conflict), re);
+        assert_eq!(Err(ErrDb::NotFindKey), lite_db.get(&key));
+
+        // 所有的事务结束之后不再保存写入过的 key
+        lite_db.add(&key, &Bytes::from("4")).unwrap();
+        assert!(lite_db.key_versions.lock().written.is_empty());
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+}
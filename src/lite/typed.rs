@@ -0,0 +1,116 @@
+use std::marker::PhantomData;
+
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::db::{Adder, Config, ErrDb, Getter, Remover, ResultDb};
+use crate::lite::LiteDb;
+
+/// 带类型的 LiteDb 封装，key 和 value 使用 bincode 序列化
+pub struct TypedDb<K, V> {
+    db: LiteDb,
+    _marker: PhantomData<fn(K, V)>,
+}
+
+impl<K, V> TypedDb<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn open(config: Config) -> ResultDb<Self> {
+        Ok(Self::new(LiteDb::open(config)?))
+    }
+
+    pub fn new(db: LiteDb) -> Self {
+        Self { db, _marker: PhantomData }
+    }
+
+    pub fn put(&self, key: &K, value: &V) -> ResultDb<()> {
+        self.db.add(&encode(key)?, &encode(value)?)
+    }
+
+    pub fn get(&self, key: &K) -> ResultDb<V> {
+        let value = self.db.get(&encode(key)?)?;
+        decode(&value)
+    }
+
+    /// if can not find Key，then return None
+    pub fn remove(&self, key: &K) -> ResultDb<Option<V>> {
+        match self.db.remove(&encode(key)?)? {
+            Some(value) => Ok(Some(decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn db(&self) -> &LiteDb {
+        &self.db
+    }
+
+    pub fn into_db(self) -> LiteDb {
+        self.db
+    }
+}
+
+fn encode<T: Serialize>(t: &T) -> ResultDb<Bytes> {
+    bincode::serialize(t).map(Bytes::from).map_err(|e| ErrDb::Serialize(e.to_string()))
+}
+
+fn decode<T: DeserializeOwned>(buf: &[u8]) -> ResultDb<T> {
+    bincode::deserialize(buf).map_err(|e| ErrDb::Serialize(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use function_name::named;
+    use serde::Deserialize;
+
+    use crate::db::ErrDb;
+    use crate::kits;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct User {
+        name: String,
+        age: u32,
+        tags: Vec<String>,
+    }
+
+    #[named]
+    #[test]
+    fn test_typed_db() {
+        let config = kits::test_config(file!(), function_name!());
+        {
+            let typed_db: TypedDb<(String, u64), User> = TypedDb::open(config.clone()).expect("failed to open engine");
+            let key = ("user".to_owned(), 7u64);
+            let user = User {
+                name: "lite".to_owned(),
+                age: 3,
+                tags: vec!["a".to_owned(), "b".to_owned()],
+            };
+
+            let re = typed_db.get(&key);
+            assert_eq!(ErrDb::NotFindKey, re.err().unwrap());
+
+            typed_db.put(&key, &user).expect("failed to put");
+            assert_eq!(user, typed_db.get(&key).unwrap());
+
+            let re2 = typed_db.remove(&key);
+            assert_eq!(Some(user), re2.unwrap());
+            assert!(typed_db.remove(&key).unwrap().is_none());
+        }
+
+        // the value can not be decoded as the type
+        {
+            let typed_db: TypedDb<u32, String> = TypedDb::open(config.clone()).expect("failed to open engine");
+            typed_db.db().add(&encode(&1u32).unwrap(), &Bytes::from_static(&[0xff])).unwrap();
+            let re = typed_db.get(&1u32);
+            assert!(matches!(re, Err(ErrDb::Serialize(_))));
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+}
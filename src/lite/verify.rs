@@ -0,0 +1,253 @@
+use std::sync::atomic::Ordering;
+
+use crate::db::{ErrDb, FileDb, ResultDb};
+use crate::lite::LiteDb;
+
+/// 单个数据文件的校验结果
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileVerifyReport {
+    pub file_id: u32,
+    // crc 正确的记录数量
+    pub valid_records: usize,
+    // crc 错误或者无法解析的记录数量
+    pub invalid_records: usize,
+    // 第一条错误记录的位置
+    pub first_invalid_offset: Option<u64>,
+}
+
+/// 数据库的校验结果
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct VerifyReport {
+    // 按照文件 id 从小到大排列
+    pub files: Vec<FileVerifyReport>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|f| f.invalid_records == 0)
+    }
+
+    pub fn invalid_records(&self) -> usize {
+        self.files.iter().map(|f| f.invalid_records).sum()
+    }
+
+    /// 第一条错误记录的文件 id 和位置
+    pub fn first_invalid(&self) -> Option<(u32, u64)> {
+        self.files.iter().find_map(|f| f.first_invalid_offset.map(|offset| (f.file_id, offset)))
+    }
+}
+
+/// 数据库的健康状态，用来决定是重新打开数据库还是报警
+#[derive(Clone, PartialEq, Debug)]
+pub enum Health {
+    Ok,
+    // 只能读取，重新打开之后可以恢复，如只读模式或者已经关闭释放了目录锁
+    DegradedReadOnly(String),
+    // 数据已经损坏或者丢失，重新打开不能恢复，如打开时截断了损坏的记录或者 verify 发现了错误的记录
+    Corrupt(String),
+}
+
+impl LiteDb {
+    /// 数据库的健康状态，Corrupt 优先于 DegradedReadOnly
+    /// 只根据打开和最近一次 verify 的结果判断，不读取数据文件
+    pub fn health(&self) -> Health {
+        if let Some(reason) = self.verify_failure.lock().clone() {
+            return Health::Corrupt(reason);
+        }
+        if let Some(reason) = self.repaired_tail.lock().clone() {
+            return Health::Corrupt(reason);
+        }
+        if self.closed.load(Ordering::SeqCst) {
+            return Health::DegradedReadOnly("the db is closed and the directory lock is released".to_owned());
+        }
+        if self.config.read_only {
+            return Health::DegradedReadOnly("the db is opened read only".to_owned());
+        }
+        Health::Ok
+    }
+
+    /// 逐条读取所有的数据文件并重新计算 crc，检查数据是否完整
+    /// 不会修改数据文件，结果记录在 health 中，校验期间写入会被阻塞
+    pub fn verify(&self) -> ResultDb<VerifyReport> {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+
+        let mut file_ids: Vec<u32> = older_files.keys().copied().collect();
+        file_ids.sort();
+
+        let mut report = VerifyReport::default();
+        for file_id in file_ids.iter() {
+            report.files.push(verify_file(older_files.get(file_id).unwrap())?);
+        }
+        report.files.push(verify_file(&active_file)?);
+        *self.verify_failure.lock() = report.first_invalid().map(|(file_id, offset)| {
+            format!(
+                "verify found {} invalid records, the first in file {} at offset {}",
+                report.invalid_records(),
+                file_id,
+                offset
+            )
+        });
+        Ok(report)
+    }
+}
+
+fn verify_file(data_file: &FileDb) -> ResultDb<FileVerifyReport> {
+    let mut report = FileVerifyReport {
+        file_id: data_file.get_file_id(),
+        valid_records: 0,
+        invalid_records: 0,
+        first_invalid_offset: None,
+    };
+    let mut offset = 0;
+    loop {
+        let size = match data_file.check_log_db(offset) {
+            Ok((true, size)) => {
+                report.valid_records += 1;
+                size
+            }
+            Ok((false, size)) => {
+                report.invalid_records += 1;
+                report.first_invalid_offset.get_or_insert(offset);
+                size
+            }
+            Err(e) => {
+                if e == ErrDb::new_io_eof("") {
+                    break;
+                }
+                if let ErrDb::IoErr(_) = e {
+                    return Err(e);
+                }
+                // header 无法解析，不知道记录的大小，后面的数据都无法读取
+                report.invalid_records += 1;
+                report.first_invalid_offset.get_or_insert(offset);
+                break;
+            }
+        };
+        offset += size as u64;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use bytes::Bytes;
+    use function_name::named;
+
+    use crate::db::{Adder, Closer, FileDb};
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::{Health, LiteDb};
+
+    #[named]
+    #[test]
+    fn test_lite_db_verify() {
+        let mut config = kits::test_config(file!(), function_name!());
+        config.file_size_db = 64 * 1024;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let mut n = 0;
+            while lite_db.active_file.read().get_file_id() < 2 {
+                lite_db.add(&get_test_key(n), &get_test_value(n)).unwrap();
+                n += 1;
+            }
+            lite_db.add(&get_test_key(n), &Bytes::from("middle-value")).unwrap();
+            lite_db.add(&get_test_key(n + 1), &get_test_value(n + 1)).unwrap();
+
+            let report = lite_db.verify().unwrap();
+            assert!(report.is_ok());
+            assert_eq!(3, report.files.len());
+            // 两个封存的文件末尾各有一条 footer 记录
+            assert_eq!(n + 2 + 2, report.files.iter().map(|f| f.valid_records).sum::<usize>());
+
+            // 修改中间一条记录的 value
+            let pos = lite_db.index.get(get_test_key(n).to_vec()).unwrap();
+            let file_name = FileDb::get_data_file_name(config.path_db.clone(), pos.file_id);
+            let mut file = fs::OpenOptions::new().write(true).open(file_name).unwrap();
+            let value_offset = pos.offset + pos.size as u64 - 4 - "middle-value".len() as u64;
+            file.seek(SeekFrom::Start(value_offset)).unwrap();
+            file.write_all(b"MIDDLE").unwrap();
+
+            let report = lite_db.verify().unwrap();
+            assert!(!report.is_ok());
+            assert_eq!(1, report.invalid_records());
+            assert_eq!(Some((pos.file_id, pos.offset)), report.first_invalid());
+            // 错误记录之后的记录仍然可以校验
+            let file_report = report.files.iter().find(|f| f.file_id == pos.file_id).unwrap();
+            assert_eq!(1, file_report.invalid_records);
+            assert!(file_report.valid_records > 0);
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+
+    #[named]
+    #[test]
+    fn test_lite_db_health() {
+        let mut config = kits::test_config(file!(), function_name!());
+        let tail = {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            assert_eq!(Health::Ok, lite_db.health());
+            for i in 0..10 {
+                lite_db.add(&get_test_key(i), &get_test_value(i)).unwrap();
+            }
+            assert!(lite_db.verify().unwrap().is_ok());
+            assert_eq!(Health::Ok, lite_db.health());
+
+            // 关闭之后释放了目录锁
+            let mut tail = lite_db.get_raw(&get_test_key(9)).unwrap();
+            lite_db.close().unwrap();
+            assert!(matches!(lite_db.health(), Health::DegradedReadOnly(_)));
+            let len = tail.len();
+            tail[len - 1] ^= 0xff;
+            tail
+        };
+
+        // 只读模式
+        {
+            let mut read_only = config.clone();
+            read_only.read_only = true;
+            let lite_db = LiteDb::open(read_only).expect("failed to open engine");
+            assert!(matches!(lite_db.health(), Health::DegradedReadOnly(_)));
+        }
+
+        // verify 发现错误的记录，再次 verify 没有错误之后恢复
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let pos = lite_db.index.get(get_test_key(5).to_vec()).unwrap();
+            let file_name = FileDb::get_data_file_name(config.path_db.clone(), pos.file_id);
+            let mut file = fs::OpenOptions::new().read(true).write(true).open(file_name).unwrap();
+            let offset = pos.offset + pos.size as u64 - 5;
+            let mut value = [0u8; 1];
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.read_exact(&mut value).unwrap();
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.write_all(&[value[0] ^ 0xff]).unwrap();
+            assert!(!lite_db.verify().unwrap().is_ok());
+            assert!(matches!(lite_db.health(), Health::Corrupt(reason) if reason.contains("1 invalid records")));
+
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.write_all(&value).unwrap();
+            assert!(lite_db.verify().unwrap().is_ok());
+            assert_eq!(Health::Ok, lite_db.health());
+        }
+
+        // 打开时截断了损坏的记录
+        let file_name = FileDb::get_data_file_name(config.path_db.clone(), 0);
+        let file = fs::OpenOptions::new().append(true).open(file_name).unwrap();
+        (&file).write_all(&tail).unwrap();
+        config.repair_on_open = true;
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to repair engine");
+            assert!(matches!(lite_db.health(), Health::Corrupt(reason) if reason.contains("truncated")));
+        }
+        let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+        assert_eq!(Health::Ok, lite_db.health());
+
+        drop(lite_db);
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use bytes::Bytes;
+
+use crate::lite::LiteDb;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChangeKind {
+    Put,
+    Delete,
+}
+
+/// 键变更事件，在内存索引更新之后发出
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChangeEvent {
+    pub key: Bytes,
+    pub kind: ChangeKind,
+}
+
+pub(crate) struct Watcher {
+    prefix: Vec<u8>,
+    sender: Sender<ChangeEvent>,
+}
+
+impl LiteDb {
+    /// 监听以 prefix 开头的 key 的变更，prefix 为空时监听所有的 key
+    /// 丢弃 Receiver 即取消监听
+    pub fn watch(&self, prefix: Vec<u8>) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = channel();
+        self.watchers.lock().push(Watcher { prefix, sender });
+        receiver
+    }
+
+    pub(crate) fn notify_change(&self, key: &[u8], kind: ChangeKind) {
+        let mut watchers = self.watchers.lock();
+        if watchers.is_empty() {
+            return;
+        }
+        let key = Bytes::copy_from_slice(key);
+        // 发送失败说明 Receiver 已经被丢弃，移除该监听
+        watchers.retain(|w| {
+            if !key.starts_with(&w.prefix) {
+                return true;
+            }
+            w.sender.send(ChangeEvent { key: key.clone(), kind }).is_ok()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bytes::Bytes;
+    use function_name::named;
+
+    use crate::db::{Adder, Remover, WriteBatchOptions};
+    use crate::kits;
+    use crate::kits::rand_kv::{get_test_key, get_test_value};
+    use crate::lite::{ChangeEvent, ChangeKind, LiteDb};
+
+    #[named]
+    #[test]
+    fn test_lite_db_watch() {
+        let config = kits::test_config(file!(), function_name!());
+        {
+            let lite_db = LiteDb::open(config.clone()).expect("failed to open engine");
+            let all = lite_db.watch(Vec::new());
+            let user = lite_db.watch(b"user-".to_vec());
+
+            lite_db.add(&Bytes::from("user-1"), &get_test_value(1)).unwrap();
+            lite_db.add(&get_test_key(2), &get_test_value(2)).unwrap();
+            lite_db.remove(&Bytes::from("user-1")).unwrap();
+            lite_db.remove_fast(&get_test_key(2)).unwrap();
+            // 不存在的 key 不产生事件
+            lite_db.remove(&Bytes::from("user-none")).unwrap();
+
+            let wb = lite_db.new_write_batch(WriteBatchOptions::default()).unwrap();
+            wb.put(Bytes::from("user-3"), get_test_value(3)).unwrap();
+            wb.commit().unwrap();
+
+            let put = |k: Bytes| ChangeEvent { key: k, kind: ChangeKind::Put };
+            let del = |k: Bytes| ChangeEvent {
+                key: k,
+                kind: ChangeKind::Delete,
+            };
+
+            let events: Vec<ChangeEvent> = all.try_iter().collect();
+            assert_eq!(
+                events,
+                vec![
+                    put(Bytes::from("user-1")),
+                    put(get_test_key(2)),
+                    del(Bytes::from("user-1")),
+                    del(get_test_key(2)),
+                    put(Bytes::from("user-3")),
+                ]
+            );
+
+            let events: Vec<ChangeEvent> = user.try_iter().collect();
+            assert_eq!(events, vec![put(Bytes::from("user-1")), del(Bytes::from("user-1")), put(Bytes::from("user-3"))]);
+
+            // 丢弃 Receiver 之后，监听被移除
+            std::mem::drop(user);
+            lite_db.add(&Bytes::from("user-4"), &get_test_value(4)).unwrap();
+            assert_eq!(1, lite_db.watchers.lock().len());
+            assert_eq!(Some(put(Bytes::from("user-4"))), all.try_recv().ok());
+        }
+
+        fs::remove_dir_all(config.path_db.clone()).expect("failed to remove path");
+    }
+}